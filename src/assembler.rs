@@ -0,0 +1,848 @@
+pub use self::disasm::disassemble;
+pub use self::elf::{write_elf, write_executable};
+use self::encode::{encode, lookup, reg_num, Format};
+pub use self::link::{link, runtime, Executable};
+
+mod disasm;
+mod elf;
+pub(crate) mod encode;
+mod link;
+
+// 内置的 RV32IM 汇编器.
+// 输入是 generate_asm 输出的汇编 (以及手写的运行时库), 先展开伪指令并确定每个标号的偏移,
+// 再编码指令. 同一段中的局部标号直接算出偏移, 其余的符号引用留给链接器重定位.
+// link 把目标文件和 runtime.S 中的运行时库静态链接成可执行文件.
+
+/// 目标文件中的段
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SectionKind {
+    Text,
+    Data,
+    Bss,
+}
+
+impl SectionKind {
+    pub const ALL: [SectionKind; 3] = [Self::Text, Self::Data, Self::Bss];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Text => ".text",
+            Self::Data => ".data",
+            Self::Bss => ".bss",
+        }
+    }
+}
+
+/// 重定位的类型, 与 RISC-V psABI 中的 R_RISCV_* 对应
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RelocKind {
+    /// .word sym
+    Abs32,
+    /// 条件跳转的 12 位偏移
+    Branch,
+    /// jal 的 20 位偏移
+    Jal,
+    /// call 展开成的 auipc + jalr
+    CallPlt,
+    /// lui 中的 %hi(sym)
+    Hi20,
+    /// I 型指令中的 %lo(sym)
+    Lo12I,
+    /// S 型指令中的 %lo(sym)
+    Lo12S,
+}
+
+impl RelocKind {
+    pub fn elf_type(self) -> u32 {
+        match self {
+            Self::Abs32 => 1,
+            Self::Branch => 16,
+            Self::Jal => 17,
+            Self::CallPlt => 19,
+            Self::Hi20 => 26,
+            Self::Lo12I => 27,
+            Self::Lo12S => 28,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reloc {
+    pub offset: u32,
+    pub kind: RelocKind,
+    pub symbol: String,
+    pub addend: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Section {
+    /// .bss 的内容全是 0, 只用来记录大小
+    pub data: Vec<u8>,
+    pub align: u32,
+    pub relocs: Vec<Reloc>,
+}
+
+/// 汇编中定义的标号
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub section: SectionKind,
+    pub offset: u32,
+    pub global: bool,
+}
+
+/// 汇编的结果, 还没有链接
+#[derive(Clone, Debug)]
+pub struct Object {
+    /// 按 SectionKind::ALL 的顺序排列
+    pub sections: Vec<Section>,
+    /// 按定义的顺序排列
+    pub symbols: Vec<Symbol>,
+}
+
+impl Object {
+    pub fn section(&self, kind: SectionKind) -> &Section {
+        &self.sections[kind as usize]
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// 被引用但没有定义的符号, 按第一次引用的顺序排列
+    pub fn undefined_symbols(&self) -> Vec<&str> {
+        let mut undefined: Vec<&str> = Vec::new();
+        for section in &self.sections {
+            for reloc in &section.relocs {
+                let name = reloc.symbol.as_str();
+                if self.symbol(name).is_none() && !undefined.contains(&name) {
+                    undefined.push(name);
+                }
+            }
+        }
+        undefined
+    }
+}
+
+// 指令中的立即数, 符号要等所有标号的位置确定之后才能求出
+#[derive(Clone, Debug)]
+enum Imm {
+    Num(i32),
+    /// 跳转目标, 相对于当前指令
+    PcRel(String),
+    Hi(String),
+    Lo(String),
+    /// call 中 auipc 的立即数, 与后面的 jalr 一起重定位
+    Call(String),
+    /// call 中 jalr 的立即数
+    CallLo(String),
+}
+
+#[derive(Clone, Debug)]
+struct Inst {
+    format: Format,
+    rd: u32,
+    rs1: u32,
+    rs2: u32,
+    imm: Imm,
+}
+
+// 第一遍扫描后段中的内容
+enum Item {
+    Inst(usize, Inst),
+    Bytes(Vec<u8>),
+    /// .word 引用的符号
+    Word(String),
+}
+
+fn parse_num(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// 去掉注释, 忽略字符串中的 #
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// 按逗号分开操作数, 忽略字符串中的逗号
+fn split_operands(s: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut cur = String::new();
+    let mut in_string = false;
+    for c in s.chars() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                cur.push(c);
+            }
+            ',' if !in_string => operands.push(std::mem::take(&mut cur).trim().to_owned()),
+            _ => cur.push(c),
+        }
+    }
+    if !cur.trim().is_empty() {
+        operands.push(cur.trim().to_owned());
+    }
+    operands
+}
+
+fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or("expected a string literal")?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            c => return Err(format!("unknown escape {c:?}")),
+        });
+    }
+    Ok(bytes)
+}
+
+struct Assembler {
+    items: Vec<Vec<(u32, Item)>>,
+    sizes: Vec<u32>,
+    aligns: Vec<u32>,
+    symbols: Vec<Symbol>,
+    globals: Vec<String>,
+    cur: SectionKind,
+    line: usize,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self {
+            items: vec![Vec::new(), Vec::new(), Vec::new()],
+            sizes: vec![0; 3],
+            aligns: vec![4, 1, 1],
+            symbols: Vec::new(),
+            globals: Vec::new(),
+            cur: SectionKind::Text,
+            line: 0,
+        }
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("line {}: {msg}", self.line))
+    }
+
+    fn reg(&self, s: &str) -> Result<u32, String> {
+        match reg_num(s) {
+            Some(reg) => Ok(reg),
+            None => self.error(&format!("unknown register `{s}`")),
+        }
+    }
+
+    fn num(&self, s: &str) -> Result<i32, String> {
+        match parse_num(s) {
+            Some(value) if (i32::MIN as i64..=u32::MAX as i64).contains(&value) => Ok(value as i32),
+            _ => self.error(&format!("invalid immediate `{s}`")),
+        }
+    }
+
+    // 立即数操作数, 可以是数字, %hi(sym) 或 %lo(sym)
+    fn imm(&self, s: &str) -> Result<Imm, String> {
+        Ok(
+            if let Some(sym) = s.strip_prefix("%hi(").and_then(|s| s.strip_suffix(')')) {
+                Imm::Hi(sym.to_owned())
+            } else if let Some(sym) = s.strip_prefix("%lo(").and_then(|s| s.strip_suffix(')')) {
+                Imm::Lo(sym.to_owned())
+            } else {
+                Imm::Num(self.num(s)?)
+            },
+        )
+    }
+
+    // 跳转目标, 数字表示相对当前指令的偏移
+    fn target(&self, s: &str) -> Result<Imm, String> {
+        Ok(match parse_num(s) {
+            Some(_) => Imm::Num(self.num(s)?),
+            None => Imm::PcRel(s.to_owned()),
+        })
+    }
+
+    // imm(reg) 形式的访存操作数
+    fn mem(&self, s: &str) -> Result<(Imm, u32), String> {
+        let Some(open) = s.rfind('(') else {
+            return self.error(&format!("expected imm(reg), found `{s}`"));
+        };
+        let reg = s[open + 1..].strip_suffix(')').unwrap_or_default();
+        let imm = match &s[..open] {
+            "" => Imm::Num(0),
+            imm => self.imm(imm)?,
+        };
+        Ok((imm, self.reg(reg)?))
+    }
+
+    fn push(&mut self, size: u32, item: Item) -> Result<(), String> {
+        let sec = self.cur as usize;
+        if self.cur == SectionKind::Bss
+            && !matches!(&item, Item::Bytes(bytes) if bytes.iter().all(|&b| b == 0))
+        {
+            return self.error("only zeros can be placed in .bss");
+        }
+        self.items[sec].push((self.sizes[sec], item));
+        self.sizes[sec] += size;
+        Ok(())
+    }
+
+    fn inst(
+        &mut self,
+        format: Format,
+        rd: u32,
+        rs1: u32,
+        rs2: u32,
+        imm: Imm,
+    ) -> Result<(), String> {
+        let inst = Inst {
+            format,
+            rd,
+            rs1,
+            rs2,
+            imm,
+        };
+        self.push(4, Item::Inst(self.line, inst))
+    }
+
+    fn base(&self, mnemonic: &str) -> Format {
+        lookup(mnemonic).unwrap()
+    }
+
+    fn expect(&self, ops: &[String], n: usize) -> Result<(), String> {
+        if ops.len() != n {
+            return self.error(&format!("expected {n} operands, found {}", ops.len()));
+        }
+        Ok(())
+    }
+
+    fn align(&mut self, align: u32) -> Result<(), String> {
+        let sec = self.cur as usize;
+        self.aligns[sec] = self.aligns[sec].max(align);
+        let padding = self.sizes[sec].next_multiple_of(align) - self.sizes[sec];
+        let bytes = if self.cur == SectionKind::Text && padding.is_multiple_of(4) {
+            // 代码段用 nop 填充
+            0x13u32.to_le_bytes().repeat(padding as usize / 4)
+        } else {
+            vec![0; padding as usize]
+        };
+        self.push(padding, Item::Bytes(bytes))
+    }
+
+    fn directive(&mut self, name: &str, ops: &[String]) -> Result<(), String> {
+        match name {
+            ".text" => self.cur = SectionKind::Text,
+            ".data" => self.cur = SectionKind::Data,
+            ".bss" => self.cur = SectionKind::Bss,
+            ".section" => {
+                let section = ops.first().map(String::as_str).unwrap_or_default();
+                self.cur = if section.starts_with(".text") {
+                    SectionKind::Text
+                } else if section.starts_with(".bss") || section.starts_with(".sbss") {
+                    SectionKind::Bss
+                } else {
+                    SectionKind::Data
+                };
+            }
+            ".globl" | ".global" => self.globals.extend(ops.iter().cloned()),
+            ".word" | ".half" | ".byte" => {
+                let size = match name {
+                    ".word" => 4,
+                    ".half" => 2,
+                    _ => 1,
+                };
+                for op in ops {
+                    if size == 4 && parse_num(op).is_none() {
+                        self.push(4, Item::Word(op.clone()))?;
+                        continue;
+                    }
+                    let bytes = self.num(op)?.to_le_bytes()[..size].to_vec();
+                    self.push(size as u32, Item::Bytes(bytes))?;
+                }
+            }
+            ".zero" | ".space" => {
+                self.expect(ops, 1)?;
+                let size = self.num(&ops[0])? as u32;
+                self.push(size, Item::Bytes(vec![0; size as usize]))?;
+            }
+            ".string" | ".asciz" => {
+                for op in ops {
+                    let mut bytes = match parse_string(op) {
+                        Ok(bytes) => bytes,
+                        Err(err) => return self.error(&err),
+                    };
+                    bytes.push(0);
+                    self.push(bytes.len() as u32, Item::Bytes(bytes))?;
+                }
+            }
+            ".align" | ".p2align" => {
+                self.expect(ops, 1)?;
+                self.align(1 << self.num(&ops[0])?)?;
+            }
+            ".balign" => {
+                self.expect(ops, 1)?;
+                self.align(self.num(&ops[0])? as u32)?;
+            }
+            ".type" | ".size" | ".file" | ".option" | ".attribute" | ".ident" => {}
+            _ => return self.error(&format!("unknown directive `{name}`")),
+        }
+        Ok(())
+    }
+
+    // 展开伪指令, 得到的每条指令都是 4 字节
+    fn instruction(&mut self, mnemonic: &str, ops: &[String]) -> Result<(), String> {
+        const ZERO: u32 = 0;
+        const RA: u32 = 1;
+        let addi = self.base("addi");
+        match mnemonic {
+            "nop" => self.inst(addi, ZERO, ZERO, 0, Imm::Num(0))?,
+            "li" => {
+                self.expect(ops, 2)?;
+                let rd = self.reg(&ops[0])?;
+                let value = self.num(&ops[1])?;
+                if (-2048..2048).contains(&value) {
+                    return self.inst(addi, rd, ZERO, 0, Imm::Num(value));
+                }
+                // 低 12 位按有符号数处理, 高 20 位相应加上进位
+                let lo = value << 20 >> 20;
+                let hi = value.wrapping_sub(lo) >> 12;
+                self.inst(self.base("lui"), rd, 0, 0, Imm::Num(hi))?;
+                if lo != 0 {
+                    self.inst(addi, rd, rd, 0, Imm::Num(lo))?;
+                }
+            }
+            "la" => {
+                self.expect(ops, 2)?;
+                let rd = self.reg(&ops[0])?;
+                let lui = self.base("lui");
+                self.inst(lui, rd, 0, 0, Imm::Hi(ops[1].clone()))?;
+                self.inst(addi, rd, rd, 0, Imm::Lo(ops[1].clone()))?;
+            }
+            "mv" | "not" | "neg" | "seqz" | "snez" | "sltz" | "sgtz" => {
+                self.expect(ops, 2)?;
+                let rd = self.reg(&ops[0])?;
+                let rs = self.reg(&ops[1])?;
+                let (inst, rs1, rs2, imm) = match mnemonic {
+                    "mv" => ("addi", rs, 0, 0),
+                    "not" => ("xori", rs, 0, -1),
+                    "neg" => ("sub", ZERO, rs, 0),
+                    "seqz" => ("sltiu", rs, 0, 1),
+                    "snez" => ("sltu", ZERO, rs, 0),
+                    "sltz" => ("slt", rs, ZERO, 0),
+                    _ => ("slt", ZERO, rs, 0),
+                };
+                self.inst(self.base(inst), rd, rs1, rs2, Imm::Num(imm))?;
+            }
+            "sgt" | "sgtu" => {
+                self.expect(ops, 3)?;
+                let rd = self.reg(&ops[0])?;
+                let (rs1, rs2) = (self.reg(&ops[2])?, self.reg(&ops[1])?);
+                let inst = if mnemonic == "sgt" { "slt" } else { "sltu" };
+                self.inst(self.base(inst), rd, rs1, rs2, Imm::Num(0))?;
+            }
+            "beqz" | "bnez" | "blez" | "bgez" | "bltz" | "bgtz" => {
+                self.expect(ops, 2)?;
+                let rs = self.reg(&ops[0])?;
+                let (inst, rs1, rs2) = match mnemonic {
+                    "beqz" => ("beq", rs, ZERO),
+                    "bnez" => ("bne", rs, ZERO),
+                    "blez" => ("bge", ZERO, rs),
+                    "bgez" => ("bge", rs, ZERO),
+                    "bltz" => ("blt", rs, ZERO),
+                    _ => ("blt", ZERO, rs),
+                };
+                let target = self.target(&ops[1])?;
+                self.inst(self.base(inst), 0, rs1, rs2, target)?;
+            }
+            "bgt" | "ble" | "bgtu" | "bleu" => {
+                self.expect(ops, 3)?;
+                let inst = match mnemonic {
+                    "bgt" => "blt",
+                    "ble" => "bge",
+                    "bgtu" => "bltu",
+                    _ => "bgeu",
+                };
+                let (rs1, rs2) = (self.reg(&ops[1])?, self.reg(&ops[0])?);
+                let target = self.target(&ops[2])?;
+                self.inst(self.base(inst), 0, rs1, rs2, target)?;
+            }
+            "j" => {
+                self.expect(ops, 1)?;
+                let target = self.target(&ops[0])?;
+                self.inst(Format::J, ZERO, 0, 0, target)?;
+            }
+            "jal" if ops.len() == 1 => {
+                let target = self.target(&ops[0])?;
+                self.inst(Format::J, RA, 0, 0, target)?;
+            }
+            "jr" => {
+                self.expect(ops, 1)?;
+                let rs = self.reg(&ops[0])?;
+                self.inst(self.base("jalr"), ZERO, rs, 0, Imm::Num(0))?;
+            }
+            "jalr" if ops.len() == 1 => {
+                let rs = self.reg(&ops[0])?;
+                self.inst(self.base("jalr"), RA, rs, 0, Imm::Num(0))?;
+            }
+            "ret" => self.inst(self.base("jalr"), ZERO, RA, 0, Imm::Num(0))?,
+            "call" => {
+                self.expect(ops, 1)?;
+                let auipc = self.base("auipc");
+                self.inst(auipc, RA, 0, 0, Imm::Call(ops[0].clone()))?;
+                let jalr = self.base("jalr");
+                self.inst(jalr, RA, RA, 0, Imm::CallLo(ops[0].clone()))?;
+            }
+            // jump label, rt: 用 rt 作为临时寄存器的远跳转
+            "jump" => {
+                self.expect(ops, 2)?;
+                let rt = self.reg(&ops[1])?;
+                let auipc = self.base("auipc");
+                self.inst(auipc, rt, 0, 0, Imm::Call(ops[0].clone()))?;
+                let jalr = self.base("jalr");
+                self.inst(jalr, ZERO, rt, 0, Imm::CallLo(ops[0].clone()))?;
+            }
+            _ => self.base_instruction(mnemonic, ops)?,
+        }
+        Ok(())
+    }
+
+    fn base_instruction(&mut self, mnemonic: &str, ops: &[String]) -> Result<(), String> {
+        let Some(format) = lookup(mnemonic) else {
+            return self.error(&format!("unknown instruction `{mnemonic}`"));
+        };
+        match format {
+            Format::R(..) => {
+                self.expect(ops, 3)?;
+                let (rd, rs1, rs2) = (self.reg(&ops[0])?, self.reg(&ops[1])?, self.reg(&ops[2])?);
+                self.inst(format, rd, rs1, rs2, Imm::Num(0))?;
+            }
+            // 访存和 jalr 使用 imm(reg) 的写法
+            Format::I(_, opcode) if opcode != encode::OP_IMM => {
+                self.expect(ops, 2)?;
+                let rd = self.reg(&ops[0])?;
+                let (imm, rs1) = self.mem(&ops[1])?;
+                self.inst(format, rd, rs1, 0, imm)?;
+            }
+            Format::I(..) | Format::Shift(..) => {
+                self.expect(ops, 3)?;
+                let (rd, rs1) = (self.reg(&ops[0])?, self.reg(&ops[1])?);
+                let imm = self.imm(&ops[2])?;
+                self.inst(format, rd, rs1, 0, imm)?;
+            }
+            Format::S(..) => {
+                self.expect(ops, 2)?;
+                let rs2 = self.reg(&ops[0])?;
+                let (imm, rs1) = self.mem(&ops[1])?;
+                self.inst(format, 0, rs1, rs2, imm)?;
+            }
+            Format::B(_) => {
+                self.expect(ops, 3)?;
+                let (rs1, rs2) = (self.reg(&ops[0])?, self.reg(&ops[1])?);
+                let target = self.target(&ops[2])?;
+                self.inst(format, 0, rs1, rs2, target)?;
+            }
+            Format::U(_) => {
+                self.expect(ops, 2)?;
+                let rd = self.reg(&ops[0])?;
+                let imm = self.imm(&ops[1])?;
+                self.inst(format, rd, 0, 0, imm)?;
+            }
+            Format::J => {
+                self.expect(ops, 2)?;
+                let rd = self.reg(&ops[0])?;
+                let target = self.target(&ops[1])?;
+                self.inst(format, rd, 0, 0, target)?;
+            }
+            Format::System(_) => {
+                self.expect(ops, 0)?;
+                self.inst(format, 0, 0, 0, Imm::Num(0))?;
+            }
+        }
+        Ok(())
+    }
+
+    // 第一遍: 展开伪指令, 记录标号的位置
+    fn scan(&mut self, asm: &str) -> Result<(), String> {
+        for (i, line) in asm.lines().enumerate() {
+            self.line = i + 1;
+            let mut rest = strip_comment(line).trim();
+            // 一行中可以有多个标号, 后面还可以跟指令
+            while let Some(colon) = rest.find(':') {
+                let name = &rest[..colon];
+                if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '"') {
+                    break;
+                }
+                if self.symbols.iter().any(|symbol| symbol.name == name) {
+                    return self.error(&format!("label `{name}` is defined twice"));
+                }
+                self.symbols.push(Symbol {
+                    name: name.to_owned(),
+                    section: self.cur,
+                    offset: self.sizes[self.cur as usize],
+                    global: false,
+                });
+                rest = rest[colon + 1..].trim();
+            }
+            if rest.is_empty() {
+                continue;
+            }
+            let (name, ops) = match rest.find(char::is_whitespace) {
+                Some(space) => (&rest[..space], split_operands(&rest[space..])),
+                None => (rest, Vec::new()),
+            };
+            if name.starts_with('.') {
+                self.directive(name, &ops)?;
+            } else {
+                self.instruction(name, &ops)?;
+            }
+        }
+        for symbol in &mut self.symbols {
+            symbol.global = self.globals.contains(&symbol.name);
+        }
+        Ok(())
+    }
+
+    // 同一段中的非全局标号可以直接求出偏移
+    fn local_offset(&self, name: &str, section: SectionKind) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name && symbol.section == section && !symbol.global)
+            .map(|symbol| symbol.offset)
+    }
+
+    fn check_imm(&self, format: Format, imm: i32) -> Result<(), String> {
+        let valid = match format {
+            Format::I(..) | Format::S(..) => (-2048..2048).contains(&imm),
+            Format::Shift(..) => (0..32).contains(&imm),
+            Format::U(_) => (-(1 << 19)..1 << 20).contains(&imm),
+            Format::B(_) => (-(1 << 12)..1 << 12).contains(&imm) && imm % 2 == 0,
+            Format::J => (-(1 << 20)..1 << 20).contains(&imm) && imm % 2 == 0,
+            Format::R(..) | Format::System(_) => true,
+        };
+        if !valid {
+            return self.error(&format!("immediate {imm} is out of range"));
+        }
+        Ok(())
+    }
+
+    // 第二遍: 编码指令, 生成重定位
+    fn emit(mut self) -> Result<Object, String> {
+        let mut sections = Vec::new();
+        for kind in SectionKind::ALL {
+            let mut data = Vec::new();
+            let mut relocs = Vec::new();
+            for (offset, item) in std::mem::take(&mut self.items[kind as usize]) {
+                let mut reloc = |kind, symbol: &str| {
+                    relocs.push(Reloc {
+                        offset,
+                        kind,
+                        symbol: symbol.to_owned(),
+                        addend: 0,
+                    })
+                };
+                let (line, inst) = match item {
+                    Item::Bytes(bytes) => {
+                        data.extend(bytes);
+                        continue;
+                    }
+                    Item::Word(symbol) => {
+                        reloc(RelocKind::Abs32, &symbol);
+                        data.extend([0; 4]);
+                        continue;
+                    }
+                    Item::Inst(line, inst) => (line, inst),
+                };
+                self.line = line;
+                let imm = match &inst.imm {
+                    Imm::Num(value) => *value,
+                    Imm::PcRel(symbol) => match self.local_offset(symbol, kind) {
+                        Some(target) => {
+                            let offset = target.wrapping_sub(offset) as i32;
+                            let range = if inst.format == Format::J {
+                                1 << 20
+                            } else {
+                                1 << 12
+                            };
+                            if !(-range..range).contains(&offset) {
+                                return self.error(&format!("jump to `{symbol}` is out of range"));
+                            }
+                            offset
+                        }
+                        None if inst.format == Format::J => {
+                            reloc(RelocKind::Jal, symbol);
+                            0
+                        }
+                        None => {
+                            reloc(RelocKind::Branch, symbol);
+                            0
+                        }
+                    },
+                    Imm::Hi(symbol) => {
+                        reloc(RelocKind::Hi20, symbol);
+                        0
+                    }
+                    Imm::Lo(symbol) => {
+                        match inst.format {
+                            Format::S(..) => reloc(RelocKind::Lo12S, symbol),
+                            _ => reloc(RelocKind::Lo12I, symbol),
+                        }
+                        0
+                    }
+                    // 调用同一段中的局部标号时直接算出偏移, 低 12 位按有符号数处理
+                    Imm::Call(symbol) => match self.local_offset(symbol, kind) {
+                        Some(target) => {
+                            let offset = target.wrapping_sub(offset) as i32;
+                            offset.wrapping_add(0x800) >> 12
+                        }
+                        None => {
+                            reloc(RelocKind::CallPlt, symbol);
+                            0
+                        }
+                    },
+                    Imm::CallLo(symbol) => match self.local_offset(symbol, kind) {
+                        Some(target) => {
+                            let offset = target.wrapping_sub(offset - 4) as i32;
+                            offset << 20 >> 20
+                        }
+                        None => 0,
+                    },
+                };
+                self.check_imm(inst.format, imm)?;
+                let word = encode(inst.format, inst.rd, inst.rs1, inst.rs2, imm);
+                data.extend(word.to_le_bytes());
+            }
+            sections.push(Section {
+                data,
+                align: self.aligns[kind as usize],
+                relocs,
+            });
+        }
+        Ok(Object {
+            sections,
+            symbols: self.symbols,
+        })
+    }
+}
+
+/// 汇编 RV32IM 代码, 输入有错误时返回带行号的错误信息
+pub fn assemble(asm: &str) -> Result<Object, String> {
+    let mut assembler = Assembler::new();
+    assembler.scan(asm)?;
+    assembler.emit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode::{decode, fields, INSTS};
+    use super::*;
+
+    // 每条指令的几种写法, 立即数取格式允许的边界值
+    fn samples(name: &str, format: Format) -> Vec<String> {
+        match format {
+            Format::R(..) => vec![format!("{name} a0, s1, t6")],
+            Format::I(_, encode::OP_IMM) => [-2048, -1, 0, 2047]
+                .map(|imm| format!("{name} a0, s1, {imm}"))
+                .to_vec(),
+            Format::I(..) => [-2048, 0, 2047]
+                .map(|imm| format!("{name} t0, {imm}(sp)"))
+                .to_vec(),
+            Format::Shift(..) => [0, 1, 31]
+                .map(|imm| format!("{name} a0, s1, {imm}"))
+                .to_vec(),
+            Format::S(..) => [-2048, 4, 2047]
+                .map(|imm| format!("{name} ra, {imm}(s0)"))
+                .to_vec(),
+            Format::B(_) => [-4096, -2, 0, 4094]
+                .map(|imm| format!("{name} a0, t6, {imm}"))
+                .to_vec(),
+            Format::U(_) => [0, 1, 0x80000, 0xfffff]
+                .map(|imm| format!("{name} a0, {imm:#x}"))
+                .to_vec(),
+            Format::J => [-(1 << 20), -2, 0, (1 << 20) - 2]
+                .map(|imm| format!("{name} ra, {imm}"))
+                .to_vec(),
+            Format::System(_) => vec![name.to_owned()],
+        }
+    }
+
+    fn text(asm: &str) -> Vec<u8> {
+        let object = assemble(asm).unwrap_or_else(|err| panic!("{asm}: {err}"));
+        object.section(SectionKind::Text).data.clone()
+    }
+
+    #[test]
+    fn round_trip_every_instruction() {
+        for &(name, format) in &INSTS {
+            for asm in samples(name, format) {
+                let code = text(&asm);
+                let word = u32::from_le_bytes(code[..].try_into().unwrap());
+                assert_eq!(decode(word), Some((name, format)), "{asm}");
+                // 反汇编后重新汇编得到相同的编码
+                let listing = disassemble(&code);
+                assert_eq!(text(&listing), code, "{asm} -> {listing}");
+            }
+        }
+    }
+
+    #[test]
+    fn fields_invert_encode() {
+        for &(name, format) in &INSTS {
+            for imm in [-2048, -16, -2, 0, 2, 30, 2046] {
+                let word = encode(format, 5, 17, 31, imm);
+                let f = fields(format, word);
+                let expected_imm = match format {
+                    Format::R(..) | Format::System(_) => 0,
+                    Format::Shift(..) => imm & 0x1f,
+                    Format::U(_) => imm & 0xfffff,
+                    _ => imm,
+                };
+                assert_eq!(f.imm, expected_imm, "{name} {imm}");
+                if !matches!(format, Format::S(..) | Format::B(_) | Format::System(_)) {
+                    assert_eq!(f.rd, 5, "{name}");
+                }
+                if matches!(format, Format::R(..) | Format::S(..) | Format::B(_)) {
+                    assert_eq!(f.rs2, 31, "{name}");
+                }
+                if !matches!(format, Format::U(_) | Format::J | Format::System(_)) {
+                    assert_eq!(f.rs1, 17, "{name}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_words_disassemble_to_data() {
+        let listing = disassemble(&[0xff; 4]);
+        assert_eq!(listing, "    .word 0xffffffff\n");
+        assert_eq!(text(&listing), [0xff; 4]);
+    }
+}
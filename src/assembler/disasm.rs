@@ -0,0 +1,39 @@
+use std::io::Write;
+
+use super::encode::{decode, fields, Format, OP_IMM, REG_ABI};
+
+/// 反汇编一条指令, 跳转目标写成相对当前指令的偏移
+pub fn disassemble_inst(inst: u32) -> String {
+    let (name, format) = match decode(inst) {
+        Some(found) => found,
+        None => return format!(".word {inst:#010x}"),
+    };
+    let fields = fields(format, inst);
+    let (rd, rs1, rs2, imm) = (
+        REG_ABI[fields.rd as usize],
+        REG_ABI[fields.rs1 as usize],
+        REG_ABI[fields.rs2 as usize],
+        fields.imm,
+    );
+    match format {
+        Format::R(..) => format!("{name} {rd}, {rs1}, {rs2}"),
+        Format::I(_, OP_IMM) | Format::Shift(..) => format!("{name} {rd}, {rs1}, {imm}"),
+        Format::I(..) => format!("{name} {rd}, {imm}({rs1})"),
+        Format::S(..) => format!("{name} {rs2}, {imm}({rs1})"),
+        Format::B(_) => format!("{name} {rs1}, {rs2}, {imm}"),
+        Format::U(_) => format!("{name} {rd}, {imm:#x}"),
+        Format::J => format!("{name} {rd}, {imm}"),
+        Format::System(_) => name.to_owned(),
+    }
+}
+
+/// 反汇编一段代码, 输出可以再交给 assemble 得到相同的编码
+pub fn disassemble(code: &[u8]) -> String {
+    let mut f = Vec::new();
+    for word in code.chunks(4) {
+        let mut bytes = [0; 4];
+        bytes[..word.len()].copy_from_slice(word);
+        writeln!(f, "    {}", disassemble_inst(u32::from_le_bytes(bytes))).unwrap();
+    }
+    String::from_utf8(f).unwrap()
+}
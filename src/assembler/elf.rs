@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+
+use super::link::Executable;
+use super::{Object, SectionKind};
+
+// ELF32 中用到的常量
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u32 = 1;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+const SHF_INFO_LINK: u32 = 0x40;
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize = 16;
+const RELA_SIZE: usize = 12;
+
+/// 可执行文件的加载地址, 文件偏移 0 对应这个地址
+pub(super) const EXEC_BASE: u32 = 0x10000;
+pub(super) const PAGE_SIZE: u32 = 0x1000;
+
+/// 字符串表, 第一个字节是空串
+struct StrTab {
+    data: Vec<u8>,
+}
+
+impl StrTab {
+    fn new() -> Self {
+        Self { data: vec![0] }
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        let offset = self.data.len() as u32;
+        self.data.extend(s.as_bytes());
+        self.data.push(0);
+        offset
+    }
+}
+
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    align: u32,
+    entsize: u32,
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn push_sym(buf: &mut Vec<u8>, name: u32, value: u32, info: u8, shndx: u16) {
+    push_u32(buf, name);
+    push_u32(buf, value);
+    push_u32(buf, 0);
+    buf.push(info);
+    buf.push(0);
+    push_u16(buf, shndx);
+}
+
+// 段内容从 offset 开始按 align 对齐后放入文件
+fn append(file: &mut Vec<u8>, data: &[u8], align: u32) -> u32 {
+    file.resize(file.len().next_multiple_of(align as usize), 0);
+    let offset = file.len() as u32;
+    file.extend(data);
+    offset
+}
+
+/// 把目标文件写成 ELF32 可重定位文件
+///
+/// 节的顺序为 .text/.data/.bss, 有重定位时是 .rela.text/.rela.data,
+/// 最后是 .symtab/.strtab/.shstrtab. 以 .L 开头的局部标号不放入符号表,
+/// 引用它们的重定位改成对所在段的段符号加偏移
+pub fn write_elf(object: &Object) -> Vec<u8> {
+    let mut file = vec![0; EHDR_SIZE];
+    let mut shstrtab = StrTab::new();
+    let mut strtab = StrTab::new();
+    let mut headers = vec![SectionHeader {
+        name: 0,
+        kind: 0,
+        flags: 0,
+        addr: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 0,
+        entsize: 0,
+    }];
+
+    // .text/.data/.bss 的节号是 1, 2, 3
+    for kind in SectionKind::ALL {
+        let section = object.section(kind);
+        let (flags, offset, sh_type) = match kind {
+            SectionKind::Text => (
+                SHF_ALLOC | SHF_EXECINSTR,
+                append(&mut file, &section.data, section.align),
+                SHT_PROGBITS,
+            ),
+            SectionKind::Data => (
+                SHF_ALLOC | SHF_WRITE,
+                append(&mut file, &section.data, section.align),
+                SHT_PROGBITS,
+            ),
+            SectionKind::Bss => (SHF_ALLOC | SHF_WRITE, file.len() as u32, SHT_NOBITS),
+        };
+        headers.push(SectionHeader {
+            name: shstrtab.add(kind.name()),
+            kind: sh_type,
+            flags,
+            addr: 0,
+            offset,
+            size: section.data.len() as u32,
+            link: 0,
+            info: 0,
+            align: section.align,
+            entsize: 0,
+        });
+    }
+    let shndx = |kind: SectionKind| kind as u16 + 1;
+
+    // 符号表: 空符号, 段符号, 局部符号, 全局符号, 未定义符号
+    let mut symtab = Vec::new();
+    let mut sym_index: HashMap<&str, u32> = HashMap::new();
+    push_sym(&mut symtab, 0, 0, 0, 0);
+    for kind in SectionKind::ALL {
+        push_sym(&mut symtab, 0, 0, STB_LOCAL << 4 | STT_SECTION, shndx(kind));
+    }
+    let emitted = |global: bool| {
+        object
+            .symbols
+            .iter()
+            .filter(move |symbol| symbol.global == global && !symbol.name.starts_with(".L"))
+    };
+    let mut count = 1 + SectionKind::ALL.len() as u32;
+    for symbol in emitted(false) {
+        let name = strtab.add(&symbol.name);
+        let info = STB_LOCAL << 4 | STT_NOTYPE;
+        push_sym(
+            &mut symtab,
+            name,
+            symbol.offset,
+            info,
+            shndx(symbol.section),
+        );
+        sym_index.insert(&symbol.name, count);
+        count += 1;
+    }
+    let first_global = count;
+    for symbol in emitted(true) {
+        let name = strtab.add(&symbol.name);
+        let info = STB_GLOBAL << 4 | STT_NOTYPE;
+        push_sym(
+            &mut symtab,
+            name,
+            symbol.offset,
+            info,
+            shndx(symbol.section),
+        );
+        sym_index.insert(&symbol.name, count);
+        count += 1;
+    }
+    for name in object.undefined_symbols() {
+        let str_offset = strtab.add(name);
+        push_sym(&mut symtab, str_offset, 0, STB_GLOBAL << 4 | STT_NOTYPE, 0);
+        sym_index.insert(name, count);
+        count += 1;
+    }
+
+    let symtab_index = headers.len() as u32
+        + SectionKind::ALL[..2]
+            .iter()
+            .filter(|&&kind| !object.section(kind).relocs.is_empty())
+            .count() as u32;
+
+    // 重定位节, 只有 .text 和 .data 会有重定位
+    for kind in [SectionKind::Text, SectionKind::Data] {
+        let section = object.section(kind);
+        if section.relocs.is_empty() {
+            continue;
+        }
+        let mut rela = Vec::new();
+        for reloc in &section.relocs {
+            let (sym, addend) = match sym_index.get(reloc.symbol.as_str()) {
+                Some(&index) => (index, reloc.addend),
+                None => {
+                    let symbol = object.symbol(&reloc.symbol).unwrap();
+                    let addend = symbol.offset as i32 + reloc.addend;
+                    (shndx(symbol.section) as u32, addend)
+                }
+            };
+            push_u32(&mut rela, reloc.offset);
+            push_u32(&mut rela, sym << 8 | reloc.kind.elf_type());
+            push_u32(&mut rela, addend as u32);
+        }
+        headers.push(SectionHeader {
+            name: shstrtab.add(&format!(".rela{}", kind.name())),
+            kind: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            addr: 0,
+            offset: append(&mut file, &rela, 4),
+            size: rela.len() as u32,
+            link: symtab_index,
+            info: shndx(kind) as u32,
+            align: 4,
+            entsize: RELA_SIZE as u32,
+        });
+    }
+
+    headers.push(SectionHeader {
+        name: shstrtab.add(".symtab"),
+        kind: SHT_SYMTAB,
+        flags: 0,
+        addr: 0,
+        offset: append(&mut file, &symtab, 4),
+        size: symtab.len() as u32,
+        link: symtab_index + 1,
+        info: first_global,
+        align: 4,
+        entsize: SYM_SIZE as u32,
+    });
+    headers.push(SectionHeader {
+        name: shstrtab.add(".strtab"),
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: append(&mut file, &strtab.data, 1),
+        size: strtab.data.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    let shstrtab_name = shstrtab.add(".shstrtab");
+    headers.push(SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: append(&mut file, &shstrtab.data, 1),
+        size: shstrtab.data.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+
+    let shoff = write_section_headers(&mut file, &headers);
+    let ehdr = elf_header(ET_REL, 0, 0, shoff, headers.len());
+    file[..EHDR_SIZE].copy_from_slice(&ehdr);
+    file
+}
+
+// 在文件末尾写入节头表, 返回它的偏移
+fn write_section_headers(file: &mut Vec<u8>, headers: &[SectionHeader]) -> u32 {
+    let shoff = append(file, &[], 4);
+    for header in headers {
+        for value in [
+            header.name,
+            header.kind,
+            header.flags,
+            header.addr,
+            header.offset,
+            header.size,
+            header.link,
+            header.info,
+            header.align,
+            header.entsize,
+        ] {
+            push_u32(file, value);
+        }
+    }
+    shoff
+}
+
+// ELF 头, 最后一个节是 .shstrtab
+fn elf_header(kind: u16, entry: u32, phnum: usize, shoff: u32, shnum: usize) -> Vec<u8> {
+    let mut ehdr = vec![0x7f, b'E', b'L', b'F', 1, 1, 1];
+    ehdr.resize(16, 0);
+    push_u16(&mut ehdr, kind);
+    push_u16(&mut ehdr, EM_RISCV);
+    push_u32(&mut ehdr, 1);
+    push_u32(&mut ehdr, entry);
+    push_u32(&mut ehdr, if phnum > 0 { EHDR_SIZE as u32 } else { 0 });
+    push_u32(&mut ehdr, shoff);
+    push_u32(&mut ehdr, 0);
+    push_u16(&mut ehdr, EHDR_SIZE as u16);
+    push_u16(&mut ehdr, if phnum > 0 { PHDR_SIZE as u16 } else { 0 });
+    push_u16(&mut ehdr, phnum as u16);
+    push_u16(&mut ehdr, SHDR_SIZE as u16);
+    push_u16(&mut ehdr, shnum as u16);
+    push_u16(&mut ehdr, shnum as u16 - 1);
+    ehdr
+}
+
+/// 把链接结果写成 ELF32 可执行文件
+///
+/// 代码段和数据段 (含 .bss) 各用一个 PT_LOAD 加载, 文件偏移等于地址减去 EXEC_BASE.
+/// 另外保留节头和符号表, 方便用 objdump 之类的工具查看
+pub fn write_executable(exe: &Executable) -> Vec<u8> {
+    let mut file = vec![0; EHDR_SIZE + 2 * PHDR_SIZE];
+    let mut shstrtab = StrTab::new();
+    let mut strtab = StrTab::new();
+    let mut headers = vec![SectionHeader {
+        name: 0,
+        kind: 0,
+        flags: 0,
+        addr: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        align: 0,
+        entsize: 0,
+    }];
+
+    let text_offset = exe.text_addr - EXEC_BASE;
+    file.resize(text_offset as usize, 0);
+    file.extend(&exe.text);
+    let data_offset = exe.data_addr - EXEC_BASE;
+    file.resize(data_offset as usize, 0);
+    file.extend(&exe.data);
+    let sections = [
+        (
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            exe.text_addr,
+            exe.text.len() as u32,
+        ),
+        (
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_WRITE,
+            exe.data_addr,
+            exe.data.len() as u32,
+        ),
+        (
+            SHT_NOBITS,
+            SHF_ALLOC | SHF_WRITE,
+            exe.bss_addr,
+            exe.bss_size,
+        ),
+    ];
+    for (kind, (sh_type, flags, addr, size)) in SectionKind::ALL.into_iter().zip(sections) {
+        headers.push(SectionHeader {
+            name: shstrtab.add(kind.name()),
+            kind: sh_type,
+            flags,
+            addr,
+            offset: addr - EXEC_BASE,
+            size,
+            link: 0,
+            info: 0,
+            align: 4,
+            entsize: 0,
+        });
+    }
+
+    // 符号表: 空符号, 局部符号, 全局符号
+    let mut symtab = Vec::new();
+    push_sym(&mut symtab, 0, 0, 0, 0);
+    let mut first_global = 1;
+    for global in [false, true] {
+        // 与目标文件一样不输出 .L 开头的局部标号
+        let emitted = exe
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.global == global && !symbol.name.starts_with(".L"));
+        for symbol in emitted {
+            let name = strtab.add(&symbol.name);
+            let info = (global as u8) << 4 | STT_NOTYPE;
+            push_sym(
+                &mut symtab,
+                name,
+                symbol.offset,
+                info,
+                symbol.section as u16 + 1,
+            );
+            first_global += !global as u32;
+        }
+    }
+    let symtab_index = headers.len() as u32;
+    headers.push(SectionHeader {
+        name: shstrtab.add(".symtab"),
+        kind: SHT_SYMTAB,
+        flags: 0,
+        addr: 0,
+        offset: append(&mut file, &symtab, 4),
+        size: symtab.len() as u32,
+        link: symtab_index + 1,
+        info: first_global,
+        align: 4,
+        entsize: SYM_SIZE as u32,
+    });
+    headers.push(SectionHeader {
+        name: shstrtab.add(".strtab"),
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: append(&mut file, &strtab.data, 1),
+        size: strtab.data.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    let shstrtab_name = shstrtab.add(".shstrtab");
+    headers.push(SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        addr: 0,
+        offset: append(&mut file, &shstrtab.data, 1),
+        size: shstrtab.data.len() as u32,
+        link: 0,
+        info: 0,
+        align: 1,
+        entsize: 0,
+    });
+    let shoff = write_section_headers(&mut file, &headers);
+
+    // 程序头: 代码段可读可执行, 数据段可读可写, .bss 只占内存不占文件
+    let mut phdrs = Vec::new();
+    let segments = [
+        (
+            text_offset,
+            exe.text_addr,
+            exe.text.len() as u32,
+            exe.text.len() as u32,
+            PF_R | PF_X,
+        ),
+        (
+            data_offset,
+            exe.data_addr,
+            exe.data.len() as u32,
+            exe.bss_addr + exe.bss_size - exe.data_addr,
+            PF_R | PF_W,
+        ),
+    ];
+    for (offset, addr, filesz, memsz, flags) in segments {
+        for value in [PT_LOAD, offset, addr, addr, filesz, memsz, flags, PAGE_SIZE] {
+            push_u32(&mut phdrs, value);
+        }
+    }
+    let ehdr = elf_header(ET_EXEC, exe.entry, 2, shoff, headers.len());
+    file[..EHDR_SIZE].copy_from_slice(&ehdr);
+    file[EHDR_SIZE..EHDR_SIZE + phdrs.len()].copy_from_slice(&phdrs);
+    file
+}
@@ -0,0 +1,207 @@
+// RV32IM 指令的编码, 汇编器和反汇编器共用同一张指令表
+
+/// 寄存器的 ABI 名字, 下标就是寄存器编号
+pub(crate) static REG_ABI: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// 寄存器名字 (ABI 名字或 x0-x31) 对应的编号
+pub(crate) fn reg_num(name: &str) -> Option<u32> {
+    if let Some(i) = REG_ABI.iter().position(|&reg| reg == name) {
+        return Some(i as u32);
+    }
+    if name == "fp" {
+        return Some(8);
+    }
+    let num: u32 = name.strip_prefix('x')?.parse().ok()?;
+    (num < 32).then_some(num)
+}
+
+/// 指令格式, 括号中是区分指令的 funct 字段
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Format {
+    /// funct7, funct3, opcode
+    R(u32, u32, u32),
+    /// funct3, opcode
+    I(u32, u32),
+    /// 移位立即数: funct7, funct3
+    Shift(u32, u32),
+    /// funct3, opcode
+    S(u32, u32),
+    /// funct3
+    B(u32),
+    /// opcode
+    U(u32),
+    J,
+    /// ecall/ebreak: imm
+    System(u32),
+}
+
+pub(crate) const OP: u32 = 0b0110011;
+pub(crate) const OP_IMM: u32 = 0b0010011;
+pub(crate) const LOAD: u32 = 0b0000011;
+pub(crate) const STORE: u32 = 0b0100011;
+pub(crate) const BRANCH: u32 = 0b1100011;
+pub(crate) const JALR: u32 = 0b1100111;
+pub(crate) const JAL: u32 = 0b1101111;
+pub(crate) const LUI: u32 = 0b0110111;
+pub(crate) const AUIPC: u32 = 0b0010111;
+pub(crate) const SYSTEM: u32 = 0b1110011;
+
+pub(crate) static INSTS: [(&str, Format); 47] = [
+    ("lui", Format::U(LUI)),
+    ("auipc", Format::U(AUIPC)),
+    ("jal", Format::J),
+    ("jalr", Format::I(0b000, JALR)),
+    ("beq", Format::B(0b000)),
+    ("bne", Format::B(0b001)),
+    ("blt", Format::B(0b100)),
+    ("bge", Format::B(0b101)),
+    ("bltu", Format::B(0b110)),
+    ("bgeu", Format::B(0b111)),
+    ("lb", Format::I(0b000, LOAD)),
+    ("lh", Format::I(0b001, LOAD)),
+    ("lw", Format::I(0b010, LOAD)),
+    ("lbu", Format::I(0b100, LOAD)),
+    ("lhu", Format::I(0b101, LOAD)),
+    ("sb", Format::S(0b000, STORE)),
+    ("sh", Format::S(0b001, STORE)),
+    ("sw", Format::S(0b010, STORE)),
+    ("addi", Format::I(0b000, OP_IMM)),
+    ("slti", Format::I(0b010, OP_IMM)),
+    ("sltiu", Format::I(0b011, OP_IMM)),
+    ("xori", Format::I(0b100, OP_IMM)),
+    ("ori", Format::I(0b110, OP_IMM)),
+    ("andi", Format::I(0b111, OP_IMM)),
+    ("slli", Format::Shift(0b0000000, 0b001)),
+    ("srli", Format::Shift(0b0000000, 0b101)),
+    ("srai", Format::Shift(0b0100000, 0b101)),
+    ("add", Format::R(0b0000000, 0b000, OP)),
+    ("sub", Format::R(0b0100000, 0b000, OP)),
+    ("sll", Format::R(0b0000000, 0b001, OP)),
+    ("slt", Format::R(0b0000000, 0b010, OP)),
+    ("sltu", Format::R(0b0000000, 0b011, OP)),
+    ("xor", Format::R(0b0000000, 0b100, OP)),
+    ("srl", Format::R(0b0000000, 0b101, OP)),
+    ("sra", Format::R(0b0100000, 0b101, OP)),
+    ("or", Format::R(0b0000000, 0b110, OP)),
+    ("and", Format::R(0b0000000, 0b111, OP)),
+    ("ecall", Format::System(0)),
+    ("ebreak", Format::System(1)),
+    ("mul", Format::R(0b0000001, 0b000, OP)),
+    ("mulh", Format::R(0b0000001, 0b001, OP)),
+    ("mulhsu", Format::R(0b0000001, 0b010, OP)),
+    ("mulhu", Format::R(0b0000001, 0b011, OP)),
+    ("div", Format::R(0b0000001, 0b100, OP)),
+    ("divu", Format::R(0b0000001, 0b101, OP)),
+    ("rem", Format::R(0b0000001, 0b110, OP)),
+    ("remu", Format::R(0b0000001, 0b111, OP)),
+];
+
+pub(crate) fn lookup(mnemonic: &str) -> Option<Format> {
+    INSTS
+        .iter()
+        .find(|(name, _)| *name == mnemonic)
+        .map(|&(_, format)| format)
+}
+
+/// 把各个字段拼成指令, imm 由调用者保证在范围内
+pub(crate) fn encode(format: Format, rd: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    match format {
+        Format::R(funct7, funct3, opcode) => {
+            funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+        }
+        Format::I(funct3, opcode) => {
+            (imm & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+        }
+        Format::Shift(funct7, funct3) => {
+            funct7 << 25 | (imm & 0x1f) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | OP_IMM
+        }
+        Format::S(funct3, opcode) => {
+            (imm >> 5 & 0x7f) << 25
+                | rs2 << 20
+                | rs1 << 15
+                | funct3 << 12
+                | (imm & 0x1f) << 7
+                | opcode
+        }
+        Format::B(funct3) => {
+            (imm >> 12 & 1) << 31
+                | (imm >> 5 & 0x3f) << 25
+                | rs2 << 20
+                | rs1 << 15
+                | funct3 << 12
+                | (imm >> 1 & 0xf) << 8
+                | (imm >> 11 & 1) << 7
+                | BRANCH
+        }
+        Format::U(opcode) => (imm & 0xfffff) << 12 | rd << 7 | opcode,
+        Format::J => {
+            (imm >> 20 & 1) << 31
+                | (imm >> 1 & 0x3ff) << 21
+                | (imm >> 11 & 1) << 20
+                | (imm >> 12 & 0xff) << 12
+                | rd << 7
+                | JAL
+        }
+        Format::System(imm) => imm << 20 | SYSTEM,
+    }
+}
+
+/// 指令中的各个字段
+#[derive(Clone, Copy)]
+pub(crate) struct Fields {
+    pub(crate) rd: u32,
+    pub(crate) rs1: u32,
+    pub(crate) rs2: u32,
+    pub(crate) imm: i32,
+}
+
+/// 根据格式取出指令中的字段, 立即数做符号扩展
+pub(crate) fn fields(format: Format, inst: u32) -> Fields {
+    let rd = inst >> 7 & 0x1f;
+    let rs1 = inst >> 15 & 0x1f;
+    let rs2 = inst >> 20 & 0x1f;
+    let signed = inst as i32;
+    let imm = match format {
+        Format::R(..) | Format::System(_) => 0,
+        Format::I(..) => signed >> 20,
+        Format::Shift(..) => (inst >> 20 & 0x1f) as i32,
+        Format::S(..) => (signed >> 25) << 5 | (inst >> 7 & 0x1f) as i32,
+        Format::B(_) => {
+            (signed >> 31) << 12
+                | ((inst >> 7 & 1) << 11) as i32
+                | ((inst >> 25 & 0x3f) << 5) as i32
+                | ((inst >> 8 & 0xf) << 1) as i32
+        }
+        // U 型的立即数按 20 位的值给出, 与汇编中的写法一致
+        Format::U(_) => (inst >> 12) as i32,
+        Format::J => {
+            (signed >> 31) << 20
+                | ((inst >> 12 & 0xff) << 12) as i32
+                | ((inst >> 20 & 1) << 11) as i32
+                | ((inst >> 21 & 0x3ff) << 1) as i32
+        }
+    };
+    Fields { rd, rs1, rs2, imm }
+}
+
+/// 找出指令对应的助记符和格式
+pub(crate) fn decode(inst: u32) -> Option<(&'static str, Format)> {
+    let opcode = inst & 0x7f;
+    let funct3 = inst >> 12 & 0x7;
+    let funct7 = inst >> 25;
+    INSTS.iter().copied().find(|&(_, format)| match format {
+        Format::R(f7, f3, op) => op == opcode && f3 == funct3 && f7 == funct7,
+        Format::I(f3, op) => op == opcode && f3 == funct3,
+        Format::Shift(f7, f3) => opcode == OP_IMM && f3 == funct3 && f7 == funct7,
+        Format::S(f3, op) => op == opcode && f3 == funct3,
+        Format::B(f3) => opcode == BRANCH && f3 == funct3,
+        Format::U(op) => op == opcode,
+        Format::J => opcode == JAL,
+        Format::System(imm) => inst == imm << 20 | SYSTEM,
+    })
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use super::elf::{EXEC_BASE, PAGE_SIZE};
+use super::encode::{encode, Format};
+use super::{assemble, Object, RelocKind, SectionKind, Symbol};
+
+// 静态链接器.
+// 把各个目标文件的同名段按顺序拼接, .text 从 EXEC_BASE 之后的第一页开始,
+// .data 从 .text 之后新的一页开始, .bss 紧跟在 .data 后面, 然后按绝对地址处理重定位.
+
+/// SysY 运行时库的源码, 入口 _start 调用 main 后以其返回值退出
+static RUNTIME: &str = include_str!("runtime.S");
+
+/// 运行时库的目标文件
+pub fn runtime() -> Object {
+    assemble(RUNTIME).unwrap()
+}
+
+/// 链接得到的可执行文件映像
+#[derive(Debug)]
+pub struct Executable {
+    /// 入口 _start 的地址
+    pub entry: u32,
+    pub text_addr: u32,
+    pub text: Vec<u8>,
+    pub data_addr: u32,
+    pub data: Vec<u8>,
+    pub bss_addr: u32,
+    pub bss_size: u32,
+    /// offset 为符号的绝对地址, 包括 .L 开头的局部标号
+    pub symbols: Vec<Symbol>,
+}
+
+// 指令中各类立即数所在的位
+const I_IMM: u32 = 0xfff0_0000;
+const S_IMM: u32 = 0xfe00_0f80;
+const U_IMM: u32 = 0xffff_f000;
+
+// 把 imm 按 format 编码后填入指令中 mask 对应的位
+fn patch(code: &mut [u8], pos: usize, format: Format, mask: u32, imm: i32) {
+    let inst = u32::from_le_bytes(code[pos..pos + 4].try_into().unwrap());
+    let inst = inst & !mask | encode(format, 0, 0, 0, imm) & mask;
+    code[pos..pos + 4].copy_from_slice(&inst.to_le_bytes());
+}
+
+// %hi 要补偿 %lo 按有符号数扩展带来的误差
+fn hi20(value: i32) -> i32 {
+    value.wrapping_add(0x800) >> 12
+}
+
+/// 对 code 中 pos 处的指令 (地址为 pc) 做重定位, 符号的值为 value
+fn relocate(
+    kind: RelocKind,
+    code: &mut [u8],
+    pos: usize,
+    pc: u32,
+    value: u32,
+    symbol: &str,
+) -> Result<(), String> {
+    let offset = value.wrapping_sub(pc) as i32;
+    let check = |range: i32| {
+        if !(-range..range).contains(&offset) {
+            return Err(format!("relocation against `{symbol}` is out of range"));
+        }
+        Ok(())
+    };
+    match kind {
+        RelocKind::Abs32 => code[pos..pos + 4].copy_from_slice(&value.to_le_bytes()),
+        RelocKind::Branch => {
+            check(1 << 12)?;
+            patch(code, pos, Format::B(0), S_IMM, offset);
+        }
+        RelocKind::Jal => {
+            check(1 << 20)?;
+            patch(code, pos, Format::J, U_IMM, offset);
+        }
+        RelocKind::CallPlt => {
+            patch(code, pos, Format::U(0), U_IMM, hi20(offset));
+            patch(code, pos + 4, Format::I(0, 0), I_IMM, offset);
+        }
+        RelocKind::Hi20 => patch(code, pos, Format::U(0), U_IMM, hi20(value as i32)),
+        RelocKind::Lo12I => patch(code, pos, Format::I(0, 0), I_IMM, value as i32),
+        RelocKind::Lo12S => patch(code, pos, Format::S(0, 0), S_IMM, value as i32),
+    }
+    Ok(())
+}
+
+/// 把目标文件链接成可执行文件, 有未定义或重复定义的符号时返回错误
+pub fn link(objects: &[Object]) -> Result<Executable, String> {
+    // 每个目标文件中各段的起始地址, 下标是 SectionKind
+    let mut bases = vec![[0u32; 3]; objects.len()];
+    let mut contents = [Vec::new(), Vec::new(), Vec::new()];
+    let mut addrs = [EXEC_BASE + PAGE_SIZE, 0, 0];
+    for kind in SectionKind::ALL {
+        let sec = kind as usize;
+        if sec > 0 {
+            let end = addrs[sec - 1] + contents[sec - 1].len() as u32;
+            addrs[sec] = match kind {
+                SectionKind::Bss => {
+                    let align = objects.iter().map(|o| o.section(kind).align).max();
+                    end.next_multiple_of(align.unwrap_or(1))
+                }
+                _ => end.next_multiple_of(PAGE_SIZE),
+            };
+        }
+        let data: &mut Vec<u8> = &mut contents[sec];
+        for (i, object) in objects.iter().enumerate() {
+            let section = object.section(kind);
+            data.resize(data.len().next_multiple_of(section.align as usize), 0);
+            bases[i][sec] = addrs[sec] + data.len() as u32;
+            data.extend(&section.data);
+        }
+    }
+
+    // 全局符号表
+    let mut globals: HashMap<&str, u32> = HashMap::new();
+    let mut symbols = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        for symbol in &object.symbols {
+            let addr = bases[i][symbol.section as usize] + symbol.offset;
+            if symbol.global && globals.insert(&symbol.name, addr).is_some() {
+                return Err(format!("multiple definition of `{}`", symbol.name));
+            }
+            symbols.push(Symbol {
+                offset: addr,
+                ..symbol.clone()
+            });
+        }
+    }
+
+    for (i, object) in objects.iter().enumerate() {
+        for kind in [SectionKind::Text, SectionKind::Data] {
+            let sec = kind as usize;
+            for reloc in &object.section(kind).relocs {
+                // 先找本文件中的定义, 再找其他文件中的全局符号
+                let target = match object.symbol(&reloc.symbol) {
+                    Some(symbol) => bases[i][symbol.section as usize] + symbol.offset,
+                    None => *globals
+                        .get(reloc.symbol.as_str())
+                        .ok_or_else(|| format!("undefined reference to `{}`", reloc.symbol))?,
+                };
+                let pc = bases[i][sec] + reloc.offset;
+                relocate(
+                    reloc.kind,
+                    &mut contents[sec],
+                    (pc - addrs[sec]) as usize,
+                    pc,
+                    target.wrapping_add(reloc.addend as u32),
+                    &reloc.symbol,
+                )?;
+            }
+        }
+    }
+
+    let entry = *globals
+        .get("_start")
+        .ok_or("undefined entry symbol `_start`")?;
+    let [text, data, bss] = contents;
+    Ok(Executable {
+        entry,
+        text_addr: addrs[0],
+        text,
+        data_addr: addrs[1],
+        data,
+        bss_addr: addrs[2],
+        bss_size: bss.len() as u32,
+        symbols,
+    })
+}
@@ -1,34 +1,45 @@
-use std::{io::Write, sync::atomic::AtomicUsize};
+use std::{cell::Cell, io::Write};
 
 use self::symbol_table::{SymbolTable, DataType};
 
+pub use self::check::SemanticError;
+#[cfg(feature = "tools")]
+pub(crate) use self::check::LVals;
+
+mod check;
+mod display;
 mod symbol_table;
 
-static VAR_NAME: AtomicUsize = AtomicUsize::new(0);
+thread_local! {
+    // 临时变量的编号, 每次生成 Koopa IR 时从 0 开始, 不同线程中的编译互不影响
+    static VAR_NAME: Cell<usize> = const { Cell::new(0) };
+}
 
 fn gen_var_name() -> String {
-    let id = VAR_NAME.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    format!("%{}", id.to_string())
+    let id = VAR_NAME.get();
+    VAR_NAME.set(id + 1);
+    format!("%{}", id)
 }
 
 fn to_logic(var: String, f: &mut Vec<u8>) -> String {
     let output_name = gen_var_name();
-    writeln!(f, "    {} = ne {}, {}", output_name, var, "0").unwrap();
+    writeln!(f, "    {} = ne {}, 0", output_name, var).unwrap();
     output_name
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CompUnit {
     pub func_def: FuncDef,
 }
 
 impl CompUnit {
     pub fn generate(&self, f: &mut Vec<u8>) {
+        VAR_NAME.set(0);
         let mut table = SymbolTable::new();
         self.func_def.generate(f, &mut table);
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FuncDef {
     pub func_type: FuncType,
     pub ident: String,
@@ -45,7 +56,7 @@ impl FuncDef {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FuncType {
     Int,
 }
@@ -56,7 +67,7 @@ impl FuncType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Block {
     pub items: Vec<BlockItem>,
 }
@@ -71,7 +82,7 @@ impl Block {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Stmt {
     Ret(Exp),
     Assign(LVal, Exp),
@@ -100,7 +111,7 @@ impl Stmt {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Exp {
     pub l_or_exp: LOrExp,
 }
@@ -115,7 +126,7 @@ impl Exp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum LOrExp {
     LAndExp(LAndExp),
     Or(Box<LOrExp>, LAndExp),
@@ -149,7 +160,7 @@ impl LOrExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum LAndExp {
     EqExp(EqExp),
     And(Box<LAndExp>, EqExp),
@@ -183,7 +194,7 @@ impl LAndExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum EqExp {
     RelExp(RelExp),
     Eq(Box<EqExp>, EqSign, RelExp),
@@ -231,13 +242,13 @@ impl EqExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum EqSign {
     Eq,
     Neq,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RelExp {
     AddExp(AddExp),
     Cmp(Box<RelExp>, CmpSign, AddExp),
@@ -297,7 +308,7 @@ impl RelExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CmpSign {
     Less,
     More,
@@ -305,7 +316,7 @@ pub enum CmpSign {
     Meq,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AddExp {
     MulExp(MulExp),
     AddExp(Box<AddExp>, AddSign, MulExp),
@@ -357,13 +368,13 @@ impl AddExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AddSign {
     Add,
     Sub,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MulExp {
     UnaryExp(UnaryExp),
     MulExp(Box<MulExp>, MulSign, UnaryExp),
@@ -419,14 +430,14 @@ impl MulExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MulSign {
     Mul,
     Div,
     Mod,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PrimaryExp {
     Exp(Box<Exp>),
     Number(Number),
@@ -451,7 +462,7 @@ impl PrimaryExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Number {
     pub num: i32,
 }
@@ -461,7 +472,7 @@ impl Number {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum UnaryExp {
     PrimaryExp(PrimaryExp),
     Unary(UnaryOp, Box<UnaryExp>),
@@ -509,13 +520,13 @@ impl UnaryExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum UnaryOp {
     Negative,
     Bang,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Decl {
     ConstDecl(ConstDecl),
     VarDecl(VarDecl),
@@ -533,8 +544,9 @@ impl Decl {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct  ConstDecl {
+    #[allow(dead_code)]
     pub typ: BType,
     pub defs: Vec<ConstDef>,
 }
@@ -547,7 +559,7 @@ impl ConstDecl {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BType {
     I32,
 }
@@ -558,10 +570,12 @@ impl BType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConstDef {
     pub ident: String,
     pub val: ConstInitVal,
+    /// 名字在源码中的字节偏移, 不是解析得到的 AST 中为 None
+    pub offset: Option<usize>,
 }
 
 impl ConstDef {
@@ -571,7 +585,7 @@ impl ConstDef {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConstInitVal {
     pub exp: ConstExp,
 }
@@ -582,7 +596,7 @@ impl ConstInitVal {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConstExp {
     pub exp: Exp,
 }
@@ -593,7 +607,7 @@ impl ConstExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BlockItem {
     Decl(Decl),
     Stmt(Stmt),
@@ -613,9 +627,11 @@ impl BlockItem {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LVal {
     pub ident: String,
+    /// 名字在源码中的字节偏移, 不是解析得到的 AST 中为 None
+    pub offset: Option<usize>,
 }
 
 impl LVal {
@@ -645,7 +661,7 @@ impl LVal {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VarDecl {
     pub typ: BType,
     pub defs: Vec<VarDef>,
@@ -659,30 +675,31 @@ impl VarDecl {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+/// 最后一项是名字在源码中的字节偏移, 与 ConstDef::offset 相同
 pub enum VarDef {
-    Init(String, InitVal),
-    NoInit(String),
+    Init(String, InitVal, Option<usize>),
+    NoInit(String, Option<usize>),
 }
 
 impl VarDef {
     fn generate(&self, f: &mut Vec<u8>, table: &mut SymbolTable, typ: &BType) {
         match self {
-            Self::Init(ident, val) => {
+            Self::Init(ident, val, _) => {
                 // @x = alloc i32
                 write!(f, "    @{ident} = alloc ").unwrap();
                 typ.generate(f);
-                write!(f, "\n").unwrap();
+                writeln!(f).unwrap();
                 let input = val.generate(f, table);
                 writeln!(f, "    store {input}, @{ident}").unwrap();
                 table.var.insert(ident.to_string(), DataType::Int);
             }
 
-            Self::NoInit(ident) => {
+            Self::NoInit(ident, _) => {
                 // @x = alloc i32
                 write!(f, "    @{ident} = alloc ").unwrap();
                 typ.generate(f);
-                write!(f, "\n").unwrap();
+                writeln!(f).unwrap();
                 table.var.insert(ident.to_string(), DataType::Int);
 
             }
@@ -690,7 +707,7 @@ impl VarDef {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InitVal {
     pub exp: Exp,
 }
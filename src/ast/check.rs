@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use super::*;
+
+// 生成 Koopa IR 之前的语义检查.
+// 生成 IR 时默认程序是合法的, 遇到未定义的名字等错误只会 panic; 这里先检查一遍,
+// 报告带有位置的错误: 名字先定义后使用且不重复定义, 常量的初始值只引用常量, 不给常量赋值.
+
+/// 语义错误, offset 是出错的名字在源码中的字节偏移
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticError {
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl SemanticError {
+    fn new(offset: Option<usize>, message: String) -> Self {
+        Self { offset, message }
+    }
+}
+
+/// 依次收集表达式中引用的名字
+pub(crate) trait LVals {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>);
+}
+
+impl LVals for Exp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        self.l_or_exp.lvals(out);
+    }
+}
+
+impl LVals for LOrExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::LAndExp(l_and_exp) => l_and_exp.lvals(out),
+            Self::Or(l_or_exp, l_and_exp) => {
+                l_or_exp.lvals(out);
+                l_and_exp.lvals(out);
+            }
+        }
+    }
+}
+
+impl LVals for LAndExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::EqExp(eq_exp) => eq_exp.lvals(out),
+            Self::And(l_and_exp, eq_exp) => {
+                l_and_exp.lvals(out);
+                eq_exp.lvals(out);
+            }
+        }
+    }
+}
+
+impl LVals for EqExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::RelExp(rel_exp) => rel_exp.lvals(out),
+            Self::Eq(eq_exp, _, rel_exp) => {
+                eq_exp.lvals(out);
+                rel_exp.lvals(out);
+            }
+        }
+    }
+}
+
+impl LVals for RelExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::AddExp(add_exp) => add_exp.lvals(out),
+            Self::Cmp(rel_exp, _, add_exp) => {
+                rel_exp.lvals(out);
+                add_exp.lvals(out);
+            }
+        }
+    }
+}
+
+impl LVals for AddExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::MulExp(mul_exp) => mul_exp.lvals(out),
+            Self::AddExp(add_exp, _, mul_exp) => {
+                add_exp.lvals(out);
+                mul_exp.lvals(out);
+            }
+        }
+    }
+}
+
+impl LVals for MulExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::UnaryExp(unary_exp) => unary_exp.lvals(out),
+            Self::MulExp(mul_exp, _, unary_exp) => {
+                mul_exp.lvals(out);
+                unary_exp.lvals(out);
+            }
+        }
+    }
+}
+
+impl LVals for UnaryExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::PrimaryExp(p_exp) => p_exp.lvals(out),
+            Self::Unary(_, u_exp) => u_exp.lvals(out),
+        }
+    }
+}
+
+impl LVals for PrimaryExp {
+    fn lvals<'a>(&'a self, out: &mut Vec<&'a LVal>) {
+        match self {
+            Self::Exp(exp) => exp.lvals(out),
+            Self::Number(_) => {}
+            Self::LVal(lval) => out.push(lval),
+        }
+    }
+}
+
+// 已经定义的名字, 值表示是否是常量
+type Names = HashMap<String, bool>;
+
+// exp 中引用的名字都已经定义, const_only 时还必须都是常量
+fn uses(exp: &Exp, names: &Names, const_only: bool) -> Result<(), SemanticError> {
+    let mut lvals = Vec::new();
+    exp.lvals(&mut lvals);
+    for lval in lvals {
+        match names.get(&lval.ident) {
+            None => {
+                let message = format!("undefined variable `{}`", lval.ident);
+                return Err(SemanticError::new(lval.offset, message));
+            }
+            Some(false) if const_only => {
+                let message = format!("variable `{}` in a constant initializer", lval.ident);
+                return Err(SemanticError::new(lval.offset, message));
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn define(
+    names: &mut Names,
+    ident: &str,
+    offset: Option<usize>,
+    is_const: bool,
+) -> Result<(), SemanticError> {
+    match names.insert(ident.to_owned(), is_const) {
+        Some(_) => Err(SemanticError::new(
+            offset,
+            format!("redefinition of `{ident}`"),
+        )),
+        None => Ok(()),
+    }
+}
+
+impl CompUnit {
+    /// 检查程序的语义, 返回遇到的第一个错误
+    pub fn check(&self) -> Result<(), SemanticError> {
+        let mut names = Names::new();
+        for item in &self.func_def.block.items {
+            match item {
+                BlockItem::Decl(Decl::ConstDecl(const_decl)) => {
+                    for def in &const_decl.defs {
+                        uses(&def.val.exp.exp, &names, true)?;
+                        define(&mut names, &def.ident, def.offset, true)?;
+                    }
+                }
+                BlockItem::Decl(Decl::VarDecl(var_decl)) => {
+                    for def in &var_decl.defs {
+                        let (ident, offset) = match def {
+                            VarDef::Init(ident, val, offset) => {
+                                uses(&val.exp, &names, false)?;
+                                (ident, *offset)
+                            }
+                            VarDef::NoInit(ident, offset) => (ident, *offset),
+                        };
+                        define(&mut names, ident, offset, false)?;
+                    }
+                }
+                BlockItem::Stmt(Stmt::Assign(lval, exp)) => {
+                    uses(exp, &names, false)?;
+                    match names.get(&lval.ident) {
+                        None => {
+                            let message = format!("undefined variable `{}`", lval.ident);
+                            return Err(SemanticError::new(lval.offset, message));
+                        }
+                        Some(true) => {
+                            let message = format!("assignment to constant `{}`", lval.ident);
+                            return Err(SemanticError::new(lval.offset, message));
+                        }
+                        Some(false) => {}
+                    }
+                }
+                BlockItem::Stmt(Stmt::Ret(exp)) => uses(exp, &names, false)?,
+            }
+        }
+        Ok(())
+    }
+}
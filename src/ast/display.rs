@@ -0,0 +1,212 @@
+use std::fmt::{Display, Formatter, Result};
+
+use super::*;
+
+// 把 AST 打印回 SysY 源码.
+// AST 的层次与文法的优先级一一对应, 按层次原样输出即可保持运算顺序, 只有 PrimaryExp::Exp 需要括号
+
+impl Display for CompUnit {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}", self.func_def)
+    }
+}
+
+impl Display for FuncDef {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{} {}() {}", self.func_type, self.ident, self.block)
+    }
+}
+
+impl Display for FuncType {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::Int => write!(f, "int"),
+        }
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        writeln!(f, "{{")?;
+        for item in &self.items {
+            writeln!(f, "    {item}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl Display for BlockItem {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::Decl(decl) => write!(f, "{decl}"),
+            Self::Stmt(stmt) => write!(f, "{stmt}"),
+        }
+    }
+}
+
+impl Display for Decl {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::ConstDecl(const_decl) => write!(f, "{const_decl}"),
+            Self::VarDecl(var_decl) => write!(f, "{var_decl}"),
+        }
+    }
+}
+
+impl Display for ConstDecl {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "const {} ", self.typ)?;
+        for (i, def) in self.defs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} = {}", def.ident, def.val.exp.exp)?;
+        }
+        write!(f, ";")
+    }
+}
+
+impl Display for VarDecl {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{} ", self.typ)?;
+        for (i, def) in self.defs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match def {
+                VarDef::Init(ident, val, _) => write!(f, "{ident} = {}", val.exp)?,
+                VarDef::NoInit(ident, _) => write!(f, "{ident}")?,
+            }
+        }
+        write!(f, ";")
+    }
+}
+
+impl Display for BType {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::I32 => write!(f, "int"),
+        }
+    }
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::Ret(exp) => write!(f, "return {exp};"),
+            Self::Assign(lval, exp) => write!(f, "{} = {exp};", lval.ident),
+        }
+    }
+}
+
+impl Display for Exp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}", self.l_or_exp)
+    }
+}
+
+impl Display for LOrExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::LAndExp(l_and_exp) => write!(f, "{l_and_exp}"),
+            Self::Or(l_or_exp, l_and_exp) => write!(f, "{l_or_exp} || {l_and_exp}"),
+        }
+    }
+}
+
+impl Display for LAndExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::EqExp(eq_exp) => write!(f, "{eq_exp}"),
+            Self::And(l_and_exp, eq_exp) => write!(f, "{l_and_exp} && {eq_exp}"),
+        }
+    }
+}
+
+impl Display for EqExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::RelExp(rel_exp) => write!(f, "{rel_exp}"),
+            Self::Eq(eq_exp, sign, rel_exp) => {
+                let sign = match sign {
+                    EqSign::Eq => "==",
+                    EqSign::Neq => "!=",
+                };
+                write!(f, "{eq_exp} {sign} {rel_exp}")
+            }
+        }
+    }
+}
+
+impl Display for RelExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::AddExp(add_exp) => write!(f, "{add_exp}"),
+            Self::Cmp(rel_exp, sign, add_exp) => {
+                let sign = match sign {
+                    CmpSign::Less => "<",
+                    CmpSign::More => ">",
+                    CmpSign::Leq => "<=",
+                    CmpSign::Meq => ">=",
+                };
+                write!(f, "{rel_exp} {sign} {add_exp}")
+            }
+        }
+    }
+}
+
+impl Display for AddExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::MulExp(mul_exp) => write!(f, "{mul_exp}"),
+            Self::AddExp(add_exp, sign, mul_exp) => {
+                let sign = match sign {
+                    AddSign::Add => "+",
+                    AddSign::Sub => "-",
+                };
+                write!(f, "{add_exp} {sign} {mul_exp}")
+            }
+        }
+    }
+}
+
+impl Display for MulExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::UnaryExp(unary_exp) => write!(f, "{unary_exp}"),
+            Self::MulExp(mul_exp, sign, unary_exp) => {
+                let sign = match sign {
+                    MulSign::Mul => "*",
+                    MulSign::Div => "/",
+                    MulSign::Mod => "%",
+                };
+                write!(f, "{mul_exp} {sign} {unary_exp}")
+            }
+        }
+    }
+}
+
+impl Display for UnaryExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::PrimaryExp(p_exp) => write!(f, "{p_exp}"),
+            Self::Unary(op, u_exp) => {
+                let op = match op {
+                    UnaryOp::Negative => "-",
+                    UnaryOp::Bang => "!",
+                };
+                write!(f, "{op}{u_exp}")
+            }
+        }
+    }
+}
+
+impl Display for PrimaryExp {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::Exp(exp) => write!(f, "({exp})"),
+            Self::Number(num) => write!(f, "{}", num.num),
+            Self::LVal(lval) => write!(f, "{}", lval.ident),
+        }
+    }
+}
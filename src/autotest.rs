@@ -0,0 +1,177 @@
+use std::fs::{read, read_dir, read_to_string};
+use std::panic::{set_hook, take_hook};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::emulator::Emulator;
+use crate::interpreter::KoopaInterpreter;
+use crate::{catch, Options};
+
+// 本地的 autotest.
+// 用例的布局与 compiler-dev 镜像中的 /opt/bin/testcases 相同: lv1-lv9 和 perf 目录下是 xxx.sy,
+// 同名的 .out 是期望的输出 (程序的输出加上退出码), .in 是可选的标准输入.
+// 每个用例分别在 -koopa 和 -riscv 模式下编译, 用 Koopa 解释器或 RISC-V 模拟器运行后与 .out 比较.
+// 编译选项与命令行相同, 默认就是 -O1 的流水线.
+
+/// 每个用例最多执行的指令数, 超过时认为死循环
+const STEP_LIMIT: u64 = 1_000_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Koopa,
+    Riscv,
+}
+
+impl Mode {
+    const ALL: [Mode; 2] = [Self::Koopa, Self::Riscv];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Koopa => "koopa",
+            Self::Riscv => "riscv",
+        }
+    }
+}
+
+struct Case {
+    // 相对于用例目录的路径, 如 lv1/1_main.sy
+    name: String,
+    source: PathBuf,
+    input: Option<PathBuf>,
+    expected: PathBuf,
+}
+
+// 递归地找出所有有 .out 的 .sy 文件, 按路径排序
+fn collect(root: &Path, dir: &Path, cases: &mut Vec<Case>) {
+    let mut entries: Vec<PathBuf> = read_dir(dir)
+        .unwrap_or_else(|err| panic!("{}: {err}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect(root, &path, cases);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "sy" || ext == "c")
+        {
+            let expected = path.with_extension("out");
+            if !expected.exists() {
+                continue;
+            }
+            let input = Some(path.with_extension("in")).filter(|input| input.exists());
+            cases.push(Case {
+                name: path.strip_prefix(root).unwrap().display().to_string(),
+                source: path,
+                input,
+                expected,
+            });
+        }
+    }
+}
+
+/// 按 .out 文件的格式拼接程序的输出和退出码
+pub fn format_output(stdout: &[u8], code: i32) -> Vec<u8> {
+    let mut out = stdout.to_vec();
+    if !out.is_empty() && !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+    out.extend(format!("{code}\n").bytes());
+    out
+}
+
+// 编译并运行一个用例, 返回与 .out 格式相同的输出
+fn run_case(case: &Case, mode: Mode, options: &Options) -> Result<Vec<u8>, String> {
+    let source = read_to_string(&case.source).map_err(|err| err.to_string())?;
+    let input = match &case.input {
+        Some(path) => read(path).map_err(|err| err.to_string())?,
+        None => Vec::new(),
+    };
+    // -koopa 模式只需要 Koopa IR
+    let options = Options {
+        emit_asm: mode == Mode::Riscv,
+        ..options.clone()
+    };
+    let artifacts = crate::compile(&source, &options).map_err(|err| err.to_string())?;
+    catch(|| {
+        let (stdout, code) = match mode {
+            Mode::Koopa => {
+                let mut interpreter = KoopaInterpreter::new(&artifacts.program);
+                interpreter.runtime().set_input(input);
+                let code = interpreter.run(Some(STEP_LIMIT))?;
+                (interpreter.runtime().stdout().to_vec(), code)
+            }
+            Mode::Riscv => {
+                let mut emulator = Emulator::from_asm(artifacts.asm.as_ref().unwrap())?;
+                emulator.set_input(input);
+                let code = emulator.run(Some(STEP_LIMIT))?;
+                (emulator.stdout().to_vec(), code)
+            }
+        };
+        Ok(format_output(&stdout, code))
+    })
+}
+
+// 比较时忽略行尾的 \r 和末尾的空白
+fn same_output(actual: &[u8], expected: &[u8]) -> bool {
+    let normalize = |bytes: &[u8]| String::from_utf8_lossy(bytes).replace("\r\n", "\n");
+    normalize(actual).trim_end() == normalize(expected).trim_end()
+}
+
+/// 运行 dir 下路径中包含 filter 的用例, 逐个打印结果, 返回最后的汇总和没有通过的用例数.
+/// 一个用例在任一模式下失败都算作没有通过
+pub fn run_tests(dir: &str, filter: Option<&str>, options: &Options) -> (String, usize) {
+    let root = Path::new(dir);
+    let mut cases = Vec::new();
+    collect(root, root, &mut cases);
+    cases.retain(|case| filter.is_none_or(|filter| case.name.contains(filter)));
+
+    // 失败原因由我们自己打印, 不需要默认的 panic 信息
+    let hook = take_hook();
+    set_hook(Box::new(|_| {}));
+    let mut passed = [0; 2];
+    let mut failed = 0;
+    let mut times = [Duration::ZERO; 2];
+    for case in &cases {
+        let expected = read(&case.expected).unwrap();
+        let mut results = Vec::new();
+        let mut case_passed = true;
+        for (i, mode) in Mode::ALL.into_iter().enumerate() {
+            let start = Instant::now();
+            let result = run_case(case, mode, options);
+            let elapsed = start.elapsed();
+            times[i] += elapsed;
+            let status = match result {
+                Ok(actual) if same_output(&actual, &expected) => {
+                    passed[i] += 1;
+                    "PASS".to_owned()
+                }
+                Ok(_) => "WRONG ANSWER".to_owned(),
+                // 只保留错误信息的开头, 避免一行太长
+                Err(err) => {
+                    let line = err.lines().next().unwrap_or_default();
+                    format!("ERROR: {}", line.chars().take(100).collect::<String>())
+                }
+            };
+            case_passed &= status == "PASS";
+            results.push(format!("{} {status} ({:.1?})", mode.name(), elapsed));
+        }
+        if !case_passed {
+            failed += 1;
+        }
+        println!("{}: {}", case.name, results.join(", "));
+    }
+    set_hook(hook);
+
+    let mut summary = String::new();
+    for (i, mode) in Mode::ALL.into_iter().enumerate() {
+        summary += &format!(
+            "{}: {}/{} passed in {:.2?}\n",
+            mode.name(),
+            passed[i],
+            cases.len(),
+            times[i]
+        );
+    }
+    (summary, failed)
+}
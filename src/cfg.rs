@@ -0,0 +1,169 @@
+use std::collections::{HashMap, HashSet};
+
+use koopa::ir::{BasicBlock, FunctionData, ValueKind};
+
+/// 函数的控制流图
+///
+/// 只包含从入口可达的基本块, 按逆后序编号
+pub struct Cfg {
+    /// 可达基本块的逆后序
+    pub rpo: Vec<BasicBlock>,
+    pub preds: HashMap<BasicBlock, Vec<BasicBlock>>,
+    /// 直接支配者, 入口的直接支配者是它自己
+    pub idom: HashMap<BasicBlock, BasicBlock>,
+    rpo_index: HashMap<BasicBlock, usize>,
+}
+
+/// 基本块末尾的 br/jump 指向的后继
+pub fn successors(func: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock> {
+    let node = func.layout().bbs().node(&bb).unwrap();
+    let last = match node.insts().back_key() {
+        Some(&last) => last,
+        None => return Vec::new(),
+    };
+    match func.dfg().value(last).kind() {
+        ValueKind::Branch(br) => vec![br.true_bb(), br.false_bb()],
+        ValueKind::Jump(jump) => vec![jump.target()],
+        _ => Vec::new(),
+    }
+}
+
+impl Cfg {
+    pub fn new(func: &FunctionData) -> Self {
+        let entry = func.layout().entry_bb().unwrap();
+
+        // 非递归的深度优先搜索求后序
+        let mut succs = HashMap::new();
+        let mut postorder = Vec::new();
+        let mut visited = HashSet::from([entry]);
+        let mut stack = vec![(entry, 0)];
+        succs.insert(entry, successors(func, entry));
+        while let Some((bb, i)) = stack.pop() {
+            let next = succs[&bb].get(i).copied();
+            match next {
+                Some(succ) => {
+                    stack.push((bb, i + 1));
+                    if visited.insert(succ) {
+                        succs.insert(succ, successors(func, succ));
+                        stack.push((succ, 0));
+                    }
+                }
+                None => postorder.push(bb),
+            }
+        }
+        let rpo: Vec<BasicBlock> = postorder.into_iter().rev().collect();
+        let rpo_index: HashMap<BasicBlock, usize> =
+            rpo.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+
+        let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> =
+            rpo.iter().map(|&bb| (bb, Vec::new())).collect();
+        for &bb in &rpo {
+            for &succ in &succs[&bb] {
+                preds.get_mut(&succ).unwrap().push(bb);
+            }
+        }
+
+        let mut cfg = Self {
+            rpo,
+            preds,
+            idom: HashMap::new(),
+            rpo_index,
+        };
+        cfg.compute_idom();
+        cfg
+    }
+
+    // Cooper, Harvey, Kennedy: A Simple, Fast Dominance Algorithm
+    fn compute_idom(&mut self) {
+        let entry = self.rpo[0];
+        self.idom.insert(entry, entry);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in &self.rpo[1..] {
+                let mut new_idom = None;
+                for &pred in &self.preds[&bb] {
+                    if !self.idom.contains_key(&pred) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => self.intersect(pred, other),
+                    });
+                }
+                let new_idom = new_idom.unwrap();
+                if self.idom.get(&bb) != Some(&new_idom) {
+                    self.idom.insert(bb, new_idom);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn intersect(&self, mut a: BasicBlock, mut b: BasicBlock) -> BasicBlock {
+        while a != b {
+            while self.rpo_index[&a] > self.rpo_index[&b] {
+                a = self.idom[&a];
+            }
+            while self.rpo_index[&b] > self.rpo_index[&a] {
+                b = self.idom[&b];
+            }
+        }
+        a
+    }
+
+    /// 基本块在逆后序中的编号
+    pub fn rpo_index(&self, bb: BasicBlock) -> usize {
+        self.rpo_index[&bb]
+    }
+
+    /// a 是否支配 b
+    pub fn dominates(&self, a: BasicBlock, mut b: BasicBlock) -> bool {
+        while self.rpo_index[&b] > self.rpo_index[&a] {
+            b = self.idom[&b];
+        }
+        a == b
+    }
+
+    /// 控制流图是否可归约: 每条指向逆后序中不靠后的基本块的边, 目标都支配来源
+    pub fn is_reducible(&self) -> bool {
+        self.rpo.iter().all(|&bb| {
+            self.preds[&bb].iter().all(|&pred| {
+                self.rpo_index[&pred] < self.rpo_index[&bb] || self.dominates(bb, pred)
+            })
+        })
+    }
+
+    /// 每个基本块的支配边界: 它支配某个前驱、但不严格支配的基本块
+    pub fn dominance_frontiers(&self) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+        let mut frontiers: HashMap<BasicBlock, Vec<BasicBlock>> =
+            self.rpo.iter().map(|&bb| (bb, Vec::new())).collect();
+        // 只有汇合点会出现在支配边界中, 从它的每个前驱沿支配树向上走到它的直接支配者
+        for &bb in &self.rpo {
+            let preds = &self.preds[&bb];
+            if preds.len() < 2 {
+                continue;
+            }
+            for &pred in preds {
+                let mut runner = pred;
+                while runner != self.idom[&bb] {
+                    let frontier = frontiers.get_mut(&runner).unwrap();
+                    if !frontier.contains(&bb) {
+                        frontier.push(bb);
+                    }
+                    runner = self.idom[&runner];
+                }
+            }
+        }
+        frontiers
+    }
+
+    /// 支配树上的子节点, 按逆后序排列
+    pub fn dom_children(&self, bb: BasicBlock) -> Vec<BasicBlock> {
+        self.rpo
+            .iter()
+            .copied()
+            .filter(|&child| child != bb && self.idom[&child] == bb)
+            .collect()
+    }
+}
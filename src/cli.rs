@@ -0,0 +1,299 @@
+use compiler::generate_asm::{PeepholeConfig, Target};
+use compiler::opt::{check_pass, preset};
+use compiler::Options;
+
+// 命令行参数的解析.
+// 用法: compiler <模式> <输入> [-o <输出>] [选项], 输入和输出为 - 时表示标准输入和标准输出, 省略 -o 时写到标准输出.
+// 模式和选项的顺序任意, 出错时返回给用户看的错误信息, 由 main 打印用法提示
+
+/// 运行模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Tokens,
+    Ast,
+    Koopa,
+    Riscv,
+    Perf,
+    Object,
+    Exe,
+    Run,
+    RunKoopa,
+    RunAst,
+    Difftest,
+    X86,
+    Wasm,
+    Llvm,
+    C,
+    Test,
+    Fuzz,
+    Reduce,
+}
+
+// 模式的参数和说明
+const MODES: &[(&str, Mode, &str)] = &[
+    ("-koopa", Mode::Koopa, "emit Koopa IR"),
+    ("-riscv", Mode::Riscv, "emit RISC-V assembly"),
+    (
+        "-perf",
+        Mode::Perf,
+        "emit RISC-V assembly, optimized (-O2 unless given)",
+    ),
+    ("-c", Mode::Object, "assemble into an ELF32 object file"),
+    (
+        "-exe",
+        Mode::Exe,
+        "assemble and link into an ELF32 executable",
+    ),
+    (
+        "-run",
+        Mode::Run,
+        "run in the built-in emulator (also accepts .s and ELF inputs)",
+    ),
+    (
+        "-run-koopa",
+        Mode::RunKoopa,
+        "interpret the generated Koopa IR",
+    ),
+    ("-run-ast", Mode::RunAst, "interpret the AST"),
+    (
+        "-difftest",
+        Mode::Difftest,
+        "compare the Koopa interpreter with the emulator",
+    ),
+    ("-x86", Mode::X86, "emit x86-64 assembly"),
+    ("-wasm", Mode::Wasm, "emit WebAssembly text"),
+    ("-llvm", Mode::Llvm, "emit LLVM IR"),
+    ("-csrc", Mode::C, "emit C source"),
+    (
+        "-test",
+        Mode::Test,
+        "run every test case under the input directory, fail if any fails",
+    ),
+    (
+        "-fuzz",
+        Mode::Fuzz,
+        "test <input> random programs (0 = forever), saving failures to -o",
+    ),
+    ("-reduce", Mode::Reduce, "reduce a failing program"),
+];
+
+// --emit=<阶段> 对应的模式
+const EMITS: &[(&str, Mode)] = &[
+    ("tokens", Mode::Tokens),
+    ("ast", Mode::Ast),
+    ("koopa", Mode::Koopa),
+    ("asm", Mode::Riscv),
+];
+
+/// 解析后的命令行参数
+pub struct Args {
+    pub mode: Mode,
+    pub input: String,
+    pub output: String,
+    /// 优化级别 0..=2
+    pub opt_level: u8,
+    /// -peephole= 显式给出的窥孔优化模式, 优先于优化级别
+    pub peephole: Option<PeepholeConfig>,
+    pub peephole_stats: bool,
+    /// --passes= 显式给出的 Koopa IR 优化遍, 优先于优化级别
+    pub passes: Option<Vec<String>>,
+    pub print_after: Vec<String>,
+    pub time_passes: bool,
+    pub m_extension: bool,
+    pub target: Target,
+    pub filter: Option<String>,
+    pub seed: u64,
+    pub interesting: Option<String>,
+}
+
+impl Args {
+    /// 解析程序名之后的参数, 遇到 --help 时返回 None
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+        let mut args = args.into_iter();
+        let mut mode = None;
+        let mut input = None;
+        let mut output = None;
+        let mut opt_level = None;
+        let mut result = Args {
+            mode: Mode::Riscv,
+            input: String::new(),
+            output: String::new(),
+            opt_level: 0,
+            peephole: None,
+            peephole_stats: false,
+            passes: None,
+            print_after: Vec::new(),
+            time_passes: false,
+            m_extension: true,
+            target: Target::Riscv32,
+            filter: None,
+            seed: 0,
+            interesting: None,
+        };
+        let mut set_mode = |new: Mode, arg: &str| match mode.replace((new, arg.to_owned())) {
+            Some((_, old)) => Err(format!("conflicting modes `{old}` and `{arg}`")),
+            None => Ok(()),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("`{name}` expects a value"))
+            };
+            if arg == "--help" || arg == "-h" {
+                return Ok(None);
+            } else if let Some(&(_, new, _)) = MODES.iter().find(|(flag, _, _)| *flag == arg) {
+                set_mode(new, &arg)?;
+            } else if let Some(stage) = arg.strip_prefix("--emit=") {
+                let new = EMITS
+                    .iter()
+                    .find(|(name, _)| *name == stage)
+                    .map(|&(_, mode)| mode)
+                    .ok_or_else(|| format!("unknown stage `{stage}` for --emit, expected one of tokens, ast, koopa, asm"))?;
+                set_mode(new, &arg)?;
+            } else if arg == "-o" {
+                if output.replace(value("-o")?).is_some() {
+                    return Err("`-o` given more than once".to_owned());
+                }
+            } else if let Some(level) = arg.strip_prefix("-O") {
+                opt_level = Some(match level {
+                    "0" => 0,
+                    "1" => 1,
+                    "2" => 2,
+                    _ => {
+                        return Err(format!(
+                            "unknown optimization level `{arg}`, expected -O0, -O1 or -O2"
+                        ))
+                    }
+                });
+            } else if let Some(list) = arg.strip_prefix("-peephole=") {
+                let config = PeepholeConfig::from_list(list)
+                    .ok_or_else(|| format!("unknown peephole pattern in `{arg}`"))?;
+                result.peephole = Some(config);
+            } else if arg == "-peephole-stats" {
+                result.peephole_stats = true;
+            } else if let Some(list) = arg.strip_prefix("--passes=") {
+                let names = split_list(list);
+                names.iter().try_for_each(|name| check_pass(name))?;
+                result.passes = Some(names);
+            } else if let Some(list) = arg.strip_prefix("--print-after=") {
+                for name in split_list(list) {
+                    if name != "all" {
+                        check_pass(&name)?;
+                    }
+                    result.print_after.push(name);
+                }
+            } else if arg == "--time-passes" {
+                result.time_passes = true;
+            } else if let Some(march) = arg.strip_prefix("-march=") {
+                result.m_extension = match march {
+                    "rv32im" => true,
+                    "rv32i" => false,
+                    _ => {
+                        return Err(format!(
+                            "unsupported `-march={march}`, expected rv32im or rv32i"
+                        ))
+                    }
+                };
+            } else if arg == "-target" {
+                result.target = match value("-target")?.as_str() {
+                    "riscv32" => Target::Riscv32,
+                    "riscv64" => Target::Riscv64,
+                    other => {
+                        return Err(format!(
+                            "unsupported target `{other}`, expected riscv32 or riscv64"
+                        ))
+                    }
+                };
+            } else if arg == "-seed" {
+                let seed = value("-seed")?;
+                result.seed = seed.parse().map_err(|_| format!("invalid seed `{seed}`"))?;
+            } else if arg == "-s" {
+                result.filter = Some(value("-s")?);
+            } else if arg == "-interesting" {
+                result.interesting = Some(value("-interesting")?);
+            } else if arg.starts_with('-') && arg != "-" {
+                return Err(format!("unknown option `{arg}`"));
+            } else if let Some(first) = input.replace(arg.clone()) {
+                return Err(format!("more than one input: `{first}` and `{arg}`"));
+            }
+        }
+        let (mode, _) = mode.ok_or("no mode given")?;
+        result.mode = mode;
+        result.input = input.ok_or("no input file given")?;
+        result.output = output.unwrap_or_else(|| "-".to_owned());
+        result.opt_level = opt_level.unwrap_or(if mode == Mode::Perf { 2 } else { 0 });
+        // 所有参数都解析完再检查组合, 与 -march 和 -target 的先后无关
+        if !result.m_extension && result.target == Target::Riscv64 {
+            return Err("`-march=rv32i` cannot be combined with `-target riscv64`".to_owned());
+        }
+        Ok(Some(result))
+    }
+
+    /// 实际使用的 Koopa IR 优化遍
+    pub fn passes(&self) -> Vec<String> {
+        match &self.passes {
+            Some(passes) => passes.clone(),
+            None => preset(self.opt_level),
+        }
+    }
+
+    /// 命令行对应的编译选项, emit_asm 为 false 时不运行后端
+    pub fn options(&self, emit_asm: bool) -> Options {
+        Options {
+            target: self.target,
+            m_extension: self.m_extension,
+            peephole: self.peephole(),
+            passes: self.passes(),
+            print_after: self.print_after.clone(),
+            emit_asm,
+        }
+    }
+
+    /// 实际使用的窥孔优化模式
+    pub fn peephole(&self) -> PeepholeConfig {
+        match self.peephole {
+            Some(config) => config,
+            None if self.opt_level == 0 => PeepholeConfig::none(),
+            None => PeepholeConfig::default(),
+        }
+    }
+}
+
+// 逗号分隔的列表
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+        .collect()
+}
+
+/// 用法说明
+pub fn usage() -> String {
+    let mut usage = String::from(
+        "usage: compiler <mode> <input> [-o <output>] [options]\n\
+         \n\
+         <input> and <output> may be `-` for stdin and stdout; output defaults to stdout.\n\
+         \n\
+         modes:\n",
+    );
+    for (flag, _, help) in MODES {
+        usage += &format!("  {flag:<20}{help}\n");
+    }
+    usage += "  --emit=<stage>      stop after a stage: tokens, ast, koopa or asm\n\
+              \n\
+              options:\n  \
+                -o <file>           write the output to <file>\n  \
+                -O0, -O1, -O2       optimization level (default -O0, which disables all passes)\n  \
+                -peephole=<list>    peephole patterns, comma separated, or `none`\n  \
+                -peephole-stats     print peephole statistics to stderr\n  \
+                --passes=<list>     Koopa IR passes to run, comma separated, instead of the -O preset\n  \
+                --print-after=<p>   print the Koopa IR to stderr after pass <p> (or `all`)\n  \
+                --time-passes       print the time spent in each pass to stderr\n  \
+                -march=<isa>        rv32im (default) or rv32i\n  \
+                -target <target>    riscv32 (default) or riscv64\n  \
+                -s <filter>         -test: only run cases whose path contains <filter>\n  \
+                -seed <n>           -fuzz: the first seed\n  \
+                -interesting <cmd>  -reduce: command deciding whether a candidate still fails\n  \
+                -h, --help          print this help\n";
+    usage
+}
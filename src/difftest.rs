@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use koopa::ir::{BasicBlock, Function, Program};
+
+use crate::assembler::{assemble, link, runtime};
+use crate::emulator::Emulator;
+use crate::generate_asm::{Isa, Riscv};
+use crate::interpreter::KoopaInterpreter;
+
+// 差分测试.
+// 同一个程序分别用 Koopa 解释器和 RISC-V 模拟器执行, 记录两边依次进入的基本块 (RISC-V 一侧按
+// 基本块的标号识别) 以及进入时的输出长度. 控制流出现分歧说明上一个基本块中算出的跳转条件不同,
+// 输出出现分歧时找到写出第一个不同字节的基本块, 都一致时再比较退出码.
+// 不比较基本块边界上的变量的值: 寄存器分配之后 RISC-V 一侧没有 Koopa 值和寄存器的对应关系.
+// 所以算错的值只有在影响到跳转, 输出或者退出码时才会被发现, 报告的是它第一次产生影响的基本块,
+// 不一定是算错的那个基本块.
+
+/// 每个执行器最多执行的指令数
+const STEP_LIMIT: u64 = 100_000_000;
+
+// 一个执行器的运行结果
+struct Execution {
+    // 依次进入的基本块编号, 以及进入时的输出长度
+    trace: Vec<(usize, usize)>,
+    stdout: Vec<u8>,
+    result: Result<i32, String>,
+}
+
+impl Execution {
+    // 写出第 pos 个字节时所在的基本块
+    fn block_of_byte(&self, pos: usize) -> Option<usize> {
+        let index = self.trace.partition_point(|&(_, len)| len <= pos);
+        index.checked_sub(1).map(|index| self.trace[index].0)
+    }
+
+    fn describe_result(&self) -> String {
+        match &self.result {
+            Ok(code) => format!("exit code {code}"),
+            Err(err) => format!("error: {err}"),
+        }
+    }
+}
+
+// 程序中的所有基本块, 编号就是下标
+struct Blocks {
+    names: Vec<String>,
+    ids: HashMap<(Function, BasicBlock), usize>,
+    labels: HashMap<String, usize>,
+}
+
+impl Blocks {
+    fn new(program: &Program) -> Self {
+        let isa = Riscv::default();
+        let mut blocks = Self {
+            names: Vec::new(),
+            ids: HashMap::new(),
+            labels: HashMap::new(),
+        };
+        for &func in program.func_layout() {
+            let func_data = program.func(func);
+            let Some(entry) = func_data.layout().entry_bb() else {
+                continue;
+            };
+            let func_name = &func_data.name()[1..];
+            for &bb in func_data.layout().bbs().keys() {
+                let bb_name = func_data.dfg().bb(bb).name().clone().unwrap_or_default();
+                // 与 generate_asm 一致, 入口基本块使用函数名作为标号
+                let label = if bb == entry {
+                    func_name.to_owned()
+                } else {
+                    isa.local_label(func_name, &bb_name[1..])
+                };
+                let id = blocks.names.len();
+                blocks.ids.insert((func, bb), id);
+                blocks.labels.insert(label, id);
+                blocks.names.push(format!("{} {bb_name}", func_data.name()));
+            }
+        }
+        blocks
+    }
+
+    fn name(&self, id: Option<usize>) -> &str {
+        id.map_or("<exit>", |id| &self.names[id])
+    }
+}
+
+fn run_koopa(program: &Program, blocks: &Blocks, input: Vec<u8>) -> Execution {
+    let mut interpreter = KoopaInterpreter::new(program);
+    interpreter.set_trace();
+    interpreter.runtime().set_input(input);
+    let result = interpreter.run(Some(STEP_LIMIT));
+    Execution {
+        trace: interpreter
+            .trace()
+            .iter()
+            .map(|&(func, bb, len)| (blocks.ids[&(func, bb)], len))
+            .collect(),
+        stdout: interpreter.runtime().stdout().to_vec(),
+        result,
+    }
+}
+
+fn run_riscv(asm: &str, blocks: &Blocks, input: Vec<u8>) -> Execution {
+    // 汇编或链接失败时当作运行出错, 与解释器的结果比较
+    let exe = match assemble(asm).and_then(|object| link(&[object, runtime()])) {
+        Ok(exe) => exe,
+        Err(err) => {
+            return Execution {
+                trace: Vec::new(),
+                stdout: Vec::new(),
+                result: Err(err),
+            }
+        }
+    };
+    let points = exe
+        .symbols
+        .iter()
+        .filter_map(|symbol| Some((symbol.offset, *blocks.labels.get(&symbol.name)?)))
+        .collect();
+    let mut emulator = Emulator::from_executable(&exe);
+    emulator.set_trace(points);
+    emulator.set_input(input);
+    let result = emulator.run(Some(STEP_LIMIT));
+    Execution {
+        trace: emulator.trace().to_vec(),
+        stdout: emulator.stdout().to_vec(),
+        result,
+    }
+}
+
+/// 比较 program 和它生成的 RISC-V 汇编 asm 的执行结果, 一致时返回 None, 否则返回第一处分歧的描述
+pub fn difftest(program: &Program, asm: &str, input: Vec<u8>) -> Option<String> {
+    let blocks = Blocks::new(program);
+    let koopa = run_koopa(program, &blocks, input.clone());
+    let riscv = run_riscv(asm, &blocks, input);
+
+    // 两边相同的基本块序列的长度
+    let common = koopa
+        .trace
+        .iter()
+        .zip(&riscv.trace)
+        .take_while(|(k, r)| k.0 == r.0)
+        .count();
+
+    // 输出的分歧发生在控制流分歧之前时, 先报告输出
+    let diff_byte = koopa
+        .stdout
+        .iter()
+        .zip(&riscv.stdout)
+        .position(|(k, r)| k != r)
+        .or_else(|| {
+            (koopa.stdout.len() != riscv.stdout.len())
+                .then(|| koopa.stdout.len().min(riscv.stdout.len()))
+        });
+    if let Some(pos) = diff_byte {
+        let index = koopa.trace.partition_point(|&(_, len)| len <= pos);
+        if index <= common {
+            let snippet = |stdout: &[u8]| {
+                let end = (pos + 16).min(stdout.len());
+                format!("{:?}", String::from_utf8_lossy(&stdout[pos.min(end)..end]))
+            };
+            return Some(format!(
+                "output differs at byte {pos} in {}: koopa wrote {}, riscv wrote {}",
+                blocks.name(koopa.block_of_byte(pos).or(riscv.block_of_byte(pos))),
+                snippet(&koopa.stdout),
+                snippet(&riscv.stdout),
+            ));
+        }
+    }
+
+    if common < koopa.trace.len() || common < riscv.trace.len() {
+        let next = |exec: &Execution| blocks.name(exec.trace.get(common).map(|&(id, _)| id));
+        let last = common.checked_sub(1).map(|index| koopa.trace[index].0);
+        return Some(format!(
+            "control flow differs after {}: koopa goes to {} ({}), riscv goes to {} ({})",
+            blocks.name(last),
+            next(&koopa),
+            koopa.describe_result(),
+            next(&riscv),
+            riscv.describe_result(),
+        ));
+    }
+
+    if koopa.result != riscv.result {
+        let last = koopa.trace.last().map(|&(id, _)| id);
+        return Some(format!(
+            "result differs in {}: koopa {}, riscv {}",
+            blocks.name(last),
+            koopa.describe_result(),
+            riscv.describe_result(),
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_asm::{GenerateAsm, ProgramInfo};
+
+    const KOOPA: &str = "\
+decl @putint(i32)
+
+fun @main(): i32 {
+%entry:
+  %x = add 40, 2
+  call @putint(%x)
+  %c = gt %x, 10
+  br %c, %big, %small
+
+%big:
+  ret 1
+
+%small:
+  ret 2
+}
+";
+
+    fn program() -> Program {
+        koopa::front::Driver::from(KOOPA)
+            .generate_program()
+            .unwrap()
+    }
+
+    fn riscv(program: &Program) -> String {
+        let mut buf = Vec::new();
+        program.generate(&mut ProgramInfo::new(program, None), &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn same_program_has_no_divergence() {
+        let program = program();
+        assert_eq!(difftest(&program, &riscv(&program), Vec::new()), None);
+    }
+
+    // 把生成的汇编中的 from 换成 to 后比较
+    fn diverge(from: &str, to: &str) -> Option<String> {
+        let program = program();
+        let asm = riscv(&program);
+        assert!(asm.contains(from), "{asm}");
+        difftest(&program, &asm.replacen(from, to, 1), Vec::new())
+    }
+
+    #[test]
+    fn reports_first_divergence() {
+        assert_eq!(
+            diverge("li t6, 2\n", "li t6, 3\n").unwrap(),
+            "output differs at byte 1 in @main %entry: koopa wrote \"2\", riscv wrote \"3\""
+        );
+        assert_eq!(
+            diverge("li t6, 10\n", "li t6, 50\n").unwrap(),
+            "control flow differs after @main %entry: koopa goes to @main %big (exit code 1), \
+             riscv goes to @main %small (exit code 2)"
+        );
+        assert_eq!(
+            diverge("li a0, 1\n", "li a0, 7\n").unwrap(),
+            "result differs in @main %big: koopa exit code 1, riscv exit code 7"
+        );
+        // 算错的值没有影响到跳转, 输出和退出码时不会被发现
+        assert_eq!(diverge("li t6, 10\n", "li t6, 11\n"), None);
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+use crate::assembler::encode::{decode, fields, Fields};
+use crate::assembler::{assemble, link, runtime, Executable};
+
+// RV32IM 用户态模拟器.
+// 加载 link 得到的可执行文件 (或者磁盘上的 ELF 文件), 运行时库中的系统调用用主机的输入输出实现.
+// 代码段在加载时预先译码, 执行时只查表.
+
+const PAGE_SIZE: u32 = 0x1000;
+/// 栈顶地址, 栈向下最多增长 STACK_SIZE 字节
+const STACK_TOP: u32 = 0x7fff_f000;
+const STACK_SIZE: u32 = 8 << 20;
+
+// 用到的 Linux 系统调用
+const SYS_READ: u32 = 63;
+const SYS_WRITE: u32 = 64;
+const SYS_EXIT: u32 = 93;
+const SYS_EXIT_GROUP: u32 = 94;
+const SYS_CLOCK_GETTIME: u32 = 113;
+const SYS_CLOCK_GETTIME64: u32 = 403;
+
+/// 运行的统计信息
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stats {
+    /// 执行的指令条数
+    pub instructions: u64,
+    /// 按简单的五级流水线估计的周期数:
+    /// 普通指令 1 周期, load 2 周期, 乘法 3 周期, 除法和取余 34 周期,
+    /// 跳转以及成立的条件跳转再加 2 周期的冲刷
+    pub cycles: u64,
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "instructions: {}", self.instructions)?;
+        writeln!(f, "cycles: {}", self.cycles)
+    }
+}
+
+/// 整数运算, 寄存器和立即数两种形式共用
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+impl AluOp {
+    fn eval(self, a: u32, b: u32) -> u32 {
+        match self {
+            Self::Add => a.wrapping_add(b),
+            Self::Sub => a.wrapping_sub(b),
+            Self::Sll => a << (b & 31),
+            Self::Slt => ((a as i32) < (b as i32)) as u32,
+            Self::Sltu => (a < b) as u32,
+            Self::Xor => a ^ b,
+            Self::Srl => a >> (b & 31),
+            Self::Sra => ((a as i32) >> (b & 31)) as u32,
+            Self::Or => a | b,
+            Self::And => a & b,
+            Self::Mul => a.wrapping_mul(b),
+            Self::Mulh => ((a as i32 as i64 * b as i32 as i64) >> 32) as u32,
+            Self::Mulhsu => ((a as i32 as i64 * b as i64) >> 32) as u32,
+            Self::Mulhu => ((a as u64 * b as u64) >> 32) as u32,
+            // 除以 0 和溢出的结果按规范给出, 不产生异常
+            Self::Div => match b {
+                0 => u32::MAX,
+                _ => (a as i32).wrapping_div(b as i32) as u32,
+            },
+            Self::Divu => a.checked_div(b).unwrap_or(u32::MAX),
+            Self::Rem => match b {
+                0 => a,
+                _ => (a as i32).wrapping_rem(b as i32) as u32,
+            },
+            Self::Remu => a.checked_rem(b).unwrap_or(a),
+        }
+    }
+
+    fn cycles(self) -> u64 {
+        match self {
+            Self::Mul | Self::Mulh | Self::Mulhsu | Self::Mulhu => 3,
+            Self::Div | Self::Divu | Self::Rem | Self::Remu => 34,
+            _ => 1,
+        }
+    }
+}
+
+/// 条件跳转的比较
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl Cond {
+    fn eval(self, a: u32, b: u32) -> bool {
+        match self {
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+            Self::Lt => (a as i32) < (b as i32),
+            Self::Ge => (a as i32) >= (b as i32),
+            Self::Ltu => a < b,
+            Self::Geu => a >= b,
+        }
+    }
+}
+
+/// 译码后的操作
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Opcode {
+    /// rd = rs1 op rs2
+    Op(AluOp),
+    /// rd = rs1 op imm, 包括移位立即数
+    OpImm(AluOp),
+    /// size 字节的 load, signed 表示做符号扩展
+    Load {
+        size: u32,
+        signed: bool,
+    },
+    Store {
+        size: u32,
+    },
+    Branch(Cond),
+    Lui,
+    Auipc,
+    Jal,
+    Jalr,
+    Ecall,
+    Ebreak,
+}
+
+impl Opcode {
+    // 指令表中的每条指令对应一个操作
+    fn from_mnemonic(name: &str) -> Self {
+        match name {
+            "add" => Self::Op(AluOp::Add),
+            "sub" => Self::Op(AluOp::Sub),
+            "sll" => Self::Op(AluOp::Sll),
+            "slt" => Self::Op(AluOp::Slt),
+            "sltu" => Self::Op(AluOp::Sltu),
+            "xor" => Self::Op(AluOp::Xor),
+            "srl" => Self::Op(AluOp::Srl),
+            "sra" => Self::Op(AluOp::Sra),
+            "or" => Self::Op(AluOp::Or),
+            "and" => Self::Op(AluOp::And),
+            "mul" => Self::Op(AluOp::Mul),
+            "mulh" => Self::Op(AluOp::Mulh),
+            "mulhsu" => Self::Op(AluOp::Mulhsu),
+            "mulhu" => Self::Op(AluOp::Mulhu),
+            "div" => Self::Op(AluOp::Div),
+            "divu" => Self::Op(AluOp::Divu),
+            "rem" => Self::Op(AluOp::Rem),
+            "remu" => Self::Op(AluOp::Remu),
+            "addi" => Self::OpImm(AluOp::Add),
+            "slti" => Self::OpImm(AluOp::Slt),
+            "sltiu" => Self::OpImm(AluOp::Sltu),
+            "xori" => Self::OpImm(AluOp::Xor),
+            "ori" => Self::OpImm(AluOp::Or),
+            "andi" => Self::OpImm(AluOp::And),
+            "slli" => Self::OpImm(AluOp::Sll),
+            "srli" => Self::OpImm(AluOp::Srl),
+            "srai" => Self::OpImm(AluOp::Sra),
+            "lb" => Self::Load {
+                size: 1,
+                signed: true,
+            },
+            "lh" => Self::Load {
+                size: 2,
+                signed: true,
+            },
+            "lw" => Self::Load {
+                size: 4,
+                signed: true,
+            },
+            "lbu" => Self::Load {
+                size: 1,
+                signed: false,
+            },
+            "lhu" => Self::Load {
+                size: 2,
+                signed: false,
+            },
+            "sb" => Self::Store { size: 1 },
+            "sh" => Self::Store { size: 2 },
+            "sw" => Self::Store { size: 4 },
+            "beq" => Self::Branch(Cond::Eq),
+            "bne" => Self::Branch(Cond::Ne),
+            "blt" => Self::Branch(Cond::Lt),
+            "bge" => Self::Branch(Cond::Ge),
+            "bltu" => Self::Branch(Cond::Ltu),
+            "bgeu" => Self::Branch(Cond::Geu),
+            "lui" => Self::Lui,
+            "auipc" => Self::Auipc,
+            "jal" => Self::Jal,
+            "jalr" => Self::Jalr,
+            "ecall" => Self::Ecall,
+            "ebreak" => Self::Ebreak,
+            _ => unreachable!("{name}"),
+        }
+    }
+}
+
+/// 按页分配的稀疏内存
+struct Memory {
+    pages: HashMap<u32, Box<[u8; PAGE_SIZE as usize]>>,
+}
+
+impl Memory {
+    fn page(&mut self, addr: u32) -> Result<&mut [u8; PAGE_SIZE as usize], String> {
+        let number = addr / PAGE_SIZE;
+        // 栈上的页第一次访问时才分配
+        if (STACK_TOP - STACK_SIZE..STACK_TOP).contains(&addr) {
+            let page = self
+                .pages
+                .entry(number)
+                .or_insert_with(|| Box::new([0; 4096]));
+            return Ok(page);
+        }
+        match self.pages.get_mut(&number) {
+            Some(page) => Ok(page),
+            None => Err(format!("segmentation fault at {addr:#010x}")),
+        }
+    }
+
+    fn map(&mut self, addr: u32, size: u32) {
+        for number in addr / PAGE_SIZE..(addr + size).div_ceil(PAGE_SIZE) {
+            self.pages
+                .entry(number)
+                .or_insert_with(|| Box::new([0; 4096]));
+        }
+    }
+
+    // 读 size 个字节, 按小端拼成整数
+    fn load(&mut self, addr: u32, size: u32) -> Result<u32, String> {
+        let offset = (addr % PAGE_SIZE) as usize;
+        if offset + size as usize <= PAGE_SIZE as usize {
+            let page = self.page(addr)?;
+            let mut bytes = [0; 4];
+            bytes[..size as usize].copy_from_slice(&page[offset..offset + size as usize]);
+            return Ok(u32::from_le_bytes(bytes));
+        }
+        // 跨页的访问逐字节进行
+        let mut value = 0;
+        for i in (0..size).rev() {
+            let addr = addr.wrapping_add(i);
+            value = value << 8 | self.page(addr)?[(addr % PAGE_SIZE) as usize] as u32;
+        }
+        Ok(value)
+    }
+
+    fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), String> {
+        for (i, byte) in value.to_le_bytes()[..size as usize].iter().enumerate() {
+            let addr = addr.wrapping_add(i as u32);
+            self.page(addr)?[(addr % PAGE_SIZE) as usize] = *byte;
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, addr: u32, bytes: &[u8]) -> Result<(), String> {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.store(addr.wrapping_add(i as u32), 1, byte as u32)?;
+        }
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, addr: u32, len: u32) -> Result<Vec<u8>, String> {
+        (0..len)
+            .map(|i| self.load(addr.wrapping_add(i), 1).map(|byte| byte as u8))
+            .collect()
+    }
+}
+
+/// 模拟器的状态
+pub struct Emulator {
+    regs: [u32; 32],
+    pc: u32,
+    memory: Memory,
+    // 预先译码的代码段
+    code_addr: u32,
+    code: Vec<Option<(Opcode, Fields)>>,
+    // 标准输入在第一次 read 时才从主机读入
+    input: Option<Vec<u8>>,
+    input_pos: usize,
+    stdout: Vec<u8>,
+    // 是否把程序的输出同时写到主机的标准输出
+    echo: bool,
+    stats: Stats,
+    start: Instant,
+    // 需要跟踪的地址及其编号, 每次执行到这些地址时记录编号和当时的输出长度
+    trace_points: HashMap<u32, usize>,
+    trace: Vec<(usize, usize)>,
+}
+
+impl Emulator {
+    fn new(entry: u32) -> Self {
+        let mut regs = [0; 32];
+        regs[2] = STACK_TOP;
+        Self {
+            regs,
+            pc: entry,
+            memory: Memory {
+                pages: HashMap::new(),
+            },
+            code_addr: 0,
+            code: Vec::new(),
+            input: None,
+            input_pos: 0,
+            stdout: Vec::new(),
+            echo: false,
+            stats: Stats::default(),
+            start: Instant::now(),
+            trace_points: HashMap::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    // 加载一个段, 可执行的段同时做译码
+    fn load_segment(&mut self, addr: u32, data: &[u8], mem_size: u32, exec: bool) {
+        self.memory.map(addr, mem_size);
+        self.memory.write_bytes(addr, data).unwrap();
+        if exec {
+            self.code_addr = addr;
+            self.code = data
+                .chunks(4)
+                .map(|word| {
+                    let mut bytes = [0; 4];
+                    bytes[..word.len()].copy_from_slice(word);
+                    let inst = u32::from_le_bytes(bytes);
+                    decode(inst)
+                        .map(|(name, format)| (Opcode::from_mnemonic(name), fields(format, inst)))
+                })
+                .collect();
+        }
+    }
+
+    /// 加载链接好的可执行文件
+    pub fn from_executable(exe: &Executable) -> Self {
+        let mut emulator = Self::new(exe.entry);
+        emulator.load_segment(exe.text_addr, &exe.text, exe.text.len() as u32, true);
+        let data_size = exe.bss_addr + exe.bss_size - exe.data_addr;
+        emulator.load_segment(exe.data_addr, &exe.data, data_size, false);
+        emulator
+    }
+
+    /// 汇编并与运行时库链接后加载
+    pub fn from_asm(asm: &str) -> Result<Self, String> {
+        let exe = link(&[assemble(asm)?, runtime()])?;
+        Ok(Self::from_executable(&exe))
+    }
+
+    /// 加载 ELF32 可执行文件, 只看 PT_LOAD 的程序头
+    pub fn from_elf(file: &[u8]) -> Result<Self, String> {
+        let bytes = |offset: usize, len: usize| {
+            file.get(offset..offset + len)
+                .ok_or_else(|| "truncated ELF file".to_owned())
+        };
+        let u16_at = |offset: usize| -> Result<usize, String> {
+            Ok(u16::from_le_bytes(bytes(offset, 2)?.try_into().unwrap()) as usize)
+        };
+        let u32_at = |offset: usize| -> Result<u32, String> {
+            Ok(u32::from_le_bytes(bytes(offset, 4)?.try_into().unwrap()))
+        };
+        if !file.starts_with(b"\x7fELF\x01\x01") {
+            return Err("not an ELF32 file".to_owned());
+        }
+        if u16_at(18)? != 243 {
+            return Err("not a RISC-V executable".to_owned());
+        }
+        let mut emulator = Self::new(u32_at(24)?);
+        let (phoff, phentsize, phnum) = (u32_at(28)? as usize, u16_at(42)?, u16_at(44)?);
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            // PT_LOAD
+            if u32_at(ph)? != 1 {
+                continue;
+            }
+            let (offset, addr) = (u32_at(ph + 4)? as usize, u32_at(ph + 8)?);
+            let (file_size, mem_size, flags) = (
+                u32_at(ph + 16)? as usize,
+                u32_at(ph + 20)?,
+                u32_at(ph + 24)?,
+            );
+            if (file_size as u64) > mem_size as u64 {
+                return Err("segment is larger in the file than in memory".to_owned());
+            }
+            let data = bytes(offset, file_size)?;
+            emulator.load_segment(addr, data, mem_size, flags & 1 != 0);
+        }
+        Ok(emulator)
+    }
+
+    /// 使用给定的标准输入, 而不是读取主机的标准输入
+    pub fn set_input(&mut self, input: Vec<u8>) {
+        self.input = Some(input);
+    }
+
+    /// 程序的输出同时写到主机的标准输出
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    /// 跟踪执行到 points 中各个地址的顺序
+    pub fn set_trace(&mut self, points: HashMap<u32, usize>) {
+        self.trace_points = points;
+    }
+
+    /// 依次执行到的跟踪点的编号, 以及当时程序已经输出的字节数
+    pub fn trace(&self) -> &[(usize, usize)] {
+        &self.trace
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn reg(&self, reg: u32) -> u32 {
+        self.regs[reg as usize]
+    }
+
+    fn set_reg(&mut self, reg: u32, value: u32) {
+        if reg != 0 {
+            self.regs[reg as usize] = value;
+        }
+    }
+
+    /// 运行到程序退出, 返回退出码. 出现非法指令, 访存错误或者执行超过 limit 条指令时返回错误
+    pub fn run(&mut self, limit: Option<u64>) -> Result<i32, String> {
+        loop {
+            if limit.is_some_and(|limit| self.stats.instructions >= limit) {
+                return Err(format!("instruction limit {} exceeded", limit.unwrap()));
+            }
+            if let Some(code) = self.step()? {
+                return Ok(code);
+            }
+        }
+    }
+
+    // 执行一条指令, 程序退出时返回退出码
+    fn step(&mut self) -> Result<Option<i32>, String> {
+        let pc = self.pc;
+        if let Some(&point) = self.trace_points.get(&pc) {
+            self.trace.push((point, self.stdout.len()));
+        }
+        let index = pc.wrapping_sub(self.code_addr) / 4;
+        let (op, f) = match self.code.get(index as usize) {
+            Some(&Some(inst)) if pc.is_multiple_of(4) => inst,
+            Some(_) => return Err(format!("illegal instruction at {pc:#010x}")),
+            None => return Err(format!("jump to non-code address {pc:#010x}")),
+        };
+        let (rs1, rs2) = (self.reg(f.rs1), self.reg(f.rs2));
+        let imm = f.imm as u32;
+        let mut next = pc.wrapping_add(4);
+        let mut cycles = 1;
+        let mut exit = None;
+        match op {
+            Opcode::Op(alu) => {
+                cycles = alu.cycles();
+                self.set_reg(f.rd, alu.eval(rs1, rs2));
+            }
+            Opcode::OpImm(alu) => self.set_reg(f.rd, alu.eval(rs1, imm)),
+            Opcode::Load { size, signed } => {
+                let value = self.memory.load(rs1.wrapping_add(imm), size)?;
+                // 符号扩展: 先移到最高位再算术右移回来
+                let shift = 32 - 8 * size;
+                let value = match signed {
+                    true => ((value << shift) as i32 >> shift) as u32,
+                    false => value,
+                };
+                cycles = 2;
+                self.set_reg(f.rd, value);
+            }
+            Opcode::Store { size } => self.memory.store(rs1.wrapping_add(imm), size, rs2)?,
+            Opcode::Branch(cond) => {
+                if cond.eval(rs1, rs2) {
+                    next = pc.wrapping_add(imm);
+                    cycles = 3;
+                }
+            }
+            Opcode::Lui => self.set_reg(f.rd, imm << 12),
+            Opcode::Auipc => self.set_reg(f.rd, pc.wrapping_add(imm << 12)),
+            Opcode::Jal => {
+                next = pc.wrapping_add(imm);
+                cycles = 3;
+                self.set_reg(f.rd, pc.wrapping_add(4));
+            }
+            Opcode::Jalr => {
+                next = rs1.wrapping_add(imm) & !1;
+                cycles = 3;
+                self.set_reg(f.rd, pc.wrapping_add(4));
+            }
+            Opcode::Ecall => exit = self.syscall()?,
+            Opcode::Ebreak => return Err(format!("ebreak at {pc:#010x}")),
+        }
+        self.pc = next;
+        self.stats.instructions += 1;
+        self.stats.cycles += cycles;
+        Ok(exit)
+    }
+
+    // 按 Linux 的约定处理系统调用: a7 是调用号, a0-a2 是参数, 结果放在 a0
+    fn syscall(&mut self) -> Result<Option<i32>, String> {
+        let (a0, a1, a2) = (self.regs[10], self.regs[11], self.regs[12]);
+        let result = match self.regs[17] {
+            SYS_READ => {
+                if a0 != 0 {
+                    return Err(format!("read from unsupported fd {a0}"));
+                }
+                let input = self.input.get_or_insert_with(|| {
+                    let mut input = Vec::new();
+                    std::io::stdin().read_to_end(&mut input).unwrap();
+                    input
+                });
+                let len = (a2 as usize).min(input.len() - self.input_pos);
+                let bytes = input[self.input_pos..self.input_pos + len].to_vec();
+                self.input_pos += len;
+                self.memory.write_bytes(a1, &bytes)?;
+                len as u32
+            }
+            SYS_WRITE => {
+                let bytes = self.memory.read_bytes(a1, a2)?;
+                match a0 {
+                    1 => {
+                        if self.echo {
+                            std::io::stdout().write_all(&bytes).unwrap();
+                        }
+                        self.stdout.extend(&bytes);
+                    }
+                    2 => std::io::stderr().write_all(&bytes).unwrap(),
+                    _ => return Err(format!("write to unsupported fd {a0}")),
+                }
+                a2
+            }
+            // 与 Linux 一样只保留退出码的低 8 位
+            SYS_EXIT | SYS_EXIT_GROUP => return Ok(Some((a0 & 0xff) as i32)),
+            SYS_CLOCK_GETTIME | SYS_CLOCK_GETTIME64 => {
+                // 返回模拟器启动以来的时间, 结构是两个 64 位整数
+                let elapsed = self.start.elapsed();
+                let mut timespec = Vec::new();
+                timespec.extend(elapsed.as_secs().to_le_bytes());
+                timespec.extend((elapsed.subsec_nanos() as u64).to_le_bytes());
+                self.memory.write_bytes(a1, &timespec)?;
+                0
+            }
+            number => return Err(format!("unsupported system call {number}")),
+        };
+        self.regs[10] = result;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::encode::INSTS;
+
+    #[test]
+    fn every_instruction_has_an_opcode() {
+        let opcodes: Vec<Opcode> = INSTS
+            .iter()
+            .map(|&(name, _)| Opcode::from_mnemonic(name))
+            .collect();
+        // 不同的指令译码成不同的操作
+        for (i, a) in opcodes.iter().enumerate() {
+            assert!(!opcodes[i + 1..].contains(a), "{}", INSTS[i].0);
+        }
+        // 立即数形式与寄存器形式的运算相同, 但是操作数不同
+        assert_eq!(Opcode::from_mnemonic("srai"), Opcode::OpImm(AluOp::Sra));
+        assert_eq!(Opcode::from_mnemonic("sra"), Opcode::Op(AluOp::Sra));
+        assert_eq!(Opcode::from_mnemonic("sltiu"), Opcode::OpImm(AluOp::Sltu));
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::HashSet;
+use std::fs::{create_dir_all, write};
+use std::panic::{set_hook, take_hook};
+use std::path::Path;
+
+use koopa::ir::Program;
+
+use crate::ast::*;
+use crate::autotest::format_output;
+use crate::emulator::Emulator;
+use crate::generate_asm::PeepholeConfig;
+use crate::interpreter::{AstInterpreter, KoopaInterpreter};
+use crate::opt::preset;
+use crate::{catch, compile, parse, tokenize, Options};
+
+// 随机程序生成和模糊测试.
+// 生成的程序覆盖 sysy.lalrpop 中的全部文法: 常量和变量声明, 赋值, 各种优先级的表达式, 以 return 结束.
+// 文法目前只有单个 main 函数, 没有 if, while 和函数调用, 所以生成的都是直线代码,
+// 后端的分支、循环和调用由 tests 下手写的 Koopa IR 覆盖. 文法扩展之后生成器也要跟着扩展.
+// AST 中没有的写法 (注释, 一元加号, 八进制和十六进制的整数) 在输出源码时随机加入, 单独检查解析的结果.
+// 生成时同步计算每个表达式的值, 遇到溢出、除以 0 的运算就换一个运算符, 变量只在初始化之后读取,
+// 因此程序没有未定义行为, 前端的常量求值也不会 panic. 程序是直线代码, 一定会终止.
+
+/// 每个程序最多执行的指令数
+const STEP_LIMIT: u64 = 10_000_000;
+
+/// 一种失败: 用来去重的特征和详细信息
+pub type Failure = (String, String);
+
+/// xorshift64* 伪随机数生成器, 同一个种子总是生成同一个程序
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // 状态不能是 0, 相邻的种子先打散
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// [0, n) 中的随机数
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// 以 percent% 的概率返回 true
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+/// 随机 SysY 程序生成器
+pub struct Generator {
+    rng: Rng,
+    // 表达式中二元运算的最大嵌套深度
+    max_depth: usize,
+    // main 中语句和声明的最大个数
+    max_items: usize,
+    consts: Vec<(String, i32)>,
+    // 变量的当前值, 还没有初始化的是 None
+    vars: Vec<(String, Option<i32>)>,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            max_depth: 4,
+            max_items: 12,
+            consts: Vec::new(),
+            vars: Vec::new(),
+        }
+    }
+
+    /// 生成一个程序, 以及按 C 语义计算出的 main 的返回值
+    pub fn generate(&mut self) -> (CompUnit, i32) {
+        self.consts.clear();
+        self.vars.clear();
+        let mut items = Vec::new();
+        for _ in 0..self.rng.below(self.max_items) + 1 {
+            let assignable = self.vars.len();
+            items.push(match self.rng.below(3) {
+                0 => BlockItem::Decl(Decl::ConstDecl(self.const_decl())),
+                1 => BlockItem::Decl(Decl::VarDecl(self.var_decl())),
+                _ if assignable > 0 => BlockItem::Stmt(self.assign()),
+                _ => BlockItem::Decl(Decl::VarDecl(self.var_decl())),
+            });
+        }
+        let (exp, value) = self.exp(0, false);
+        items.push(BlockItem::Stmt(Stmt::Ret(exp)));
+        let unit = CompUnit {
+            func_def: FuncDef {
+                func_type: FuncType::Int,
+                ident: "main".to_owned(),
+                block: Block { items },
+            },
+        };
+        (unit, value)
+    }
+
+    /// 把程序写成源码, 随机加入 AST 中不保留的写法: 注释, 一元加号, 八进制和十六进制的整数.
+    /// 解析得到的 AST 应当与 unit 相同
+    pub fn render(&mut self, unit: &CompUnit) -> String {
+        let mut source = String::new();
+        for token in tokenize(&unit.to_string()).unwrap() {
+            let text = token.text;
+            if let Ok(num) = text.parse::<i32>() {
+                // 整数出现的位置都是 UnaryExp, 前面可以加一元加号
+                if self.rng.chance(20) {
+                    source += "+";
+                }
+                source += &match self.rng.below(4) {
+                    0 => format!("{num:#x}"),
+                    1 => format!("0X{num:X}"),
+                    2 if num != 0 => format!("0{num:o}"),
+                    _ => text,
+                };
+            } else {
+                source += &text;
+            }
+            // token 之间的空白, 偶尔换成注释
+            source += match self.rng.below(20) {
+                0 => " /* comment * / **/ ",
+                1 => " // comment\n",
+                _ => " ",
+            };
+        }
+        source
+    }
+
+    fn const_decl(&mut self) -> ConstDecl {
+        let mut defs = Vec::new();
+        for _ in 0..self.rng.below(3) + 1 {
+            let (exp, value) = self.exp(0, true);
+            let ident = format!("c{}", self.consts.len());
+            self.consts.push((ident.clone(), value));
+            defs.push(ConstDef {
+                ident,
+                val: ConstInitVal {
+                    exp: ConstExp { exp },
+                },
+                offset: None,
+            });
+        }
+        ConstDecl {
+            typ: BType::I32,
+            defs,
+        }
+    }
+
+    fn var_decl(&mut self) -> VarDecl {
+        let mut defs = Vec::new();
+        for _ in 0..self.rng.below(3) + 1 {
+            let ident = format!("v{}", self.vars.len());
+            if self.rng.chance(75) {
+                let (exp, value) = self.exp(0, false);
+                self.vars.push((ident.clone(), Some(value)));
+                defs.push(VarDef::Init(ident, InitVal { exp }, None));
+            } else {
+                self.vars.push((ident.clone(), None));
+                defs.push(VarDef::NoInit(ident, None));
+            }
+        }
+        VarDecl {
+            typ: BType::I32,
+            defs,
+        }
+    }
+
+    fn assign(&mut self) -> Stmt {
+        let index = self.rng.below(self.vars.len());
+        let (exp, value) = self.exp(0, false);
+        self.vars[index].1 = Some(value);
+        let lval = LVal {
+            ident: self.vars[index].0.clone(),
+            offset: None,
+        };
+        Stmt::Assign(lval, exp)
+    }
+
+    // 是否在这一层生成二元运算, 没有达到最大深度时概率为 percent%
+    fn grow(&mut self, depth: usize, percent: usize) -> bool {
+        depth < self.max_depth && self.rng.chance(percent)
+    }
+
+    // constant 为 true 时只能引用常量
+    fn exp(&mut self, depth: usize, constant: bool) -> (Exp, i32) {
+        let (l_or_exp, value) = self.l_or(depth, constant);
+        (Exp { l_or_exp }, value)
+    }
+
+    fn l_or(&mut self, depth: usize, constant: bool) -> (LOrExp, i32) {
+        if self.grow(depth, 10) {
+            let (lhs, a) = self.l_or(depth + 1, constant);
+            let (rhs, b) = self.l_and(depth + 1, constant);
+            (LOrExp::Or(Box::new(lhs), rhs), (a != 0 || b != 0) as i32)
+        } else {
+            let (exp, value) = self.l_and(depth, constant);
+            (LOrExp::LAndExp(exp), value)
+        }
+    }
+
+    fn l_and(&mut self, depth: usize, constant: bool) -> (LAndExp, i32) {
+        if self.grow(depth, 10) {
+            let (lhs, a) = self.l_and(depth + 1, constant);
+            let (rhs, b) = self.eq(depth + 1, constant);
+            (LAndExp::And(Box::new(lhs), rhs), (a != 0 && b != 0) as i32)
+        } else {
+            let (exp, value) = self.eq(depth, constant);
+            (LAndExp::EqExp(exp), value)
+        }
+    }
+
+    fn eq(&mut self, depth: usize, constant: bool) -> (EqExp, i32) {
+        if self.grow(depth, 10) {
+            let (lhs, a) = self.eq(depth + 1, constant);
+            let (rhs, b) = self.rel(depth + 1, constant);
+            let (sign, value) = match self.rng.below(2) {
+                0 => (EqSign::Eq, a == b),
+                _ => (EqSign::Neq, a != b),
+            };
+            (EqExp::Eq(Box::new(lhs), sign, rhs), value as i32)
+        } else {
+            let (exp, value) = self.rel(depth, constant);
+            (EqExp::RelExp(exp), value)
+        }
+    }
+
+    fn rel(&mut self, depth: usize, constant: bool) -> (RelExp, i32) {
+        if self.grow(depth, 15) {
+            let (lhs, a) = self.rel(depth + 1, constant);
+            let (rhs, b) = self.add(depth + 1, constant);
+            let (sign, value) = match self.rng.below(4) {
+                0 => (CmpSign::Less, a < b),
+                1 => (CmpSign::More, a > b),
+                2 => (CmpSign::Leq, a <= b),
+                _ => (CmpSign::Meq, a >= b),
+            };
+            (RelExp::Cmp(Box::new(lhs), sign, rhs), value as i32)
+        } else {
+            let (exp, value) = self.add(depth, constant);
+            (RelExp::AddExp(exp), value)
+        }
+    }
+
+    fn add(&mut self, depth: usize, constant: bool) -> (AddExp, i32) {
+        if self.grow(depth, 35) {
+            let (lhs, a) = self.add(depth + 1, constant);
+            let (rhs, b) = self.mul(depth + 1, constant);
+            // a + b 和 a - b 不会同时溢出
+            let (sign, value) = match (self.rng.below(2), a.checked_add(b), a.checked_sub(b)) {
+                (0, Some(value), _) | (_, Some(value), None) => (AddSign::Add, value),
+                (_, _, Some(value)) => (AddSign::Sub, value),
+                (_, None, None) => unreachable!(),
+            };
+            (AddExp::AddExp(Box::new(lhs), sign, rhs), value)
+        } else {
+            let (exp, value) = self.mul(depth, constant);
+            (AddExp::MulExp(exp), value)
+        }
+    }
+
+    fn mul(&mut self, depth: usize, constant: bool) -> (MulExp, i32) {
+        if !self.grow(depth, 30) {
+            let (exp, value) = self.unary(depth, constant);
+            return (MulExp::UnaryExp(exp), value);
+        }
+        let (lhs, a) = self.mul(depth + 1, constant);
+        let (rhs, b) = self.unary(depth + 1, constant);
+        // 从随机的位置开始依次尝试三种运算, 都没有定义时 (INT_MIN 与 -1) 把右边换成 1
+        let start = self.rng.below(3);
+        for i in 0..3 {
+            let (sign, value) = match (start + i) % 3 {
+                0 => (MulSign::Mul, a.checked_mul(b)),
+                1 => (MulSign::Div, a.checked_div(b)),
+                _ => (MulSign::Mod, a.checked_rem(b)),
+            };
+            if let Some(value) = value {
+                return (MulExp::MulExp(Box::new(lhs), sign, rhs), value);
+            }
+        }
+        let one = UnaryExp::PrimaryExp(PrimaryExp::Number(Number { num: 1 }));
+        (MulExp::MulExp(Box::new(lhs), MulSign::Mul, one), a)
+    }
+
+    fn unary(&mut self, depth: usize, constant: bool) -> (UnaryExp, i32) {
+        if depth < self.max_depth && self.rng.chance(25) {
+            let (exp, a) = self.unary(depth + 1, constant);
+            // INT_MIN 不能取负, 换成逻辑非
+            let (op, value) = match (self.rng.below(2), a.checked_neg()) {
+                (0, Some(value)) => (UnaryOp::Negative, value),
+                _ => (UnaryOp::Bang, (a == 0) as i32),
+            };
+            (UnaryExp::Unary(op, Box::new(exp)), value)
+        } else {
+            let (exp, value) = self.primary(depth, constant);
+            (UnaryExp::PrimaryExp(exp), value)
+        }
+    }
+
+    fn primary(&mut self, depth: usize, constant: bool) -> (PrimaryExp, i32) {
+        if depth < self.max_depth && self.rng.chance(15) {
+            let (exp, value) = self.exp(depth + 1, constant);
+            return (PrimaryExp::Exp(Box::new(exp)), value);
+        }
+        // 可以读取的常量和已经初始化的变量
+        let mut readable: Vec<(String, i32)> = self.consts.clone();
+        if !constant {
+            readable.extend(
+                self.vars
+                    .iter()
+                    .filter_map(|(ident, value)| Some((ident.clone(), (*value)?))),
+            );
+        }
+        if !readable.is_empty() && self.rng.chance(50) {
+            let (ident, value) = readable.swap_remove(self.rng.below(readable.len()));
+            return (
+                PrimaryExp::LVal(LVal {
+                    ident,
+                    offset: None,
+                }),
+                value,
+            );
+        }
+        // 多数是小整数, 偶尔是容易溢出的大数
+        let num = match self.rng.below(10) {
+            0..=5 => self.rng.below(10) as i32,
+            6..=8 => self.rng.below(1000) as i32,
+            _ => [i32::MAX, 1 << 30, 65536, 46341, 0x7fff][self.rng.below(5)],
+        };
+        (PrimaryExp::Number(Number { num }), num)
+    }
+}
+
+// 解析 render 得到的源码, 应当得到与 unit 相同的程序
+fn check_parse(source: &str, unit: &CompUnit) -> Option<Failure> {
+    match catch(|| parse(source).map_err(|err| err.to_string().trim_end().to_owned())) {
+        Ok(parsed) if parsed.to_string() == unit.to_string() => None,
+        Ok(parsed) => Some(("parse: different program".to_owned(), parsed.to_string())),
+        Err(err) => {
+            let line = err.lines().next().unwrap_or_default().to_owned();
+            Some((format!("parse: {line}"), err))
+        }
+    }
+}
+
+// 比较一次运行的输出 (与 .out 文件的格式相同) 和期望的输出
+fn check(expected: &[u8], stage: &str, result: Result<Vec<u8>, String>) -> Option<Failure> {
+    match result {
+        Ok(actual) if actual == expected => None,
+        Ok(actual) => Some((
+            format!("{stage}: wrong result"),
+            format!(
+                "expected {:?}, got {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(&actual)
+            ),
+        )),
+        // 同一处 panic 的信息第一行相同, 用来去重
+        Err(err) => {
+            let line = err.lines().next().unwrap_or_default().to_owned();
+            Some((format!("{stage}: {line}"), err))
+        }
+    }
+}
+
+// 与命令行的 -O<level> 相同的编译选项
+fn options(level: u8, emit_asm: bool) -> Options {
+    Options {
+        peephole: match level {
+            0 => PeepholeConfig::none(),
+            _ => PeepholeConfig::default(),
+        },
+        passes: preset(level),
+        emit_asm,
+        ..Options::default()
+    }
+}
+
+/// 以 AST 解释器的结果为准, 检查前端、Koopa 解释器 (优化前和 -O2) 和 RISC-V 模拟器 (-O0 到 -O2).
+/// 返回期望的输出和所有的失败, 程序本身在 AST 解释器中出错时返回错误.
+/// 编译器中的 panic 会被捕获, 调用者需要自己关掉默认的 panic 信息
+pub fn failures(unit: &CompUnit) -> Result<(Vec<u8>, Vec<Failure>), String> {
+    let mut interpreter = AstInterpreter::new();
+    interpreter.runtime().set_input(Vec::new());
+    let code = interpreter.run(unit)?;
+    let expected = format_output(interpreter.runtime().stdout(), code);
+
+    let source = unit.to_string();
+    let compile = |options: &Options| {
+        compile(&source, options).map_err(|err| err.to_string().trim_end().to_owned())
+    };
+    let program = match compile(&options(0, false)) {
+        Ok(artifacts) => artifacts.program,
+        Err(err) => {
+            let line = err.lines().next().unwrap_or_default().to_owned();
+            return Ok((expected, vec![(format!("frontend: {line}"), err)]));
+        }
+    };
+    let interpret = |program: &Program| {
+        let mut interpreter = KoopaInterpreter::new(program);
+        interpreter.runtime().set_input(Vec::new());
+        let code = interpreter.run(Some(STEP_LIMIT))?;
+        Ok(format_output(interpreter.runtime().stdout(), code))
+    };
+    let mut failures = Vec::new();
+    failures.extend(check(&expected, "koopa", catch(|| interpret(&program))));
+    let opt_out =
+        compile(&options(2, false)).and_then(|artifacts| catch(|| interpret(&artifacts.program)));
+    failures.extend(check(&expected, "opt", opt_out));
+    // 后端分别处理优化前 (全部在内存中) 和优化后 (基本块参数) 的 Koopa IR
+    for level in 0..=2 {
+        let riscv_out = compile(&options(level, true)).and_then(|artifacts| {
+            catch(|| {
+                let mut emulator = Emulator::from_asm(artifacts.asm.as_ref().unwrap())?;
+                emulator.set_input(Vec::new());
+                let code = emulator.run(Some(STEP_LIMIT))?;
+                Ok(format_output(emulator.stdout(), code))
+            })
+        });
+        failures.extend(check(&expected, &format!("riscv -O{level}"), riscv_out));
+    }
+    Ok((expected, failures))
+}
+
+/// 从种子 seed 开始生成 count 个程序 (0 表示不停止), 分别用 AST 解释器、Koopa 解释器和 RISC-V 模拟器运行.
+/// 每种新的失败把程序和期望的输出写到 dir 下, 可以直接用 -test 模式重现. 返回最后的汇总
+pub fn fuzz(seed: u64, count: u64, dir: &str) -> String {
+    create_dir_all(dir).unwrap();
+    let hook = take_hook();
+    set_hook(Box::new(|_| {}));
+    let mut signatures = HashSet::new();
+    let mut total = 0;
+    let mut programs = 0;
+    while count == 0 || programs < count {
+        let seed = seed.wrapping_add(programs);
+        programs += 1;
+        let mut generator = Generator::new(seed);
+        let (unit, value) = generator.generate();
+        let source = generator.render(&unit);
+        // 生成器按 C 语义算出的值与 AST 解释器应当一致, 不一致时作为失败报告, 以 AST 解释器为准
+        let generated = format_output(&[], value & 0xff);
+        let (expected, mut failures) = match failures(&unit) {
+            Ok(result) => result,
+            Err(err) => {
+                let line = err.lines().next().unwrap_or_default().to_owned();
+                (generated.clone(), vec![(format!("ast: {line}"), err)])
+            }
+        };
+        if expected != generated {
+            failures.push((
+                "generator: disagrees with the AST interpreter".to_owned(),
+                format!(
+                    "generator expected {:?}, the AST interpreter got {:?}",
+                    String::from_utf8_lossy(&generated),
+                    String::from_utf8_lossy(&expected)
+                ),
+            ));
+        }
+        failures.extend(check_parse(&source, &unit));
+        for failure in failures {
+            report(dir, seed, &source, &expected, failure, &mut signatures);
+            total += 1;
+        }
+        if programs.is_multiple_of(1000) {
+            eprintln!("{programs} programs, {total} failures");
+        }
+    }
+    set_hook(hook);
+    format!(
+        "{programs} programs, {total} failures, {} distinct\n",
+        signatures.len()
+    )
+}
+
+// 第一次遇到的失败保存为用例
+fn report(
+    dir: &str,
+    seed: u64,
+    source: &str,
+    expected: &[u8],
+    (signature, detail): Failure,
+    signatures: &mut HashSet<String>,
+) {
+    if !signatures.insert(signature.clone()) {
+        return;
+    }
+    let path = Path::new(dir).join(format!("fuzz_{seed}.sy"));
+    write(&path, source).unwrap();
+    write(path.with_extension("out"), expected).unwrap();
+    println!("seed {seed}: {signature}");
+    println!("  {}", detail.lines().next().unwrap_or_default());
+    println!("  saved to {}", path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_covers_lexical_forms() {
+        // 源码中出现 AST 不保留的每种写法, 解析后仍然是同一个程序
+        let mut sources = String::new();
+        for seed in 0..20 {
+            let mut generator = Generator::new(seed);
+            let (unit, _) = generator.generate();
+            let source = generator.render(&unit);
+            assert_eq!(check_parse(&source, &unit), None, "{source}");
+            sources += &source;
+        }
+        for form in ["/*", "//", "0x", "0X"] {
+            assert!(sources.contains(form), "{form}");
+        }
+        // 一元加号紧跟着整数, 二元加号之后总有空白
+        assert!(sources
+            .as_bytes()
+            .windows(2)
+            .any(|pair| pair[0] == b'+' && pair[1].is_ascii_digit()));
+        // 以 0 开头的八进制整数
+        assert!(sources
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word.len() > 1
+                && word.starts_with('0')
+                && word.bytes().all(|b| b.is_ascii_digit())));
+    }
+}
@@ -1,29 +1,58 @@
+use std::collections::{BTreeSet, HashMap};
 use std::io::Write;
-use std::{collections::HashMap, sync::atomic::AtomicUsize};
-
+use std::rc::Rc;
+
+pub use self::isa::Isa;
+use self::isa::{Addr, Data, Frame};
+pub use self::peephole::{PeepholeConfig, PeepholeStats};
+use self::regalloc::{Allocation, Location};
+pub use self::riscv::{Riscv, Target};
+pub use self::x86::X86_64;
 use koopa::ir::{
     entities::ValueData,
     layout::BasicBlockNode,
-    values::{Binary, Integer, Return},
-    BinaryOp, Function, FunctionData, Program, Value, ValueKind,
+    values::{Binary, Branch, Call, GetElemPtr, GetPtr, Jump, Load, Return, Store},
+    BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind,
 };
 
-static RIG_ID: AtomicUsize = AtomicUsize::new(0);
-static RIG_NAME: [&str; 15] = [
-    "t0", "t1", "t2", "t3", "t4", "t5", "t6", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
-];
+mod branch_relax;
+mod isa;
+mod peephole;
+mod regalloc;
+mod riscv;
+mod soft_muldiv;
+mod x86;
+
+// 并行移动的目的地和来源: 寄存器或相对于 sp 的栈上位置
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Place {
+    Reg(&'static str),
+    Stack(usize),
+}
 
-fn gen_rig_name() -> String {
-    let id = RIG_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    format!("{}", RIG_NAME[id])
+// 常数和地址不占用位置, 在其余移动完成之后再装入
+enum Src {
+    Place(Place),
+    Value(Value),
 }
 
 #[derive(Clone)]
 pub struct ProgramInfo<'p> {
     program: &'p Program,
     which_func: Option<Function>,
-    values: HashMap<Value, String>,
     cur_value: Option<Value>,
+    peephole: PeepholeConfig,
+    peephole_stats: PeepholeStats,
+    isa: Rc<dyn Isa>,
+    // 没有乘除法指令时用到的例程, 放在所有函数之后
+    soft_routines: BTreeSet<&'static str>,
+    // 当前函数的寄存器分配结果和栈帧
+    alloc: Option<Allocation>,
+    frame: Option<Frame>,
+    // 当前函数中每个 alloc 在栈帧中的偏移
+    allocs: HashMap<Value, usize>,
+    // 带块参数的 br 的真分支需要一个中转标号, 在整个程序中编号
+    edge_id: usize,
 }
 
 impl<'p> ProgramInfo<'p> {
@@ -31,11 +60,30 @@ impl<'p> ProgramInfo<'p> {
         Self {
             program,
             which_func,
-            values: HashMap::new(),
             cur_value: None,
+            peephole: PeepholeConfig::default(),
+            peephole_stats: PeepholeStats::default(),
+            isa: Rc::new(Riscv::default()),
+            soft_routines: BTreeSet::new(),
+            alloc: None,
+            frame: None,
+            allocs: HashMap::new(),
+            edge_id: 0,
         }
     }
 
+    pub fn set_isa(&mut self, isa: impl Isa + 'static) {
+        self.isa = Rc::new(isa);
+    }
+
+    pub fn set_peephole(&mut self, config: PeepholeConfig) {
+        self.peephole = config;
+    }
+
+    pub fn peephole_stats(&self) -> PeepholeStats {
+        self.peephole_stats
+    }
+
     fn get_key(&self) -> Value {
         self.cur_value.unwrap()
     }
@@ -44,40 +92,299 @@ impl<'p> ProgramInfo<'p> {
         self.cur_value = Some(key);
     }
 
-    fn get_data(&self, value: Value) -> &ValueData {
+    fn get_data(&self, value: Value) -> &'p ValueData {
+        self.get_func_data().dfg().value(value)
+    }
+
+    fn get_func_data(&self) -> &'p FunctionData {
         assert_ne!(self.which_func, None);
-        self.program
-            .func(self.which_func.unwrap())
-            .dfg()
-            .value(value)
+        self.program.func(self.which_func.unwrap())
+    }
+
+    // 基本块对应的汇编标号.
+    // 没有名字的基本块按它在布局中的位置命名, Koopa IR 的名字中不会出现 '.', 不会与有名字的基本块重复
+    fn bb_label(&self, bb: BasicBlock) -> String {
+        let func_data = self.get_func_data();
+        let name = match func_data.dfg().bb(bb).name() {
+            Some(name) => name[1..].to_owned(),
+            None => {
+                let layout = func_data.layout().bbs();
+                let index = layout.keys().position(|&b| b == bb).unwrap();
+                format!("anon.{index}")
+            }
+        };
+        self.isa.local_label(&func_data.name()[1..], &name)
+    }
+
+    fn gen_edge_label(&mut self) -> String {
+        let id = self.edge_id;
+        self.edge_id += 1;
+        let func = &self.get_func_data().name()[1..];
+        self.isa.local_label(func, &format!("edge.{id}"))
     }
 
     fn set_func(&mut self, func: Function) {
         self.which_func = Some(func);
     }
 
-    fn add_value(&mut self, key: Value, name: String) {
-        self.values.insert(key, name);
+    fn frame(&self) -> &Frame {
+        self.frame.as_ref().unwrap()
+    }
+
+    fn is_fused(&self, value: Value) -> bool {
+        self.alloc.as_ref().unwrap().fused.contains(&value)
+    }
+
+    fn location(&self, value: Value) -> Location {
+        self.alloc.as_ref().unwrap().locations[&value]
+    }
+
+    fn slot_offset(&self, n: usize) -> usize {
+        self.frame().locals + n * self.isa.word_size()
+    }
+
+    // value 作为并行移动的来源
+    fn src_of(&self, value: Value) -> Src {
+        match self.get_func_data().dfg().values().get(&value).map(|data| data.kind()) {
+            Some(ValueKind::Integer(_) | ValueKind::Alloc(_)) | None => Src::Value(value),
+            _ => Src::Place(self.place_of(value)),
+        }
+    }
+
+    // 全局变量的符号名, value 不是全局变量时返回 None
+    fn global_name(&self, value: Value) -> Option<String> {
+        if self.get_func_data().dfg().values().contains_key(&value) {
+            return None;
+        }
+        let data = self.program.borrow_value(value);
+        Some(data.name().as_ref().unwrap()[1..].to_owned())
+    }
+
+    fn value_type(&self, value: Value) -> Type {
+        match self.get_func_data().dfg().values().get(&value) {
+            Some(data) => data.ty().clone(),
+            None => self.program.borrow_value(value).ty().clone(),
+        }
+    }
+
+    // 类型的字节数, 指针与寄存器一样宽
+    fn type_size(&self, ty: &Type) -> usize {
+        match ty.kind() {
+            TypeKind::Int32 => 4,
+            TypeKind::Unit => 0,
+            TypeKind::Array(base, len) => self.type_size(base) * len,
+            TypeKind::Pointer(_) | TypeKind::Function(..) => self.isa.word_size(),
+        }
+    }
+
+    // 指针 pointer 指向的值的字节数
+    fn pointee_size(&self, pointer: Value) -> usize {
+        match self.value_type(pointer).kind() {
+            TypeKind::Pointer(base) => self.type_size(base),
+            _ => unreachable!(),
+        }
+    }
+
+    // 把全局变量的初始值展开成数据段中的若干项
+    fn global_init(&self, init: Value, data: &mut Vec<Data>) {
+        let value = self.program.borrow_value(init);
+        match value.kind() {
+            ValueKind::Integer(int) => data.push(Data::Word(int.value())),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+                data.push(Data::Zero(self.type_size(value.ty())))
+            }
+            ValueKind::Aggregate(agg) => {
+                for &elem in agg.elems() {
+                    self.global_init(elem, data);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // 用指针 pointer 表示的地址访存. alloc 和全局变量直接寻址, 其余指针先读入寄存器, 必要时用 scratch
+    fn access(
+        &self,
+        f: &mut Vec<u8>,
+        pointer: Value,
+        scratch: &'static str,
+        op: impl FnOnce(&mut Vec<u8>, Addr),
+    ) {
+        if let Some(name) = self.global_name(pointer) {
+            op(f, Addr::Global(&name));
+        } else if let Some(&offset) = self.allocs.get(&pointer) {
+            op(f, Addr::Stack(offset));
+        } else {
+            let reg = self.read(f, pointer, scratch);
+            op(f, Addr::Reg(reg));
+        }
+    }
+
+    fn place_of(&self, value: Value) -> Place {
+        match self.location(value) {
+            Location::Reg(reg) => Place::Reg(reg),
+            Location::Slot(n) => Place::Stack(self.slot_offset(n)),
+        }
+    }
+
+    // 取得 value 所在的寄存器, 常数, 地址和溢出的值先装入 scratch
+    fn read(&self, f: &mut Vec<u8>, value: Value, scratch: &'static str) -> &'static str {
+        if let Some(name) = self.global_name(value) {
+            self.isa.address(f, scratch, Addr::Global(&name));
+            return scratch;
+        }
+        if let Some(&offset) = self.allocs.get(&value) {
+            self.isa.address(f, scratch, Addr::Stack(offset));
+            return scratch;
+        }
+        if let ValueKind::Integer(int) = self.get_data(value).kind() {
+            if let (0, Some(zero)) = (int.value(), self.isa.zero_reg()) {
+                return zero;
+            }
+            self.isa.load_imm(f, scratch, int.value());
+            return scratch;
+        }
+        match self.location(value) {
+            Location::Reg(reg) => reg,
+            Location::Slot(n) => {
+                self.isa.load_stack(f, scratch, self.slot_offset(n));
+                scratch
+            }
+        }
+    }
+
+    // 计算 value 时写入的寄存器, 溢出的值先写入 scratch 再由 write_back 存回
+    fn dest(&self, value: Value, scratch: &'static str) -> &'static str {
+        match self.location(value) {
+            Location::Reg(reg) => reg,
+            Location::Slot(_) => scratch,
+        }
+    }
+
+    fn write_back(&self, f: &mut Vec<u8>, value: Value, reg: &'static str) {
+        match self.location(value) {
+            Location::Reg(dst) => {
+                if dst != reg {
+                    self.isa.mov(f, dst, reg);
+                }
+            }
+            Location::Slot(n) => self.isa.store_stack(f, reg, self.slot_offset(n)),
+        }
     }
 
-    fn query_value(&self, key: Value) -> Option<&String> {
-        self.values.get(&key)
+    fn move_place(&self, f: &mut Vec<u8>, dst: Place, src: Place) {
+        match (dst, src) {
+            (Place::Reg(dst), Place::Reg(src)) => self.isa.mov(f, dst, src),
+            (Place::Reg(dst), Place::Stack(src)) => self.isa.load_stack(f, dst, src),
+            (Place::Stack(dst), Place::Reg(src)) => self.isa.store_stack(f, src, dst),
+            (Place::Stack(dst), Place::Stack(src)) => {
+                let tmp = self.isa.scratch()[0];
+                self.isa.load_stack(f, tmp, src);
+                self.isa.store_stack(f, tmp, dst);
+            }
+        }
+    }
+
+    // 同时完成所有移动, 每个目的地只出现一次.
+    // 先做目的地不再被读取的移动; 只剩环时把环中一个目的地的旧值存入 scratch[1], 打开这个环
+    fn parallel_move(&self, f: &mut Vec<u8>, moves: Vec<(Place, Src)>) {
+        let mut pending = Vec::new();
+        let mut values = Vec::new();
+        for (dst, src) in moves {
+            match src {
+                Src::Place(src) if src != dst => pending.push((dst, src)),
+                Src::Place(_) => {}
+                Src::Value(value) => values.push((dst, value)),
+            }
+        }
+        let tmp = Place::Reg(self.isa.scratch()[1]);
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .position(|&(dst, _)| pending.iter().all(|&(_, src)| src != dst));
+            match ready {
+                Some(i) => {
+                    let (dst, src) = pending.remove(i);
+                    self.move_place(f, dst, src);
+                }
+                None => {
+                    let blocked = pending[0].0;
+                    self.move_place(f, tmp, blocked);
+                    for (_, src) in pending.iter_mut() {
+                        if *src == blocked {
+                            *src = tmp;
+                        }
+                    }
+                }
+            }
+        }
+        for (dst, value) in values {
+            match dst {
+                Place::Reg(reg) => {
+                    let src = self.read(f, value, reg);
+                    if src != reg {
+                        self.isa.mov(f, reg, src);
+                    }
+                }
+                Place::Stack(offset) => {
+                    let src = self.read(f, value, self.isa.scratch()[0]);
+                    self.isa.store_stack(f, src, offset);
+                }
+            }
+        }
+    }
+
+    // 跳转到 bb 之前把 args 传给它的块参数
+    fn pass_block_args(&self, f: &mut Vec<u8>, bb: BasicBlock, args: &[Value]) {
+        let params = self.get_func_data().dfg().bb(bb).params();
+        let moves = params
+            .iter()
+            .zip(args)
+            .map(|(&param, &arg)| (self.place_of(param), self.src_of(arg)))
+            .collect();
+        self.parallel_move(f, moves);
+    }
+
+    // 第 i 个参数在调用处的位置, offset 是栈上的第一个参数相对于 sp 的偏移
+    fn arg_place(&self, i: usize, offset: usize) -> Place {
+        let regs = self.isa.arg_regs();
+        match regs.get(i) {
+            Some(&reg) => Place::Reg(reg),
+            None => Place::Stack(offset + (i - regs.len()) * self.isa.word_size()),
+        }
     }
 }
+
 pub trait GenerateAsm {
     fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String>;
 }
 
 impl GenerateAsm for Program {
     fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
-        writeln!(f, "    .text").unwrap(); // 声明之后的数据需要被放入代码段中
+        branch_relax::reset_relax_labels();
+
+        // 全局变量放在数据段中
+        for &global in self.inst_layout() {
+            let data = self.borrow_value(global);
+            let init = match data.kind() {
+                ValueKind::GlobalAlloc(alloc) => alloc.init(),
+                _ => unreachable!(),
+            };
+            let mut items = Vec::new();
+            info.global_init(init, &mut items);
+            info.isa.global_data(f, &data.name().as_ref().unwrap()[1..], &items);
+        }
+
+        info.isa.text_section(f); // 声明之后的数据需要被放入代码段中
 
         // 声明全局符号
-        // 遍历所有的指向函数的指针
+        // 遍历所有的指向函数的指针, 只有声明的库函数由链接器提供
         for &func in self.func_layout() {
             // 从指向函数的指针来获得函数本身
             let func_data = self.func(func);
-            writeln!(f, "    .globl {}", &func_data.name()[1..]).unwrap();
+            if func_data.layout().entry_bb().is_some() {
+                info.isa.global_symbol(f, &func_data.name()[1..]);
+            }
         }
 
         for &func in self.func_layout() {
@@ -85,19 +392,79 @@ impl GenerateAsm for Program {
             info.set_func(func);
             func_data.generate(info, f);
         }
+
+        // 用到的乘除法例程放在所有函数之后
+        for name in &info.soft_routines {
+            let soft = info.isa.soft_muldiv().unwrap();
+            f.write_all(soft.routine(name).as_bytes()).unwrap();
+        }
         None
     }
 }
 
 impl GenerateAsm for FunctionData {
     fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
-        writeln!(f, "{}:", &self.name()[1..]).unwrap();
+        // 库函数只有声明, 没有基本块
+        let entry = self.layout().entry_bb()?;
+        info.isa.label(f, &self.name()[1..]);
+
+        // 先分配寄存器, 才知道栈帧的大小和需要保存的寄存器.
+        // 局部变量放在溢出槽位之后, 各自按 word_size 对齐
+        let alloc = regalloc::allocate(self, info.isa.as_ref());
+        let word = info.isa.word_size();
+        let mut allocs = Vec::new();
+        let mut locals = alloc.slots * word;
+        for (_, node) in self.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Alloc(_) = self.dfg().value(inst).kind() {
+                    allocs.push((inst, locals));
+                    locals += info.pointee_size(inst).next_multiple_of(word);
+                }
+            }
+        }
+        let frame = info
+            .isa
+            .frame(alloc.stack_args * word, locals, &alloc.saved);
+        info.allocs = allocs
+            .into_iter()
+            .map(|(inst, offset)| (inst, frame.locals + offset))
+            .collect();
+        let mut body = info.isa.prologue(&frame).into_bytes();
+        info.alloc = Some(alloc);
+        info.frame = Some(frame);
+
+        // 参数从调用约定规定的位置移到分配的位置
+        let incoming = info.frame().incoming;
+        let moves = self
+            .params()
+            .iter()
+            .enumerate()
+            .map(|(i, &param)| {
+                (
+                    info.place_of(param),
+                    Src::Place(info.arg_place(i, incoming)),
+                )
+            })
+            .collect();
+        info.parallel_move(&mut body, moves);
 
         // 遍历函数，查看函数内部的基本块
-        for (&_bb, node) in self.layout().bbs() {
+        for (&bb, node) in self.layout().bbs() {
+            // 入口基本块紧接在序言之后, 只有被跳转到时才需要标号
+            if bb != entry || !self.dfg().bb(bb).used_by().is_empty() {
+                info.isa.label(&mut body, &info.bb_label(bb));
+            }
             // 生成基本块的信息
-            node.generate(info, f);
+            node.generate(info, &mut body);
         }
+
+        let config = info.peephole;
+        let body = info.isa.finish_function(
+            &String::from_utf8(body).unwrap(),
+            &config,
+            &mut info.peephole_stats,
+        );
+        f.write_all(body.as_bytes()).unwrap();
         None
     }
 }
@@ -106,8 +473,12 @@ impl GenerateAsm for BasicBlockNode {
     fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
         // 遍历基本块里的指令(value)的指针
         for &inst in self.insts().keys() {
+            // 只被 br 使用的比较指令交给 br 生成
+            if info.is_fused(inst) {
+                continue;
+            }
             // 获取指令
-            let value_data = info.get_data(inst).clone();
+            let value_data = info.get_data(inst);
             // 处理指令
             info.set_key(inst);
             value_data.generate(info, f);
@@ -119,265 +490,230 @@ impl GenerateAsm for BasicBlockNode {
 impl GenerateAsm for ValueData {
     fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
         match self.kind() {
-            ValueKind::Integer(int) => int.generate(info, f),
             ValueKind::Return(ret) => ret.generate(info, f),
             ValueKind::Binary(bin) => bin.generate(info, f),
+            ValueKind::Branch(br) => br.generate(info, f),
+            ValueKind::Jump(jump) => jump.generate(info, f),
+            ValueKind::Call(call) => call.generate(info, f),
+            ValueKind::Load(load) => load.generate(info, f),
+            ValueKind::Store(store) => store.generate(info, f),
+            ValueKind::GetElemPtr(gep) => gep.generate(info, f),
+            ValueKind::GetPtr(gp) => gp.generate(info, f),
+            // 栈帧中的位置已经在生成函数时分配
+            ValueKind::Alloc(_) => None,
             // 其他
             _ => unreachable!(),
         }
     }
 }
 
-impl GenerateAsm for Integer {
-    fn generate(&self, _info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
-        // 处理 integer 指令
-        let val = self.value();
-        if val == 0 {
-            return Some("x0".to_owned());
-        }
-        let output = gen_rig_name();
-        writeln!(f, "    li {output}, {val}").unwrap();
-        Some(output)
-    }
-}
-
 impl GenerateAsm for Return {
     fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
         // 处理 ret 指令
-        if self.value() != None {
-            let tmp = info.get_key();
-            info.set_key(self.value().unwrap());
-            let ret = info
-                .get_data(self.value().unwrap())
-                .clone()
-                .generate(info, f);
-            writeln!(f, "    mv a0, {}", ret.unwrap()).unwrap();
-            info.set_key(tmp);
-        }
-        writeln!(f, "    ret").unwrap();
+        if let Some(value) = self.value() {
+            let moves = vec![(Place::Reg(info.isa.ret_reg()), info.src_of(value))];
+            info.parallel_move(f, moves);
+        }
+        f.write_all(info.isa.epilogue(info.frame()).as_bytes()).unwrap();
+        info.isa.ret(f);
         None
     }
 }
 
-fn get_output_for_binary(
-    bin: &Binary,
-    info: &mut ProgramInfo,
-    lhs: &String,
-    rhs: &String,
-) -> String {
-    let output: String;
-    if lhs[0..=0] != "x".to_owned() && rhs[0..=0] != "x".to_owned() {
-        if let ValueKind::Integer(_) = info.get_data(bin.lhs()).kind() {
-            output = lhs.clone();
-        } else if let ValueKind::Integer(_) = info.get_data(bin.rhs()).kind() {
-            output = rhs.clone();
+impl GenerateAsm for Branch {
+    fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
+        // 处理 br 指令
+        let true_label = info.bb_label(self.true_bb());
+        let false_label = info.bb_label(self.false_bb());
+        // 真分支有块参数时先跳到中转标号, 在那里传参
+        let target = if self.true_args().is_empty() {
+            true_label.clone()
         } else {
-            output = gen_rig_name();
-        }
-    } else {
-        if lhs[0..=0] != "x".to_owned() || rhs[0..=0] != "x".to_owned() {
-            if lhs[0..=0] != "x".to_owned() {
-                if let ValueKind::Integer(_) = info.get_data(bin.lhs()).kind() {
-                    output = lhs.clone();
-                } else {
-                    output = gen_rig_name();
-                }
-            } else {
-                if let ValueKind::Integer(_) = info.get_data(bin.rhs()).kind() {
-                    output = rhs.clone();
-                } else {
-                    output = gen_rig_name();
-                }
-            }
+            info.gen_edge_label()
+        };
+        let [s0, s1] = info.isa.scratch();
+        if info.is_fused(self.cond()) {
+            // 比较和跳转合并成一条指令
+            let cmp = match info.get_data(self.cond()).kind() {
+                ValueKind::Binary(cmp) => cmp,
+                _ => unreachable!(),
+            };
+            let lhs = info.read(f, cmp.lhs(), s0);
+            let rhs = info.read(f, cmp.rhs(), s1);
+            info.isa.branch_cmp(f, cmp.op(), lhs, rhs, &target);
         } else {
-            output = gen_rig_name();
+            let cond = info.read(f, self.cond(), s0);
+            info.isa.branch_nonzero(f, cond, &target);
         }
+        info.pass_block_args(f, self.false_bb(), self.false_args());
+        info.isa.jump(f, &false_label);
+        if !self.true_args().is_empty() {
+            info.isa.label(f, &target);
+            info.pass_block_args(f, self.true_bb(), self.true_args());
+            info.isa.jump(f, &true_label);
+        }
+        None
     }
-    output
 }
 
-impl GenerateAsm for Binary {
+impl GenerateAsm for Jump {
     fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
-        let find = info.query_value(info.get_key());
-        if find != None {
-            return Some(find.unwrap().clone());
-        }
-        let tmp = info.get_key();
-        match self.op() {
-            BinaryOp::Eq => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    xor {output}, {lhs}, {rhs}").unwrap();
-                writeln!(f, "    seqz {output}, {output}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
-
-            BinaryOp::Sub => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    sub {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
-
-            BinaryOp::Add => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    add {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
-
-            BinaryOp::Div => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    div {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
+        // 处理 jump 指令
+        info.pass_block_args(f, self.target(), self.args());
+        info.isa.jump(f, &info.bb_label(self.target()));
+        None
+    }
+}
 
-            BinaryOp::Mul => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    mul {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
+impl GenerateAsm for Call {
+    fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
+        // 前几个参数放入参数寄存器, 其余的从 sp 开始依次放在栈上
+        let moves = self
+            .args()
+            .iter()
+            .enumerate()
+            .map(|(i, &arg)| (info.arg_place(i, 0), info.src_of(arg)))
+            .collect();
+        info.parallel_move(f, moves);
+        let callee = info.program.func(self.callee());
+        info.isa.call(f, &callee.name()[1..]);
+        let value = info.get_key();
+        if !info.get_data(value).ty().is_unit() {
+            info.write_back(f, value, info.isa.ret_reg());
+        }
+        None
+    }
+}
 
-            BinaryOp::Mod => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    rem {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
+fn get_integer(info: &ProgramInfo, value: Value) -> Option<i32> {
+    match info.get_data(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
 
-            BinaryOp::Le => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    sgt {output}, {lhs}, {rhs}").unwrap();
-                writeln!(f, "    seqz {output}, {output}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
+// 没有乘除法指令时, 乘常数用 SoftMulDiv::mul_by_const, 其余乘除法调用例程.
+// 结果不能与操作数共用临时寄存器, 溢出时先写入 scratch[1]
+fn generate_soft_muldiv(bin: &Binary, info: &mut ProgramInfo, f: &mut Vec<u8>) {
+    let value = info.get_key();
+    let [s0, s1] = info.isa.scratch();
+    let output = info.dest(value, s1);
+    if bin.op() == BinaryOp::Mul {
+        let (x, c) = match (get_integer(info, bin.lhs()), get_integer(info, bin.rhs())) {
+            (Some(lhs), Some(rhs)) => {
+                info.isa.load_imm(f, output, lhs.wrapping_mul(rhs));
+                info.write_back(f, value, output);
+                return;
             }
+            (Some(c), None) => (bin.rhs(), Some(c)),
+            (None, Some(c)) => (bin.lhs(), Some(c)),
+            (None, None) => (bin.lhs(), None),
+        };
+        if let Some(c) = c {
+            let x = info.read(f, x, s0);
+            let soft = info.isa.soft_muldiv().unwrap();
+            soft.mul_by_const(f, output, x, c);
+            info.write_back(f, value, output);
+            return;
+        }
+    }
 
-            BinaryOp::Ge => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    slt {output}, {lhs}, {rhs}").unwrap();
-                writeln!(f, "    seqz {output}, {output}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
+    let lhs = info.read(f, bin.lhs(), s0);
+    let rhs = info.read(f, bin.rhs(), s1);
+    let soft = info.isa.soft_muldiv().unwrap();
+    let name = soft.call_routine(f, bin.op(), output, lhs, rhs);
+    info.soft_routines.insert(name);
+    info.write_back(f, value, output);
+}
 
-            BinaryOp::And => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    and {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
+impl GenerateAsm for Binary {
+    fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
+        match self.op() {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod if info.isa.soft_muldiv().is_some() => {
+                generate_soft_muldiv(self, info, f)
             }
-
-            BinaryOp::Gt => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    sgt {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
+            op => {
+                let value = info.get_key();
+                let [s0, s1] = info.isa.scratch();
+                let lhs = info.read(f, self.lhs(), s0);
+                let rhs = info.read(f, self.rhs(), s1);
+                let output = info.dest(value, s0);
+                info.isa.binary(f, op, output, lhs, rhs);
+                info.write_back(f, value, output);
             }
+        }
+        None
+    }
+}
 
-            BinaryOp::Lt => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    slt {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
+impl GenerateAsm for Load {
+    fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
+        let value = info.get_key();
+        let scratch = info.isa.scratch()[0];
+        let dst = info.dest(value, scratch);
+        let size = info.pointee_size(self.src());
+        info.access(f, self.src(), scratch, |f, addr| {
+            info.isa.load(f, dst, addr, size)
+        });
+        info.write_back(f, value, dst);
+        None
+    }
+}
 
-            BinaryOp::Or => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    or {output}, {lhs}, {rhs}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
+impl GenerateAsm for Store {
+    fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
+        let [s0, s1] = info.isa.scratch();
+        let src = info.read(f, self.value(), s0);
+        let size = info.pointee_size(self.dest());
+        info.access(f, self.dest(), s1, |f, addr| {
+            info.isa.store(f, src, addr, size)
+        });
+        None
+    }
+}
 
-            BinaryOp::NotEq => {
-                info.set_key(self.lhs());
-                let lhs = info.get_data(self.lhs()).clone().generate(info, f).unwrap();
-                info.set_key(self.rhs());
-                let rhs = info.get_data(self.rhs()).clone().generate(info, f).unwrap();
-                let output = get_output_for_binary(self, info, &lhs, &rhs);
-                writeln!(f, "    xor {output}, {lhs}, {rhs}").unwrap();
-                writeln!(f, "    snez {output}, {output}").unwrap();
-                // 表明当前value已经处理过了
-                info.set_key(tmp);
-                info.add_value(info.get_key(), output.clone());
-                Some(output)
-            }
+// dst = src + index * scale, 常数下标直接算出偏移
+fn generate_ptr_offset(
+    info: &mut ProgramInfo,
+    f: &mut Vec<u8>,
+    src: Value,
+    index: Value,
+    scale: usize,
+) {
+    let value = info.get_key();
+    let [s0, s1] = info.isa.scratch();
+    let base = info.read(f, src, s0);
+    let dst = info.dest(value, s0);
+    match get_integer(info, index) {
+        Some(index) => info.isa.add_ptr_imm(f, dst, base, index as i64 * scale as i64),
+        None => {
+            let index = info.read(f, index, s1);
+            info.isa.add_ptr_scaled(f, dst, base, index, scale);
+        }
+    }
+    info.write_back(f, value, dst);
+}
 
+impl GenerateAsm for GetElemPtr {
+    fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
+        // src 指向数组, 结果指向第 index 个元素
+        let scale = match info.value_type(self.src()).kind() {
+            TypeKind::Pointer(array) => match array.kind() {
+                TypeKind::Array(elem, _) => info.type_size(elem),
+                _ => unreachable!(),
+            },
             _ => unreachable!(),
-        }
+        };
+        generate_ptr_offset(info, f, self.src(), self.index(), scale);
+        None
+    }
+}
+
+impl GenerateAsm for GetPtr {
+    fn generate(&self, info: &mut ProgramInfo, f: &mut Vec<u8>) -> Option<String> {
+        // 结果与 src 类型相同, 向后移动 index 个所指的值
+        let scale = info.pointee_size(self.src());
+        generate_ptr_offset(info, f, self.src(), self.index(), scale);
+        None
     }
 }
@@ -0,0 +1,202 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+
+thread_local! {
+    // 标号在整个汇编文件中唯一, 每次生成汇编时从 0 开始
+    static RELAX_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+// 条件跳转 (B 型) 的偏移范围为 ±4KiB, j (J 型) 为 ±1MiB
+const BRANCH_RANGE: (i64, i64) = (-4096, 4094);
+const JUMP_RANGE: (i64, i64) = (-1048576, 1048574);
+
+fn gen_relax_label() -> String {
+    let id = RELAX_ID.get();
+    RELAX_ID.set(id + 1);
+    format!(".Lrelax_{}", id)
+}
+
+pub(super) fn reset_relax_labels() {
+    RELAX_ID.set(0);
+}
+
+// 条件跳转指令及其取反后的指令
+fn invert_branch(op: &str) -> Option<&'static str> {
+    let inv = match op {
+        "beq" => "bne",
+        "bne" => "beq",
+        "blt" => "bge",
+        "bge" => "blt",
+        "bltu" => "bgeu",
+        "bgeu" => "bltu",
+        "bgt" => "ble",
+        "ble" => "bgt",
+        "bgtu" => "bleu",
+        "bleu" => "bgtu",
+        "beqz" => "bnez",
+        "bnez" => "beqz",
+        "bltz" => "bgez",
+        "bgez" => "bltz",
+        "bgtz" => "blez",
+        "blez" => "bgtz",
+        _ => return None,
+    };
+    Some(inv)
+}
+
+// 一行汇编展开后占用的字节数, 伪指令按最坏情况估计
+fn inst_size(line: &str) -> i64 {
+    let line = line.trim();
+    if line.is_empty() || line.ends_with(':') || line.starts_with('.') {
+        return 0;
+    }
+    let mut parts = line.splitn(2, ' ');
+    match parts.next().unwrap() {
+        "call" | "tail" | "jump" | "la" | "lla" => 8,
+        "li" => {
+            let imm = parts.next().unwrap().rsplit(',').next().unwrap().trim();
+            match imm.parse::<i64>() {
+                Ok(imm) if (-2048..2048).contains(&imm) => 4,
+                _ => 8,
+            }
+        }
+        _ => 4,
+    }
+}
+
+// 拆出指令名和最后一个操作数 (跳转目标)
+fn split_jump(line: &str) -> Option<(&str, &str, &str)> {
+    let line = line.trim();
+    let (op, operands) = line.split_once(' ')?;
+    let (regs, target) = match operands.rsplit_once(',') {
+        Some((regs, target)) => (regs.trim(), target.trim()),
+        None => ("", operands.trim()),
+    };
+    Some((op, regs, target))
+}
+
+fn in_range(dist: i64, range: (i64, i64)) -> bool {
+    range.0 <= dist && dist <= range.1
+}
+
+// 偏移超出范围的跳转改写后的指令, 不需要改写时返回 None
+fn relax(line: &str, pc: i64, labels: &HashMap<String, i64>, scratch: &str) -> Option<Vec<String>> {
+    let (op, regs, target) = split_jump(line)?;
+    let dist = labels.get(target)? - pc;
+    // j 也超出范围时用 jump 伪指令, 展开为 auipc + jalr, 需要一个临时寄存器
+    let long = format!("    jump {target}, {scratch}");
+    if op == "j" {
+        return (!in_range(dist, JUMP_RANGE)).then(|| vec![long]);
+    }
+    let inv = invert_branch(op)?;
+    if in_range(dist, BRANCH_RANGE) {
+        return None;
+    }
+    let skip = gen_relax_label();
+    let short = if regs.is_empty() {
+        format!("    {inv} {skip}")
+    } else {
+        format!("    {inv} {regs}, {skip}")
+    };
+    // j 自身位于原跳转之后 4 字节处
+    let jump = if in_range(dist - 4, JUMP_RANGE) {
+        format!("    j {target}")
+    } else {
+        long
+    };
+    Some(vec![short, jump, format!("{skip}:")])
+}
+
+/// 对一个函数的汇编做分支松弛:
+/// 目标超出 ±4KiB 的条件跳转改写为取反的短跳转越过一条 j, j 也到不了时换成 auipc + jalr;
+/// 超出 ±1MiB 的 j 同样换成 auipc + jalr. scratch 是 auipc + jalr 使用的临时寄存器, 不能参与寄存器分配
+pub(super) fn relax_branches(asm: &str, scratch: &str) -> String {
+    let mut lines: Vec<String> = asm.lines().map(|line| line.to_owned()).collect();
+    // 每一遍计算一次偏移, 改写这一遍中所有超出范围的跳转.
+    // 改写只会让代码变长, 可能又有跳转超出范围, 重复到没有需要改写的跳转为止
+    loop {
+        let mut offsets = Vec::with_capacity(lines.len());
+        let mut labels = HashMap::new();
+        let mut pc = 0;
+        for line in &lines {
+            offsets.push(pc);
+            let trimmed = line.trim();
+            if let Some(label) = trimmed.strip_suffix(':') {
+                labels.insert(label.to_owned(), pc);
+            }
+            pc += inst_size(trimmed);
+        }
+
+        let mut changed = false;
+        let mut relaxed = Vec::with_capacity(lines.len());
+        for (line, pc) in lines.into_iter().zip(offsets) {
+            match relax(&line, pc, &labels, scratch) {
+                Some(new) => {
+                    relaxed.extend(new);
+                    changed = true;
+                }
+                None => relaxed.push(line),
+            }
+        }
+        lines = relaxed;
+        if !changed {
+            break;
+        }
+    }
+
+    let mut output = lines.join("\n");
+    if !lines.is_empty() {
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 跳转到 n 条 nop 之后的标号
+    fn far(jump: &str, n: usize) -> String {
+        format!("{jump}\n{}.Lfar:\n    ret\n", "    nop\n".repeat(n))
+    }
+
+    #[test]
+    fn in_range() {
+        let asm = far("    bnez t0, .Lfar", 1000);
+        assert_eq!(relax_branches(&asm, "t6"), asm);
+        let asm = far("    j .Lfar", 200000);
+        assert_eq!(relax_branches(&asm, "t6"), asm);
+    }
+
+    #[test]
+    fn needs_j() {
+        reset_relax_labels();
+        let asm = far("    blt t0, t1, .Lfar", 1100);
+        let relaxed = relax_branches(&asm, "t6");
+        let expected = "    bge t0, t1, .Lrelax_0\n    j .Lfar\n.Lrelax_0:\n    nop\n";
+        assert!(relaxed.starts_with(expected), "{}", &relaxed[..100]);
+        assert_eq!(relaxed.lines().count(), asm.lines().count() + 2);
+    }
+
+    #[test]
+    fn needs_auipc() {
+        reset_relax_labels();
+        let asm = far("    beqz t0, .Lfar\n    j .Lfar", 270000);
+        let relaxed = relax_branches(&asm, "t6");
+        let expected =
+            "    bnez t0, .Lrelax_0\n    jump .Lfar, t6\n.Lrelax_0:\n    jump .Lfar, t6\n    nop\n";
+        assert!(relaxed.starts_with(expected), "{}", &relaxed[..100]);
+    }
+
+    #[test]
+    fn backward_branch() {
+        reset_relax_labels();
+        let asm = format!(".Ltop:\n{}    bnez t0, .Ltop\n", "    nop\n".repeat(1100));
+        let relaxed = relax_branches(&asm, "t6");
+        assert!(
+            relaxed.ends_with("    beqz t0, .Lrelax_0\n    j .Ltop\n.Lrelax_0:\n"),
+            "{}",
+            &relaxed[relaxed.len() - 100..]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use koopa::ir::BinaryOp;
+
+use super::{PeepholeConfig, PeepholeStats};
+
+/// 函数的栈帧, 偏移都相对于序言执行完之后的栈指针
+///
+/// 从栈顶往上依次是: 调用时通过栈传递的参数, 保存的寄存器, 溢出槽位和局部变量.
+/// 具体的位置和对齐由 Isa::frame 决定
+#[derive(Clone)]
+pub struct Frame {
+    /// 栈帧的字节数
+    pub size: usize,
+    /// 需要保存的寄存器和保存的位置
+    pub saved: Vec<(&'static str, usize)>,
+    /// 溢出槽位和局部变量的起始偏移
+    pub locals: usize,
+    /// 调用者通过栈传来的第一个参数的偏移
+    pub incoming: usize,
+}
+
+/// 访存的地址
+#[derive(Clone, Copy)]
+pub enum Addr<'a> {
+    /// 栈帧中的偏移
+    Stack(usize),
+    /// 保存在寄存器中的地址
+    Reg(&'a str),
+    /// 全局变量
+    Global(&'a str),
+}
+
+/// 全局变量初始值的一段
+pub enum Data {
+    Word(i32),
+    /// 若干字节的 0
+    Zero(usize),
+}
+
+/// 目标指令集
+///
+/// 与 Koopa IR 的遍历无关的部分都放在这里: 寄存器, 调用约定, 栈帧布局,
+/// 指令选择以及汇编的写法. 新的目标只需要实现这个 trait,
+/// 再通过 `ProgramInfo::set_isa` 交给 `GenerateAsm` 使用
+pub trait Isa {
+    // ---------- 寄存器 ----------
+
+    /// 可以分配的调用者保存的寄存器, 按分配的先后排列. 不跨越调用的值优先使用这些寄存器
+    fn caller_saved(&self) -> &'static [&'static str];
+
+    /// 可以分配的被调用者保存的寄存器, 按分配的先后排列. 跨越调用的值只能使用这些寄存器
+    fn callee_saved(&self) -> &'static [&'static str];
+
+    /// 两个不参与分配的临时寄存器, 用来装入常数和溢出的值
+    fn scratch(&self) -> [&'static str; 2];
+
+    /// 恒为 0 的寄存器, 没有时常数 0 也要装入寄存器
+    fn zero_reg(&self) -> Option<&'static str>;
+
+    // ---------- 调用约定 ----------
+
+    /// 依次传递前几个参数的寄存器, 其余参数通过栈传递, 每个占 word_size 字节
+    fn arg_regs(&self) -> &'static [&'static str];
+
+    /// 存放返回值的寄存器
+    fn ret_reg(&self) -> &'static str;
+
+    /// 保存返回地址的寄存器, 有调用的函数需要在序言中保存它. 返回地址在栈上时为 None
+    fn link_reg(&self) -> Option<&'static str>;
+
+    /// 调用函数 func, 参数已经放好
+    fn call(&self, f: &mut Vec<u8>, func: &str);
+
+    // ---------- 栈帧布局 ----------
+
+    /// 寄存器和指针的字节数, 栈上的参数和溢出槽位各占这么多字节
+    fn word_size(&self) -> usize;
+
+    /// 在 outgoing 字节的栈上参数和 locals 字节的溢出槽位及局部变量之外, 安排保存寄存器的位置和对齐
+    fn frame(&self, outgoing: usize, locals: usize, saved: &[&'static str]) -> Frame;
+
+    /// 函数开头分配栈帧并保存寄存器的代码
+    fn prologue(&self, frame: &Frame) -> String;
+
+    /// 每次返回前恢复寄存器并释放栈帧的代码
+    fn epilogue(&self, frame: &Frame) -> String;
+
+    /// 从栈帧中偏移为 offset 处读取一个 word_size 的值
+    fn load_stack(&self, f: &mut Vec<u8>, dst: &str, offset: usize) {
+        self.load(f, dst, Addr::Stack(offset), self.word_size());
+    }
+
+    /// 把 src 写到栈帧中偏移为 offset 处, 宽度为 word_size
+    fn store_stack(&self, f: &mut Vec<u8>, src: &str, offset: usize) {
+        self.store(f, src, Addr::Stack(offset), self.word_size());
+    }
+
+    // ---------- 访存和地址计算 ----------
+
+    /// 从 addr 读取 size 字节 (int 或指针) 到 dst, int 符号扩展到整个寄存器
+    fn load(&self, f: &mut Vec<u8>, dst: &str, addr: Addr, size: usize);
+
+    /// 把 src 的低 size 字节写到 addr
+    fn store(&self, f: &mut Vec<u8>, src: &str, addr: Addr, size: usize);
+
+    /// dst = addr
+    fn address(&self, f: &mut Vec<u8>, dst: &str, addr: Addr);
+
+    /// dst = base + offset, 按指针的宽度计算
+    fn add_ptr_imm(&self, f: &mut Vec<u8>, dst: &str, base: &str, offset: i64);
+
+    /// dst = base + index * scale, index 是 int, 按符号扩展到指针的宽度计算
+    fn add_ptr_scaled(&self, f: &mut Vec<u8>, dst: &str, base: &str, index: &str, scale: usize);
+
+    // ---------- 指令选择 ----------
+
+    fn load_imm(&self, f: &mut Vec<u8>, dst: &str, imm: i32);
+
+    fn mov(&self, f: &mut Vec<u8>, dst: &str, src: &str);
+
+    /// dst = lhs op rhs, dst 可以与 lhs 或 rhs 相同
+    fn binary(&self, f: &mut Vec<u8>, op: BinaryOp, dst: &str, lhs: &str, rhs: &str);
+
+    /// 没有乘除法指令时返回软件乘除法的实现, 有乘除法指令的目标不用实现
+    fn soft_muldiv(&self) -> Option<&dyn SoftMulDiv> {
+        None
+    }
+
+    /// 比较 lhs op rhs 成立时跳转到 label, op 是比较运算
+    fn branch_cmp(&self, f: &mut Vec<u8>, op: BinaryOp, lhs: &str, rhs: &str, label: &str);
+
+    /// cond 不为 0 时跳转到 label
+    fn branch_nonzero(&self, f: &mut Vec<u8>, cond: &str, label: &str);
+
+    fn jump(&self, f: &mut Vec<u8>, label: &str);
+
+    fn ret(&self, f: &mut Vec<u8>);
+
+    // ---------- 汇编的写法 ----------
+
+    /// 代码段的开头
+    fn text_section(&self, f: &mut Vec<u8>);
+
+    /// 声明全局符号
+    fn global_symbol(&self, f: &mut Vec<u8>, name: &str);
+
+    /// 数据段中的全局变量
+    fn global_data(&self, f: &mut Vec<u8>, name: &str, data: &[Data]);
+
+    fn label(&self, f: &mut Vec<u8>, name: &str);
+
+    /// 函数 func 中基本块 bb 的局部标号
+    fn local_label(&self, func: &str, bb: &str) -> String;
+
+    /// 整个函数生成完之后的处理, 如窥孔优化和跳转范围的修正
+    fn finish_function(
+        &self,
+        body: &str,
+        peephole: &PeepholeConfig,
+        stats: &mut PeepholeStats,
+    ) -> String;
+}
+
+/// 没有乘除法指令的目标用移位加法和例程完成乘除法
+pub trait SoftMulDiv {
+    /// dst = x * c, dst 不能与 x 相同
+    fn mul_by_const(&self, f: &mut Vec<u8>, dst: &str, x: &str, c: i32);
+
+    /// 调用例程计算 dst = lhs op rhs, 返回用到的例程名
+    fn call_routine(
+        &self,
+        f: &mut Vec<u8>,
+        op: BinaryOp,
+        dst: &str,
+        lhs: &str,
+        rhs: &str,
+    ) -> &'static str;
+
+    /// 例程的汇编代码
+    fn routine(&self, name: &str) -> String;
+}
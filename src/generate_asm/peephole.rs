@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// 窥孔优化中每种模式是否启用
+#[derive(Clone, Copy)]
+pub struct PeepholeConfig {
+    /// 删除 mv a0, a0 (x86 上是 movq %rax, %rax)
+    pub self_move: bool,
+    /// 把紧跟在 sw 之后对同一位置的 lw 改写为 mv (x86 上是 movl 之后的 movslq)
+    pub store_load: bool,
+    /// 删除目标寄存器中已经是该值的 li (x86 上是 movq $1, %rax)
+    pub redundant_li: bool,
+    /// 删除跳转到紧接着的下一个基本块的 j (x86 上是 jmp)
+    pub jump_to_next: bool,
+}
+
+impl PeepholeConfig {
+    pub const PATTERNS: [&'static str; 4] =
+        ["self-move", "store-load", "redundant-li", "jump-to-next"];
+
+    pub fn none() -> Self {
+        Self {
+            self_move: false,
+            store_load: false,
+            redundant_li: false,
+            jump_to_next: false,
+        }
+    }
+
+    /// 从逗号分隔的模式名解析, 如 self-move,jump-to-next; none 表示全部关闭
+    pub fn from_list(list: &str) -> Option<Self> {
+        let mut config = Self::none();
+        for name in list.split(',').filter(|name| !name.is_empty()) {
+            match name {
+                "none" => {}
+                "self-move" => config.self_move = true,
+                "store-load" => config.store_load = true,
+                "redundant-li" => config.redundant_li = true,
+                "jump-to-next" => config.jump_to_next = true,
+                _ => return None,
+            }
+        }
+        Some(config)
+    }
+}
+
+impl Default for PeepholeConfig {
+    fn default() -> Self {
+        Self {
+            self_move: true,
+            store_load: true,
+            redundant_li: true,
+            jump_to_next: true,
+        }
+    }
+}
+
+/// 每种模式被应用的次数
+#[derive(Clone, Copy, Default)]
+pub struct PeepholeStats {
+    pub self_move: usize,
+    pub store_load: usize,
+    pub redundant_li: usize,
+    pub jump_to_next: usize,
+}
+
+impl fmt::Display for PeepholeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = [
+            self.self_move,
+            self.store_load,
+            self.redundant_li,
+            self.jump_to_next,
+        ];
+        for (name, count) in PeepholeConfig::PATTERNS.iter().zip(counts) {
+            writeln!(f, "{name}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 汇编的写法, 决定如何识别各模式中的指令
+#[derive(Clone, Copy)]
+pub(super) enum Syntax {
+    Riscv,
+    /// x86-64 的 AT&T 语法, 目标操作数在最后
+    Att,
+}
+
+// 模式关心的几类指令
+enum Inst<'a> {
+    /// 寄存器之间的复制
+    Move { dst: &'a str, src: &'a str },
+    /// 装入常数
+    LoadImm { dst: &'a str, imm: &'a str },
+    /// 把寄存器低 size 字节写入 addr
+    Store {
+        src: &'a str,
+        addr: &'a str,
+        size: usize,
+    },
+    /// 从 addr 读 size 字节到寄存器
+    Load {
+        dst: &'a str,
+        addr: &'a str,
+        size: usize,
+    },
+    /// 无条件跳转
+    Jump(&'a str),
+    /// 调用和系统调用, 可能改写任何调用者保存的寄存器
+    Clobber,
+    /// 其余指令和它改写的寄存器
+    Other(Option<String>),
+}
+
+// 拆出指令名和操作数, 标号和伪操作返回 None.
+// 操作数按括号外的逗号分开, 如 x86 的 (%rdi,%rax,4)
+fn parse_inst(line: &str) -> Option<(&str, Vec<&str>)> {
+    let line = line.trim();
+    if line.is_empty() || line.ends_with(':') || line.starts_with('.') {
+        return None;
+    }
+    let (op, operands) = match line.split_once(' ') {
+        Some((op, operands)) => (op, operands),
+        None => return Some((line, Vec::new())),
+    };
+    let mut ops = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in operands.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                ops.push(operands[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    ops.push(operands[start..].trim());
+    Some((op, ops))
+}
+
+fn is_label(line: &str) -> bool {
+    line.trim().ends_with(':')
+}
+
+// 会写入第一个操作数的 RISC-V 指令, 其余 (store, 跳转) 不改变寄存器
+fn writes_first_operand(op: &str) -> bool {
+    !matches!(op, "sb" | "sh" | "sw" | "sd")
+        && !op.starts_with('b')
+        && !matches!(op, "j" | "jr" | "ret")
+}
+
+fn is_x86_reg(operand: &str) -> bool {
+    operand.starts_with('%')
+}
+
+// x86 寄存器的 64 位名字, 写入低位部分也算作改写了整个寄存器
+fn x86_reg64(reg: &str) -> String {
+    let name = match reg {
+        "%eax" | "%ax" | "%al" => "%rax",
+        "%ebx" | "%bx" | "%bl" => "%rbx",
+        "%ecx" | "%cx" | "%cl" => "%rcx",
+        "%edx" | "%dx" | "%dl" => "%rdx",
+        "%esi" | "%si" | "%sil" => "%rsi",
+        "%edi" | "%di" | "%dil" => "%rdi",
+        "%ebp" | "%bp" | "%bpl" => "%rbp",
+        "%esp" | "%sp" | "%spl" => "%rsp",
+        // %r8d, %r8w, %r8b
+        _ if reg.starts_with("%r") && reg[2..].starts_with(|c: char| c.is_ascii_digit()) => {
+            reg.trim_end_matches(['d', 'w', 'b'])
+        }
+        _ => reg,
+    };
+    name.to_owned()
+}
+
+impl Syntax {
+    fn classify<'a>(self, op: &'a str, ops: &[&'a str]) -> Inst<'a> {
+        match self {
+            Self::Riscv => match (op, ops) {
+                ("mv", &[dst, src]) => Inst::Move { dst, src },
+                ("li", &[dst, imm]) => Inst::LoadImm { dst, imm },
+                ("sw" | "sd", &[src, addr]) => Inst::Store {
+                    src,
+                    addr,
+                    size: if op == "sw" { 4 } else { 8 },
+                },
+                ("lw" | "ld", &[dst, addr]) => Inst::Load {
+                    dst,
+                    addr,
+                    size: if op == "lw" { 4 } else { 8 },
+                },
+                ("j", &[target]) => Inst::Jump(target),
+                ("call" | "ecall", _) => Inst::Clobber,
+                (op, [first, ..]) if writes_first_operand(op) => {
+                    Inst::Other(Some((*first).to_owned()))
+                }
+                _ => Inst::Other(None),
+            },
+            Self::Att => match (op, ops) {
+                ("movq", &[src, dst]) if is_x86_reg(dst) => {
+                    if let Some(imm) = src.strip_prefix('$') {
+                        Inst::LoadImm { dst, imm }
+                    } else if is_x86_reg(src) {
+                        Inst::Move { dst, src }
+                    } else {
+                        Inst::Load {
+                            dst,
+                            addr: src,
+                            size: 8,
+                        }
+                    }
+                }
+                ("movslq", &[addr, dst]) if !is_x86_reg(addr) => Inst::Load { dst, addr, size: 4 },
+                ("movq" | "movl", &[src, addr]) if is_x86_reg(src) && !is_x86_reg(addr) => {
+                    Inst::Store {
+                        src,
+                        addr,
+                        size: if op == "movl" { 4 } else { 8 },
+                    }
+                }
+                ("jmp", &[target]) => Inst::Jump(target),
+                // cltd 和 idivl 隐式改写 %eax 和 %edx, 与调用一样保守处理
+                ("call" | "cltd" | "cqto" | "idivl" | "idivq", _) => Inst::Clobber,
+                // 比较和条件跳转不改写寄存器
+                (op, _)
+                    if op.starts_with("cmp") || op.starts_with("test") || op.starts_with('j') =>
+                {
+                    Inst::Other(None)
+                }
+                (_, [.., last]) if is_x86_reg(last) => Inst::Other(Some(x86_reg64(last))),
+                _ => Inst::Other(None),
+            },
+        }
+    }
+
+    // 把 store 写入的值复制到 load 的目标寄存器, 与 load 的宽度一致
+    fn copy(self, dst: &str, src: &str, size: usize) -> String {
+        match (self, size) {
+            (Self::Riscv, _) => format!("    mv {dst}, {src}"),
+            // movl 存的是低 32 位, movslq 读回时符号扩展
+            (Self::Att, 4) => format!("    movslq {src}, {dst}"),
+            (Self::Att, _) => format!("    movq {src}, {dst}"),
+        }
+    }
+
+    fn inst<'a>(self, line: &'a str) -> Option<Inst<'a>> {
+        parse_inst(line).map(|(op, ops)| self.classify(op, &ops))
+    }
+}
+
+fn self_move(lines: &mut Vec<String>, syntax: Syntax) -> usize {
+    let before = lines.len();
+    lines.retain(|line| !matches!(syntax.inst(line), Some(Inst::Move { dst, src }) if dst == src));
+    before - lines.len()
+}
+
+fn store_load(lines: &mut [String], syntax: Syntax) -> usize {
+    let mut count = 0;
+    for i in 1..lines.len() {
+        let rewrite = match (syntax.inst(&lines[i - 1]), syntax.inst(&lines[i])) {
+            (
+                Some(Inst::Store { src, addr, size }),
+                Some(Inst::Load {
+                    dst,
+                    addr: ld_addr,
+                    size: ld_size,
+                }),
+            ) if addr == ld_addr && size == ld_size => Some(syntax.copy(dst, src, size)),
+            _ => None,
+        };
+        if let Some(rewrite) = rewrite {
+            lines[i] = rewrite;
+            count += 1;
+        }
+    }
+    count
+}
+
+fn redundant_li(lines: &mut Vec<String>, syntax: Syntax) -> usize {
+    let mut count = 0;
+    // 当前基本块内已知保存常量的寄存器
+    let mut known: HashMap<String, String> = HashMap::new();
+    let mut output = Vec::with_capacity(lines.len());
+    for line in lines.drain(..) {
+        match syntax.inst(&line) {
+            Some(Inst::LoadImm { dst, imm }) => {
+                if known.get(dst).map(|known| known == imm) == Some(true) {
+                    count += 1;
+                    continue;
+                }
+                known.insert(dst.to_owned(), imm.to_owned());
+            }
+            Some(Inst::Move { dst, .. } | Inst::Load { dst, .. }) => {
+                known.remove(dst);
+            }
+            Some(Inst::Other(Some(dst))) => {
+                known.remove(&dst);
+            }
+            Some(Inst::Store { .. } | Inst::Jump(_) | Inst::Other(None)) => {}
+            Some(Inst::Clobber) => known.clear(),
+            // 标号处可能有别的前驱跳入
+            None => known.clear(),
+        }
+        output.push(line);
+    }
+    *lines = output;
+    count
+}
+
+fn jump_to_next(lines: &mut Vec<String>, syntax: Syntax) -> usize {
+    let mut count = 0;
+    let mut output = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(Inst::Jump(target)) = syntax.inst(line) {
+            // 跳转之后紧跟的若干标号中有跳转目标
+            let falls_through = lines[i + 1..]
+                .iter()
+                .take_while(|next| is_label(next))
+                .any(|next| next.trim().trim_end_matches(':') == target);
+            if falls_through {
+                count += 1;
+                continue;
+            }
+        }
+        output.push(line.clone());
+    }
+    *lines = output;
+    count
+}
+
+/// 对一个函数的汇编做窥孔优化, 直到没有模式可以应用
+pub(super) fn run_peephole(
+    asm: &str,
+    syntax: Syntax,
+    config: &PeepholeConfig,
+    stats: &mut PeepholeStats,
+) -> String {
+    let mut lines: Vec<String> = asm.lines().map(|line| line.to_owned()).collect();
+    loop {
+        let mut changed = 0;
+        if config.self_move {
+            let n = self_move(&mut lines, syntax);
+            stats.self_move += n;
+            changed += n;
+        }
+        if config.store_load {
+            let n = store_load(&mut lines, syntax);
+            stats.store_load += n;
+            changed += n;
+        }
+        if config.redundant_li {
+            let n = redundant_li(&mut lines, syntax);
+            stats.redundant_li += n;
+            changed += n;
+        }
+        if config.jump_to_next {
+            let n = jump_to_next(&mut lines, syntax);
+            stats.jump_to_next += n;
+            changed += n;
+        }
+        if changed == 0 {
+            break;
+        }
+    }
+
+    let mut output = lines.join("\n");
+    if !lines.is_empty() {
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 只启用 pattern 一种模式, 返回优化结果和该模式应用的次数
+    fn run_syntax(asm: &str, syntax: Syntax, pattern: &str) -> (String, usize) {
+        let config = PeepholeConfig::from_list(pattern).unwrap();
+        let mut stats = PeepholeStats::default();
+        let output = run_peephole(asm, syntax, &config, &mut stats);
+        let count = stats.self_move + stats.store_load + stats.redundant_li + stats.jump_to_next;
+        (output, count)
+    }
+
+    fn run(asm: &str, pattern: &str) -> (String, usize) {
+        run_syntax(asm, Syntax::Riscv, pattern)
+    }
+
+    fn run_att(asm: &str, pattern: &str) -> (String, usize) {
+        run_syntax(asm, Syntax::Att, pattern)
+    }
+
+    // asm 不变
+    fn unchanged(asm: &str, pattern: &str) {
+        assert_eq!(run(asm, pattern), (asm.to_owned(), 0));
+    }
+
+    fn unchanged_att(asm: &str, pattern: &str) {
+        assert_eq!(run_att(asm, pattern), (asm.to_owned(), 0));
+    }
+
+    #[test]
+    fn self_move() {
+        let asm = "    mv a0, a0\n    mv a0, a1\n    ret\n";
+        let expected = "    mv a0, a1\n    ret\n";
+        assert_eq!(run(asm, "self-move"), (expected.to_owned(), 1));
+        unchanged("    mv a0, a1\n    mv a1, a0\n", "self-move");
+    }
+
+    #[test]
+    fn store_load() {
+        let asm = "    sw t0, 4(sp)\n    lw t1, 4(sp)\n";
+        let expected = "    sw t0, 4(sp)\n    mv t1, t0\n";
+        assert_eq!(run(asm, "store-load"), (expected.to_owned(), 1));
+        let asm = "    sd t0, 8(sp)\n    ld t1, 8(sp)\n";
+        let expected = "    sd t0, 8(sp)\n    mv t1, t0\n";
+        assert_eq!(run(asm, "store-load"), (expected.to_owned(), 1));
+        // 地址不同, 宽度不同, 或者中间有标号
+        unchanged("    sw t0, 4(sp)\n    lw t1, 8(sp)\n", "store-load");
+        unchanged("    sw t0, 4(sp)\n    ld t1, 4(sp)\n", "store-load");
+        unchanged("    sw t0, 4(sp)\n.L1:\n    lw t1, 4(sp)\n", "store-load");
+    }
+
+    #[test]
+    fn redundant_li() {
+        let asm = "    li t0, 1\n    add t1, t0, t0\n    li t0, 1\n";
+        let expected = "    li t0, 1\n    add t1, t0, t0\n";
+        assert_eq!(run(asm, "redundant-li"), (expected.to_owned(), 1));
+        // 值不同, 寄存器被改写, 中间有标号, 调用或系统调用
+        unchanged("    li t0, 1\n    li t0, 2\n", "redundant-li");
+        unchanged(
+            "    li t0, 1\n    addi t0, t0, 1\n    li t0, 1\n",
+            "redundant-li",
+        );
+        unchanged("    li t0, 1\n.L1:\n    li t0, 1\n", "redundant-li");
+        unchanged("    li a0, 1\n    call f\n    li a0, 1\n", "redundant-li");
+        unchanged("    li a0, 1\n    ecall\n    li a0, 1\n", "redundant-li");
+        // store 和跳转不改写第一个操作数
+        let asm = "    li t0, 1\n    sw t0, 0(sp)\n    bnez t0, .L1\n    li t0, 1\n";
+        let expected = "    li t0, 1\n    sw t0, 0(sp)\n    bnez t0, .L1\n";
+        assert_eq!(run(asm, "redundant-li"), (expected.to_owned(), 1));
+    }
+
+    #[test]
+    fn jump_to_next() {
+        let asm = "    j .L1\n.L1:\n    ret\n";
+        let expected = ".L1:\n    ret\n";
+        assert_eq!(run(asm, "jump-to-next"), (expected.to_owned(), 1));
+        // 目标是紧接着的几个标号之一
+        let asm = "    j .L2\n.L1:\n.L2:\n    ret\n";
+        let expected = ".L1:\n.L2:\n    ret\n";
+        assert_eq!(run(asm, "jump-to-next"), (expected.to_owned(), 1));
+        unchanged("    j .L2\n.L1:\n    nop\n.L2:\n    ret\n", "jump-to-next");
+        unchanged("    bnez t0, .L1\n.L1:\n    ret\n", "jump-to-next");
+    }
+
+    #[test]
+    fn disabled_patterns() {
+        unchanged(
+            "    mv a0, a0\n    li t0, 1\n    li t0, 1\n    j .L1\n.L1:\n",
+            "none",
+        );
+    }
+
+    #[test]
+    fn patterns_enable_each_other() {
+        // store-load 产生的 mv t0, t0 再被 self-move 删除
+        let asm = "    sw t0, 0(sp)\n    lw t0, 0(sp)\n";
+        let mut stats = PeepholeStats::default();
+        let output = run_peephole(asm, Syntax::Riscv, &PeepholeConfig::default(), &mut stats);
+        assert_eq!(output, "    sw t0, 0(sp)\n");
+        assert_eq!((stats.store_load, stats.self_move), (1, 1));
+    }
+
+    #[test]
+    fn att_self_move() {
+        let asm = "    movq %rsi, %rsi\n    movq %rsi, %rdi\n";
+        let expected = "    movq %rsi, %rdi\n";
+        assert_eq!(run_att(asm, "self-move"), (expected.to_owned(), 1));
+        // movl 会清零高 32 位, 不是空操作
+        unchanged_att("    movl %esi, %esi\n", "self-move");
+    }
+
+    #[test]
+    fn att_store_load() {
+        let asm = "    movl %esi, 8(%rsp)\n    movslq 8(%rsp), %rdi\n";
+        let expected = "    movl %esi, 8(%rsp)\n    movslq %esi, %rdi\n";
+        assert_eq!(run_att(asm, "store-load"), (expected.to_owned(), 1));
+        let asm = "    movq %rsi, (%rdi,%rax,4)\n    movq (%rdi,%rax,4), %r8\n";
+        let expected = "    movq %rsi, (%rdi,%rax,4)\n    movq %rsi, %r8\n";
+        assert_eq!(run_att(asm, "store-load"), (expected.to_owned(), 1));
+        // 地址不同, 宽度不同
+        unchanged_att(
+            "    movl %esi, 8(%rsp)\n    movslq 16(%rsp), %rdi\n",
+            "store-load",
+        );
+        unchanged_att(
+            "    movl %esi, 8(%rsp)\n    movq 8(%rsp), %rdi\n",
+            "store-load",
+        );
+    }
+
+    #[test]
+    fn att_redundant_li() {
+        let asm =
+            "    movq $1, %r10\n    movl %r10d, 0(%rsp)\n    cmpl $-1, %r10d\n    movq $1, %r10\n";
+        let expected = "    movq $1, %r10\n    movl %r10d, 0(%rsp)\n    cmpl $-1, %r10d\n";
+        assert_eq!(run_att(asm, "redundant-li"), (expected.to_owned(), 1));
+        // 改写低 32 位, 8 位, 以及隐式改写 %rdx 的 cltd
+        unchanged_att(
+            "    movq $1, %r10\n    addl %esi, %r10d\n    movq $1, %r10\n",
+            "redundant-li",
+        );
+        unchanged_att(
+            "    movq $0, %rax\n    setg %al\n    movq $0, %rax\n",
+            "redundant-li",
+        );
+        unchanged_att(
+            "    movq $1, %rdx\n    cltd\n    movq $1, %rdx\n",
+            "redundant-li",
+        );
+        unchanged_att(
+            "    movq $1, %rdi\n    call f\n    movq $1, %rdi\n",
+            "redundant-li",
+        );
+    }
+
+    #[test]
+    fn att_jump_to_next() {
+        let asm = "    jmp .L1\n.L1:\n    ret\n";
+        let expected = ".L1:\n    ret\n";
+        assert_eq!(run_att(asm, "jump-to-next"), (expected.to_owned(), 1));
+        unchanged_att("    jne .L1\n.L1:\n    ret\n", "jump-to-next");
+    }
+}
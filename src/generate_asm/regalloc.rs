@@ -0,0 +1,269 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Value, ValueKind};
+
+use super::isa::Isa;
+use crate::cfg::successors;
+
+// 基于活跃区间的线性扫描寄存器分配.
+//
+// 函数参数定义在位置 0, 之后按布局顺序给每个基本块的开头 (定义块参数) 和每条指令编号.
+// 值的活跃区间取覆盖所有定义和使用的最小区间, 不考虑区间中的空洞.
+// 跨越调用的值只能放在被调用者保存的寄存器中, 其余的值优先使用调用者保存的寄存器,
+// 这样没有调用的函数不需要保存任何寄存器. 寄存器不够时溢出到栈上的槽位
+
+/// 值的存放位置
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Location {
+    Reg(&'static str),
+    /// 第 n 个溢出槽位
+    Slot(usize),
+}
+
+/// 一个函数的寄存器分配结果
+#[derive(Clone)]
+pub(super) struct Allocation {
+    pub locations: HashMap<Value, Location>,
+    /// 溢出槽位的个数
+    pub slots: usize,
+    /// 需要在序言中保存的寄存器, 有调用时包括保存返回地址的寄存器
+    pub saved: Vec<&'static str>,
+    /// 调用时通过栈传递的参数个数的最大值
+    pub stack_args: usize,
+    /// 只被同一基本块中的 br 用作条件的比较指令, 与跳转合并生成, 不分配位置
+    pub fused: HashSet<Value>,
+}
+
+/// value 是否是只被同一基本块中的 br 用作条件的比较指令
+pub(super) fn is_fused_cmp(func: &FunctionData, value: Value) -> bool {
+    let data = func.dfg().value(value);
+    match data.kind() {
+        ValueKind::Binary(bin)
+            if matches!(
+                bin.op(),
+                BinaryOp::Lt
+                    | BinaryOp::Gt
+                    | BinaryOp::Le
+                    | BinaryOp::Ge
+                    | BinaryOp::Eq
+                    | BinaryOp::NotEq
+            ) => {}
+        _ => return false,
+    }
+    if data.used_by().len() != 1 {
+        return false;
+    }
+    let user = *data.used_by().iter().next().unwrap();
+    // 同时作为块参数传递时仍需要放在寄存器中
+    match func.dfg().value(user).kind() {
+        ValueKind::Branch(br)
+            if br.cond() == value
+                && !br.true_args().contains(&value)
+                && !br.false_args().contains(&value) => {}
+        _ => return false,
+    }
+    let layout = func.layout();
+    layout.parent_bb(user) == layout.parent_bb(value)
+}
+
+// 指令用到的需要分配位置的值, 合并的比较指令的操作数算作 br 的使用
+fn inst_uses(func: &FunctionData, inst: Value, fused: &HashSet<Value>) -> Vec<Value> {
+    let kind = func.dfg().value(inst).kind();
+    let mut uses: Vec<Value> = kind.value_uses().collect();
+    if let ValueKind::Branch(br) = kind {
+        if fused.contains(&br.cond()) {
+            uses.retain(|&v| v != br.cond());
+            uses.extend(func.dfg().value(br.cond()).kind().value_uses());
+        }
+    }
+    uses
+}
+
+/// 为函数中的每个值分配寄存器或溢出槽位
+pub(super) fn allocate(func: &FunctionData, isa: &dyn Isa) -> Allocation {
+    let layout = func.layout();
+    let dfg = func.dfg();
+
+    let fused: HashSet<Value> = layout
+        .bbs()
+        .iter()
+        .flat_map(|(_, node)| node.insts().keys())
+        .copied()
+        .filter(|&inst| is_fused_cmp(func, inst))
+        .collect();
+
+    // 需要分配位置的值按定义的顺序排列, 记录定义的位置
+    let mut defs: Vec<(Value, usize)> = func.params().iter().map(|&p| (p, 0)).collect();
+    let mut block_start = HashMap::new();
+    let mut block_end = HashMap::new();
+    let mut inst_pos = HashMap::new();
+    let mut calls = Vec::new();
+    let mut stack_args = 0;
+    let mut pos = 0;
+    for (&bb, node) in layout.bbs() {
+        pos += 1;
+        block_start.insert(bb, pos);
+        defs.extend(dfg.bb(bb).params().iter().map(|&p| (p, pos)));
+        for &inst in node.insts().keys() {
+            pos += 1;
+            inst_pos.insert(inst, pos);
+            let data = dfg.value(inst);
+            if let ValueKind::Call(call) = data.kind() {
+                calls.push(pos);
+                stack_args = stack_args.max(call.args().len().saturating_sub(isa.arg_regs().len()));
+            }
+            let has_result = !data.ty().is_unit() && !matches!(data.kind(), ValueKind::Alloc(_));
+            if has_result && !fused.contains(&inst) {
+                defs.push((inst, pos));
+            }
+        }
+        block_end.insert(bb, pos);
+    }
+    let mut ranges: HashMap<Value, (usize, usize)> =
+        defs.iter().map(|&(v, pos)| (v, (pos, pos))).collect();
+    let extend = |ranges: &mut HashMap<Value, (usize, usize)>, v: Value, pos: usize| {
+        let range = ranges.get_mut(&v).unwrap();
+        range.0 = range.0.min(pos);
+        range.1 = range.1.max(pos);
+    };
+
+    // 每个基本块中向上暴露的使用和定义
+    let mut gen: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+    let mut kill: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+    for (&bb, node) in layout.bbs() {
+        let mut defined: HashSet<Value> = dfg.bb(bb).params().iter().copied().collect();
+        let mut used = HashSet::new();
+        for &inst in node.insts().keys() {
+            if fused.contains(&inst) {
+                continue;
+            }
+            for v in inst_uses(func, inst, &fused) {
+                if !ranges.contains_key(&v) {
+                    continue;
+                }
+                extend(&mut ranges, v, inst_pos[&inst]);
+                if !defined.contains(&v) {
+                    used.insert(v);
+                }
+            }
+            defined.insert(inst);
+        }
+        gen.insert(bb, used);
+        kill.insert(bb, defined);
+    }
+
+    // 反向数据流求每个基本块入口和出口活跃的值
+    let bbs: Vec<BasicBlock> = layout.bbs().keys().copied().collect();
+    let succs: HashMap<BasicBlock, Vec<BasicBlock>> =
+        bbs.iter().map(|&bb| (bb, successors(func, bb))).collect();
+    let mut live_in: HashMap<BasicBlock, HashSet<Value>> =
+        bbs.iter().map(|&bb| (bb, HashSet::new())).collect();
+    let mut live_out = live_in.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &bb in bbs.iter().rev() {
+            let out: HashSet<Value> = succs[&bb]
+                .iter()
+                .flat_map(|succ| live_in[succ].iter().copied())
+                .collect();
+            let mut inn = gen[&bb].clone();
+            inn.extend(out.difference(&kill[&bb]).copied());
+            if inn.len() != live_in[&bb].len() {
+                changed = true;
+                live_in.insert(bb, inn);
+            }
+            live_out.insert(bb, out);
+        }
+    }
+    for &bb in &bbs {
+        for &v in &live_in[&bb] {
+            extend(&mut ranges, v, block_start[&bb]);
+        }
+        for &v in &live_out[&bb] {
+            extend(&mut ranges, v, block_end[&bb]);
+        }
+        // 块参数在前驱的跳转处写入
+        for &succ in &succs[&bb] {
+            for &param in dfg.bb(succ).params() {
+                extend(&mut ranges, param, block_end[&bb]);
+            }
+        }
+    }
+
+    // 线性扫描, 按区间起点排序, 起点相同时保持定义的顺序
+    let mut intervals: Vec<(Value, usize, usize)> = defs
+        .iter()
+        .map(|&(v, _)| (v, ranges[&v].0, ranges[&v].1))
+        .collect();
+    intervals.sort_by_key(|&(_, start, _)| start);
+
+    let caller = isa.caller_saved();
+    let callee = isa.callee_saved();
+    let mut free_caller: BTreeSet<usize> = (0..caller.len()).collect();
+    let mut free_callee: BTreeSet<usize> = (0..callee.len()).collect();
+    let mut used_callee = BTreeSet::new();
+    let mut free_slots = BTreeSet::new();
+    let mut slots = 0;
+    let mut locations = HashMap::new();
+    // (终点, 位置, 寄存器是否被调用者保存)
+    let mut active: Vec<(usize, Location, bool)> = Vec::new();
+    for (v, start, end) in intervals {
+        // 终点严格在起点之前才释放, 结果不会与操作数共用寄存器
+        active.retain(|&(active_end, location, is_callee)| {
+            if active_end >= start {
+                return true;
+            }
+            match location {
+                Location::Reg(reg) if is_callee => {
+                    free_callee.insert(callee.iter().position(|&r| r == reg).unwrap());
+                }
+                Location::Reg(reg) => {
+                    free_caller.insert(caller.iter().position(|&r| r == reg).unwrap());
+                }
+                Location::Slot(n) => {
+                    free_slots.insert(n);
+                }
+            }
+            false
+        });
+
+        let crosses_call = calls.iter().any(|&c| start < c && c < end);
+        let reg = if crosses_call {
+            None
+        } else {
+            free_caller.pop_first().map(|i| (caller[i], false))
+        };
+        let reg = reg.or_else(|| {
+            free_callee.pop_first().map(|i| {
+                used_callee.insert(i);
+                (callee[i], true)
+            })
+        });
+        let (location, is_callee) = match reg {
+            Some((reg, is_callee)) => (Location::Reg(reg), is_callee),
+            None => {
+                let n = free_slots.pop_first().unwrap_or_else(|| {
+                    slots += 1;
+                    slots - 1
+                });
+                (Location::Slot(n), false)
+            }
+        };
+        locations.insert(v, location);
+        active.push((end, location, is_callee));
+    }
+
+    let mut saved = Vec::new();
+    if !calls.is_empty() {
+        saved.extend(isa.link_reg());
+    }
+    saved.extend(used_callee.iter().map(|&i| callee[i]));
+    Allocation {
+        locations,
+        slots,
+        saved,
+        stack_args,
+        fused,
+    }
+}
@@ -0,0 +1,388 @@
+use std::io::Write;
+
+use koopa::ir::BinaryOp;
+
+use super::branch_relax::relax_branches;
+use super::isa::{Addr, Data, Frame, Isa, SoftMulDiv};
+use super::peephole::{run_peephole, Syntax};
+use super::soft_muldiv::{call_routine, mul_by_const, routine, DIVSI3, MODSI3, MULSI3};
+use super::{PeepholeConfig, PeepholeStats};
+
+// 不跨越调用的值优先使用 t0-t3 和 a0-a7, 跨越调用的值使用 s0-s11.
+// t5, t6 是装入常数和溢出值的临时寄存器, t6 同时用于分支松弛中的 auipc + jalr;
+// t4 用来计算地址: 超出 12 位立即数的栈帧偏移, 全局变量和数组下标
+static CALLER_SAVED: [&str; 12] = [
+    "t0", "t1", "t2", "t3", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
+];
+static CALLEE_SAVED: [&str; 12] = [
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+];
+static ARG_REGS: [&str; 8] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+const SCRATCH: [&str; 2] = ["t5", "t6"];
+const ADDR_TEMP: &str = "t4";
+
+/// 生成代码的目标架构
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Riscv32,
+    Riscv64,
+}
+
+impl Target {
+    // 寄存器的字节数
+    fn xlen(self) -> usize {
+        match self {
+            Self::Riscv32 => 4,
+            Self::Riscv64 => 8,
+        }
+    }
+
+    // 保存/恢复整个寄存器的指令
+    fn store_reg(self) -> &'static str {
+        match self {
+            Self::Riscv32 => "sw",
+            Self::Riscv64 => "sd",
+        }
+    }
+
+    fn load_reg(self) -> &'static str {
+        match self {
+            Self::Riscv32 => "lw",
+            Self::Riscv64 => "ld",
+        }
+    }
+
+    // int 的算术指令, RV64 上使用 *w 版本, 结果截断为 32 位后再符号扩展
+    fn int_op(self, op: &str) -> String {
+        match self {
+            Self::Riscv32 => op.to_owned(),
+            Self::Riscv64 => format!("{op}w"),
+        }
+    }
+}
+
+/// RISC-V 指令集, 支持 RV32/RV64 以及有无 M 扩展
+pub struct Riscv {
+    target: Target,
+    // 目标是否支持 M 扩展, 不支持时乘除法调用 soft_muldiv 中的例程
+    m_extension: bool,
+}
+
+impl Riscv {
+    /// 乘除法例程只按 32 位寄存器编写, RV64 的目标必须有 M 扩展
+    pub fn new(target: Target, m_extension: bool) -> Result<Self, String> {
+        if !m_extension && target == Target::Riscv64 {
+            return Err(
+                "riscv64 targets require the M extension (-march=rv32i is RV32 only)".to_owned(),
+            );
+        }
+        Ok(Self {
+            target,
+            m_extension,
+        })
+    }
+
+    // sp += delta
+    fn adjust_sp(&self, output: &mut String, delta: i64) {
+        if (-2048..2048).contains(&delta) {
+            *output += &format!("    addi sp, sp, {delta}\n");
+        } else {
+            *output += &format!("    li {ADDR_TEMP}, {delta}\n");
+            *output += &format!("    add sp, sp, {ADDR_TEMP}\n");
+        }
+    }
+
+    // 访存指令的地址操作数, 栈帧偏移超出 12 位立即数或者是全局变量时先把地址算到 t4
+    fn mem_operand(&self, f: &mut Vec<u8>, addr: Addr) -> String {
+        match addr {
+            Addr::Stack(offset) if offset < 2048 => format!("{offset}(sp)"),
+            Addr::Stack(offset) => {
+                writeln!(f, "    li {ADDR_TEMP}, {offset}").unwrap();
+                writeln!(f, "    add {ADDR_TEMP}, sp, {ADDR_TEMP}").unwrap();
+                format!("0({ADDR_TEMP})")
+            }
+            Addr::Reg(reg) => format!("0({reg})"),
+            Addr::Global(name) => {
+                writeln!(f, "    la {ADDR_TEMP}, {name}").unwrap();
+                format!("0({ADDR_TEMP})")
+            }
+        }
+    }
+}
+
+impl Default for Riscv {
+    fn default() -> Self {
+        Self::new(Target::Riscv32, true).unwrap()
+    }
+}
+
+impl Isa for Riscv {
+    fn caller_saved(&self) -> &'static [&'static str] {
+        &CALLER_SAVED
+    }
+
+    fn callee_saved(&self) -> &'static [&'static str] {
+        &CALLEE_SAVED
+    }
+
+    fn scratch(&self) -> [&'static str; 2] {
+        SCRATCH
+    }
+
+    fn zero_reg(&self) -> Option<&'static str> {
+        Some("x0")
+    }
+
+    fn arg_regs(&self) -> &'static [&'static str] {
+        &ARG_REGS
+    }
+
+    fn ret_reg(&self) -> &'static str {
+        "a0"
+    }
+
+    fn link_reg(&self) -> Option<&'static str> {
+        Some("ra")
+    }
+
+    fn call(&self, f: &mut Vec<u8>, func: &str) {
+        writeln!(f, "    call {func}").unwrap();
+    }
+
+    fn word_size(&self) -> usize {
+        self.target.xlen()
+    }
+
+    // 保存的寄存器紧挨着栈上的参数, 偏移较小; 栈帧按 psABI 要求 16 字节对齐
+    fn frame(&self, outgoing: usize, locals: usize, saved: &[&'static str]) -> Frame {
+        let xlen = self.target.xlen();
+        let saved: Vec<_> = saved
+            .iter()
+            .enumerate()
+            .map(|(i, &reg)| (reg, outgoing + i * xlen))
+            .collect();
+        let start = outgoing + saved.len() * xlen;
+        let size = (start + locals).next_multiple_of(16);
+        Frame {
+            size,
+            saved,
+            locals: start,
+            incoming: size,
+        }
+    }
+
+    fn prologue(&self, frame: &Frame) -> String {
+        let mut output = String::new();
+        if frame.size > 0 {
+            self.adjust_sp(&mut output, -(frame.size as i64));
+        }
+        let mut f = Vec::new();
+        for &(reg, offset) in &frame.saved {
+            self.store_stack(&mut f, reg, offset);
+        }
+        output + &String::from_utf8(f).unwrap()
+    }
+
+    fn epilogue(&self, frame: &Frame) -> String {
+        let mut f = Vec::new();
+        for &(reg, offset) in &frame.saved {
+            self.load_stack(&mut f, reg, offset);
+        }
+        let mut output = String::from_utf8(f).unwrap();
+        if frame.size > 0 {
+            self.adjust_sp(&mut output, frame.size as i64);
+        }
+        output
+    }
+
+    fn load(&self, f: &mut Vec<u8>, dst: &str, addr: Addr, size: usize) {
+        let op = match size {
+            4 => "lw",
+            _ => self.target.load_reg(),
+        };
+        let addr = self.mem_operand(f, addr);
+        writeln!(f, "    {op} {dst}, {addr}").unwrap();
+    }
+
+    fn store(&self, f: &mut Vec<u8>, src: &str, addr: Addr, size: usize) {
+        let op = match size {
+            4 => "sw",
+            _ => self.target.store_reg(),
+        };
+        let addr = self.mem_operand(f, addr);
+        writeln!(f, "    {op} {src}, {addr}").unwrap();
+    }
+
+    fn address(&self, f: &mut Vec<u8>, dst: &str, addr: Addr) {
+        match addr {
+            Addr::Stack(offset) => self.add_ptr_imm(f, dst, "sp", offset as i64),
+            Addr::Reg(reg) => writeln!(f, "    mv {dst}, {reg}").unwrap(),
+            Addr::Global(name) => writeln!(f, "    la {dst}, {name}").unwrap(),
+        }
+    }
+
+    fn add_ptr_imm(&self, f: &mut Vec<u8>, dst: &str, base: &str, offset: i64) {
+        if (-2048..2048).contains(&offset) {
+            writeln!(f, "    addi {dst}, {base}, {offset}").unwrap();
+        } else {
+            writeln!(f, "    li {ADDR_TEMP}, {offset}").unwrap();
+            writeln!(f, "    add {dst}, {base}, {ADDR_TEMP}").unwrap();
+        }
+    }
+
+    // 地址按 XLEN 计算: RV64 上 slli/mul/add 都是 64 位的, index 已经符号扩展
+    fn add_ptr_scaled(&self, f: &mut Vec<u8>, dst: &str, base: &str, index: &str, scale: usize) {
+        if scale.is_power_of_two() {
+            let shift = scale.trailing_zeros();
+            writeln!(f, "    slli {ADDR_TEMP}, {index}, {shift}").unwrap();
+        } else if self.m_extension {
+            writeln!(f, "    li {ADDR_TEMP}, {scale}").unwrap();
+            writeln!(f, "    mul {ADDR_TEMP}, {index}, {ADDR_TEMP}").unwrap();
+        } else {
+            mul_by_const(f, index, scale as i32, ADDR_TEMP);
+        }
+        writeln!(f, "    add {dst}, {base}, {ADDR_TEMP}").unwrap();
+    }
+
+    fn load_imm(&self, f: &mut Vec<u8>, dst: &str, imm: i32) {
+        writeln!(f, "    li {dst}, {imm}").unwrap();
+    }
+
+    fn mov(&self, f: &mut Vec<u8>, dst: &str, src: &str) {
+        writeln!(f, "    mv {dst}, {src}").unwrap();
+    }
+
+    fn binary(&self, f: &mut Vec<u8>, op: BinaryOp, dst: &str, lhs: &str, rhs: &str) {
+        let target = self.target;
+        match op {
+            BinaryOp::Add => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("add")),
+            BinaryOp::Sub => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("sub")),
+            BinaryOp::Mul => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("mul")),
+            BinaryOp::Div => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("div")),
+            BinaryOp::Mod => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("rem")),
+            BinaryOp::Shl => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("sll")),
+            BinaryOp::Shr => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("srl")),
+            BinaryOp::Sar => writeln!(f, "    {} {dst}, {lhs}, {rhs}", target.int_op("sra")),
+            BinaryOp::And => writeln!(f, "    and {dst}, {lhs}, {rhs}"),
+            BinaryOp::Or => writeln!(f, "    or {dst}, {lhs}, {rhs}"),
+            BinaryOp::Xor => writeln!(f, "    xor {dst}, {lhs}, {rhs}"),
+            BinaryOp::Lt => writeln!(f, "    slt {dst}, {lhs}, {rhs}"),
+            BinaryOp::Gt => writeln!(f, "    sgt {dst}, {lhs}, {rhs}"),
+            // 比较结果再取反
+            BinaryOp::Le | BinaryOp::Ge | BinaryOp::Eq | BinaryOp::NotEq => {
+                let (inst, set) = match op {
+                    BinaryOp::Le => ("sgt", "seqz"),
+                    BinaryOp::Ge => ("slt", "seqz"),
+                    BinaryOp::Eq => ("xor", "seqz"),
+                    BinaryOp::NotEq => ("xor", "snez"),
+                    _ => unreachable!(),
+                };
+                writeln!(f, "    {inst} {dst}, {lhs}, {rhs}").unwrap();
+                writeln!(f, "    {set} {dst}, {dst}")
+            }
+        }
+        .unwrap();
+    }
+
+    fn soft_muldiv(&self) -> Option<&dyn SoftMulDiv> {
+        if self.m_extension {
+            None
+        } else {
+            Some(self)
+        }
+    }
+
+    fn branch_cmp(&self, f: &mut Vec<u8>, op: BinaryOp, lhs: &str, rhs: &str, label: &str) {
+        // gt/le 交换操作数
+        let (inst, lhs, rhs) = match op {
+            BinaryOp::Lt => ("blt", lhs, rhs),
+            BinaryOp::Gt => ("blt", rhs, lhs),
+            BinaryOp::Ge => ("bge", lhs, rhs),
+            BinaryOp::Le => ("bge", rhs, lhs),
+            BinaryOp::Eq => ("beq", lhs, rhs),
+            BinaryOp::NotEq => ("bne", lhs, rhs),
+            _ => unreachable!(),
+        };
+        writeln!(f, "    {inst} {lhs}, {rhs}, {label}").unwrap();
+    }
+
+    fn branch_nonzero(&self, f: &mut Vec<u8>, cond: &str, label: &str) {
+        writeln!(f, "    bnez {cond}, {label}").unwrap();
+    }
+
+    fn jump(&self, f: &mut Vec<u8>, label: &str) {
+        writeln!(f, "    j {label}").unwrap();
+    }
+
+    fn ret(&self, f: &mut Vec<u8>) {
+        writeln!(f, "    ret").unwrap();
+    }
+
+    fn text_section(&self, f: &mut Vec<u8>) {
+        writeln!(f, "    .text").unwrap();
+    }
+
+    fn global_symbol(&self, f: &mut Vec<u8>, name: &str) {
+        writeln!(f, "    .globl {name}").unwrap();
+    }
+
+    fn global_data(&self, f: &mut Vec<u8>, name: &str, data: &[Data]) {
+        writeln!(f, "    .data").unwrap();
+        writeln!(f, "    .globl {name}").unwrap();
+        writeln!(f, "{name}:").unwrap();
+        for item in data {
+            match item {
+                Data::Word(value) => writeln!(f, "    .word {value}").unwrap(),
+                Data::Zero(size) => writeln!(f, "    .zero {size}").unwrap(),
+            }
+        }
+    }
+
+    fn label(&self, f: &mut Vec<u8>, name: &str) {
+        writeln!(f, "{name}:").unwrap();
+    }
+
+    // 形如 .Lmain_then
+    fn local_label(&self, func: &str, bb: &str) -> String {
+        format!(".L{func}_{bb}")
+    }
+
+    fn finish_function(
+        &self,
+        body: &str,
+        peephole: &PeepholeConfig,
+        stats: &mut PeepholeStats,
+    ) -> String {
+        let body = run_peephole(body, Syntax::Riscv, peephole, stats);
+        // 函数体生成完之后才知道各条指令的偏移, 再处理超出范围的跳转
+        relax_branches(&body, SCRATCH[1])
+    }
+}
+
+impl SoftMulDiv for Riscv {
+    fn mul_by_const(&self, f: &mut Vec<u8>, dst: &str, x: &str, c: i32) {
+        mul_by_const(f, x, c, dst);
+    }
+
+    fn call_routine(
+        &self,
+        f: &mut Vec<u8>,
+        op: BinaryOp,
+        dst: &str,
+        lhs: &str,
+        rhs: &str,
+    ) -> &'static str {
+        let name = match op {
+            BinaryOp::Mul => MULSI3,
+            BinaryOp::Div => DIVSI3,
+            BinaryOp::Mod => MODSI3,
+            _ => unreachable!(),
+        };
+        call_routine(f, name, lhs, rhs, dst);
+        name
+    }
+
+    fn routine(&self, name: &str) -> String {
+        routine(name)
+    }
+}
@@ -0,0 +1,137 @@
+use std::io::Write;
+
+// 没有 M 扩展 (只实现 RV32I) 的目标上, 乘除法由编译器自己输出的例程完成.
+// 这些例程只修改 a0 和 a1, 用到的其它寄存器都先保存在栈上,
+// 因此调用处不需要保存分配出去的临时寄存器.
+
+pub(super) const MULSI3: &str = "__mulsi3";
+pub(super) const DIVSI3: &str = "__divsi3";
+pub(super) const MODSI3: &str = "__modsi3";
+
+/// 调用 routine 计算 output = routine(lhs, rhs)
+pub(super) fn call_routine(f: &mut Vec<u8>, routine: &str, lhs: &str, rhs: &str, output: &str) {
+    // 保存 ra, a0, a1, 再通过栈传参, 避免 lhs/rhs 本身就在 a0/a1 中时互相覆盖
+    writeln!(f, "    addi sp, sp, -16").unwrap();
+    writeln!(f, "    sw ra, 12(sp)").unwrap();
+    writeln!(f, "    sw a0, 8(sp)").unwrap();
+    writeln!(f, "    sw a1, 4(sp)").unwrap();
+    writeln!(f, "    sw {rhs}, 0(sp)").unwrap();
+    writeln!(f, "    mv a0, {lhs}").unwrap();
+    writeln!(f, "    lw a1, 0(sp)").unwrap();
+    writeln!(f, "    call {routine}").unwrap();
+    // 结果最后再取回, 以免 output 恰好是 a0/a1 时被恢复的值覆盖
+    writeln!(f, "    sw a0, 0(sp)").unwrap();
+    writeln!(f, "    lw ra, 12(sp)").unwrap();
+    writeln!(f, "    lw a0, 8(sp)").unwrap();
+    writeln!(f, "    lw a1, 4(sp)").unwrap();
+    writeln!(f, "    lw {output}, 0(sp)").unwrap();
+    writeln!(f, "    addi sp, sp, 16").unwrap();
+}
+
+/// 用移位和加法计算 output = x * c, output 不能与 x 相同
+pub(super) fn mul_by_const(f: &mut Vec<u8>, x: &str, c: i32, output: &str) {
+    let m = c.unsigned_abs();
+    if m == 0 {
+        writeln!(f, "    li {output}, 0").unwrap();
+        return;
+    }
+    // 从最高位开始按 Horner 规则: output = (output << k) + x
+    let top = 31 - m.leading_zeros();
+    writeln!(f, "    mv {output}, {x}").unwrap();
+    let mut shift = 0;
+    for i in (0..top).rev() {
+        shift += 1;
+        if (m >> i) & 1 == 1 {
+            writeln!(f, "    slli {output}, {output}, {shift}").unwrap();
+            writeln!(f, "    add {output}, {output}, {x}").unwrap();
+            shift = 0;
+        }
+    }
+    if shift > 0 {
+        writeln!(f, "    slli {output}, {output}, {shift}").unwrap();
+    }
+    if c < 0 {
+        writeln!(f, "    neg {output}, {output}").unwrap();
+    }
+}
+
+fn mulsi3() -> String {
+    let mut f = Vec::new();
+    writeln!(f, "{MULSI3}:").unwrap();
+    writeln!(f, "    addi sp, sp, -16").unwrap();
+    writeln!(f, "    sw t0, 12(sp)").unwrap();
+    writeln!(f, "    sw t1, 8(sp)").unwrap();
+    writeln!(f, "    mv t0, a0").unwrap();
+    writeln!(f, "    li a0, 0").unwrap();
+    writeln!(f, ".L{MULSI3}_loop:").unwrap();
+    writeln!(f, "    beqz a1, .L{MULSI3}_end").unwrap();
+    writeln!(f, "    andi t1, a1, 1").unwrap();
+    writeln!(f, "    beqz t1, .L{MULSI3}_skip").unwrap();
+    writeln!(f, "    add a0, a0, t0").unwrap();
+    writeln!(f, ".L{MULSI3}_skip:").unwrap();
+    writeln!(f, "    slli t0, t0, 1").unwrap();
+    writeln!(f, "    srli a1, a1, 1").unwrap();
+    writeln!(f, "    j .L{MULSI3}_loop").unwrap();
+    writeln!(f, ".L{MULSI3}_end:").unwrap();
+    writeln!(f, "    lw t0, 12(sp)").unwrap();
+    writeln!(f, "    lw t1, 8(sp)").unwrap();
+    writeln!(f, "    addi sp, sp, 16").unwrap();
+    writeln!(f, "    ret").unwrap();
+    String::from_utf8(f).unwrap()
+}
+
+// 先对绝对值做无符号的恢复余数除法, 再修正符号.
+// 与 div/rem 一致: 商向零取整, 余数与被除数同号, INT_MIN / -1 = INT_MIN
+fn divmodsi3(name: &str, want_rem: bool) -> String {
+    let mut f = Vec::new();
+    writeln!(f, "{name}:").unwrap();
+    writeln!(f, "    addi sp, sp, -32").unwrap();
+    for (i, reg) in ["t0", "t1", "t2", "t3", "t4"].iter().enumerate() {
+        writeln!(f, "    sw {reg}, {}(sp)", 28 - 4 * i).unwrap();
+    }
+    // t4 = 结果的符号 (0 或 -1), a0/a1 取绝对值
+    writeln!(f, "    srai t4, a0, 31").unwrap();
+    writeln!(f, "    srai t3, a1, 31").unwrap();
+    writeln!(f, "    xor a0, a0, t4").unwrap();
+    writeln!(f, "    sub a0, a0, t4").unwrap();
+    writeln!(f, "    xor a1, a1, t3").unwrap();
+    writeln!(f, "    sub a1, a1, t3").unwrap();
+    if !want_rem {
+        writeln!(f, "    xor t4, t4, t3").unwrap();
+    }
+    // t0 = 商, t1 = 余数, t2 = 剩余位数
+    writeln!(f, "    li t0, 0").unwrap();
+    writeln!(f, "    li t1, 0").unwrap();
+    writeln!(f, "    li t2, 32").unwrap();
+    writeln!(f, ".L{name}_loop:").unwrap();
+    writeln!(f, "    slli t1, t1, 1").unwrap();
+    writeln!(f, "    srli t3, a0, 31").unwrap();
+    writeln!(f, "    or t1, t1, t3").unwrap();
+    writeln!(f, "    slli a0, a0, 1").unwrap();
+    writeln!(f, "    slli t0, t0, 1").unwrap();
+    writeln!(f, "    bltu t1, a1, .L{name}_skip").unwrap();
+    writeln!(f, "    sub t1, t1, a1").unwrap();
+    writeln!(f, "    ori t0, t0, 1").unwrap();
+    writeln!(f, ".L{name}_skip:").unwrap();
+    writeln!(f, "    addi t2, t2, -1").unwrap();
+    writeln!(f, "    bnez t2, .L{name}_loop").unwrap();
+    writeln!(f, "    mv a0, {}", if want_rem { "t1" } else { "t0" }).unwrap();
+    writeln!(f, "    xor a0, a0, t4").unwrap();
+    writeln!(f, "    sub a0, a0, t4").unwrap();
+    for (i, reg) in ["t0", "t1", "t2", "t3", "t4"].iter().enumerate() {
+        writeln!(f, "    lw {reg}, {}(sp)", 28 - 4 * i).unwrap();
+    }
+    writeln!(f, "    addi sp, sp, 32").unwrap();
+    writeln!(f, "    ret").unwrap();
+    String::from_utf8(f).unwrap()
+}
+
+/// 例程的汇编代码
+pub(super) fn routine(name: &str) -> String {
+    match name {
+        MULSI3 => mulsi3(),
+        DIVSI3 => divmodsi3(DIVSI3, false),
+        MODSI3 => divmodsi3(MODSI3, true),
+        _ => unreachable!(),
+    }
+}
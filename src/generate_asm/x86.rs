@@ -0,0 +1,312 @@
+use std::cell::Cell;
+use std::io::Write;
+
+use koopa::ir::BinaryOp;
+
+use super::isa::{Addr, Data, Frame, Isa};
+use super::peephole::{run_peephole, Syntax};
+use super::{PeepholeConfig, PeepholeStats};
+
+// System V AMD64 调用约定, AT&T 语法. 寄存器都用 64 位的名字, 按 int 运算时换成低 32 位的名字.
+// %rax 存放返回值, 同时与 %rdx 一起用于除法, %rcx 存放移位的位数, 这三个寄存器不参与分配;
+// %r10, %r11 是装入常数和溢出值的临时寄存器.
+// 其余调用者保存的寄存器都是参数寄存器, 不跨越调用的值可以使用它们
+static CALLER_SAVED: [&str; 4] = ["%rsi", "%rdi", "%r8", "%r9"];
+static CALLEE_SAVED: [&str; 6] = ["%rbx", "%r12", "%r13", "%r14", "%r15", "%rbp"];
+static ARG_REGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const SCRATCH: [&str; 2] = ["%r10", "%r11"];
+
+// 寄存器的低 32 位
+fn low32(reg: &str) -> String {
+    match reg {
+        "%rax" => "%eax".to_owned(),
+        "%rbx" => "%ebx".to_owned(),
+        "%rcx" => "%ecx".to_owned(),
+        "%rdx" => "%edx".to_owned(),
+        "%rsi" => "%esi".to_owned(),
+        "%rdi" => "%edi".to_owned(),
+        "%rbp" => "%ebp".to_owned(),
+        _ => format!("{reg}d"),
+    }
+}
+
+/// x86-64 指令集
+///
+/// int 的运算使用 32 位指令, 结果写入寄存器时高 32 位清零, 不保证是符号扩展的;
+/// 用作下标时由 add_ptr_scaled 重新符号扩展
+#[derive(Default)]
+pub struct X86_64 {
+    // 除法中特判除数为 -1 时用到的标号, 在整个程序中编号
+    label_id: Cell<usize>,
+}
+
+impl X86_64 {
+    fn gen_label(&self) -> String {
+        let id = self.label_id.get();
+        self.label_id.set(id + 1);
+        format!(".Lx86.{id}")
+    }
+
+    fn mem_operand(addr: Addr) -> String {
+        match addr {
+            Addr::Stack(offset) => format!("{offset}(%rsp)"),
+            Addr::Reg(reg) => format!("({reg})"),
+            Addr::Global(name) => format!("{name}(%rip)"),
+        }
+    }
+}
+
+impl Isa for X86_64 {
+    fn caller_saved(&self) -> &'static [&'static str] {
+        &CALLER_SAVED
+    }
+
+    fn callee_saved(&self) -> &'static [&'static str] {
+        &CALLEE_SAVED
+    }
+
+    fn scratch(&self) -> [&'static str; 2] {
+        SCRATCH
+    }
+
+    fn zero_reg(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn arg_regs(&self) -> &'static [&'static str] {
+        &ARG_REGS
+    }
+
+    fn ret_reg(&self) -> &'static str {
+        "%rax"
+    }
+
+    // 返回地址由 call 压栈
+    fn link_reg(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn call(&self, f: &mut Vec<u8>, func: &str) {
+        writeln!(f, "    call {func}").unwrap();
+    }
+
+    fn word_size(&self) -> usize {
+        8
+    }
+
+    // 进入函数时返回地址占 8 字节, 栈帧的大小使调用前的 %rsp 保持 16 字节对齐
+    fn frame(&self, outgoing: usize, locals: usize, saved: &[&'static str]) -> Frame {
+        let saved: Vec<_> = saved
+            .iter()
+            .enumerate()
+            .map(|(i, &reg)| (reg, outgoing + i * 8))
+            .collect();
+        let start = outgoing + saved.len() * 8;
+        let size = (start + locals + 8).next_multiple_of(16) - 8;
+        Frame {
+            size,
+            saved,
+            locals: start,
+            incoming: size + 8,
+        }
+    }
+
+    fn prologue(&self, frame: &Frame) -> String {
+        let mut f = Vec::new();
+        if frame.size > 0 {
+            writeln!(f, "    subq ${}, %rsp", frame.size).unwrap();
+        }
+        for &(reg, offset) in &frame.saved {
+            self.store_stack(&mut f, reg, offset);
+        }
+        String::from_utf8(f).unwrap()
+    }
+
+    fn epilogue(&self, frame: &Frame) -> String {
+        let mut f = Vec::new();
+        for &(reg, offset) in &frame.saved {
+            self.load_stack(&mut f, reg, offset);
+        }
+        if frame.size > 0 {
+            writeln!(f, "    addq ${}, %rsp", frame.size).unwrap();
+        }
+        String::from_utf8(f).unwrap()
+    }
+
+    fn load(&self, f: &mut Vec<u8>, dst: &str, addr: Addr, size: usize) {
+        let op = match size {
+            4 => "movslq",
+            _ => "movq",
+        };
+        writeln!(f, "    {op} {}, {dst}", Self::mem_operand(addr)).unwrap();
+    }
+
+    fn store(&self, f: &mut Vec<u8>, src: &str, addr: Addr, size: usize) {
+        let addr = Self::mem_operand(addr);
+        match size {
+            4 => writeln!(f, "    movl {}, {addr}", low32(src)).unwrap(),
+            _ => writeln!(f, "    movq {src}, {addr}").unwrap(),
+        }
+    }
+
+    fn address(&self, f: &mut Vec<u8>, dst: &str, addr: Addr) {
+        match addr {
+            Addr::Reg(reg) => self.mov(f, dst, reg),
+            addr => writeln!(f, "    leaq {}, {dst}", Self::mem_operand(addr)).unwrap(),
+        }
+    }
+
+    fn add_ptr_imm(&self, f: &mut Vec<u8>, dst: &str, base: &str, offset: i64) {
+        if i32::try_from(offset).is_ok() {
+            writeln!(f, "    leaq {offset}({base}), {dst}").unwrap();
+        } else {
+            writeln!(f, "    movabsq ${offset}, %rax").unwrap();
+            writeln!(f, "    leaq ({base},%rax), {dst}").unwrap();
+        }
+    }
+
+    fn add_ptr_scaled(&self, f: &mut Vec<u8>, dst: &str, base: &str, index: &str, scale: usize) {
+        writeln!(f, "    movslq {}, %rax", low32(index)).unwrap();
+        if matches!(scale, 1 | 2 | 4 | 8) {
+            writeln!(f, "    leaq ({base},%rax,{scale}), {dst}").unwrap();
+        } else {
+            writeln!(f, "    imulq ${scale}, %rax").unwrap();
+            writeln!(f, "    leaq ({base},%rax), {dst}").unwrap();
+        }
+    }
+
+    fn load_imm(&self, f: &mut Vec<u8>, dst: &str, imm: i32) {
+        writeln!(f, "    movq ${imm}, {dst}").unwrap();
+    }
+
+    fn mov(&self, f: &mut Vec<u8>, dst: &str, src: &str) {
+        writeln!(f, "    movq {src}, {dst}").unwrap();
+    }
+
+    // 在 %eax 中计算, 最后写回 dst, dst 与操作数相同也不影响
+    fn binary(&self, f: &mut Vec<u8>, op: BinaryOp, dst: &str, lhs: &str, rhs: &str) {
+        let (lhs, rhs) = (low32(lhs), low32(rhs));
+        writeln!(f, "    movl {lhs}, %eax").unwrap();
+        match op {
+            BinaryOp::Add => writeln!(f, "    addl {rhs}, %eax").unwrap(),
+            BinaryOp::Sub => writeln!(f, "    subl {rhs}, %eax").unwrap(),
+            BinaryOp::Mul => writeln!(f, "    imull {rhs}, %eax").unwrap(),
+            BinaryOp::And => writeln!(f, "    andl {rhs}, %eax").unwrap(),
+            BinaryOp::Or => writeln!(f, "    orl {rhs}, %eax").unwrap(),
+            BinaryOp::Xor => writeln!(f, "    xorl {rhs}, %eax").unwrap(),
+            BinaryOp::Shl | BinaryOp::Shr | BinaryOp::Sar => {
+                let inst = match op {
+                    BinaryOp::Shl => "shll",
+                    BinaryOp::Shr => "shrl",
+                    _ => "sarl",
+                };
+                writeln!(f, "    movl {rhs}, %ecx").unwrap();
+                writeln!(f, "    {inst} %cl, %eax").unwrap();
+            }
+            BinaryOp::Div | BinaryOp::Mod => {
+                // idivl 在 INT_MIN / -1 时陷入, 与其他后端一样按回绕计算:
+                // 除数为 -1 时商为 -lhs, 余数为 0
+                let general = self.gen_label();
+                let end = self.gen_label();
+                writeln!(f, "    cmpl $-1, {rhs}").unwrap();
+                writeln!(f, "    jne {general}").unwrap();
+                if op == BinaryOp::Div {
+                    writeln!(f, "    negl %eax").unwrap();
+                } else {
+                    writeln!(f, "    xorl %eax, %eax").unwrap();
+                }
+                writeln!(f, "    jmp {end}").unwrap();
+                writeln!(f, "{general}:").unwrap();
+                // 被除数符号扩展到 %edx:%eax, 商在 %eax, 余数在 %edx
+                writeln!(f, "    cltd").unwrap();
+                writeln!(f, "    idivl {rhs}").unwrap();
+                if op == BinaryOp::Mod {
+                    writeln!(f, "    movl %edx, %eax").unwrap();
+                }
+                writeln!(f, "{end}:").unwrap();
+            }
+            op => {
+                let set = match op {
+                    BinaryOp::Eq => "sete",
+                    BinaryOp::NotEq => "setne",
+                    BinaryOp::Lt => "setl",
+                    BinaryOp::Gt => "setg",
+                    BinaryOp::Le => "setle",
+                    BinaryOp::Ge => "setge",
+                    _ => unreachable!(),
+                };
+                writeln!(f, "    cmpl {rhs}, %eax").unwrap();
+                writeln!(f, "    {set} %al").unwrap();
+                writeln!(f, "    movzbl %al, %eax").unwrap();
+            }
+        }
+        writeln!(f, "    movl %eax, {}", low32(dst)).unwrap();
+    }
+
+    fn branch_cmp(&self, f: &mut Vec<u8>, op: BinaryOp, lhs: &str, rhs: &str, label: &str) {
+        let inst = match op {
+            BinaryOp::Lt => "jl",
+            BinaryOp::Gt => "jg",
+            BinaryOp::Le => "jle",
+            BinaryOp::Ge => "jge",
+            BinaryOp::Eq => "je",
+            BinaryOp::NotEq => "jne",
+            _ => unreachable!(),
+        };
+        writeln!(f, "    cmpl {}, {}", low32(rhs), low32(lhs)).unwrap();
+        writeln!(f, "    {inst} {label}").unwrap();
+    }
+
+    fn branch_nonzero(&self, f: &mut Vec<u8>, cond: &str, label: &str) {
+        let cond = low32(cond);
+        writeln!(f, "    testl {cond}, {cond}").unwrap();
+        writeln!(f, "    jne {label}").unwrap();
+    }
+
+    fn jump(&self, f: &mut Vec<u8>, label: &str) {
+        writeln!(f, "    jmp {label}").unwrap();
+    }
+
+    fn ret(&self, f: &mut Vec<u8>) {
+        writeln!(f, "    ret").unwrap();
+    }
+
+    fn text_section(&self, f: &mut Vec<u8>) {
+        writeln!(f, "    .text").unwrap();
+    }
+
+    fn global_symbol(&self, f: &mut Vec<u8>, name: &str) {
+        writeln!(f, "    .globl {name}").unwrap();
+    }
+
+    fn global_data(&self, f: &mut Vec<u8>, name: &str, data: &[Data]) {
+        writeln!(f, "    .data").unwrap();
+        writeln!(f, "    .globl {name}").unwrap();
+        writeln!(f, "    .p2align 2").unwrap();
+        writeln!(f, "{name}:").unwrap();
+        for item in data {
+            match item {
+                Data::Word(value) => writeln!(f, "    .long {value}").unwrap(),
+                Data::Zero(size) => writeln!(f, "    .zero {size}").unwrap(),
+            }
+        }
+    }
+
+    fn label(&self, f: &mut Vec<u8>, name: &str) {
+        writeln!(f, "{name}:").unwrap();
+    }
+
+    fn local_label(&self, func: &str, bb: &str) -> String {
+        format!(".L{func}_{bb}")
+    }
+
+    // x86 的跳转没有范围限制, 只做窥孔优化
+    fn finish_function(
+        &self,
+        body: &str,
+        peephole: &PeepholeConfig,
+        stats: &mut PeepholeStats,
+    ) -> String {
+        run_peephole(body, Syntax::Att, peephole, stats)
+    }
+}
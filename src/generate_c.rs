@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use koopa::ir::{
+    entities::ValueData,
+    layout::BasicBlockNode,
+    values::{Binary, Branch, Call, GetElemPtr, GetPtr, Jump, Load, Return, Store},
+    BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind,
+};
+
+// 有符号溢出和 INT_MIN / -1 在 C 中是未定义行为, 运算统一换成回绕的写法
+static PRELUDE: &str = "\
+#include <stdint.h>
+
+static inline int32_t koopa_div(int32_t a, int32_t b) {
+  return b == -1 ? (int32_t)(0u - (uint32_t)a) : a / b;
+}
+
+static inline int32_t koopa_mod(int32_t a, int32_t b) {
+  return b == -1 ? 0 : a % b;
+}
+";
+
+/// 把 Koopa IR 翻译成 C 时的上下文
+///
+/// 每条指令对应一条 C 语句, 基本块对应标号和 goto.
+/// 所有内存都按 32 位字访问, 指针统一为 `int32_t *`, 数组展开成一维
+pub struct CInfo<'p> {
+    program: &'p Program,
+    which_func: Option<Function>,
+    // 参数, 基本块参数和指令结果对应的 C 变量名
+    names: HashMap<Value, String>,
+    cur_value: Option<Value>,
+}
+
+impl<'p> CInfo<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        Self {
+            program,
+            which_func: None,
+            names: HashMap::new(),
+            cur_value: None,
+        }
+    }
+
+    fn get_key(&self) -> Value {
+        self.cur_value.unwrap()
+    }
+
+    fn set_key(&mut self, key: Value) {
+        self.cur_value = Some(key);
+    }
+
+    fn get_func_data(&self) -> &FunctionData {
+        self.program.func(self.which_func.unwrap())
+    }
+
+    fn get_data(&self, value: Value) -> &ValueData {
+        self.get_func_data().dfg().value(value)
+    }
+
+    fn bb_label(&self, bb: BasicBlock) -> String {
+        let name = self.get_func_data().dfg().bb(bb).name().as_ref().unwrap();
+        format!("L_{}", &name[1..])
+    }
+
+    fn set_func(&mut self, func: Function) {
+        self.which_func = Some(func);
+        self.names.clear();
+        let func_data = self.program.func(func);
+        for (i, &param) in func_data.params().iter().enumerate() {
+            self.names.insert(param, format!("p{i}"));
+        }
+        let mut count = 0;
+        for (&bb, node) in func_data.layout().bbs() {
+            let params = func_data.dfg().bb(bb).params();
+            for &value in params.iter().chain(node.insts().keys()) {
+                if func_data.dfg().value(value).ty().is_unit() {
+                    continue;
+                }
+                self.names.insert(value, format!("v{count}"));
+                count += 1;
+            }
+        }
+    }
+
+    fn value_type(&self, value: Value) -> Type {
+        if value.is_global() {
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            self.get_data(value).ty().clone()
+        }
+    }
+
+    // value 作为操作数的写法: 常量, 全局变量或局部变量
+    fn operand(&self, value: Value) -> String {
+        if value.is_global() {
+            return global_name(self.program, value);
+        }
+        match self.get_data(value).kind() {
+            ValueKind::Integer(int) if int.value() == i32::MIN => "INT32_MIN".to_owned(),
+            ValueKind::Integer(int) => int.value().to_string(),
+            ValueKind::Undef(_) => "0".to_owned(),
+            _ => self.names[&value].clone(),
+        }
+    }
+
+    // 当前指令的结果变量
+    fn result(&self) -> &str {
+        &self.names[&self.get_key()]
+    }
+
+    // 跳转到 target 前给基本块参数赋值, 先读出全部实参再写入, 相当于并行赋值
+    fn assign_args(&self, target: BasicBlock, args: &[Value]) -> String {
+        let params = self.get_func_data().dfg().bb(target).params();
+        let mut stmt = String::new();
+        if args.is_empty() {
+            return stmt;
+        }
+        stmt += "{ ";
+        for (i, (&param, &arg)) in params.iter().zip(args).enumerate() {
+            let ty = c_type(&self.value_type(param));
+            stmt += &format!("{ty} a{i} = {}; ", self.operand(arg));
+        }
+        for (i, param) in params.iter().enumerate() {
+            stmt += &format!("{} = a{i}; ", self.names[param]);
+        }
+        stmt += "} ";
+        stmt
+    }
+}
+
+// 全局变量加上前缀, 避免与 C 的关键字或库函数重名
+fn global_name(program: &Program, global: Value) -> String {
+    let data = program.borrow_value(global);
+    format!("g_{}", &data.name().as_ref().unwrap()[1..])
+}
+
+// 函数也加上前缀, 以免与 C 标准库的函数 (如 abs, exit) 冲突.
+// main 和只有声明的函数 (SysY 运行时库) 由外部提供或调用, 保留原名
+fn func_name(program: &Program, func: Function) -> String {
+    let data = program.func(func);
+    let name = &data.name()[1..];
+    if name == "main" || data.layout().entry_bb().is_none() {
+        name.to_owned()
+    } else {
+        format!("f_{name}")
+    }
+}
+
+// 除了 unit 之外只有 i32 和指针两种值
+fn c_type(ty: &Type) -> &'static str {
+    match ty.kind() {
+        TypeKind::Int32 => "int32_t",
+        TypeKind::Unit => "void",
+        TypeKind::Pointer(_) => "int32_t *",
+        _ => unreachable!(),
+    }
+}
+
+// 类型占用的 32 位字数
+fn words(ty: &Type) -> usize {
+    ty.size() / 4
+}
+
+fn pointee(ty: &Type) -> Type {
+    match ty.kind() {
+        TypeKind::Pointer(base) => base.clone(),
+        _ => unreachable!(),
+    }
+}
+
+// 全局变量的初始值展开成一维的整数列表
+fn flatten_init(program: &Program, init: Value, elems: &mut Vec<String>) {
+    let data = program.borrow_value(init);
+    match data.kind() {
+        ValueKind::Integer(int) => elems.push(int.value().to_string()),
+        ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+            elems.extend(std::iter::repeat_n("0".to_owned(), words(data.ty())))
+        }
+        ValueKind::Aggregate(agg) => {
+            for &elem in agg.elems() {
+                flatten_init(program, elem, elems);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn prototype(name: &str, ty: &Type, params: &[String]) -> String {
+    let (param_tys, ret) = match ty.kind() {
+        TypeKind::Function(params, ret) => (params, ret),
+        _ => unreachable!(),
+    };
+    let params: Vec<String> = if param_tys.is_empty() {
+        vec!["void".to_owned()]
+    } else {
+        param_tys
+            .iter()
+            .zip(params)
+            .map(|(ty, name)| match name.as_str() {
+                "" => c_type(ty).to_owned(),
+                name => format!("{} {name}", c_type(ty)),
+            })
+            .collect()
+    };
+    format!("{} {name}({})", c_type(ret), params.join(", "))
+}
+
+pub trait GenerateC {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>);
+}
+
+impl GenerateC for Program {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        writeln!(f, "{PRELUDE}").unwrap();
+
+        // 所有函数先声明, 未定义的函数 (SysY 运行时库) 由链接器提供
+        for &func in self.func_layout() {
+            let func_data = self.func(func);
+            let params = vec![String::new(); func_data.params().len()];
+            let proto = prototype(&func_name(self, func), func_data.ty(), &params);
+            writeln!(f, "{proto};").unwrap();
+        }
+        writeln!(f).unwrap();
+
+        // 全局变量, 标量也按长度为 1 的数组存放, 使变量名就是它的地址
+        for &global in self.inst_layout() {
+            let data = self.borrow_value(global);
+            let init = match data.kind() {
+                ValueKind::GlobalAlloc(alloc) => alloc.init(),
+                _ => unreachable!(),
+            };
+            let mut elems = Vec::new();
+            flatten_init(self, init, &mut elems);
+            let name = global_name(self, global);
+            let len = words(&pointee(data.ty()));
+            writeln!(f, "int32_t {name}[{len}] = {{{}}};", elems.join(", ")).unwrap();
+        }
+        if !self.inst_layout().is_empty() {
+            writeln!(f).unwrap();
+        }
+
+        for &func in self.func_layout() {
+            let func_data = self.func(func);
+            if func_data.layout().entry_bb().is_none() {
+                continue;
+            }
+            info.set_func(func);
+            func_data.generate_c(info, f);
+        }
+    }
+}
+
+impl GenerateC for FunctionData {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let params: Vec<String> = (0..self.params().len()).map(|i| format!("p{i}")).collect();
+        let name = func_name(info.program, info.which_func.unwrap());
+        writeln!(f, "{} {{", prototype(&name, self.ty(), &params)).unwrap();
+
+        // 变量都在函数开头定义, 以免 goto 跳过初始化
+        for (&bb, node) in self.layout().bbs() {
+            let bb_params = self.dfg().bb(bb).params();
+            for &value in bb_params.iter().chain(node.insts().keys()) {
+                let data = self.dfg().value(value);
+                if data.ty().is_unit() {
+                    continue;
+                }
+                let name = &info.names[&value];
+                if let ValueKind::Alloc(_) = data.kind() {
+                    let len = words(&pointee(data.ty()));
+                    writeln!(f, "  int32_t {name}_mem[{len}];").unwrap();
+                }
+                writeln!(f, "  {} {name};", c_type(data.ty())).unwrap();
+            }
+        }
+
+        // 只给会被跳转到的基本块加标号, 避免未使用标号的警告
+        for (&bb, node) in self.layout().bbs() {
+            if !self.dfg().bb(bb).used_by().is_empty() {
+                writeln!(f, "{}:", info.bb_label(bb)).unwrap();
+            }
+            node.generate_c(info, f);
+        }
+        writeln!(f, "}}").unwrap();
+        writeln!(f).unwrap();
+    }
+}
+
+impl GenerateC for BasicBlockNode {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        for &inst in self.insts().keys() {
+            let value_data = info.get_data(inst).clone();
+            info.set_key(inst);
+            value_data.generate_c(info, f);
+        }
+    }
+}
+
+impl GenerateC for ValueData {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        match self.kind() {
+            ValueKind::Alloc(_) => {
+                let name = info.result();
+                writeln!(f, "  {name} = {name}_mem;").unwrap();
+            }
+            ValueKind::Load(load) => load.generate_c(info, f),
+            ValueKind::Store(store) => store.generate_c(info, f),
+            ValueKind::GetPtr(ptr) => ptr.generate_c(info, f),
+            ValueKind::GetElemPtr(ptr) => ptr.generate_c(info, f),
+            ValueKind::Binary(bin) => bin.generate_c(info, f),
+            ValueKind::Branch(br) => br.generate_c(info, f),
+            ValueKind::Jump(jump) => jump.generate_c(info, f),
+            ValueKind::Call(call) => call.generate_c(info, f),
+            ValueKind::Return(ret) => ret.generate_c(info, f),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl GenerateC for Load {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        writeln!(f, "  {} = *{};", info.result(), info.operand(self.src())).unwrap();
+    }
+}
+
+impl GenerateC for Store {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let value = info.operand(self.value());
+        writeln!(f, "  *{} = {value};", info.operand(self.dest())).unwrap();
+    }
+}
+
+impl GenerateC for GetPtr {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let stride = words(&pointee(&info.value_type(self.src())));
+        let src = info.operand(self.src());
+        let index = info.operand(self.index());
+        writeln!(f, "  {} = {src} + {index} * {stride};", info.result()).unwrap();
+    }
+}
+
+impl GenerateC for GetElemPtr {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let stride = match pointee(&info.value_type(self.src())).kind() {
+            TypeKind::Array(base, _) => words(base),
+            _ => unreachable!(),
+        };
+        let src = info.operand(self.src());
+        let index = info.operand(self.index());
+        writeln!(f, "  {} = {src} + {index} * {stride};", info.result()).unwrap();
+    }
+}
+
+impl GenerateC for Binary {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let lhs = info.operand(self.lhs());
+        let rhs = info.operand(self.rhs());
+        let expr = match self.op() {
+            // 加减乘在无符号数上做, 结果按补码回绕
+            BinaryOp::Add => format!("(int32_t)((uint32_t){lhs} + (uint32_t){rhs})"),
+            BinaryOp::Sub => format!("(int32_t)((uint32_t){lhs} - (uint32_t){rhs})"),
+            BinaryOp::Mul => format!("(int32_t)((uint32_t){lhs} * (uint32_t){rhs})"),
+            BinaryOp::Div => format!("koopa_div({lhs}, {rhs})"),
+            BinaryOp::Mod => format!("koopa_mod({lhs}, {rhs})"),
+            BinaryOp::And => format!("{lhs} & {rhs}"),
+            BinaryOp::Or => format!("{lhs} | {rhs}"),
+            BinaryOp::Xor => format!("{lhs} ^ {rhs}"),
+            BinaryOp::Shl => format!("(int32_t)((uint32_t){lhs} << ({rhs} & 31))"),
+            BinaryOp::Shr => format!("(int32_t)((uint32_t){lhs} >> ({rhs} & 31))"),
+            BinaryOp::Sar => format!("{lhs} >> ({rhs} & 31)"),
+            BinaryOp::Eq => format!("{lhs} == {rhs}"),
+            BinaryOp::NotEq => format!("{lhs} != {rhs}"),
+            BinaryOp::Lt => format!("{lhs} < {rhs}"),
+            BinaryOp::Gt => format!("{lhs} > {rhs}"),
+            BinaryOp::Le => format!("{lhs} <= {rhs}"),
+            BinaryOp::Ge => format!("{lhs} >= {rhs}"),
+        };
+        writeln!(f, "  {} = {expr};", info.result()).unwrap();
+    }
+}
+
+impl GenerateC for Branch {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let cond = info.operand(self.cond());
+        let true_args = info.assign_args(self.true_bb(), self.true_args());
+        let false_args = info.assign_args(self.false_bb(), self.false_args());
+        writeln!(
+            f,
+            "  if ({cond}) {{ {true_args}goto {}; }} else {{ {false_args}goto {}; }}",
+            info.bb_label(self.true_bb()),
+            info.bb_label(self.false_bb())
+        )
+        .unwrap();
+    }
+}
+
+impl GenerateC for Jump {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let args = info.assign_args(self.target(), self.args());
+        writeln!(f, "  {args}goto {};", info.bb_label(self.target())).unwrap();
+    }
+}
+
+impl GenerateC for Call {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        let args: Vec<String> = self.args().iter().map(|&arg| info.operand(arg)).collect();
+        let call = format!(
+            "{}({})",
+            func_name(info.program, self.callee()),
+            args.join(", ")
+        );
+        if info.get_data(info.get_key()).ty().is_unit() {
+            writeln!(f, "  {call};").unwrap();
+        } else {
+            writeln!(f, "  {} = {call};", info.result()).unwrap();
+        }
+    }
+}
+
+impl GenerateC for Return {
+    fn generate_c(&self, info: &mut CInfo, f: &mut Vec<u8>) {
+        match self.value() {
+            Some(value) => writeln!(f, "  return {};", info.operand(value)).unwrap(),
+            None => writeln!(f, "  return;").unwrap(),
+        }
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use koopa::ir::{
+    entities::ValueData,
+    layout::BasicBlockNode,
+    values::{Binary, Branch, Call, GetElemPtr, GetPtr, Jump, Load, Return, Store},
+    BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind,
+};
+
+/// 生成 LLVM IR 文本时的上下文
+///
+/// 指针统一是不透明的 `ptr`, 基本块参数转换成 phi
+pub struct LlvmInfo<'p> {
+    program: &'p Program,
+    which_func: Option<Function>,
+    // 参数, 基本块参数和指令结果对应的 LLVM 名字
+    names: HashMap<Value, String>,
+    // 把 i32 条件转成 i1 时用到的临时名字编号
+    tmp_id: usize,
+    cur_value: Option<Value>,
+}
+
+impl<'p> LlvmInfo<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        Self {
+            program,
+            which_func: None,
+            names: HashMap::new(),
+            tmp_id: 0,
+            cur_value: None,
+        }
+    }
+
+    fn get_key(&self) -> Value {
+        self.cur_value.unwrap()
+    }
+
+    fn set_key(&mut self, key: Value) {
+        self.cur_value = Some(key);
+    }
+
+    fn get_func_data(&self) -> &FunctionData {
+        self.program.func(self.which_func.unwrap())
+    }
+
+    fn get_data(&self, value: Value) -> &ValueData {
+        self.get_func_data().dfg().value(value)
+    }
+
+    // 没有名字的基本块按在函数中的序号命名, 与 RISC-V 后端相同
+    fn bb_label(&self, bb: BasicBlock) -> String {
+        let func_data = self.get_func_data();
+        match func_data.dfg().bb(bb).name() {
+            Some(name) => format!("bb_{}", &name[1..]),
+            None => {
+                let index = func_data.layout().bbs().keys().position(|&b| b == bb);
+                format!("bb_anon.{}", index.unwrap())
+            }
+        }
+    }
+
+    // br 的两个目标是同一个带参数的块时, 假分支经过的中转块.
+    // phi 对每个前驱只能有一个值, 两条边的实参可能不同, 需要把假分支的边拆开
+    fn false_edge_label(&self, pred: BasicBlock) -> String {
+        format!("{}_false", self.bb_label(pred))
+    }
+
+    // 为函数中的值起名字, Koopa 中的名字可能是数字, 统一换成 %v{n}
+    fn name_values(&mut self, func: Function) {
+        self.which_func = Some(func);
+        self.names.clear();
+        self.tmp_id = 0;
+        let func_data = self.program.func(func);
+        for (i, &param) in func_data.params().iter().enumerate() {
+            self.names.insert(param, format!("%p{i}"));
+        }
+        let mut count = 0;
+        for (&bb, node) in func_data.layout().bbs() {
+            let params = func_data.dfg().bb(bb).params();
+            for &value in params.iter().chain(node.insts().keys()) {
+                if func_data.dfg().value(value).ty().is_unit() {
+                    continue;
+                }
+                self.names.insert(value, format!("%v{count}"));
+                count += 1;
+            }
+        }
+    }
+
+    fn new_tmp(&mut self) -> String {
+        self.tmp_id += 1;
+        format!("%t{}", self.tmp_id)
+    }
+
+    fn value_type(&self, value: Value) -> Type {
+        if value.is_global() {
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            self.get_data(value).ty().clone()
+        }
+    }
+
+    // value 作为操作数的写法: 常量, 全局变量或局部名字
+    fn operand(&self, value: Value) -> String {
+        if value.is_global() {
+            let data = self.program.borrow_value(value);
+            return format!("@{}", &data.name().as_ref().unwrap()[1..]);
+        }
+        match self.get_data(value).kind() {
+            ValueKind::Integer(int) => int.value().to_string(),
+            ValueKind::Undef(_) => "undef".to_owned(),
+            _ => self.names[&value].clone(),
+        }
+    }
+
+    // 带类型的操作数, 如 `i32 %v0`
+    fn typed_operand(&self, value: Value) -> String {
+        format!(
+            "{} {}",
+            llvm_type(&self.value_type(value)),
+            self.operand(value)
+        )
+    }
+
+    // 当前指令的结果名字
+    fn result(&self) -> &str {
+        &self.names[&self.get_key()]
+    }
+}
+
+fn llvm_type(ty: &Type) -> String {
+    match ty.kind() {
+        TypeKind::Int32 => "i32".to_owned(),
+        TypeKind::Unit => "void".to_owned(),
+        TypeKind::Array(base, len) => format!("[{len} x {}]", llvm_type(base)),
+        TypeKind::Pointer(_) => "ptr".to_owned(),
+        TypeKind::Function(params, ret) => {
+            let params: Vec<String> = params.iter().map(llvm_type).collect();
+            format!("{} ({})", llvm_type(ret), params.join(", "))
+        }
+    }
+}
+
+fn pointee(ty: &Type) -> Type {
+    match ty.kind() {
+        TypeKind::Pointer(base) => base.clone(),
+        _ => unreachable!(),
+    }
+}
+
+// 全局变量的初始值
+fn initializer(program: &Program, init: Value) -> String {
+    let data = program.borrow_value(init);
+    match data.kind() {
+        ValueKind::Integer(int) => int.value().to_string(),
+        ValueKind::ZeroInit(_) => "zeroinitializer".to_owned(),
+        ValueKind::Undef(_) => "undef".to_owned(),
+        ValueKind::Aggregate(agg) => {
+            let elems: Vec<String> = agg
+                .elems()
+                .iter()
+                .map(|&elem| {
+                    let ty = llvm_type(program.borrow_value(elem).ty());
+                    format!("{ty} {}", initializer(program, elem))
+                })
+                .collect();
+            format!("[{}]", elems.join(", "))
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub trait GenerateLlvm {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>);
+}
+
+impl GenerateLlvm for Program {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        // 全局变量
+        for &global in self.inst_layout() {
+            let data = self.borrow_value(global);
+            let name = &data.name().as_ref().unwrap()[1..];
+            let init = match data.kind() {
+                ValueKind::GlobalAlloc(alloc) => alloc.init(),
+                _ => unreachable!(),
+            };
+            let ty = llvm_type(&pointee(data.ty()));
+            writeln!(f, "@{name} = global {ty} {}", initializer(self, init)).unwrap();
+        }
+        if !self.inst_layout().is_empty() {
+            writeln!(f).unwrap();
+        }
+
+        for &func in self.func_layout() {
+            let func_data = self.func(func);
+            // 只有声明的函数 (如 SysY 运行时库) 由链接器提供
+            if func_data.layout().entry_bb().is_none() {
+                let (params, ret) = match func_data.ty().kind() {
+                    TypeKind::Function(params, ret) => (params, ret),
+                    _ => unreachable!(),
+                };
+                let params: Vec<String> = params.iter().map(llvm_type).collect();
+                let name = &func_data.name()[1..];
+                writeln!(
+                    f,
+                    "declare {} @{name}({})",
+                    llvm_type(ret),
+                    params.join(", ")
+                )
+                .unwrap();
+                writeln!(f).unwrap();
+                continue;
+            }
+            info.name_values(func);
+            func_data.generate_llvm(info, f);
+        }
+    }
+}
+
+impl GenerateLlvm for FunctionData {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let ret = match self.ty().kind() {
+            TypeKind::Function(_, ret) => llvm_type(ret),
+            _ => unreachable!(),
+        };
+        let params: Vec<String> = self
+            .params()
+            .iter()
+            .map(|&param| info.typed_operand(param))
+            .collect();
+        writeln!(
+            f,
+            "define {ret} @{}({}) {{",
+            &self.name()[1..],
+            params.join(", ")
+        )
+        .unwrap();
+
+        // alloca 都放在入口块中, 在函数开头分配一次栈空间.
+        // 放在循环里的 alloca 每次执行都会再分配, 栈会越来越大
+        let mut allocas = Vec::new();
+        for (_, node) in self.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let data = self.dfg().value(inst);
+                if let ValueKind::Alloc(_) = data.kind() {
+                    let ty = llvm_type(&pointee(data.ty()));
+                    writeln!(allocas, "  {} = alloca {ty}", info.names[&inst]).unwrap();
+                }
+            }
+        }
+
+        // LLVM 的入口块不能有前驱, 需要时多加一个入口块
+        let entry = self.layout().entry_bb().unwrap();
+        let synthetic_entry = !self.dfg().bb(entry).used_by().is_empty();
+        if synthetic_entry {
+            writeln!(f, "entry:").unwrap();
+            f.extend(&allocas);
+            writeln!(f, "  br label %{}", info.bb_label(entry)).unwrap();
+        }
+
+        for (&bb, node) in self.layout().bbs() {
+            writeln!(f, "{}:", info.bb_label(bb)).unwrap();
+            if bb == entry && !synthetic_entry {
+                f.extend(&allocas);
+            }
+            generate_phis(info, bb, f);
+            node.generate_llvm(info, f);
+        }
+        writeln!(f, "}}").unwrap();
+        writeln!(f).unwrap();
+    }
+}
+
+// 基本块参数: 在每个跳转到该块的位置取出对应的实参, 组成 phi
+fn generate_phis(info: &LlvmInfo, bb: BasicBlock, f: &mut Vec<u8>) {
+    let func_data = info.get_func_data();
+    let params = func_data.dfg().bb(bb).params();
+    if params.is_empty() {
+        return;
+    }
+    let mut incoming: Vec<Vec<String>> = vec![Vec::new(); params.len()];
+    // 按基本块的排列顺序遍历前驱, 使输出稳定
+    let used_by = func_data.dfg().bb(bb).used_by();
+    for (&pred, node) in func_data.layout().bbs() {
+        let user = match node.insts().back_key() {
+            Some(user) if used_by.contains(user) => *user,
+            _ => continue,
+        };
+        // (实参, 来自的 LLVM 基本块)
+        let edges = match func_data.dfg().value(user).kind() {
+            ValueKind::Jump(jump) => vec![(jump.args(), info.bb_label(pred))],
+            ValueKind::Branch(br) if br.true_bb() == bb && br.false_bb() == bb => vec![
+                (br.true_args(), info.bb_label(pred)),
+                (br.false_args(), info.false_edge_label(pred)),
+            ],
+            ValueKind::Branch(br) if br.true_bb() == bb => {
+                vec![(br.true_args(), info.bb_label(pred))]
+            }
+            ValueKind::Branch(br) => vec![(br.false_args(), info.bb_label(pred))],
+            _ => unreachable!(),
+        };
+        for (args, from) in edges {
+            for (i, &arg) in args.iter().enumerate() {
+                incoming[i].push(format!("[ {}, %{from} ]", info.operand(arg)));
+            }
+        }
+    }
+    for (&param, incoming) in params.iter().zip(incoming) {
+        let ty = llvm_type(func_data.dfg().value(param).ty());
+        let name = &info.names[&param];
+        writeln!(f, "  {name} = phi {ty} {}", incoming.join(", ")).unwrap();
+    }
+}
+
+impl GenerateLlvm for BasicBlockNode {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        for &inst in self.insts().keys() {
+            let value_data = info.get_data(inst).clone();
+            info.set_key(inst);
+            value_data.generate_llvm(info, f);
+        }
+    }
+}
+
+impl GenerateLlvm for ValueData {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        match self.kind() {
+            // 已经在入口块中生成
+            ValueKind::Alloc(_) => {}
+            ValueKind::Load(load) => load.generate_llvm(info, f),
+            ValueKind::Store(store) => store.generate_llvm(info, f),
+            ValueKind::GetPtr(ptr) => ptr.generate_llvm(info, f),
+            ValueKind::GetElemPtr(ptr) => ptr.generate_llvm(info, f),
+            ValueKind::Binary(bin) => bin.generate_llvm(info, f),
+            ValueKind::Branch(br) => br.generate_llvm(info, f),
+            ValueKind::Jump(jump) => jump.generate_llvm(info, f),
+            ValueKind::Call(call) => call.generate_llvm(info, f),
+            ValueKind::Return(ret) => ret.generate_llvm(info, f),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl GenerateLlvm for Load {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let ty = llvm_type(&pointee(&info.value_type(self.src())));
+        let src = info.typed_operand(self.src());
+        writeln!(f, "  {} = load {ty}, {src}", info.result()).unwrap();
+    }
+}
+
+impl GenerateLlvm for Store {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let value = info.typed_operand(self.value());
+        let dest = info.typed_operand(self.dest());
+        writeln!(f, "  store {value}, {dest}").unwrap();
+    }
+}
+
+impl GenerateLlvm for GetPtr {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let ty = llvm_type(&pointee(&info.value_type(self.src())));
+        let src = info.typed_operand(self.src());
+        let index = info.typed_operand(self.index());
+        writeln!(
+            f,
+            "  {} = getelementptr {ty}, {src}, {index}",
+            info.result()
+        )
+        .unwrap();
+    }
+}
+
+impl GenerateLlvm for GetElemPtr {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let ty = llvm_type(&pointee(&info.value_type(self.src())));
+        let src = info.typed_operand(self.src());
+        let index = info.typed_operand(self.index());
+        writeln!(
+            f,
+            "  {} = getelementptr {ty}, {src}, i32 0, {index}",
+            info.result()
+        )
+        .unwrap();
+    }
+}
+
+impl GenerateLlvm for Binary {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let lhs = info.operand(self.lhs());
+        let rhs = info.operand(self.rhs());
+        if let BinaryOp::Div | BinaryOp::Mod = self.op() {
+            // INT_MIN / -1 在 LLVM 中是未定义行为, 与其他后端一样按回绕计算:
+            // 除数为 -1 时商为 0 - lhs, 余数为 0, 否则用 1 代替除数避免溢出
+            let is_neg1 = info.new_tmp();
+            let divisor = info.new_tmp();
+            let normal = info.new_tmp();
+            writeln!(f, "  {is_neg1} = icmp eq i32 {rhs}, -1").unwrap();
+            writeln!(f, "  {divisor} = select i1 {is_neg1}, i32 1, i32 {rhs}").unwrap();
+            let (inst, special) = if self.op() == BinaryOp::Div {
+                let neg = info.new_tmp();
+                writeln!(f, "  {neg} = sub i32 0, {lhs}").unwrap();
+                ("sdiv", neg)
+            } else {
+                ("srem", "0".to_owned())
+            };
+            writeln!(f, "  {normal} = {inst} i32 {lhs}, {divisor}").unwrap();
+            writeln!(
+                f,
+                "  {} = select i1 {is_neg1}, i32 {special}, i32 {normal}",
+                info.result()
+            )
+            .unwrap();
+            return;
+        }
+        let inst = match self.op() {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Xor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Shr => "lshr",
+            BinaryOp::Sar => "ashr",
+            op => {
+                // 比较的结果是 i1, 再零扩展回 i32
+                let cond = match op {
+                    BinaryOp::Eq => "eq",
+                    BinaryOp::NotEq => "ne",
+                    BinaryOp::Lt => "slt",
+                    BinaryOp::Gt => "sgt",
+                    BinaryOp::Le => "sle",
+                    BinaryOp::Ge => "sge",
+                    _ => unreachable!(),
+                };
+                let tmp = info.new_tmp();
+                writeln!(f, "  {tmp} = icmp {cond} i32 {lhs}, {rhs}").unwrap();
+                writeln!(f, "  {} = zext i1 {tmp} to i32", info.result()).unwrap();
+                return;
+            }
+        };
+        writeln!(f, "  {} = {inst} i32 {lhs}, {rhs}", info.result()).unwrap();
+    }
+}
+
+impl GenerateLlvm for Branch {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let tmp = info.new_tmp();
+        let cond = info.operand(self.cond());
+        writeln!(f, "  {tmp} = icmp ne i32 {cond}, 0").unwrap();
+        let target = info.bb_label(self.false_bb());
+        let func_data = info.get_func_data();
+        let split = self.true_bb() == self.false_bb()
+            && !func_data.dfg().bb(self.false_bb()).params().is_empty();
+        let false_label = if split {
+            let pred = func_data.layout().parent_bb(info.get_key()).unwrap();
+            info.false_edge_label(pred)
+        } else {
+            target.clone()
+        };
+        writeln!(
+            f,
+            "  br i1 {tmp}, label %{}, label %{false_label}",
+            info.bb_label(self.true_bb()),
+        )
+        .unwrap();
+        if split {
+            writeln!(f, "{false_label}:").unwrap();
+            writeln!(f, "  br label %{target}").unwrap();
+        }
+    }
+}
+
+impl GenerateLlvm for Jump {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        writeln!(f, "  br label %{}", info.bb_label(self.target())).unwrap();
+    }
+}
+
+impl GenerateLlvm for Call {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        let callee = info.program.func(self.callee());
+        let ret = match callee.ty().kind() {
+            TypeKind::Function(_, ret) => ret.clone(),
+            _ => unreachable!(),
+        };
+        let args: Vec<String> = self
+            .args()
+            .iter()
+            .map(|&arg| info.typed_operand(arg))
+            .collect();
+        let call = format!(
+            "call {} @{}({})",
+            llvm_type(&ret),
+            &callee.name()[1..],
+            args.join(", ")
+        );
+        if ret.is_unit() {
+            writeln!(f, "  {call}").unwrap();
+        } else {
+            writeln!(f, "  {} = {call}", info.result()).unwrap();
+        }
+    }
+}
+
+impl GenerateLlvm for Return {
+    fn generate_llvm(&self, info: &mut LlvmInfo, f: &mut Vec<u8>) {
+        match self.value() {
+            Some(value) => writeln!(f, "  ret {}", info.typed_operand(value)).unwrap(),
+            None => writeln!(f, "  ret void").unwrap(),
+        }
+    }
+}
@@ -0,0 +1,604 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use koopa::ir::{
+    entities::ValueData,
+    values::{Binary, Call, GetElemPtr, GetPtr, Load, Return, Store},
+    BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind,
+};
+
+use crate::cfg::Cfg;
+
+// 线性内存中放在全局变量之后的栈空间, 栈指针 $sp 从内存末尾向下增长
+const STACK_SIZE: i32 = 64 * 1024;
+const PAGE_SIZE: i32 = 64 * 1024;
+
+/// 生成 WebAssembly 文本格式 (.wat) 时的上下文
+pub struct WasmInfo<'p> {
+    program: &'p Program,
+    which_func: Option<Function>,
+    // 全局变量在线性内存中的地址
+    global_addrs: HashMap<Value, i32>,
+    // value 对应的 wasm local
+    locals: HashMap<Value, String>,
+    // 只被 load/store 访问的标量 alloc 直接放在 local 中
+    promoted: HashSet<Value>,
+    // 其余 alloc 在线性内存栈帧中的偏移
+    frame_offsets: HashMap<Value, i32>,
+    frame_size: i32,
+    cfg: Option<Cfg>,
+    // 当前所在的 block/loop 标号, 用于检查控制流是否可以结构化
+    labels: Vec<String>,
+    indent: usize,
+    cur_value: Option<Value>,
+}
+
+impl<'p> WasmInfo<'p> {
+    /// 由支配树生成的结构化控制流要求控制流图可归约, SysY 生成的都是可归约的
+    pub fn new(program: &'p Program) -> Result<Self, String> {
+        for &func in program.func_layout() {
+            let func_data = program.func(func);
+            if func_data.layout().entry_bb().is_some() && !Cfg::new(func_data).is_reducible() {
+                return Err(format!(
+                    "{}: irreducible control flow is not supported by the wasm backend",
+                    func_data.name()
+                ));
+            }
+        }
+        Ok(Self {
+            program,
+            which_func: None,
+            global_addrs: HashMap::new(),
+            locals: HashMap::new(),
+            promoted: HashSet::new(),
+            frame_offsets: HashMap::new(),
+            frame_size: 0,
+            cfg: None,
+            labels: Vec::new(),
+            indent: 0,
+            cur_value: None,
+        })
+    }
+
+    fn get_key(&self) -> Value {
+        self.cur_value.unwrap()
+    }
+
+    fn set_key(&mut self, key: Value) {
+        self.cur_value = Some(key);
+    }
+
+    fn get_func_data(&self) -> &'p FunctionData {
+        self.program.func(self.which_func.unwrap())
+    }
+
+    fn get_data(&self, value: Value) -> &'p ValueData {
+        self.get_func_data().dfg().value(value)
+    }
+
+    fn cfg(&self) -> &Cfg {
+        self.cfg.as_ref().unwrap()
+    }
+
+    // 按当前缩进输出一行
+    fn line(&self, f: &mut Vec<u8>, text: &str) {
+        writeln!(f, "{}{}", "  ".repeat(self.indent), text).unwrap();
+    }
+
+    // 没有名字的基本块按在函数中的序号命名, 与 RISC-V 后端相同
+    fn bb_name(&self, bb: BasicBlock) -> String {
+        let func_data = self.get_func_data();
+        match func_data.dfg().bb(bb).name() {
+            Some(name) => name[1..].to_owned(),
+            None => {
+                let index = func_data.layout().bbs().keys().position(|&b| b == bb);
+                format!("anon.{}", index.unwrap())
+            }
+        }
+    }
+
+    // 为参数, 基本块参数和指令结果分配 local, 为不能提升的 alloc 分配栈帧
+    fn alloc_locals(&mut self, func: Function) {
+        self.which_func = Some(func);
+        self.cfg = Some(Cfg::new(self.program.func(func)));
+        self.locals.clear();
+        self.promoted.clear();
+        self.frame_offsets.clear();
+        let func_data = self.get_func_data();
+        for (i, &param) in func_data.params().iter().enumerate() {
+            self.locals.insert(param, format!("$p{i}"));
+        }
+        let mut frame_size = 0;
+        let mut count = 0;
+        for (&bb, node) in func_data.layout().bbs() {
+            let values = func_data
+                .dfg()
+                .bb(bb)
+                .params()
+                .iter()
+                .chain(node.insts().keys());
+            for &value in values {
+                let data = func_data.dfg().value(value);
+                if data.ty().is_unit() {
+                    continue;
+                }
+                if let ValueKind::Alloc(_) = data.kind() {
+                    if can_promote(func_data, value) {
+                        self.promoted.insert(value);
+                    } else {
+                        let size = match data.ty().kind() {
+                            TypeKind::Pointer(base) => base.size() as i32,
+                            _ => unreachable!(),
+                        };
+                        self.frame_offsets.insert(value, frame_size);
+                        frame_size += (size + 3) / 4 * 4;
+                    }
+                }
+                self.locals.insert(value, format!("$v{count}"));
+                count += 1;
+            }
+        }
+        self.frame_size = frame_size;
+    }
+
+    // 把 value 的值压栈
+    fn push_operand(&self, f: &mut Vec<u8>, value: Value) {
+        if value.is_global() {
+            self.line(f, &format!("i32.const {}", self.global_addrs[&value]));
+            return;
+        }
+        match self.get_data(value).kind() {
+            ValueKind::Integer(int) => self.line(f, &format!("i32.const {}", int.value())),
+            ValueKind::Undef(_) => self.line(f, "i32.const 0"),
+            _ => self.line(f, &format!("local.get {}", self.locals[&value])),
+        }
+    }
+
+    // 把栈顶保存为当前指令的结果
+    fn set_result(&self, f: &mut Vec<u8>) {
+        self.line(f, &format!("local.set {}", self.locals[&self.get_key()]));
+    }
+
+    fn is_loop_header(&self, bb: BasicBlock) -> bool {
+        let cfg = self.cfg();
+        cfg.preds[&bb]
+            .iter()
+            .any(|&pred| cfg.rpo_index(pred) >= cfg.rpo_index(bb))
+    }
+
+    // 有多于一个前向前驱的基本块需要用 block 包住, 通过 br 到达
+    fn is_merge_node(&self, bb: BasicBlock) -> bool {
+        let cfg = self.cfg();
+        let forward = cfg.preds[&bb]
+            .iter()
+            .filter(|&&pred| cfg.rpo_index(pred) < cfg.rpo_index(bb))
+            .count();
+        forward >= 2
+    }
+
+    // 跳转到 target 是否需要 br, 否则 target 可以直接内联在跳转处
+    fn branch_label(&self, source: BasicBlock, target: BasicBlock) -> Option<String> {
+        let cfg = self.cfg();
+        let label = if cfg.rpo_index(target) <= cfg.rpo_index(source) {
+            format!("$loop_{}", self.bb_name(target))
+        } else if self.is_merge_node(target) {
+            format!("$block_{}", self.bb_name(target))
+        } else {
+            return None;
+        };
+        // WasmInfo::new 已经检查过控制流图可归约, 目标一定在外层的 block/loop 中
+        assert!(self.labels.contains(&label));
+        Some(label)
+    }
+
+    // 以下按 Ramsey, Beyond Relooper 的方法由支配树生成结构化控制流
+    fn do_tree(&mut self, bb: BasicBlock, f: &mut Vec<u8>) {
+        // 合并节点按逆后序从大到小, 编号最大的在最外层的 block 之后
+        let merges: Vec<BasicBlock> = self
+            .cfg()
+            .dom_children(bb)
+            .into_iter()
+            .filter(|&child| self.is_merge_node(child))
+            .rev()
+            .collect();
+        if self.is_loop_header(bb) {
+            let label = format!("$loop_{}", self.bb_name(bb));
+            self.line(f, &format!("loop {label}"));
+            self.indent += 1;
+            self.labels.push(label);
+            self.node_within(bb, &merges, f);
+            self.labels.pop();
+            self.indent -= 1;
+            self.line(f, "end");
+        } else {
+            self.node_within(bb, &merges, f);
+        }
+    }
+
+    fn node_within(&mut self, bb: BasicBlock, merges: &[BasicBlock], f: &mut Vec<u8>) {
+        if let Some((&merge, rest)) = merges.split_first() {
+            let label = format!("$block_{}", self.bb_name(merge));
+            self.line(f, &format!("block {label}"));
+            self.indent += 1;
+            self.labels.push(label);
+            self.node_within(bb, rest, f);
+            self.labels.pop();
+            self.indent -= 1;
+            self.line(f, "end");
+            self.do_tree(merge, f);
+            return;
+        }
+
+        let func_data = self.get_func_data();
+        let node = func_data.layout().bbs().node(&bb).unwrap();
+        for &inst in node.insts().keys() {
+            self.set_key(inst);
+            match func_data.dfg().value(inst).kind() {
+                ValueKind::Jump(jump) => self.do_branch(bb, jump.target(), jump.args(), f),
+                ValueKind::Branch(br) => {
+                    self.push_operand(f, br.cond());
+                    let true_label = self.branch_label(bb, br.true_bb());
+                    match true_label {
+                        Some(label) if br.true_args().is_empty() => {
+                            self.line(f, &format!("br_if {label}"));
+                            self.do_branch(bb, br.false_bb(), br.false_args(), f);
+                        }
+                        _ => {
+                            self.line(f, "if");
+                            self.indent += 1;
+                            self.labels.push(String::new());
+                            self.do_branch(bb, br.true_bb(), br.true_args(), f);
+                            self.indent -= 1;
+                            self.line(f, "else");
+                            self.indent += 1;
+                            self.do_branch(bb, br.false_bb(), br.false_args(), f);
+                            self.labels.pop();
+                            self.indent -= 1;
+                            self.line(f, "end");
+                        }
+                    }
+                }
+                _ => func_data.dfg().value(inst).generate_wasm(self, f),
+            }
+        }
+    }
+
+    fn do_branch(
+        &mut self,
+        source: BasicBlock,
+        target: BasicBlock,
+        args: &[Value],
+        f: &mut Vec<u8>,
+    ) {
+        // 基本块参数: 先把所有实参压栈再倒序写入, 相当于并行赋值
+        let params = self.get_func_data().dfg().bb(target).params();
+        for &arg in args {
+            self.push_operand(f, arg);
+        }
+        for param in params.iter().rev() {
+            self.line(f, &format!("local.set {}", self.locals[param]));
+        }
+        match self.branch_label(source, target) {
+            Some(label) => self.line(f, &format!("br {label}")),
+            None => self.do_tree(target, f),
+        }
+    }
+}
+
+// 标量 alloc 只作为 load 的地址和 store 的目标时可以放进 local
+fn can_promote(func: &FunctionData, alloc: Value) -> bool {
+    let data = func.dfg().value(alloc);
+    match data.ty().kind() {
+        TypeKind::Pointer(base) if base.is_i32() => {}
+        _ => return false,
+    }
+    data.used_by()
+        .iter()
+        .all(|&user| match func.dfg().value(user).kind() {
+            ValueKind::Load(_) => true,
+            ValueKind::Store(store) => store.dest() == alloc && store.value() != alloc,
+            _ => false,
+        })
+}
+
+// 全局变量初始值按小端序展开成字节
+fn init_bytes(program: &Program, init: Value, bytes: &mut Vec<u8>) {
+    let data = program.borrow_value(init);
+    match data.kind() {
+        ValueKind::Integer(int) => bytes.extend(int.value().to_le_bytes()),
+        ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+            bytes.extend(std::iter::repeat_n(0, data.ty().size()))
+        }
+        ValueKind::Aggregate(agg) => {
+            for &elem in agg.elems() {
+                init_bytes(program, elem, bytes);
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn func_signature(params: &[String], ret_unit: bool) -> String {
+    let mut sig = String::new();
+    for param in params {
+        sig += &format!(" (param {param}i32)");
+    }
+    if !ret_unit {
+        sig += " (result i32)";
+    }
+    sig
+}
+
+pub trait GenerateWasm {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>);
+}
+
+impl GenerateWasm for Program {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        info.line(f, "(module");
+        info.indent += 1;
+
+        // 只有声明的函数 (SysY 运行时库) 从宿主环境导入
+        for &func in self.func_layout() {
+            let func_data = self.func(func);
+            if func_data.layout().entry_bb().is_some() {
+                continue;
+            }
+            let (params, ret) = match func_data.ty().kind() {
+                TypeKind::Function(params, ret) => (params, ret),
+                _ => unreachable!(),
+            };
+            let params = vec![String::new(); params.len()];
+            let name = &func_data.name()[1..];
+            let sig = func_signature(&params, ret.is_unit());
+            info.line(
+                f,
+                &format!("(import \"sysy\" \"{name}\" (func ${name}{sig}))"),
+            );
+        }
+
+        // 全局变量从地址 0 开始依次排布, 之后是栈
+        let mut data = Vec::new();
+        for &global in self.inst_layout() {
+            let init = match self.borrow_value(global).kind() {
+                ValueKind::GlobalAlloc(alloc) => alloc.init(),
+                _ => unreachable!(),
+            };
+            info.global_addrs.insert(global, data.len() as i32);
+            init_bytes(self, init, &mut data);
+        }
+        let pages = (data.len() as i32 + STACK_SIZE + PAGE_SIZE - 1) / PAGE_SIZE;
+        info.line(f, &format!("(memory (export \"memory\") {pages})"));
+        info.line(
+            f,
+            &format!("(global $sp (mut i32) (i32.const {}))", pages * PAGE_SIZE),
+        );
+        if data.iter().any(|&byte| byte != 0) {
+            let escaped: String = data.iter().map(|byte| format!("\\{byte:02x}")).collect();
+            info.line(f, &format!("(data (i32.const 0) \"{escaped}\")"));
+        }
+
+        for &func in self.func_layout() {
+            let func_data = self.func(func);
+            if func_data.layout().entry_bb().is_none() {
+                continue;
+            }
+            info.alloc_locals(func);
+            func_data.generate_wasm(info, f);
+        }
+
+        info.indent -= 1;
+        info.line(f, ")");
+    }
+}
+
+impl GenerateWasm for FunctionData {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        let name = &self.name()[1..];
+        let params: Vec<String> = (0..self.params().len())
+            .map(|i| format!("$p{i} "))
+            .collect();
+        let ret_unit = match self.ty().kind() {
+            TypeKind::Function(_, ret) => ret.is_unit(),
+            _ => unreachable!(),
+        };
+        let sig = func_signature(&params, ret_unit);
+        info.line(f, &format!("(func ${name} (export \"{name}\"){sig}"));
+        info.indent += 1;
+
+        let mut locals: Vec<&String> = info
+            .locals
+            .iter()
+            .filter(|(value, _)| !self.params().contains(value))
+            .map(|(_, local)| local)
+            .collect();
+        locals.sort_by_key(|local| local[2..].parse::<usize>().unwrap());
+        for local in locals {
+            info.line(f, &format!("(local {local} i32)"));
+        }
+
+        // 在线性内存栈上分配栈帧, 求出每个 alloc 的地址
+        if info.frame_size > 0 {
+            info.line(f, "global.get $sp");
+            info.line(f, &format!("i32.const {}", info.frame_size));
+            info.line(f, "i32.sub");
+            info.line(f, "global.set $sp");
+            let mut allocs: Vec<(&Value, &i32)> = info.frame_offsets.iter().collect();
+            allocs.sort_by_key(|(_, &offset)| offset);
+            for (value, offset) in allocs {
+                info.line(f, "global.get $sp");
+                info.line(f, &format!("i32.const {offset}"));
+                info.line(f, "i32.add");
+                info.line(f, &format!("local.set {}", info.locals[value]));
+            }
+        }
+
+        let entry = self.layout().entry_bb().unwrap();
+        info.do_tree(entry, f);
+        // 所有路径都以 return 结束
+        info.line(f, "unreachable");
+
+        info.indent -= 1;
+        info.line(f, ")");
+    }
+}
+
+impl GenerateWasm for ValueData {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        match self.kind() {
+            // alloc 的地址已经在函数开头求出
+            ValueKind::Alloc(_) => {}
+            ValueKind::Load(load) => load.generate_wasm(info, f),
+            ValueKind::Store(store) => store.generate_wasm(info, f),
+            ValueKind::GetPtr(ptr) => ptr.generate_wasm(info, f),
+            ValueKind::GetElemPtr(ptr) => ptr.generate_wasm(info, f),
+            ValueKind::Binary(bin) => bin.generate_wasm(info, f),
+            ValueKind::Call(call) => call.generate_wasm(info, f),
+            ValueKind::Return(ret) => ret.generate_wasm(info, f),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl GenerateWasm for Load {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        if info.promoted.contains(&self.src()) {
+            info.line(f, &format!("local.get {}", info.locals[&self.src()]));
+        } else {
+            info.push_operand(f, self.src());
+            info.line(f, "i32.load");
+        }
+        info.set_result(f);
+    }
+}
+
+impl GenerateWasm for Store {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        if info.promoted.contains(&self.dest()) {
+            info.push_operand(f, self.value());
+            info.line(f, &format!("local.set {}", info.locals[&self.dest()]));
+        } else {
+            info.push_operand(f, self.dest());
+            info.push_operand(f, self.value());
+            info.line(f, "i32.store");
+        }
+    }
+}
+
+// 地址计算: src + index * 元素大小
+fn generate_address(info: &WasmInfo, f: &mut Vec<u8>, src: Value, index: Value, size: usize) {
+    info.push_operand(f, src);
+    info.push_operand(f, index);
+    info.line(f, &format!("i32.const {size}"));
+    info.line(f, "i32.mul");
+    info.line(f, "i32.add");
+    info.set_result(f);
+}
+
+fn pointee_type(info: &WasmInfo, ptr: Value) -> Type {
+    let ty = if ptr.is_global() {
+        info.program.borrow_value(ptr).ty().clone()
+    } else {
+        info.get_data(ptr).ty().clone()
+    };
+    match ty.kind() {
+        TypeKind::Pointer(base) => base.clone(),
+        _ => unreachable!(),
+    }
+}
+
+impl GenerateWasm for GetPtr {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        let size = pointee_type(info, self.src()).size();
+        generate_address(info, f, self.src(), self.index(), size);
+    }
+}
+
+impl GenerateWasm for GetElemPtr {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        let size = match pointee_type(info, self.src()).kind() {
+            TypeKind::Array(elem, _) => elem.size(),
+            _ => unreachable!(),
+        };
+        generate_address(info, f, self.src(), self.index(), size);
+    }
+}
+
+impl GenerateWasm for Binary {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        if self.op() == BinaryOp::Div {
+            // i32.div_s 在 INT_MIN / -1 时陷入, 与其他后端一样按取负回绕.
+            // i32.rem_s 此时结果为 0, 不需要特殊处理
+            info.push_operand(f, self.rhs());
+            info.line(f, "i32.const -1");
+            info.line(f, "i32.eq");
+            info.line(f, "if (result i32)");
+            info.indent += 1;
+            info.line(f, "i32.const 0");
+            info.push_operand(f, self.lhs());
+            info.line(f, "i32.sub");
+            info.indent -= 1;
+            info.line(f, "else");
+            info.indent += 1;
+            info.push_operand(f, self.lhs());
+            info.push_operand(f, self.rhs());
+            info.line(f, "i32.div_s");
+            info.indent -= 1;
+            info.line(f, "end");
+            info.set_result(f);
+            return;
+        }
+        info.push_operand(f, self.lhs());
+        info.push_operand(f, self.rhs());
+        let inst = match self.op() {
+            BinaryOp::NotEq => "i32.ne",
+            BinaryOp::Eq => "i32.eq",
+            BinaryOp::Gt => "i32.gt_s",
+            BinaryOp::Lt => "i32.lt_s",
+            BinaryOp::Ge => "i32.ge_s",
+            BinaryOp::Le => "i32.le_s",
+            BinaryOp::Add => "i32.add",
+            BinaryOp::Sub => "i32.sub",
+            BinaryOp::Mul => "i32.mul",
+            BinaryOp::Div => "i32.div_s",
+            BinaryOp::Mod => "i32.rem_s",
+            BinaryOp::And => "i32.and",
+            BinaryOp::Or => "i32.or",
+            BinaryOp::Xor => "i32.xor",
+            BinaryOp::Shl => "i32.shl",
+            BinaryOp::Shr => "i32.shr_u",
+            BinaryOp::Sar => "i32.shr_s",
+        };
+        info.line(f, inst);
+        info.set_result(f);
+    }
+}
+
+impl GenerateWasm for Call {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        for &arg in self.args() {
+            info.push_operand(f, arg);
+        }
+        let callee = info.program.func(self.callee());
+        info.line(f, &format!("call ${}", &callee.name()[1..]));
+        if !info.get_data(info.get_key()).ty().is_unit() {
+            info.set_result(f);
+        }
+    }
+}
+
+impl GenerateWasm for Return {
+    fn generate_wasm(&self, info: &mut WasmInfo, f: &mut Vec<u8>) {
+        if let Some(value) = self.value() {
+            info.push_operand(f, value);
+        }
+        // 释放栈帧
+        if info.frame_size > 0 {
+            info.line(f, "global.get $sp");
+            info.line(f, &format!("i32.const {}", info.frame_size));
+            info.line(f, "i32.add");
+            info.line(f, "global.set $sp");
+        }
+        info.line(f, "return");
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{Read, Write};
+use std::time::Instant;
+
+pub use self::ast::AstInterpreter;
+pub(crate) use self::ir::binary;
+pub use self::ir::KoopaInterpreter;
+
+mod ast;
+mod ir;
+
+// 解释器共用的 SysY 运行时库.
+// 内存是以 i32 为单位的一维数组, 指针就是数组下标.
+// 输入输出的格式与 assembler/runtime.S 中的实现保持一致, 这样各种执行方式的结果可以直接比较.
+
+// 数组参数对应的内存
+fn array(memory: &mut [i32], ptr: i32, len: i32) -> Result<&mut [i32], String> {
+    let (ptr, len) = (ptr as usize, len.max(0) as usize);
+    memory
+        .get_mut(ptr..ptr + len)
+        .ok_or_else(|| "array argument out of bounds".to_owned())
+}
+
+/// 运行时库的状态
+#[derive(Default)]
+pub struct Runtime {
+    // 标准输入在第一次读取时才从主机读入
+    input: Option<Vec<u8>>,
+    input_pos: usize,
+    stdout: Vec<u8>,
+    // 是否把程序的输出同时写到主机的标准输出
+    echo: bool,
+    start: Option<Instant>,
+}
+
+impl Runtime {
+    /// 使用给定的标准输入, 而不是读取主机的标准输入
+    pub fn set_input(&mut self, input: Vec<u8>) {
+        self.input = Some(input);
+    }
+
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    fn input(&mut self) -> &[u8] {
+        let input = self.input.get_or_insert_with(|| {
+            let mut input = Vec::new();
+            std::io::stdin().read_to_end(&mut input).unwrap();
+            input
+        });
+        &input[self.input_pos..]
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.input().first().copied()
+    }
+
+    fn getch(&mut self) -> i32 {
+        match self.peek() {
+            Some(c) => {
+                self.input_pos += 1;
+                c as i32
+            }
+            None => -1,
+        }
+    }
+
+    // 跳过空白后读入一个十进制整数, 数字后面的字符留在输入中
+    fn getint(&mut self) -> i32 {
+        while self.peek().is_some_and(|c| c <= b' ') {
+            self.input_pos += 1;
+        }
+        let negative = self.peek() == Some(b'-');
+        if negative {
+            self.input_pos += 1;
+        }
+        let mut value: i32 = 0;
+        while let Some(c @ b'0'..=b'9') = self.peek() {
+            value = value.wrapping_mul(10).wrapping_add((c - b'0') as i32);
+            self.input_pos += 1;
+        }
+        if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if self.echo {
+            std::io::stdout().write_all(bytes).unwrap();
+        }
+        self.stdout.extend(bytes);
+    }
+
+    /// 调用运行时库函数, 数组参数是 memory 中的下标
+    pub fn call(&mut self, name: &str, args: &[i32], memory: &mut [i32]) -> Result<i32, String> {
+        let result = match name {
+            "getint" => self.getint(),
+            "getch" => self.getch(),
+            "getarray" => {
+                let len = self.getint();
+                for elem in array(memory, args[0], len)? {
+                    *elem = self.getint();
+                }
+                len
+            }
+            "putint" => {
+                self.write(args[0].to_string().as_bytes());
+                0
+            }
+            "putch" => {
+                self.write(&[args[0] as u8]);
+                0
+            }
+            "putarray" => {
+                let mut line = format!("{}:", args[0]);
+                for value in array(memory, args[1], args[0])?.iter() {
+                    line += &format!(" {value}");
+                }
+                line.push('\n');
+                self.write(line.as_bytes());
+                0
+            }
+            "starttime" | "_sysy_starttime" => {
+                self.start = Some(Instant::now());
+                0
+            }
+            "stoptime" | "_sysy_stoptime" => {
+                let elapsed = self.start.map(|start| start.elapsed()).unwrap_or_default();
+                let (secs, nanos) = (elapsed.as_secs(), elapsed.subsec_nanos());
+                eprintln!("Timer: {secs}s {nanos}ns");
+                0
+            }
+            _ => return Err(format!("undefined function `{name}`")),
+        };
+        Ok(result)
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use koopa::ir::BinaryOp;
+
+use super::ir::binary;
+use super::Runtime;
+use crate::ast::*;
+
+// 符号表中的值, 常量不能被赋值
+enum Binding {
+    Const(i32),
+    Var(i32),
+}
+
+/// 直接执行 AST 的解释器
+///
+/// 整数运算与 RISC-V 一致: 溢出回绕, 除法向 0 截断, 除以 0 不报错.
+/// 与前端的常量求值不同, 这里不会因为溢出而 panic
+#[derive(Default)]
+pub struct AstInterpreter {
+    vars: HashMap<String, Binding>,
+    runtime: Runtime,
+}
+
+impl AstInterpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 目前的语法中没有函数调用, 运行时库只用来收集输出
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    /// 执行 main, 返回值与进程的退出码一样只保留低 8 位
+    pub fn run(&mut self, unit: &CompUnit) -> Result<i32, String> {
+        self.vars.clear();
+        for item in &unit.func_def.block.items {
+            if let Some(value) = item.exec(self)? {
+                return Ok(value & 0xff);
+            }
+        }
+        Ok(0)
+    }
+
+    fn lookup(&self, ident: &str) -> Result<i32, String> {
+        match self.vars.get(ident) {
+            Some(Binding::Const(value) | Binding::Var(value)) => Ok(*value),
+            None => Err(format!("undefined variable `{ident}`")),
+        }
+    }
+}
+
+trait Exec {
+    /// 执行语句, 遇到 return 时返回它的值
+    fn exec(&self, interp: &mut AstInterpreter) -> Result<Option<i32>, String>;
+}
+
+trait Eval {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String>;
+}
+
+impl Exec for BlockItem {
+    fn exec(&self, interp: &mut AstInterpreter) -> Result<Option<i32>, String> {
+        match self {
+            Self::Decl(decl) => decl.exec(interp),
+            Self::Stmt(stmt) => stmt.exec(interp),
+        }
+    }
+}
+
+impl Exec for Decl {
+    fn exec(&self, interp: &mut AstInterpreter) -> Result<Option<i32>, String> {
+        match self {
+            Self::ConstDecl(const_decl) => {
+                for def in &const_decl.defs {
+                    let value = def.val.exp.exp.eval(interp)?;
+                    interp.vars.insert(def.ident.clone(), Binding::Const(value));
+                }
+            }
+            Self::VarDecl(var_decl) => {
+                for def in &var_decl.defs {
+                    // 没有初始值的变量按 0 处理
+                    let (ident, value) = match def {
+                        VarDef::Init(ident, val, _) => (ident, val.exp.eval(interp)?),
+                        VarDef::NoInit(ident, _) => (ident, 0),
+                    };
+                    interp.vars.insert(ident.clone(), Binding::Var(value));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Exec for Stmt {
+    fn exec(&self, interp: &mut AstInterpreter) -> Result<Option<i32>, String> {
+        match self {
+            Self::Ret(exp) => Ok(Some(exp.eval(interp)?)),
+            Self::Assign(lval, exp) => {
+                let value = exp.eval(interp)?;
+                match interp.vars.get_mut(&lval.ident) {
+                    Some(Binding::Var(var)) => *var = value,
+                    Some(Binding::Const(_)) => {
+                        return Err(format!("assignment to constant `{}`", lval.ident))
+                    }
+                    None => return Err(format!("undefined variable `{}`", lval.ident)),
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Eval for Exp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        self.l_or_exp.eval(interp)
+    }
+}
+
+// 逻辑运算按 SysY 的语义短路求值
+impl Eval for LOrExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::LAndExp(l_and_exp) => l_and_exp.eval(interp),
+            Self::Or(l_or_exp, l_and_exp) => {
+                if l_or_exp.eval(interp)? != 0 {
+                    return Ok(1);
+                }
+                Ok((l_and_exp.eval(interp)? != 0) as i32)
+            }
+        }
+    }
+}
+
+impl Eval for LAndExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::EqExp(eq_exp) => eq_exp.eval(interp),
+            Self::And(l_and_exp, eq_exp) => {
+                if l_and_exp.eval(interp)? == 0 {
+                    return Ok(0);
+                }
+                Ok((eq_exp.eval(interp)? != 0) as i32)
+            }
+        }
+    }
+}
+
+impl Eval for EqExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::RelExp(rel_exp) => rel_exp.eval(interp),
+            Self::Eq(eq_exp, sign, rel_exp) => {
+                let op = match sign {
+                    EqSign::Eq => BinaryOp::Eq,
+                    EqSign::Neq => BinaryOp::NotEq,
+                };
+                Ok(binary(op, eq_exp.eval(interp)?, rel_exp.eval(interp)?))
+            }
+        }
+    }
+}
+
+impl Eval for RelExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::AddExp(add_exp) => add_exp.eval(interp),
+            Self::Cmp(rel_exp, sign, add_exp) => {
+                let op = match sign {
+                    CmpSign::Less => BinaryOp::Lt,
+                    CmpSign::More => BinaryOp::Gt,
+                    CmpSign::Leq => BinaryOp::Le,
+                    CmpSign::Meq => BinaryOp::Ge,
+                };
+                Ok(binary(op, rel_exp.eval(interp)?, add_exp.eval(interp)?))
+            }
+        }
+    }
+}
+
+impl Eval for AddExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::MulExp(mul_exp) => mul_exp.eval(interp),
+            Self::AddExp(add_exp, sign, mul_exp) => {
+                let op = match sign {
+                    AddSign::Add => BinaryOp::Add,
+                    AddSign::Sub => BinaryOp::Sub,
+                };
+                Ok(binary(op, add_exp.eval(interp)?, mul_exp.eval(interp)?))
+            }
+        }
+    }
+}
+
+impl Eval for MulExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::UnaryExp(unary_exp) => unary_exp.eval(interp),
+            Self::MulExp(mul_exp, sign, unary_exp) => {
+                let op = match sign {
+                    MulSign::Mul => BinaryOp::Mul,
+                    MulSign::Div => BinaryOp::Div,
+                    MulSign::Mod => BinaryOp::Mod,
+                };
+                Ok(binary(op, mul_exp.eval(interp)?, unary_exp.eval(interp)?))
+            }
+        }
+    }
+}
+
+impl Eval for UnaryExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::PrimaryExp(p_exp) => p_exp.eval(interp),
+            Self::Unary(op, u_exp) => {
+                let value = u_exp.eval(interp)?;
+                Ok(match op {
+                    UnaryOp::Negative => value.wrapping_neg(),
+                    UnaryOp::Bang => (value == 0) as i32,
+                })
+            }
+        }
+    }
+}
+
+impl Eval for PrimaryExp {
+    fn eval(&self, interp: &mut AstInterpreter) -> Result<i32, String> {
+        match self {
+            Self::Exp(exp) => exp.eval(interp),
+            Self::Number(num) => Ok(num.num),
+            Self::LVal(lval) => interp.lookup(&lval.ident),
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+
+use koopa::ir::{BasicBlock, BinaryOp, Function, Program, Type, TypeKind, Value, ValueKind};
+
+use super::Runtime;
+
+/// 类型占用的 i32 个数, 指针也只占一个
+fn words(ty: &Type) -> usize {
+    match ty.kind() {
+        TypeKind::Int32 | TypeKind::Pointer(_) => 1,
+        TypeKind::Array(base, len) => words(base) * len,
+        TypeKind::Unit | TypeKind::Function(..) => 0,
+    }
+}
+
+// 指针指向的类型
+fn pointee(ty: &Type) -> &Type {
+    match ty.kind() {
+        TypeKind::Pointer(base) => base,
+        _ => unreachable!(),
+    }
+}
+
+/// 按 RISC-V 的语义计算二元运算: 溢出回绕, 除以 0 不报错, 移位量取低 5 位
+pub fn binary(op: BinaryOp, lhs: i32, rhs: i32) -> i32 {
+    match op {
+        BinaryOp::NotEq => (lhs != rhs) as i32,
+        BinaryOp::Eq => (lhs == rhs) as i32,
+        BinaryOp::Gt => (lhs > rhs) as i32,
+        BinaryOp::Lt => (lhs < rhs) as i32,
+        BinaryOp::Ge => (lhs >= rhs) as i32,
+        BinaryOp::Le => (lhs <= rhs) as i32,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div => match rhs {
+            0 => -1,
+            _ => lhs.wrapping_div(rhs),
+        },
+        BinaryOp::Mod => match rhs {
+            0 => lhs,
+            _ => lhs.wrapping_rem(rhs),
+        },
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        BinaryOp::Sar => lhs.wrapping_shr(rhs as u32),
+    }
+}
+
+// 一次函数调用的状态
+struct Frame {
+    func: Function,
+    bb: BasicBlock,
+    // 下一条要执行的指令在基本块中的位置
+    index: usize,
+    values: HashMap<Value, i32>,
+    // 进入函数时栈的位置, 返回时释放这之后的空间
+    stack_base: usize,
+    // 调用者中接收返回值的 call 指令
+    call: Option<Value>,
+}
+
+/// Koopa IR 解释器
+///
+/// 函数调用用显式的栈帧保存, 不依赖宿主的递归深度
+pub struct KoopaInterpreter<'p> {
+    program: &'p Program,
+    // 全局变量在前, 之后是各个栈帧中 alloc 的空间
+    memory: Vec<i32>,
+    globals: HashMap<Value, i32>,
+    // 每个函数中各基本块的指令
+    blocks: HashMap<Function, HashMap<BasicBlock, Vec<Value>>>,
+    // 每个函数中的 alloc 及其占用的 i32 个数, 进入函数时一次分配
+    allocs: HashMap<Function, Vec<(Value, usize)>>,
+    runtime: Runtime,
+    steps: u64,
+    // 依次进入的基本块, 以及当时程序已经输出的字节数
+    trace: Option<Vec<(Function, BasicBlock, usize)>>,
+}
+
+impl<'p> KoopaInterpreter<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        let mut interpreter = Self {
+            program,
+            memory: Vec::new(),
+            globals: HashMap::new(),
+            blocks: HashMap::new(),
+            allocs: HashMap::new(),
+            runtime: Runtime::default(),
+            steps: 0,
+            trace: None,
+        };
+        for &global in program.inst_layout() {
+            let init = match program.borrow_value(global).kind() {
+                ValueKind::GlobalAlloc(alloc) => alloc.init(),
+                _ => unreachable!(),
+            };
+            let addr = interpreter.memory.len() as i32;
+            interpreter.globals.insert(global, addr);
+            interpreter.init_global(init);
+        }
+        for (&func, func_data) in program.funcs() {
+            let blocks = func_data
+                .layout()
+                .bbs()
+                .iter()
+                .map(|(&bb, node)| (bb, node.insts().keys().copied().collect()))
+                .collect();
+            interpreter.blocks.insert(func, blocks);
+            let allocs = func_data
+                .layout()
+                .bbs()
+                .nodes()
+                .flat_map(|node| node.insts().keys())
+                .filter_map(|&inst| {
+                    let data = func_data.dfg().value(inst);
+                    matches!(data.kind(), ValueKind::Alloc(_))
+                        .then(|| (inst, words(pointee(data.ty()))))
+                })
+                .collect();
+            interpreter.allocs.insert(func, allocs);
+        }
+        interpreter
+    }
+
+    // 全局变量的初始值依次放入内存
+    fn init_global(&mut self, init: Value) {
+        let data = self.program.borrow_value(init);
+        match data.kind() {
+            ValueKind::Integer(int) => self.memory.push(int.value()),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+                let len = self.memory.len() + words(data.ty());
+                self.memory.resize(len, 0);
+            }
+            ValueKind::Aggregate(agg) => {
+                for &elem in agg.elems() {
+                    self.init_global(elem);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    /// 记录执行时依次进入的基本块
+    pub fn set_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    pub fn trace(&self) -> &[(Function, BasicBlock, usize)] {
+        self.trace.as_deref().unwrap_or_default()
+    }
+
+    /// 执行的指令条数
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    fn value(&self, frame: &Frame, value: Value) -> i32 {
+        if value.is_global() {
+            return self.globals[&value];
+        }
+        let data = self.program.func(frame.func).dfg().value(value);
+        match data.kind() {
+            ValueKind::Integer(int) => int.value(),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => 0,
+            _ => frame.values[&value],
+        }
+    }
+
+    fn load(&self, ptr: i32) -> Result<i32, String> {
+        self.memory
+            .get(ptr as usize)
+            .copied()
+            .ok_or_else(|| format!("load from invalid address {ptr}"))
+    }
+
+    fn store(&mut self, ptr: i32, value: i32) -> Result<(), String> {
+        match self.memory.get_mut(ptr as usize) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(format!("store to invalid address {ptr}")),
+        }
+    }
+
+    // 跳转到 target, 先求出所有参数再赋给基本块的参数
+    fn jump(&self, frame: &mut Frame, target: BasicBlock, args: &[Value]) {
+        let args: Vec<i32> = args.iter().map(|&arg| self.value(frame, arg)).collect();
+        let params = self.program.func(frame.func).dfg().bb(target).params();
+        for (&param, arg) in params.iter().zip(args) {
+            frame.values.insert(param, arg);
+        }
+        frame.bb = target;
+        frame.index = 0;
+    }
+
+    // 进入函数时为其中所有的 alloc 分配空间, 循环中的 alloc 每次得到同一个地址
+    fn enter(&mut self, func: Function, args: &[i32], call: Option<Value>) -> Frame {
+        let func_data = self.program.func(func);
+        let stack_base = self.memory.len();
+        let mut values: HashMap<Value, i32> = func_data
+            .params()
+            .iter()
+            .copied()
+            .zip(args.iter().copied())
+            .collect();
+        for &(alloc, size) in &self.allocs[&func] {
+            values.insert(alloc, self.memory.len() as i32);
+            self.memory.resize(self.memory.len() + size, 0);
+        }
+        Frame {
+            func,
+            bb: func_data.layout().entry_bb().unwrap(),
+            index: 0,
+            values,
+            stack_base,
+            call,
+        }
+    }
+
+    /// 从 @main 开始执行, 返回 main 的返回值. 执行超过 limit 条指令或者访存越界时返回错误
+    pub fn run(&mut self, limit: Option<u64>) -> Result<i32, String> {
+        let program = self.program;
+        let main = program
+            .func_layout()
+            .iter()
+            .copied()
+            .find(|&func| program.func(func).name() == "@main")
+            .ok_or("no `@main` function")?;
+        let mut frames = vec![self.enter(main, &[], None)];
+        loop {
+            if limit.is_some_and(|limit| self.steps >= limit) {
+                return Err(format!("instruction limit {} exceeded", limit.unwrap()));
+            }
+            self.steps += 1;
+            let mut frame = frames.pop().unwrap();
+            if let (0, Some(trace)) = (frame.index, &mut self.trace) {
+                trace.push((frame.func, frame.bb, self.runtime.stdout().len()));
+            }
+            let func_data = program.func(frame.func);
+            let inst = self.blocks[&frame.func][&frame.bb][frame.index];
+            frame.index += 1;
+            let data = func_data.dfg().value(inst);
+            let result = match data.kind() {
+                // 地址在进入函数时已经分配
+                ValueKind::Alloc(_) => None,
+                ValueKind::Load(load) => Some(self.load(self.value(&frame, load.src()))?),
+                ValueKind::Store(store) => {
+                    let value = self.value(&frame, store.value());
+                    self.store(self.value(&frame, store.dest()), value)?;
+                    None
+                }
+                // getptr 的结果与 src 类型相同, getelemptr 的结果指向数组的元素,
+                // 两者的步长都是结果指针指向的类型的大小
+                ValueKind::GetPtr(ptr) => {
+                    let src = self.value(&frame, ptr.src());
+                    let index = self.value(&frame, ptr.index());
+                    let size = words(pointee(data.ty())) as i32;
+                    Some(src.wrapping_add(index.wrapping_mul(size)))
+                }
+                ValueKind::GetElemPtr(ptr) => {
+                    let src = self.value(&frame, ptr.src());
+                    let index = self.value(&frame, ptr.index());
+                    let size = words(pointee(data.ty())) as i32;
+                    Some(src.wrapping_add(index.wrapping_mul(size)))
+                }
+                ValueKind::Binary(bin) => {
+                    let lhs = self.value(&frame, bin.lhs());
+                    let rhs = self.value(&frame, bin.rhs());
+                    Some(binary(bin.op(), lhs, rhs))
+                }
+                ValueKind::Branch(br) => {
+                    if self.value(&frame, br.cond()) != 0 {
+                        self.jump(&mut frame, br.true_bb(), br.true_args());
+                    } else {
+                        self.jump(&mut frame, br.false_bb(), br.false_args());
+                    }
+                    None
+                }
+                ValueKind::Jump(jump) => {
+                    self.jump(&mut frame, jump.target(), jump.args());
+                    None
+                }
+                ValueKind::Call(call) => {
+                    let args: Vec<i32> = call
+                        .args()
+                        .iter()
+                        .map(|&arg| self.value(&frame, arg))
+                        .collect();
+                    let callee = program.func(call.callee());
+                    if callee.layout().entry_bb().is_none() {
+                        // 只有声明的函数由运行时库实现
+                        Some(
+                            self.runtime
+                                .call(&callee.name()[1..], &args, &mut self.memory)?,
+                        )
+                    } else {
+                        let callee = self.enter(call.callee(), &args, Some(inst));
+                        frames.push(frame);
+                        frames.push(callee);
+                        continue;
+                    }
+                }
+                ValueKind::Return(ret) => {
+                    let value = ret.value().map(|value| self.value(&frame, value));
+                    self.memory.truncate(frame.stack_base);
+                    match frames.last_mut() {
+                        Some(caller) => {
+                            caller
+                                .values
+                                .insert(frame.call.unwrap(), value.unwrap_or(0));
+                            continue;
+                        }
+                        // main 的返回值与进程的退出码一样只保留低 8 位
+                        None => return Ok(value.unwrap_or(0) & 0xff),
+                    }
+                }
+                _ => unreachable!(),
+            };
+            if let Some(result) = result {
+                frame.values.insert(inst, result);
+            }
+            frames.push(frame);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt::{self, Display, Formatter};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use koopa::ir::Program;
+use lalrpop_util::ParseError;
+
+use ast::CompUnit;
+use generate_asm::{GenerateAsm, PeepholeConfig, PeepholeStats, ProgramInfo, Riscv, Target};
+use opt::{PassManager, PassTimings};
+
+pub mod assembler;
+pub mod ast;
+#[cfg(feature = "tools")]
+pub mod autotest;
+mod cfg;
+#[cfg(feature = "tools")]
+pub mod difftest;
+pub mod emulator;
+#[cfg(feature = "tools")]
+pub mod fuzz;
+pub mod generate_asm;
+pub mod generate_c;
+pub mod generate_llvm;
+pub mod generate_wasm;
+pub mod interpreter;
+pub mod opt;
+#[cfg(feature = "tools")]
+pub mod reduce;
+
+// 引用 lalrpop 生成的解析器
+// 因为我们刚刚创建了 sysy.lalrpop, 所以模块名是 sysy.
+// 与 lalrpop_mod! 展开的结果相同, 另外取出生成的词法分析器, 用来输出 token 序列
+#[allow(clippy::all)]
+mod sysy {
+    include!(concat!(env!("OUT_DIR"), "/sysy.rs"));
+
+    /// 按 radix 进制转换从 start 开始的整数字面量 digits, 超出 i32 范围时报错
+    fn int_const(
+        start: usize,
+        digits: &str,
+        radix: u32,
+    ) -> Result<i32, lalrpop_util::ParseError<usize, Token<'_>, (usize, &'static str)>> {
+        i32::from_str_radix(digits, radix).map_err(|_| lalrpop_util::ParseError::User {
+            error: (start, "integer literal out of range"),
+        })
+    }
+
+    /// 按 sysy.lalrpop 的词法规则切分 input, 返回每个 token 的起止位置和文本, 出错时返回出错的位置
+    pub(crate) fn lex(input: &str) -> Result<Vec<(usize, &str, usize)>, usize> {
+        let builder = __intern_token::new_builder();
+        builder
+            .matcher::<&str>(input)
+            .map(|token| match token {
+                Ok((start, Token(_, text), end)) => Ok((start, text, end)),
+                Err(lalrpop_util::ParseError::InvalidToken { location }) => Err(location),
+                Err(_) => unreachable!(),
+            })
+            .collect()
+    }
+}
+
+// 编译器的库接口: 把 SysY 源码编译成 AST、Koopa IR 和 RISC-V 汇编.
+// 编译器内部的错误仍然是 panic, 这里捕获下来转换成诊断信息, 但默认的 panic hook 照常打印.
+// 生成临时名字的计数器是线程局部的, 每次编译都从 0 开始, 多个线程可以同时编译.
+
+/// 编译选项
+#[derive(Clone)]
+pub struct Options {
+    pub target: Target,
+    /// 目标是否支持 M 扩展
+    pub m_extension: bool,
+    pub peephole: PeepholeConfig,
+    /// Koopa IR 上依次运行的优化遍, 默认为空, opt::preset 给出各优化级别的流水线
+    pub passes: Vec<String>,
+    /// 在这些优化遍之后保存 Koopa IR, 见 PassManager::print_after
+    pub print_after: Vec<String>,
+    /// 是否生成汇编, 只需要 Koopa IR 时可以关掉, 避开后端不支持的指令
+    pub emit_asm: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            target: Target::Riscv32,
+            m_extension: true,
+            peephole: PeepholeConfig::default(),
+            passes: Vec::new(),
+            print_after: Vec::new(),
+            emit_asm: true,
+        }
+    }
+}
+
+/// 编译的各个阶段的产物
+pub struct Artifacts {
+    pub ast: CompUnit,
+    /// 优化后的 Koopa IR 文本, 没有优化遍时是前端生成的文本
+    pub koopa: String,
+    pub program: Program,
+    pub pass_timings: PassTimings,
+    /// 要求保存的 (优化遍, 之后的 Koopa IR 文本)
+    pub ir_dumps: Vec<(String, String)>,
+    /// Options::emit_asm 为 false 时是 None
+    pub asm: Option<String>,
+    pub peephole_stats: PeepholeStats,
+}
+
+/// 出错的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Parse,
+    Frontend,
+    Optimize,
+    Backend,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Parse => "parse",
+            Self::Frontend => "frontend",
+            Self::Optimize => "optimizer",
+            Self::Backend => "backend",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// 一条诊断信息, 语法错误和从源码解析得到的 AST 中的语义错误带有从 1 开始的行号和列号
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub stage: Stage,
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some((line, column)) = self.position {
+            write!(f, "{line}:{column}: ")?;
+        }
+        write!(f, "{} error: {}", self.stage, self.message)
+    }
+}
+
+/// 编译失败时的所有诊断信息
+#[derive(Debug, Clone)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    fn new(stage: Stage, position: Option<(usize, usize)>, message: String) -> Self {
+        Self(vec![Diagnostic {
+            stage,
+            position,
+            message,
+        }])
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for diagnostic in &self.0 {
+            writeln!(f, "{diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+// 字节偏移对应的行号和列号
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+// 捕获 f 中的 panic, 把 panic 的信息作为错误
+pub(crate) fn catch<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => Err(payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panicked".to_owned())),
+    }
+}
+
+/// 一个 token 和它的位置
+#[derive(Debug, Clone)]
+pub struct Token {
+    /// 从 1 开始的行号和列号
+    pub position: (usize, usize),
+    pub text: String,
+}
+
+/// 把 SysY 源码切分成 token, 与解析器使用同一个词法分析器
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Diagnostics> {
+    let tokens = sysy::lex(source).map_err(|offset| {
+        let message = "invalid token".to_owned();
+        Diagnostics::new(Stage::Parse, Some(position(source, offset)), message)
+    })?;
+    // 每行开头的字节偏移, 按 token 的偏移二分查找所在的行
+    let lines: Vec<usize> = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    Ok(tokens
+        .into_iter()
+        .map(|(start, text, _)| {
+            let line = lines.partition_point(|&begin| begin <= start);
+            Token {
+                position: (line, start - lines[line - 1] + 1),
+                text: text.to_owned(),
+            }
+        })
+        .collect())
+}
+
+/// 解析 SysY 源码
+pub fn parse(source: &str) -> Result<CompUnit, Diagnostics> {
+    sysy::CompUnitParser::new().parse(source).map_err(|err| {
+        let (offset, message) = match err {
+            ParseError::InvalidToken { location } => (location, "invalid token".to_owned()),
+            ParseError::UnrecognizedEOF { location, expected } => (
+                location,
+                format!("unexpected end of file, expected {}", expected.join(", ")),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, _),
+                expected,
+            } => (
+                start,
+                format!("unexpected `{token}`, expected {}", expected.join(", ")),
+            ),
+            ParseError::ExtraToken {
+                token: (start, token, _),
+            } => (start, format!("extra token `{token}`")),
+            ParseError::User {
+                error: (offset, message),
+            } => (offset, message.to_owned()),
+        };
+        Diagnostics::new(Stage::Parse, Some(position(source, offset)), message)
+    })
+}
+
+/// 编译 SysY 源码
+pub fn compile(source: &str, options: &Options) -> Result<Artifacts, Diagnostics> {
+    compile_ast(parse(source)?, Some(source), options)
+}
+
+/// 从已经解析好的 AST 开始编译, source 是解析出 AST 的源码, 用来给语义错误加上行号和列号
+pub fn compile_ast(
+    ast: CompUnit,
+    source: Option<&str>,
+    options: &Options,
+) -> Result<Artifacts, Diagnostics> {
+    let frontend = |message| Diagnostics::new(Stage::Frontend, None, message);
+    ast.check().map_err(|err| {
+        let position = source
+            .zip(err.offset)
+            .map(|(source, offset)| position(source, offset));
+        Diagnostics::new(Stage::Frontend, position, err.message)
+    })?;
+    let koopa = catch(|| {
+        let mut buf = Vec::new();
+        ast.generate(&mut buf);
+        Ok(String::from_utf8(buf).unwrap())
+    })
+    .map_err(frontend)?;
+    // 调用库将 koopa ir 转换成 koopa ir 对应的 AST
+    let mut program = koopa::front::Driver::from(koopa)
+        .generate_program()
+        .map_err(|err| frontend(format!("invalid Koopa IR: {err:?}")))?;
+
+    let optimize = |message| Diagnostics::new(Stage::Optimize, None, message);
+    let mut passes = PassManager::new(&options.passes).map_err(optimize)?;
+    for name in &options.print_after {
+        passes.print_after(name).map_err(optimize)?;
+    }
+    catch(|| passes.run(&mut program)).map_err(optimize)?;
+    // 不论是否优化, 都用同一种格式输出
+    let koopa = opt::dump(&program);
+
+    let mut peephole_stats = PeepholeStats::default();
+    let asm = if options.emit_asm {
+        let backend = |message| Diagnostics::new(Stage::Backend, None, message);
+        let isa = Riscv::new(options.target, options.m_extension).map_err(backend)?;
+        let asm = catch(|| {
+            let mut info = ProgramInfo::new(&program, None);
+            info.set_peephole(options.peephole);
+            info.set_isa(isa);
+            let mut buf = Vec::new();
+            program.generate(&mut info, &mut buf);
+            peephole_stats = info.peephole_stats();
+            Ok(String::from_utf8(buf).unwrap())
+        })
+        .map_err(backend)?;
+        Some(asm)
+    } else {
+        None
+    };
+    Ok(Artifacts {
+        ast,
+        koopa,
+        program,
+        pass_timings: passes.timings().clone(),
+        ir_dumps: passes.dumps().to_vec(),
+        asm,
+        peephole_stats,
+    })
+}
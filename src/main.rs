@@ -1,49 +1,308 @@
-use lalrpop_util::lalrpop_mod;
+use compiler::assembler::{assemble, link, runtime, write_elf, write_executable};
+use compiler::ast::CompUnit;
+use compiler::autotest::{format_output, run_tests};
+use compiler::difftest::difftest;
+use compiler::emulator::Emulator;
+use compiler::fuzz::{failures, fuzz};
+use compiler::generate_asm::{GenerateAsm, ProgramInfo, Target, X86_64};
+use compiler::generate_c::{CInfo, GenerateC};
+use compiler::generate_llvm::{GenerateLlvm, LlvmInfo};
+use compiler::generate_wasm::{GenerateWasm, WasmInfo};
+use compiler::interpreter::{AstInterpreter, KoopaInterpreter};
+use compiler::reduce::{external, same_failure, Reducer};
+use compiler::{compile_ast, parse, tokenize, Diagnostics};
 use std::env::args;
-use std::fs::{read_to_string, write};
-use std::io::Result;
-mod ast;
-mod generate_asm;
-use generate_asm::{GenerateAsm, ProgramInfo};
-
-// 引用 lalrpop 生成的解析器
-// 因为我们刚刚创建了 sysy.lalrpop, 所以模块名是 sysy
-lalrpop_mod!(sysy);
-
-fn main() -> Result<()> {
-    // 解析命令行参数
-    let mut args = args();
-    args.next();
-    let mode = args.next().unwrap();
-    let input = args.next().unwrap();
-    args.next();
-    let output = args.next().unwrap();
-
-    // 读取输入文件
-    let input = read_to_string(input)?;
+use std::fs::write;
+use std::io::{stdin, stdout, Read, Write};
+use std::process::exit;
+
+use cli::{usage, Args, Mode};
+
+mod cli;
+
+fn main() {
+    // 解析命令行参数, 参数有误时打印错误和用法提示
+    let args = match Args::parse(args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", usage());
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            eprintln!("run `compiler --help` for usage");
+            exit(2);
+        }
+    };
+    if let Err(err) = execute(&args) {
+        eprint!("{err}");
+        if !err.ends_with('\n') {
+            eprintln!();
+        }
+        exit(1);
+    }
+}
+
+// 按模式执行, 错误信息已经格式化好, 直接打印
+fn execute(args: &Args) -> Result<(), String> {
+    let input = args.input.as_str();
+    let output = args.output.as_str();
+    let mode = args.mode;
+
+    if mode == Mode::Fuzz {
+        // 输入是要生成的程序个数, 0 表示一直运行, 输出是保存失败用例的目录
+        let count = input
+            .parse()
+            .map_err(|_| format!("invalid program count `{input}`"))?;
+        let dir = if output == "-" { "." } else { output };
+        print!("{}", fuzz(args.seed, count, dir));
+        return Ok(());
+    }
+
+    if mode == Mode::Test {
+        // 与其他模式使用相同的编译选项, 模拟器只支持 riscv32
+        if args.target != Target::Riscv32 {
+            return Err("-test only supports riscv32".into());
+        }
+        let (summary, failed) = run_tests(input, args.filter.as_deref(), &args.options(true));
+        // 汇总总是打印, 给出 -o 时另外写到文件
+        print!("{summary}");
+        if output != "-" {
+            write_output(output, summary.as_bytes())?;
+        }
+        if failed > 0 {
+            return Err(format!("{failed} test cases failed"));
+        }
+        return Ok(());
+    }
+
+    // 程序本身要从标准输入读数据时, 源码不能也从标准输入读
+    if input == "-" && mode == Mode::Difftest {
+        return Err("-difftest reads program input from stdin, the source must be a file".into());
+    }
+    let file = read_input(input)?;
+
+    // -run 模式也可以直接运行汇编文件或 ELF 可执行文件
+    if mode == Mode::Run {
+        if file.starts_with(b"\x7fELF") {
+            return run(Emulator::from_elf(&file)?, output);
+        }
+        if input.ends_with(".s") || input.ends_with(".S") {
+            let asm = String::from_utf8(file).map_err(|_| format!("{input}: not UTF-8"))?;
+            return run(Emulator::from_asm(&asm)?, output);
+        }
+    }
+
+    let source = String::from_utf8(file).map_err(|_| format!("{input}: not UTF-8"))?;
+    let name = if input == "-" { "<stdin>" } else { input };
+    let diagnostics = |err: Diagnostics| {
+        err.0
+            .iter()
+            .map(|diagnostic| match diagnostic.position {
+                Some(_) => format!("{name}:{diagnostic}\n"),
+                None => format!("{name}: {diagnostic}\n"),
+            })
+            .collect::<String>()
+    };
+
+    if mode == Mode::Tokens {
+        let tokens = tokenize(&source).map_err(diagnostics)?;
+        let text: String = tokens
+            .iter()
+            .map(|token| {
+                let (line, column) = token.position;
+                format!("{line}:{column}\t{}\n", token.text)
+            })
+            .collect();
+        return write_output(output, text.as_bytes());
+    }
 
     // 调用 lalrpop 生成的 parser 解析输入文件
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
+    let ast = parse(&source).map_err(diagnostics)?;
 
-    // println!("{:#?}", ast);
+    if mode == Mode::Ast {
+        return write_output(output, format!("{ast:#?}\n").as_bytes());
+    }
 
-    let mut buf = Vec::new();
-    ast.generate(&mut buf);
-    let koopa_ir = String::from_utf8(buf).unwrap();
+    if mode == Mode::RunAst {
+        // 直接解释执行 AST, 作为语义的参考
+        let mut interpreter = AstInterpreter::new();
+        interpreter.runtime().set_echo(output != "-");
+        let code = interpreter.run(&ast)?;
+        write_output(output, &format_output(interpreter.runtime().stdout(), code))?;
+        eprintln!("exit code: {code}");
+        return Ok(());
+    }
 
-    // 调用库将koopa ir转换成koopa ir对应的AST
-    let driver = koopa::front::Driver::from(koopa_ir.clone());
-    let program = driver.generate_program().unwrap();
+    if mode == Mode::Reduce {
+        return reduce(args, &ast);
+    }
 
-    if mode == "-koopa" {
-        write(&output, koopa_ir.clone()).unwrap();
-        println!("{}", koopa_ir);
-    } else {
+    // 只有 RISC-V 相关的模式需要后端
+    let emit_asm = !matches!(
+        mode,
+        Mode::RunKoopa | Mode::Koopa | Mode::X86 | Mode::Wasm | Mode::Llvm | Mode::C
+    );
+    let options = args.options(emit_asm);
+    // 诊断信息已经包含 panic 的内容, 不再打印默认的 panic 信息
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let artifacts = compile_ast(ast, Some(&source), &options);
+    std::panic::set_hook(hook);
+    let artifacts = artifacts.map_err(diagnostics)?;
+    for (pass, koopa) in &artifacts.ir_dumps {
+        eprint!("// *** IR after {pass} ***\n{koopa}");
+    }
+    if args.time_passes {
+        eprint!("{}", artifacts.pass_timings);
+    }
+    let program = artifacts.program;
+
+    let emit = |f: &dyn Fn(&mut Vec<u8>)| {
         let mut buf = Vec::new();
-        program.generate(&mut ProgramInfo::new(&program, None), &mut buf);
-        let risc_v = String::from_utf8(buf).unwrap();
-        write(&output, risc_v.clone()).unwrap();
-        println!("{}", risc_v);
+        f(&mut buf);
+        write_output(output, &buf)
+    };
+    match mode {
+        Mode::RunKoopa => {
+            // 直接解释执行 Koopa IR, 不经过后端
+            let mut interpreter = KoopaInterpreter::new(&program);
+            interpreter.runtime().set_echo(output != "-");
+            let code = interpreter.run(None)?;
+            write_output(output, &format_output(interpreter.runtime().stdout(), code))?;
+            eprintln!("exit code: {code}");
+            eprintln!("koopa instructions: {}", interpreter.steps());
+            Ok(())
+        }
+        Mode::Koopa => write_output(output, artifacts.koopa.as_bytes()),
+        Mode::X86 => emit(&|buf| {
+            // 与 RISC-V 共用 GenerateAsm, 只换成 x86-64 的 Isa
+            let mut info = ProgramInfo::new(&program, None);
+            info.set_isa(X86_64::default());
+            info.set_peephole(args.peephole());
+            program.generate(&mut info, buf);
+            if args.peephole_stats {
+                eprint!("{}", info.peephole_stats());
+            }
+        }),
+        Mode::Wasm => {
+            let mut info = WasmInfo::new(&program)?;
+            let mut buf = Vec::new();
+            program.generate_wasm(&mut info, &mut buf);
+            write_output(output, &buf)
+        }
+        Mode::Llvm => emit(&|buf| program.generate_llvm(&mut LlvmInfo::new(&program), buf)),
+        Mode::C => emit(&|buf| program.generate_c(&mut CInfo::new(&program), buf)),
+        _ => {
+            if args.peephole_stats {
+                eprint!("{}", artifacts.peephole_stats);
+            }
+            let risc_v = artifacts.asm.unwrap();
+            let riscv32_only = |flag: &str| match args.target {
+                Target::Riscv32 => Ok(()),
+                Target::Riscv64 => Err(format!("{flag} only supports riscv32")),
+            };
+            match mode {
+                Mode::Run => {
+                    riscv32_only("-run")?;
+                    run(Emulator::from_asm(&risc_v)?, output)
+                }
+                Mode::Difftest => {
+                    riscv32_only("-difftest")?;
+                    // 两边读到相同的标准输入
+                    let program_input = read_input("-")?;
+                    // 有分歧时照常写出报告, 再以错误退出
+                    match difftest(&program, &risc_v, program_input) {
+                        Some(report) => {
+                            write_output(output, (report + "\n").as_bytes())?;
+                            Err("-difftest: the Koopa interpreter and the emulator diverge".into())
+                        }
+                        None => write_output(output, b"no divergence\n"),
+                    }
+                }
+                Mode::Object | Mode::Exe => {
+                    // 内置汇编器只支持 RV32IM
+                    riscv32_only(if mode == Mode::Object { "-c" } else { "-exe" })?;
+                    let object = assemble(&risc_v)?;
+                    if mode == Mode::Object {
+                        return write_output(output, &write_elf(&object));
+                    }
+                    let exe = link(&[object, runtime()])?;
+                    write_output(output, &write_executable(&exe))?;
+                    // 生成的是可执行文件
+                    #[cfg(unix)]
+                    if output != "-" {
+                        use std::os::unix::fs::PermissionsExt;
+                        std::fs::set_permissions(output, std::fs::Permissions::from_mode(0o755))
+                            .map_err(|err| format!("{output}: {err}"))?;
+                    }
+                    Ok(())
+                }
+                _ => write_output(output, risc_v.as_bytes()),
+            }
+        }
+    }
+}
+
+// 化简出错的程序, 默认保留原程序的第一种失败
+fn reduce(args: &Args, ast: &CompUnit) -> Result<(), String> {
+    // 外部命令通过 -o 指定的文件读取候选程序
+    if args.interesting.is_some() && args.output == "-" {
+        return Err("-reduce -interesting needs an output file given by -o".into());
     }
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let reduced = (|| {
+        let interesting: Box<dyn FnMut(&CompUnit) -> bool> = match &args.interesting {
+            Some(command) => Box::new(external(command.clone(), args.output.clone())),
+            None => {
+                let (_, failures) = failures(ast)?;
+                let (signature, _) = failures
+                    .into_iter()
+                    .next()
+                    .ok_or("the input does not fail")?;
+                eprintln!("reducing: {signature}");
+                Box::new(same_failure(signature))
+            }
+        };
+        let mut reducer = Reducer::new(interesting);
+        let reduced = reducer.reduce(ast)?;
+        eprintln!("{} tests", reducer.tests());
+        Ok::<_, String>(reduced)
+    })();
+    std::panic::set_hook(hook);
+    write_output(&args.output, reduced?.to_string().as_bytes())
+}
+
+// 运行程序, 统计信息写到标准错误
+fn run(mut emulator: Emulator, output: &str) -> Result<(), String> {
+    // 输出到标准输出时不需要再回显
+    emulator.set_echo(output != "-");
+    let code = emulator.run(None)?;
+    write_output(output, &format_output(emulator.stdout(), code))?;
+    eprintln!("exit code: {code}");
+    eprint!("{}", emulator.stats());
     Ok(())
 }
+
+// 读取输入, - 表示标准输入
+fn read_input(input: &str) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    let result = if input == "-" {
+        stdin().read_to_end(&mut buf).map(|_| ())
+    } else {
+        std::fs::read(input).map(|file| buf = file)
+    };
+    result.map_err(|err| format!("{input}: {err}"))?;
+    Ok(buf)
+}
+
+// 写出结果, - 表示标准输出
+fn write_output(output: &str, data: &[u8]) -> Result<(), String> {
+    let result = if output == "-" {
+        stdout().write_all(data)
+    } else {
+        write(output, data)
+    };
+    result.map_err(|err| format!("{output}: {err}"))
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use koopa::back::KoopaGenerator;
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, FunctionData, Program, Value, ValueKind};
+use koopa::opt::Pass;
+
+use const_fold::ConstFold;
+use dce::Dce;
+use dead_func::DeadFunc;
+use mem2reg::Mem2Reg;
+use simplify_cfg::SimplifyCfg;
+
+mod const_fold;
+mod dce;
+mod dead_func;
+mod mem2reg;
+mod simplify_cfg;
+
+// Koopa IR 上的优化.
+// 每个优化遍实现 koopa::opt 的 FunctionPass 或 ModulePass, PassManager 按名字把它们组成流水线.
+// koopa 0.0.7 的 replace_value_with 会清空被替换的 value 的 used_by, 所以优化遍不使用 used_by, 需要时自己统计;
+// 删除指令和基本块时也只从布局中删除. 每个优化遍结束后把程序输出成文本再重新解析,
+// 后端看到的 used_by 是完整的, 也不会留下不在布局中的 value; 解析失败时报告是哪个优化遍生成了不合法的 IR.
+
+// 优化遍的构造函数
+type NewPass = fn() -> Pass;
+
+/// 所有优化遍的名字和构造函数
+const PASSES: &[(&str, NewPass)] = &[
+    ("const-fold", || Pass::Function(Box::new(ConstFold))),
+    ("dce", || Pass::Function(Box::new(Dce))),
+    ("simplify-cfg", || Pass::Function(Box::new(SimplifyCfg))),
+    ("dead-func", || Pass::Module(Box::new(DeadFunc))),
+    ("mem2reg", || Pass::Function(Box::new(Mem2Reg))),
+];
+
+/// 优化级别对应的流水线
+pub fn preset(level: u8) -> Vec<String> {
+    let names: &[&str] = match level {
+        0 => &[],
+        1 => &["const-fold", "dce"],
+        _ => &[
+            "dead-func",
+            "mem2reg",
+            "const-fold",
+            "simplify-cfg",
+            "const-fold",
+            "dce",
+        ],
+    };
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// 检查优化遍的名字
+pub fn check_pass(name: &str) -> Result<(), String> {
+    if PASSES.iter().any(|&(pass, _)| pass == name) {
+        return Ok(());
+    }
+    let names: Vec<&str> = PASSES.iter().map(|&(pass, _)| pass).collect();
+    Err(format!(
+        "unknown pass `{name}`, expected one of {}",
+        names.join(", ")
+    ))
+}
+
+/// 每个优化遍的耗时, 按运行顺序排列
+#[derive(Debug, Clone, Default)]
+pub struct PassTimings(pub Vec<(String, Duration)>);
+
+impl Display for PassTimings {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        for (name, time) in &self.0 {
+            writeln!(f, "{name}: {:.3}ms", ms(*time))?;
+        }
+        let total = self.0.iter().map(|(_, time)| *time).sum();
+        writeln!(f, "total: {:.3}ms", ms(total))
+    }
+}
+
+/// 按顺序运行一组优化遍
+pub struct PassManager {
+    passes: Vec<(&'static str, Pass)>,
+    print_after: Vec<String>,
+    timings: PassTimings,
+    dumps: Vec<(String, String)>,
+}
+
+impl PassManager {
+    /// 按名字组成流水线, 同一个优化遍可以出现多次
+    pub fn new(names: &[String]) -> Result<Self, String> {
+        let mut passes = Vec::new();
+        for name in names {
+            check_pass(name)?;
+            let &(name, new) = PASSES.iter().find(|&&(pass, _)| pass == name).unwrap();
+            passes.push((name, new()));
+        }
+        Ok(Self {
+            passes,
+            print_after: Vec::new(),
+            timings: PassTimings::default(),
+            dumps: Vec::new(),
+        })
+    }
+
+    /// 在名字为 name 的优化遍之后保存 Koopa IR 文本, all 表示每个优化遍之后都保存
+    pub fn print_after(&mut self, name: &str) -> Result<(), String> {
+        if name != "all" {
+            check_pass(name)?;
+        }
+        self.print_after.push(name.to_owned());
+        Ok(())
+    }
+
+    /// 运行流水线, 某个优化遍生成了不合法的 Koopa IR 时返回错误
+    pub fn run(&mut self, program: &mut Program) -> Result<(), String> {
+        for (name, pass) in &mut self.passes {
+            let start = Instant::now();
+            match pass {
+                Pass::Module(pass) => pass.run_on(program),
+                Pass::Function(pass) => {
+                    for (&func, data) in program.funcs_mut() {
+                        // 跳过函数声明
+                        if data.layout().entry_bb().is_some() {
+                            pass.run_on(func, data);
+                        }
+                    }
+                }
+            }
+            self.timings.0.push((name.to_string(), start.elapsed()));
+            let text = dump(program);
+            if self.print_after.iter().any(|p| p == name || p == "all") {
+                self.dumps.push((name.to_string(), text.clone()));
+            }
+            // 重新解析, 恢复 used_by
+            *program = koopa::front::Driver::from(text)
+                .generate_program()
+                .map_err(|err| format!("pass `{name}` produced invalid Koopa IR: {err:?}"))?;
+        }
+        Ok(())
+    }
+
+    pub fn timings(&self) -> &PassTimings {
+        &self.timings
+    }
+
+    /// print_after 要求保存的 (优化遍, Koopa IR 文本)
+    pub fn dumps(&self) -> &[(String, String)] {
+        &self.dumps
+    }
+}
+
+/// 把程序输出成 Koopa IR 文本, 指令与前端生成的一样缩进 4 个空格
+pub fn dump(program: &Program) -> String {
+    let mut gen = KoopaGenerator::new(Vec::new());
+    gen.generate_on(program).unwrap();
+    String::from_utf8(gen.writer())
+        .unwrap()
+        .lines()
+        .map(|line| match line.strip_prefix("  ") {
+            Some(inst) => format!("    {inst}\n"),
+            None => format!("{line}\n"),
+        })
+        .collect()
+}
+
+// 以下是优化遍共用的辅助函数
+
+// 按布局顺序排列的基本块和指令
+fn layout(data: &FunctionData) -> Vec<(BasicBlock, Vec<Value>)> {
+    data.layout()
+        .bbs()
+        .iter()
+        .map(|(&bb, node)| (bb, node.insts().keys().copied().collect()))
+        .collect()
+}
+
+// 局部的整数常量
+fn integer(data: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
+
+// 布局中每个 value 的使用者, 不依赖 used_by
+fn users(data: &FunctionData) -> HashMap<Value, Vec<Value>> {
+    let mut users: HashMap<Value, Vec<Value>> = HashMap::new();
+    for (_, insts) in layout(data) {
+        for inst in insts {
+            for value in data.dfg().value(inst).kind().value_uses() {
+                users.entry(value).or_default().push(inst);
+            }
+        }
+    }
+    users
+}
+
+// 把布局中的指令对 map 中的 value 的使用替换成对应的 value, 替换可以是链式的
+fn replace_uses(data: &mut FunctionData, map: &HashMap<Value, Value>) {
+    let get = |mut value: Value| {
+        while let Some(&next) = map.get(&value) {
+            value = next;
+        }
+        value
+    };
+    let args = |args: &[Value]| args.iter().map(|&arg| get(arg)).collect::<Vec<_>>();
+    for (_, insts) in layout(data) {
+        for inst in insts {
+            let kind = data.dfg().value(inst).kind().clone();
+            if !kind.value_uses().any(|value| map.contains_key(&value)) {
+                continue;
+            }
+            let builder = data.dfg_mut().replace_value_with(inst);
+            match kind {
+                ValueKind::Binary(bin) => builder.binary(bin.op(), get(bin.lhs()), get(bin.rhs())),
+                ValueKind::Load(load) => builder.load(get(load.src())),
+                ValueKind::Store(store) => builder.store(get(store.value()), get(store.dest())),
+                ValueKind::GetPtr(ptr) => builder.get_ptr(get(ptr.src()), get(ptr.index())),
+                ValueKind::GetElemPtr(ptr) => {
+                    builder.get_elem_ptr(get(ptr.src()), get(ptr.index()))
+                }
+                ValueKind::Branch(br) => builder.branch_with_args(
+                    get(br.cond()),
+                    br.true_bb(),
+                    br.false_bb(),
+                    args(br.true_args()),
+                    args(br.false_args()),
+                ),
+                ValueKind::Jump(jump) => builder.jump_with_args(jump.target(), args(jump.args())),
+                ValueKind::Call(call) => builder.call(call.callee(), args(call.args())),
+                ValueKind::Return(ret) => builder.ret(ret.value().map(get)),
+                _ => unreachable!(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use koopa::opt::FunctionPass;
+
+    use super::*;
+
+    // 删掉入口块中所有指令的优化遍, 生成的 IR 没有结尾的 ret
+    struct Broken;
+
+    impl FunctionPass for Broken {
+        fn run_on(&mut self, _: koopa::ir::Function, data: &mut FunctionData) {
+            let entry = data.layout().entry_bb().unwrap();
+            data.layout_mut().bb_mut(entry).insts_mut().clear();
+        }
+    }
+
+    #[test]
+    fn invalid_ir_names_the_pass() {
+        let mut program = koopa::front::Driver::from("fun @main(): i32 {\n%entry:\n  ret 0\n}\n")
+            .generate_program()
+            .unwrap();
+        let mut passes = PassManager::new(&["dce".to_owned()]).unwrap();
+        passes
+            .passes
+            .push(("broken", Pass::Function(Box::new(Broken))));
+        let err = passes.run(&mut program).unwrap_err();
+        assert!(
+            err.starts_with("pass `broken` produced invalid Koopa IR"),
+            "{err}"
+        );
+    }
+}
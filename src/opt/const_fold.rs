@@ -0,0 +1,36 @@
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BinaryOp, Function, FunctionData, ValueKind};
+use koopa::opt::FunctionPass;
+
+use super::{integer, layout};
+use crate::interpreter::binary;
+
+/// 常量折叠: 两个操作数都是整数常量的二元运算替换成常量.
+/// 按 RISC-V 的语义计算, 与后端和解释器的结果一致; 除以 0 留到运行时
+pub struct ConstFold;
+
+impl FunctionPass for ConstFold {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        // 按布局顺序处理, 折叠出的常量可以继续参与后面的折叠
+        for (bb, insts) in layout(data) {
+            for inst in insts {
+                let ValueKind::Binary(bin) = data.dfg().value(inst).kind() else {
+                    continue;
+                };
+                let op = bin.op();
+                let (Some(lhs), Some(rhs)) = (integer(data, bin.lhs()), integer(data, bin.rhs()))
+                else {
+                    continue;
+                };
+                if matches!(op, BinaryOp::Div | BinaryOp::Mod) && rhs == 0 {
+                    continue;
+                }
+                // value 本身变成常量, 使用它的指令不需要修改
+                data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                data.dfg_mut()
+                    .replace_value_with(inst)
+                    .integer(binary(op, lhs, rhs));
+            }
+        }
+    }
+}
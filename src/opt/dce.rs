@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+use super::{layout, users};
+
+/// 死代码删除: 删除结果没有被使用的无副作用指令, 只被写入、从不被读取的 alloc 和写入它的 store,
+/// 以及只被传给死参数的基本块参数 (例如 mem2reg 为之后不再读取的变量添加的参数) 和跳转时传给它们的值
+pub struct Dce;
+
+impl FunctionPass for Dce {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        // 删除一条指令后它的操作数可能也变成死代码, 重复到不再变化
+        loop {
+            let params = dead_params(data);
+            remove_params(data, &params);
+            let users = users(data);
+            let mut dead = Vec::new();
+            for (bb, insts) in layout(data) {
+                for inst in insts {
+                    let inst_users = users.get(&inst).map_or(&[][..], |users| &users[..]);
+                    match data.dfg().value(inst).kind() {
+                        ValueKind::Alloc(_) if only_stored(data, inst, inst_users) => {
+                            dead.push((bb, inst));
+                            for &store in inst_users {
+                                let parent = data.layout().parent_bb(store).unwrap();
+                                dead.push((parent, store));
+                            }
+                        }
+                        ValueKind::Binary(_)
+                        | ValueKind::Load(_)
+                        | ValueKind::GetPtr(_)
+                        | ValueKind::GetElemPtr(_)
+                            if inst_users.is_empty() =>
+                        {
+                            dead.push((bb, inst))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            if dead.is_empty() && params.is_empty() {
+                break;
+            }
+            for (bb, inst) in dead {
+                data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            }
+        }
+    }
+}
+
+// alloc 的使用者都是以它为地址的 store
+fn only_stored(data: &FunctionData, alloc: Value, users: &[Value]) -> bool {
+    users
+        .iter()
+        .all(|&user| match data.dfg().value(user).kind() {
+            ValueKind::Store(store) => store.dest() == alloc && store.value() != alloc,
+            _ => false,
+        })
+}
+
+// 每个基本块中死参数的下标.
+// 从有副作用的指令 (以及 br 的条件) 使用的值出发标记活的值: 活的无副作用指令用到的值是活的,
+// 活的参数在跳转时接收的值也是活的. 其余的参数都是死的, 例如只在循环中经过计算再传给自己的参数
+fn dead_params(data: &FunctionData) -> HashMap<BasicBlock, Vec<usize>> {
+    // 无副作用的指令用到的值, 以及每个参数接收的实参
+    let mut deps: HashMap<Value, Vec<Value>> = HashMap::new();
+    let mut work = Vec::new();
+    for (_, insts) in layout(data) {
+        for inst in insts {
+            let mut pass = |target: BasicBlock, args: &[Value]| {
+                for (&param, &arg) in data.dfg().bb(target).params().iter().zip(args) {
+                    deps.entry(param).or_default().push(arg);
+                }
+            };
+            match data.dfg().value(inst).kind() {
+                ValueKind::Jump(jump) => pass(jump.target(), jump.args()),
+                ValueKind::Branch(br) => {
+                    work.push(br.cond());
+                    pass(br.true_bb(), br.true_args());
+                    pass(br.false_bb(), br.false_args());
+                }
+                kind @ (ValueKind::Binary(_)
+                | ValueKind::Load(_)
+                | ValueKind::GetPtr(_)
+                | ValueKind::GetElemPtr(_)) => {
+                    deps.entry(inst).or_default().extend(kind.value_uses())
+                }
+                kind => work.extend(kind.value_uses()),
+            }
+        }
+    }
+    let mut live = HashSet::new();
+    while let Some(value) = work.pop() {
+        if live.insert(value) {
+            work.extend(deps.get(&value).into_iter().flatten());
+        }
+    }
+    let mut dead: HashMap<BasicBlock, Vec<usize>> = HashMap::new();
+    for &bb in data.layout().bbs().keys() {
+        for (i, param) in data.dfg().bb(bb).params().iter().enumerate() {
+            if !live.contains(param) {
+                dead.entry(bb).or_default().push(i);
+            }
+        }
+    }
+    dead
+}
+
+// 删除死参数, 以及所有跳转中对应的实参
+fn remove_params(data: &mut FunctionData, dead: &HashMap<BasicBlock, Vec<usize>>) {
+    if dead.is_empty() {
+        return;
+    }
+    let keep = |target: BasicBlock, values: &[Value]| -> Vec<Value> {
+        let dead = dead.get(&target).map_or(&[][..], |dead| &dead[..]);
+        values
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !dead.contains(i))
+            .map(|(_, &value)| value)
+            .collect()
+    };
+    // 先删除参数, 重新构造的跳转要与新的参数匹配
+    for &bb in dead.keys() {
+        let params = keep(bb, data.dfg().bb(bb).params());
+        *data.dfg_mut().bb_mut(bb).params_mut() = params;
+    }
+    for (_, insts) in layout(data) {
+        let last = *insts.last().unwrap();
+        let kind = data.dfg().value(last).kind().clone();
+        match kind {
+            ValueKind::Jump(jump) if dead.contains_key(&jump.target()) => {
+                let args = keep(jump.target(), jump.args());
+                data.dfg_mut()
+                    .replace_value_with(last)
+                    .jump_with_args(jump.target(), args);
+            }
+            ValueKind::Branch(br)
+                if dead.contains_key(&br.true_bb()) || dead.contains_key(&br.false_bb()) =>
+            {
+                let true_args = keep(br.true_bb(), br.true_args());
+                let false_args = keep(br.false_bb(), br.false_args());
+                data.dfg_mut().replace_value_with(last).branch_with_args(
+                    br.cond(),
+                    br.true_bb(),
+                    br.false_bb(),
+                    true_args,
+                    false_args,
+                );
+            }
+            _ => {}
+        }
+    }
+}
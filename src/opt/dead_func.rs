@@ -0,0 +1,42 @@
+use std::collections::HashSet;
+
+use koopa::ir::{Function, Program, ValueKind};
+use koopa::opt::ModulePass;
+
+/// 删除从 main 出发调用不到的函数和函数声明, 没有 main 时什么也不做
+pub struct DeadFunc;
+
+impl ModulePass for DeadFunc {
+    fn run_on(&mut self, program: &mut Program) {
+        let Some(&main) = program
+            .func_layout()
+            .iter()
+            .find(|&&func| program.func(func).name() == "@main")
+        else {
+            return;
+        };
+        let mut live = HashSet::from([main]);
+        let mut stack = vec![main];
+        while let Some(func) = stack.pop() {
+            let data = program.func(func);
+            for (_, node) in data.layout().bbs() {
+                for &inst in node.insts().keys() {
+                    if let ValueKind::Call(call) = data.dfg().value(inst).kind() {
+                        if live.insert(call.callee()) {
+                            stack.push(call.callee());
+                        }
+                    }
+                }
+            }
+        }
+        let dead: Vec<Function> = program
+            .func_layout()
+            .iter()
+            .copied()
+            .filter(|func| !live.contains(func))
+            .collect();
+        for func in dead {
+            program.remove_func(func);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::{HashMap, HashSet};
+
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, Type, TypeKind, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+use super::simplify_cfg::remove_unreachable;
+use super::{layout, replace_uses, users};
+use crate::cfg::{successors, Cfg};
+
+/// 把只被 load/store 访问的 i32 alloc 提升成 SSA 值.
+/// 在写入变量的基本块的迭代支配边界上添加基本块参数 (相当于 phi), 再沿支配树重命名:
+/// load 替换成变量当前的值, store 只更新当前的值, 跳转时把当前的值作为参数传给后继.
+/// 参数按迭代支配边界添加, 不看变量之后是否还被读取, 用不到的参数留给 dce 删除
+pub struct Mem2Reg;
+
+impl FunctionPass for Mem2Reg {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        // 不可达的基本块不在支配树上, 先删掉
+        remove_unreachable(data);
+        let cfg = Cfg::new(data);
+        let entry = cfg.rpo[0];
+        // 入口基本块不能有参数
+        if !cfg.preds[&entry].is_empty() {
+            return;
+        }
+        let users = users(data);
+        let no_users = Vec::new();
+        let vars: Vec<Value> = layout(data)
+            .into_iter()
+            .flat_map(|(_, insts)| insts)
+            .filter(|&inst| promotable(data, inst, users.get(&inst).unwrap_or(&no_users)))
+            .collect();
+        if vars.is_empty() {
+            return;
+        }
+
+        // 每个基本块新增的参数对应的变量
+        let frontiers = cfg.dominance_frontiers();
+        let mut phis: HashMap<BasicBlock, Vec<Value>> = HashMap::new();
+        for &var in &vars {
+            let mut work: Vec<BasicBlock> = users
+                .get(&var)
+                .unwrap_or(&no_users)
+                .iter()
+                .filter(|&&user| matches!(data.dfg().value(user).kind(), ValueKind::Store(_)))
+                .map(|&store| data.layout().parent_bb(store).unwrap())
+                .collect();
+            let mut placed = HashSet::new();
+            while let Some(bb) = work.pop() {
+                for &frontier in &frontiers[&bb] {
+                    if placed.insert(frontier) {
+                        phis.entry(frontier).or_default().push(var);
+                        work.push(frontier);
+                    }
+                }
+            }
+        }
+        let params: HashMap<BasicBlock, Vec<Value>> = phis
+            .iter()
+            .map(|(&bb, vars)| (bb, new_params(data, bb, vars.len())))
+            .collect();
+
+        // 沿支配树重命名, 读取未初始化的变量是未定义行为, 这里取 0
+        let zero = data.dfg_mut().new_value().integer(0);
+        let mut map = HashMap::new();
+        let mut args: HashMap<(BasicBlock, BasicBlock), Vec<Value>> = HashMap::new();
+        let mut dead = Vec::new();
+        let mut stack: Vec<(BasicBlock, HashMap<Value, Value>)> =
+            vec![(entry, vars.iter().map(|&var| (var, zero)).collect())];
+        while let Some((bb, mut current)) = stack.pop() {
+            if let Some(vars) = phis.get(&bb) {
+                current.extend(vars.iter().copied().zip(params[&bb].iter().copied()));
+            }
+            let node = data.layout().bbs().node(&bb).unwrap();
+            let insts: Vec<Value> = node.insts().keys().copied().collect();
+            for inst in insts {
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Load(load) if current.contains_key(&load.src()) => {
+                        map.insert(inst, current[&load.src()]);
+                        dead.push((bb, inst));
+                    }
+                    ValueKind::Store(store) if current.contains_key(&store.dest()) => {
+                        current.insert(store.dest(), store.value());
+                        dead.push((bb, inst));
+                    }
+                    _ => {}
+                }
+            }
+            for succ in successors(data, bb) {
+                if let Some(vars) = phis.get(&succ) {
+                    let values = vars.iter().map(|var| current[var]).collect();
+                    args.insert((bb, succ), values);
+                }
+            }
+            for child in cfg.dom_children(bb) {
+                stack.push((child, current.clone()));
+            }
+        }
+
+        // 跳转到添加了参数的基本块时传入变量的值
+        let preds: HashSet<BasicBlock> = args.keys().map(|&(pred, _)| pred).collect();
+        for pred in preds {
+            let node = data.layout().bbs().node(&pred).unwrap();
+            let last = *node.insts().back_key().unwrap();
+            let extend = |target: BasicBlock, old: &[Value]| {
+                let mut new = old.to_vec();
+                new.extend(args.get(&(pred, target)).into_iter().flatten());
+                new
+            };
+            let kind = data.dfg().value(last).kind().clone();
+            let builder = data.dfg_mut().replace_value_with(last);
+            match kind {
+                ValueKind::Jump(jump) => {
+                    builder.jump_with_args(jump.target(), extend(jump.target(), jump.args()))
+                }
+                ValueKind::Branch(br) => builder.branch_with_args(
+                    br.cond(),
+                    br.true_bb(),
+                    br.false_bb(),
+                    extend(br.true_bb(), br.true_args()),
+                    extend(br.false_bb(), br.false_args()),
+                ),
+                _ => unreachable!(),
+            };
+        }
+
+        for (bb, inst) in dead {
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+        }
+        for var in vars {
+            let bb = data.layout().parent_bb(var).unwrap();
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&var);
+        }
+        replace_uses(data, &map);
+    }
+}
+
+// 类型为 *i32, 并且只作为 load 和 store 的地址使用的 alloc
+fn promotable(data: &FunctionData, inst: Value, users: &[Value]) -> bool {
+    let value = data.dfg().value(inst);
+    if !matches!(value.kind(), ValueKind::Alloc(_)) {
+        return false;
+    }
+    let TypeKind::Pointer(base) = value.ty().kind() else {
+        return false;
+    };
+    base.is_i32()
+        && users
+            .iter()
+            .all(|&user| match data.dfg().value(user).kind() {
+                ValueKind::Load(load) => load.src() == inst,
+                ValueKind::Store(store) => store.dest() == inst && store.value() != inst,
+                _ => false,
+            })
+}
+
+// 给 bb 添加 count 个 i32 参数.
+// koopa 不能直接给已有的基本块添加参数, 这里从一个带参数的新基本块中取出编号正确的参数值,
+// 新基本块不在布局中, 重新解析时丢弃
+fn new_params(data: &mut FunctionData, bb: BasicBlock, count: usize) -> Vec<Value> {
+    let old = data.dfg().bb(bb).params().len();
+    let scratch = data
+        .dfg_mut()
+        .new_bb()
+        .basic_block_with_params(None, vec![Type::get_i32(); old + count]);
+    let params = data.dfg().bb(scratch).params()[old..].to_vec();
+    data.dfg_mut()
+        .bb_mut(bb)
+        .params_mut()
+        .extend(params.iter().copied());
+    params
+}
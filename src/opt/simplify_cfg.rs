@@ -0,0 +1,92 @@
+use std::collections::{HashMap, HashSet};
+
+use koopa::ir::builder_traits::*;
+use koopa::ir::{Function, FunctionData, ValueKind};
+use koopa::opt::FunctionPass;
+
+use super::{integer, layout, replace_uses};
+use crate::cfg::Cfg;
+
+/// 化简控制流图: 条件为常量的 br 改成 jump, 删除不可达的基本块,
+/// 把只有一个前驱、且前驱以 jump 跳过来的基本块合并到前驱中
+pub struct SimplifyCfg;
+
+impl FunctionPass for SimplifyCfg {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        while fold_branches(data) | remove_unreachable(data) | merge_block(data) {}
+    }
+}
+
+// 条件为常量的 br 改成 jump
+fn fold_branches(data: &mut FunctionData) -> bool {
+    let mut changed = false;
+    for (_, insts) in layout(data) {
+        let Some(&last) = insts.last() else {
+            continue;
+        };
+        let ValueKind::Branch(br) = data.dfg().value(last).kind() else {
+            continue;
+        };
+        let Some(cond) = integer(data, br.cond()) else {
+            continue;
+        };
+        let (target, args) = match cond {
+            0 => (br.false_bb(), br.false_args().to_vec()),
+            _ => (br.true_bb(), br.true_args().to_vec()),
+        };
+        data.dfg_mut()
+            .replace_value_with(last)
+            .jump_with_args(target, args);
+        changed = true;
+    }
+    changed
+}
+
+// 从布局中删除不可达的基本块
+pub(super) fn remove_unreachable(data: &mut FunctionData) -> bool {
+    let reachable: HashSet<_> = Cfg::new(data).rpo.into_iter().collect();
+    let unreachable: Vec<_> = data
+        .layout()
+        .bbs()
+        .keys()
+        .copied()
+        .filter(|bb| !reachable.contains(bb))
+        .collect();
+    for bb in &unreachable {
+        data.layout_mut().bbs_mut().remove(bb);
+    }
+    !unreachable.is_empty()
+}
+
+// 合并一对基本块, 每次只合并一对, 之后重新计算控制流图
+fn merge_block(data: &mut FunctionData) -> bool {
+    let cfg = Cfg::new(data);
+    for &bb in &cfg.rpo[1..] {
+        let [pred] = cfg.preds[&bb][..] else {
+            continue;
+        };
+        let node = data.layout().bbs().node(&pred).unwrap();
+        let jump = *node.insts().back_key().unwrap();
+        let ValueKind::Jump(jump_data) = data.dfg().value(jump).kind() else {
+            continue;
+        };
+        if pred == bb {
+            continue;
+        }
+        // 基本块参数替换成 jump 传入的值
+        let params = data.dfg().bb(bb).params().to_vec();
+        let map: HashMap<_, _> = params.into_iter().zip(jump_data.args().to_vec()).collect();
+        data.layout_mut().bb_mut(pred).insts_mut().remove(&jump);
+        let (_, mut node) = data.layout_mut().bbs_mut().remove(&bb).unwrap();
+        while let Some((inst, _)) = node.insts_mut().pop_front() {
+            data.layout_mut()
+                .bb_mut(pred)
+                .insts_mut()
+                .push_key_back(inst)
+                .unwrap();
+        }
+        replace_uses(data, &map);
+        return true;
+    }
+    false
+}
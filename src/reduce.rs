@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::write;
+use std::process::Command;
+
+use crate::ast::*;
+use crate::fuzz::failures;
+
+// 基于 AST 的测试用例化简.
+// 先按 delta debugging 的方式成块地删除 main 中的语句和声明, 再对剩下的每一项依次尝试只改动一处的
+// 化简: 删除多余的定义, 把二元运算换成它的一个操作数, 去掉一元运算和括号, 把整数换成 0 或 1,
+// 把常量的引用换成它的初始值. 只保留让判定函数仍然成立的修改, 直到不能再化简为止.
+// 候选程序先做语义检查, 不合法的程序 (例如删掉了仍在使用的声明) 不交给判定函数,
+// 否则判定函数可能把前端报出的另一种错误当成原来的问题.
+
+// 常量名到初始值表达式的映射, 用来内联常量
+type Consts = HashMap<String, Exp>;
+
+trait Shrink: Sized {
+    /// 只改动一处、比自身更简单的所有版本, 改动大的在前
+    fn shrink(&self, consts: &Consts) -> Vec<Self>;
+}
+
+// 把子节点的每个化简版本放回原处
+fn replace<C: Shrink, T>(child: &C, consts: &Consts, f: impl Fn(C) -> T) -> Vec<T> {
+    child.shrink(consts).into_iter().map(f).collect()
+}
+
+// 把 UnaryExp 逐层包装成 Exp
+fn lift(unary_exp: UnaryExp) -> Exp {
+    Exp {
+        l_or_exp: LOrExp::LAndExp(LAndExp::EqExp(EqExp::RelExp(RelExp::AddExp(
+            AddExp::MulExp(MulExp::UnaryExp(unary_exp)),
+        )))),
+    }
+}
+
+// 只由一个 PrimaryExp 构成的表达式
+fn as_primary(exp: &Exp) -> Option<&PrimaryExp> {
+    let LOrExp::LAndExp(LAndExp::EqExp(EqExp::RelExp(RelExp::AddExp(AddExp::MulExp(
+        MulExp::UnaryExp(UnaryExp::PrimaryExp(p_exp)),
+    ))))) = &exp.l_or_exp
+    else {
+        return None;
+    };
+    Some(p_exp)
+}
+
+impl Shrink for Exp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        let mut out = Vec::new();
+        if !matches!(as_primary(self), Some(PrimaryExp::Number(_))) {
+            out.push(lift(UnaryExp::PrimaryExp(PrimaryExp::Number(Number {
+                num: 0,
+            }))));
+        }
+        out.extend(replace(&self.l_or_exp, consts, |l_or_exp| Exp { l_or_exp }));
+        out
+    }
+}
+
+impl Shrink for LOrExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::LAndExp(l_and_exp) => replace(l_and_exp, consts, Self::LAndExp),
+            Self::Or(l_or_exp, l_and_exp) => {
+                let mut out = vec![(**l_or_exp).clone(), Self::LAndExp(l_and_exp.clone())];
+                out.extend(replace(&**l_or_exp, consts, |l| {
+                    Self::Or(Box::new(l), l_and_exp.clone())
+                }));
+                out.extend(replace(l_and_exp, consts, |r| {
+                    Self::Or(l_or_exp.clone(), r)
+                }));
+                out
+            }
+        }
+    }
+}
+
+impl Shrink for LAndExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::EqExp(eq_exp) => replace(eq_exp, consts, Self::EqExp),
+            Self::And(l_and_exp, eq_exp) => {
+                let mut out = vec![(**l_and_exp).clone(), Self::EqExp(eq_exp.clone())];
+                out.extend(replace(&**l_and_exp, consts, |l| {
+                    Self::And(Box::new(l), eq_exp.clone())
+                }));
+                out.extend(replace(eq_exp, consts, |r| Self::And(l_and_exp.clone(), r)));
+                out
+            }
+        }
+    }
+}
+
+impl Shrink for EqExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::RelExp(rel_exp) => replace(rel_exp, consts, Self::RelExp),
+            Self::Eq(eq_exp, sign, rel_exp) => {
+                let mut out = vec![(**eq_exp).clone(), Self::RelExp(rel_exp.clone())];
+                out.extend(replace(&**eq_exp, consts, |l| {
+                    Self::Eq(Box::new(l), sign.clone(), rel_exp.clone())
+                }));
+                out.extend(replace(rel_exp, consts, |r| {
+                    Self::Eq(eq_exp.clone(), sign.clone(), r)
+                }));
+                out
+            }
+        }
+    }
+}
+
+impl Shrink for RelExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::AddExp(add_exp) => replace(add_exp, consts, Self::AddExp),
+            Self::Cmp(rel_exp, sign, add_exp) => {
+                let mut out = vec![(**rel_exp).clone(), Self::AddExp(add_exp.clone())];
+                out.extend(replace(&**rel_exp, consts, |l| {
+                    Self::Cmp(Box::new(l), sign.clone(), add_exp.clone())
+                }));
+                out.extend(replace(add_exp, consts, |r| {
+                    Self::Cmp(rel_exp.clone(), sign.clone(), r)
+                }));
+                out
+            }
+        }
+    }
+}
+
+impl Shrink for AddExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::MulExp(mul_exp) => replace(mul_exp, consts, Self::MulExp),
+            Self::AddExp(add_exp, sign, mul_exp) => {
+                let mut out = vec![(**add_exp).clone(), Self::MulExp(mul_exp.clone())];
+                out.extend(replace(&**add_exp, consts, |l| {
+                    Self::AddExp(Box::new(l), sign.clone(), mul_exp.clone())
+                }));
+                out.extend(replace(mul_exp, consts, |r| {
+                    Self::AddExp(add_exp.clone(), sign.clone(), r)
+                }));
+                out
+            }
+        }
+    }
+}
+
+impl Shrink for MulExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::UnaryExp(unary_exp) => replace(unary_exp, consts, Self::UnaryExp),
+            Self::MulExp(mul_exp, sign, unary_exp) => {
+                let mut out = vec![(**mul_exp).clone(), Self::UnaryExp(unary_exp.clone())];
+                out.extend(replace(&**mul_exp, consts, |l| {
+                    Self::MulExp(Box::new(l), sign.clone(), unary_exp.clone())
+                }));
+                out.extend(replace(unary_exp, consts, |r| {
+                    Self::MulExp(mul_exp.clone(), sign.clone(), r)
+                }));
+                out
+            }
+        }
+    }
+}
+
+impl Shrink for UnaryExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::PrimaryExp(p_exp) => replace(p_exp, consts, Self::PrimaryExp),
+            Self::Unary(op, u_exp) => {
+                let mut out = vec![(**u_exp).clone()];
+                out.extend(replace(&**u_exp, consts, |u| {
+                    Self::Unary(op.clone(), Box::new(u))
+                }));
+                out
+            }
+        }
+    }
+}
+
+impl Shrink for PrimaryExp {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::Exp(exp) => {
+                // 括号里只有一个 PrimaryExp 时去掉括号
+                let mut out: Vec<Self> = as_primary(exp).into_iter().cloned().collect();
+                out.extend(replace(&**exp, consts, |e| Self::Exp(Box::new(e))));
+                out
+            }
+            Self::Number(num) => (0..num.num.min(2))
+                .map(|num| Self::Number(Number { num }))
+                .collect(),
+            // 内联常量, 初始值不是单个 PrimaryExp 时加上括号
+            Self::LVal(lval) => match consts.get(&lval.ident) {
+                Some(exp) => vec![as_primary(exp)
+                    .cloned()
+                    .unwrap_or_else(|| Self::Exp(Box::new(exp.clone())))],
+                None => Vec::new(),
+            },
+        }
+    }
+}
+
+impl Shrink for Stmt {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::Ret(exp) => replace(exp, consts, Self::Ret),
+            Self::Assign(lval, exp) => replace(exp, consts, |e| Self::Assign(lval.clone(), e)),
+        }
+    }
+}
+
+// 依次删除 defs 中的每一个, 但至少保留一个
+fn remove_each<T: Clone>(defs: &[T]) -> Vec<Vec<T>> {
+    if defs.len() < 2 {
+        return Vec::new();
+    }
+    (0..defs.len())
+        .map(|i| [&defs[..i], &defs[i + 1..]].concat())
+        .collect()
+}
+
+impl Shrink for ConstDecl {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        let decl = |defs| Self {
+            typ: self.typ.clone(),
+            defs,
+        };
+        let mut out: Vec<Self> = remove_each(&self.defs).into_iter().map(decl).collect();
+        for (i, def) in self.defs.iter().enumerate() {
+            out.extend(replace(&def.val.exp.exp, consts, |exp| {
+                let mut defs = self.defs.clone();
+                defs[i].val.exp.exp = exp;
+                decl(defs)
+            }));
+        }
+        out
+    }
+}
+
+impl Shrink for VarDecl {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        let decl = |defs| Self {
+            typ: self.typ.clone(),
+            defs,
+        };
+        let mut out: Vec<Self> = remove_each(&self.defs).into_iter().map(decl).collect();
+        for (i, def) in self.defs.iter().enumerate() {
+            let VarDef::Init(ident, val, offset) = def else {
+                continue;
+            };
+            // 先尝试去掉初始值
+            let mut defs = self.defs.clone();
+            defs[i] = VarDef::NoInit(ident.clone(), *offset);
+            out.push(decl(defs));
+            out.extend(replace(&val.exp, consts, |exp| {
+                let mut defs = self.defs.clone();
+                defs[i] = VarDef::Init(ident.clone(), InitVal { exp }, *offset);
+                decl(defs)
+            }));
+        }
+        out
+    }
+}
+
+impl Shrink for BlockItem {
+    fn shrink(&self, consts: &Consts) -> Vec<Self> {
+        match self {
+            Self::Decl(Decl::ConstDecl(const_decl)) => {
+                replace(const_decl, consts, |d| Self::Decl(Decl::ConstDecl(d)))
+            }
+            Self::Decl(Decl::VarDecl(var_decl)) => {
+                replace(var_decl, consts, |d| Self::Decl(Decl::VarDecl(d)))
+            }
+            Self::Stmt(stmt) => replace(stmt, consts, Self::Stmt),
+        }
+    }
+}
+
+// 语义检查: 前端的检查 (见 CompUnit::check) 之外, 还要求以 return 结束, 变量在赋值之前不读取.
+// 没有初始值的变量读到的值是未定义的, 化简的结果不能依赖它
+fn well_formed(unit: &CompUnit) -> bool {
+    let items = &unit.func_def.block.items;
+    if unit.check().is_err() || !matches!(items.last(), Some(BlockItem::Stmt(Stmt::Ret(_)))) {
+        return false;
+    }
+    // 声明了但还没有赋值的变量
+    let mut uninit = HashSet::new();
+    let reads = |exp: &Exp, uninit: &HashSet<&str>| {
+        let mut lvals = Vec::new();
+        exp.lvals(&mut lvals);
+        lvals
+            .iter()
+            .all(|lval| !uninit.contains(lval.ident.as_str()))
+    };
+    for item in items {
+        let ok = match item {
+            BlockItem::Decl(Decl::ConstDecl(const_decl)) => const_decl
+                .defs
+                .iter()
+                .all(|def| reads(&def.val.exp.exp, &uninit)),
+            BlockItem::Decl(Decl::VarDecl(var_decl)) => var_decl.defs.iter().all(|def| match def {
+                VarDef::Init(_, val, _) => reads(&val.exp, &uninit),
+                VarDef::NoInit(ident, _) => uninit.insert(ident.as_str()),
+            }),
+            BlockItem::Stmt(Stmt::Assign(lval, exp)) => {
+                let ok = reads(exp, &uninit);
+                uninit.remove(lval.ident.as_str());
+                ok
+            }
+            BlockItem::Stmt(Stmt::Ret(exp)) => reads(exp, &uninit),
+        };
+        if !ok {
+            return false;
+        }
+    }
+    true
+}
+
+/// 化简器, interesting 是判定函数, 返回 true 表示程序仍然能重现问题
+pub struct Reducer<F> {
+    interesting: F,
+    // 调用判定函数的次数
+    tests: usize,
+}
+
+impl<F: FnMut(&CompUnit) -> bool> Reducer<F> {
+    pub fn new(interesting: F) -> Self {
+        Self {
+            interesting,
+            tests: 0,
+        }
+    }
+
+    pub fn tests(&self) -> usize {
+        self.tests
+    }
+
+    // 语义检查不通过的程序不调用判定函数
+    fn test(&mut self, unit: &CompUnit) -> bool {
+        if !well_formed(unit) {
+            return false;
+        }
+        self.tests += 1;
+        (self.interesting)(unit)
+    }
+
+    /// 化简 unit, 返回判定函数仍然成立的最小程序. unit 本身不合法或不满足判定函数时返回错误
+    pub fn reduce(&mut self, unit: &CompUnit) -> Result<CompUnit, String> {
+        if !well_formed(unit) {
+            return Err("the input is not a well-formed program".to_owned());
+        }
+        if !self.test(unit) {
+            return Err("the input is not interesting".to_owned());
+        }
+        let mut unit = unit.clone();
+        loop {
+            let removed = self.remove_items(&mut unit);
+            let shrunk = self.shrink_items(&mut unit);
+            if !removed && !shrunk {
+                return Ok(unit);
+            }
+        }
+    }
+
+    // 从一半开始, 成块地删除连续的若干项, 块的大小逐次减半. 最后的 return 总是保留
+    fn remove_items(&mut self, unit: &mut CompUnit) -> bool {
+        let removable = |unit: &CompUnit| unit.func_def.block.items.len() - 1;
+        let mut changed = false;
+        let mut chunk = removable(unit).div_ceil(2);
+        while chunk > 0 {
+            let mut start = 0;
+            while start < removable(unit) {
+                let mut candidate = unit.clone();
+                let end = (start + chunk).min(removable(unit));
+                let items = &mut candidate.func_def.block.items;
+                items.drain(start..end);
+                if self.test(&candidate) {
+                    *unit = candidate;
+                    changed = true;
+                } else {
+                    start += chunk;
+                }
+            }
+            chunk /= 2;
+        }
+        changed
+    }
+
+    // 逐项尝试只改动一处的化简, 接受第一个成立的版本后继续化简同一项
+    fn shrink_items(&mut self, unit: &mut CompUnit) -> bool {
+        let mut changed = false;
+        let mut consts = Consts::new();
+        for i in 0..unit.func_def.block.items.len() {
+            'item: loop {
+                for item in unit.func_def.block.items[i].shrink(&consts) {
+                    let mut candidate = unit.clone();
+                    candidate.func_def.block.items[i] = item;
+                    if self.test(&candidate) {
+                        *unit = candidate;
+                        changed = true;
+                        continue 'item;
+                    }
+                }
+                break;
+            }
+            if let BlockItem::Decl(Decl::ConstDecl(const_decl)) = &unit.func_def.block.items[i] {
+                for def in &const_decl.defs {
+                    consts.insert(def.ident.clone(), def.val.exp.exp.clone());
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// 默认的判定函数: 程序仍然出现 signature 这种失败 (见 fuzz::failures)
+pub fn same_failure(signature: String) -> impl FnMut(&CompUnit) -> bool {
+    move |unit| {
+        failures(unit).is_ok_and(|(_, failures)| failures.iter().any(|(s, _)| *s == signature))
+    }
+}
+
+/// 用外部命令作为判定函数: 程序写到 path, 以 path 为最后一个参数运行 command, 退出码为 0 表示仍然能重现.
+/// path 作为 sh 的位置参数传入, 其中的空格和特殊字符不会被 shell 解释
+pub fn external(command: String, path: String) -> impl FnMut(&CompUnit) -> bool {
+    move |unit| {
+        write(&path, unit.to_string()).unwrap();
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("{command} \"$1\""))
+            .arg("_")
+            .arg(&path)
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn program(body: &str) -> CompUnit {
+        parse(&format!("int main() {{\n{body}}}\n")).unwrap()
+    }
+
+    #[test]
+    fn semantic_checks() {
+        assert!(well_formed(&program(
+            "const int c = 1; int a; a = c; int b = a + c; return b;"
+        )));
+        let bad = [
+            "int a = 1;",
+            "return x;",
+            "int a = 1; const int c = a; return c;",
+            "const int c = 1; c = 2; return c;",
+            "int a; return a;",
+            "int a = 1; int a = 2; return a;",
+            "x = 1; return 0;",
+        ];
+        for body in bad {
+            assert!(!well_formed(&program(body)), "{body}");
+        }
+    }
+
+    #[test]
+    fn keeps_return_and_used_declarations() {
+        let unit = program(
+            "int a = 3; const int c = 2; int b = a + c; b = b * 7; int d = 5; return b / 3;",
+        );
+        let interesting = |unit: &CompUnit| unit.to_string().contains('/');
+        let reduced = Reducer::new(interesting).reduce(&unit).unwrap();
+        assert!(well_formed(&reduced), "{reduced}");
+        assert_eq!(
+            reduced.to_string(),
+            program("int b = 0; return b / 0;").to_string()
+        );
+    }
+
+    #[test]
+    fn external_command_gets_the_path() {
+        let dir = std::env::temp_dir().join(format!("reduce test {}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("case $1.sy").to_str().unwrap().to_owned();
+        let unit = program("int a = 1; int b = 2; return a + b;");
+        let mut interesting = external("grep -q b".to_owned(), path);
+        let reduced = Reducer::new(&mut interesting).reduce(&unit).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reduced.to_string(), program("int b; return 0;").to_string());
+    }
+}
@@ -0,0 +1,106 @@
+use std::thread;
+
+use compiler::generate_asm::Target;
+use compiler::{compile, Options, Stage};
+
+// 编译接口的测试
+
+#[test]
+fn parse_error_has_position() {
+    let Err(diagnostics) = compile("int main() {\n  return 1 +;\n}\n", &Options::default()) else {
+        panic!("expected a parse error");
+    };
+    let diagnostic = &diagnostics.0[0];
+    assert_eq!(diagnostic.stage, Stage::Parse);
+    assert_eq!(diagnostic.position, Some((2, 13)));
+}
+
+#[test]
+fn int_literal_out_of_range() {
+    for literal in ["2147483648", "020000000000", "0x80000000"] {
+        let source = format!("int main() {{\n  return -{literal};\n}}\n");
+        let Err(diagnostics) = compile(&source, &Options::default()) else {
+            panic!("expected a parse error: {source}");
+        };
+        let diagnostic = &diagnostics.0[0];
+        assert_eq!(diagnostic.stage, Stage::Parse);
+        assert_eq!(diagnostic.position, Some((2, 11)));
+        assert_eq!(diagnostic.message, "integer literal out of range");
+    }
+    let artifacts = compile("int main() { return 2147483647; }", &Options::default()).unwrap();
+    assert!(artifacts.koopa.contains("ret 2147483647"));
+}
+
+#[test]
+fn frontend_error_has_position() {
+    let cases = [
+        ("  return x;\n", (2, 10), "undefined variable `x`"),
+        (
+            "  const int c = 1;\n  c = 2;\n  return c;\n",
+            (3, 3),
+            "assignment to constant `c`",
+        ),
+        (
+            "  int a = 1;\n  const int c = a;\n  return c;\n",
+            (3, 17),
+            "variable `a` in a constant initializer",
+        ),
+        (
+            "  int a = 1, a = 2;\n  return a;\n",
+            (2, 14),
+            "redefinition of `a`",
+        ),
+    ];
+    for (body, position, message) in cases {
+        let source = format!("int main() {{\n{body}}}\n");
+        let Err(diagnostics) = compile(&source, &Options::default()) else {
+            panic!("expected a frontend error: {source}");
+        };
+        let diagnostic = &diagnostics.0[0];
+        assert_eq!(diagnostic.stage, Stage::Frontend);
+        assert_eq!(diagnostic.position, Some(position), "{source}");
+        assert_eq!(diagnostic.message, message);
+    }
+}
+
+#[test]
+fn backend_error_keeps_frontend_output() {
+    // RV64 的目标必须有 M 扩展, 后端拒绝这个组合
+    let source = "int main() { int x = 1; return x; }";
+    let options = Options {
+        target: Target::Riscv64,
+        m_extension: false,
+        ..Options::default()
+    };
+    let Err(diagnostics) = compile(source, &options) else {
+        panic!("riscv64 without the M extension should be rejected");
+    };
+    assert_eq!(diagnostics.0[0].stage, Stage::Backend);
+    let options = Options {
+        emit_asm: false,
+        ..options
+    };
+    let artifacts = compile(source, &options).unwrap();
+    assert!(artifacts.koopa.contains("@x = alloc i32"));
+    assert!(artifacts.asm.is_none());
+}
+
+// 临时名字的编号每次从 0 开始, 不同线程之间互不影响
+#[test]
+fn compile_is_repeatable() {
+    let source = "int main() { return (1 + 2) * 3 || 4 && 5; }";
+    let first = compile(source, &Options::default()).unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(move || {
+                let artifacts = compile(source, &Options::default()).unwrap();
+                (artifacts.koopa, artifacts.asm)
+            })
+        })
+        .collect();
+    for handle in threads {
+        let (koopa, asm) = handle.join().unwrap();
+        assert_eq!(koopa, first.koopa);
+        assert_eq!(asm, first.asm);
+    }
+}
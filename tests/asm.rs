@@ -0,0 +1,426 @@
+use compiler::generate_asm::{GenerateAsm, PeepholeConfig, ProgramInfo, Riscv, Target};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{FunctionData, Program, Type};
+
+// RISC-V 后端的测试, 输入是手写的 Koopa IR, 覆盖 SysY 前端还生成不了的指令.
+// 关掉窥孔优化, 检查的是指令选择的结果
+
+fn generate(program: &Program) -> String {
+    generate_for(program, Riscv::default())
+}
+
+fn generate_for(program: &Program, isa: Riscv) -> String {
+    let mut info = ProgramInfo::new(program, None);
+    info.set_peephole(PeepholeConfig::none());
+    info.set_isa(isa);
+    let mut buf = Vec::new();
+    program.generate(&mut info, &mut buf);
+    String::from_utf8(buf).unwrap()
+}
+
+fn parse_koopa(koopa: &str) -> Program {
+    koopa::front::Driver::from(koopa)
+        .generate_program()
+        .unwrap()
+}
+
+fn generate_koopa(koopa: &str) -> String {
+    generate(&parse_koopa(koopa))
+}
+
+#[test]
+fn fused_compare_and_branch() {
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  %0 = lt 1, 2
+  br %0, %then, %else
+
+%then:
+  %1 = ge 3, 4
+  br %1, %else, %end
+
+%else:
+  jump %end
+
+%end:
+  ret 0
+}
+";
+    let asm = generate_koopa(koopa);
+    // 比较指令不再单独生成
+    assert!(!asm.contains("slt"), "{asm}");
+    assert!(
+        asm.contains("    blt t5, t6, .Lmain_then\n    j .Lmain_else\n"),
+        "{asm}"
+    );
+    assert!(
+        asm.contains("    bge t5, t6, .Lmain_else\n    j .Lmain_end\n"),
+        "{asm}"
+    );
+    assert!(asm.contains(".Lmain_else:\n    j .Lmain_end\n"), "{asm}");
+}
+
+#[test]
+fn compare_used_twice_is_not_fused() {
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  %0 = lt 1, 2
+  br %0, %then, %end
+
+%then:
+  ret %0
+
+%end:
+  ret 0
+}
+";
+    let asm = generate_koopa(koopa);
+    assert!(asm.contains("slt") && asm.contains("bnez"), "{asm}");
+}
+
+#[test]
+fn unnamed_basic_blocks() {
+    // 文本形式的 Koopa IR 总是给基本块命名, 这里直接构造没有名字的基本块
+    let mut program = Program::new();
+    let func = program.new_func(FunctionData::new("@main".into(), vec![], Type::get_i32()));
+    let data = program.func_mut(func);
+    let entry = data.dfg_mut().new_bb().basic_block(Some("%entry".into()));
+    let first = data.dfg_mut().new_bb().basic_block(None);
+    let second = data.dfg_mut().new_bb().basic_block(None);
+    let jump = data.dfg_mut().new_value().jump(first);
+    let zero = data.dfg_mut().new_value().integer(0);
+    let cond = data.dfg_mut().new_value().integer(1);
+    let br = data.dfg_mut().new_value().branch(cond, second, second);
+    let ret = data.dfg_mut().new_value().ret(Some(zero));
+    for (bb, inst) in [(entry, jump), (first, br), (second, ret)] {
+        data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
+        data.layout_mut()
+            .bb_mut(bb)
+            .insts_mut()
+            .push_key_back(inst)
+            .unwrap();
+    }
+    let asm = generate(&program);
+    assert!(
+        asm.contains("    j .Lmain_anon.1\n.Lmain_anon.1:\n"),
+        "{asm}"
+    );
+    assert!(asm.contains("bnez t5, .Lmain_anon.2\n"), "{asm}");
+    assert!(asm.contains(".Lmain_anon.2:\n"), "{asm}");
+}
+
+// 在模拟器中运行汇编, 返回 main 的返回值. 与进程的退出码一样只保留低 8 位
+fn run(asm: &str) -> i32 {
+    compiler::emulator::Emulator::from_asm(asm)
+        .unwrap()
+        .run(Some(1_000_000))
+        .unwrap_or_else(|err| panic!("{err}\n{asm}"))
+}
+
+// 汇编中是否用到了 s0-s11
+fn uses_callee_saved(asm: &str) -> bool {
+    asm.split(|c: char| !c.is_ascii_alphanumeric())
+        .any(|word| word.len() > 1 && word.starts_with('s') && word[1..].parse::<u32>().is_ok())
+}
+
+#[test]
+fn call_crossing_value_uses_callee_saved() {
+    let koopa = "\
+fun @id(%x: i32): i32 {
+%entry:
+  ret %x
+}
+
+fun @main(): i32 {
+%entry:
+  %0 = add 1, 2
+  %1 = call @id(5)
+  %2 = add %0, %1
+  ret %2
+}
+";
+    let asm = generate_koopa(koopa);
+    // %0 跨越调用, 放在 s0 中; 调用前后保存 ra 和 s0
+    assert!(asm.contains("    add s0, t5, t6\n"), "{asm}");
+    assert!(
+        asm.contains("    sw ra, 0(sp)\n    sw s0, 4(sp)\n"),
+        "{asm}"
+    );
+    assert!(
+        asm.contains("    lw ra, 0(sp)\n    lw s0, 4(sp)\n"),
+        "{asm}"
+    );
+    // id 中没有调用, 不需要栈帧
+    let id = &asm[asm.find("id:").unwrap()..asm.find("main:").unwrap()];
+    assert!(!id.contains("sp"), "{asm}");
+    assert_eq!(run(&asm), 8);
+}
+
+#[test]
+fn long_expression_reuses_temporaries() {
+    // 40 项的和: 每个中间结果只活到下一次加法, 不应该用到 s 寄存器或者栈
+    let mut koopa = String::from("fun @main(): i32 {\n%entry:\n  %0 = add 0, 1\n");
+    for i in 1..40 {
+        koopa += &format!("  %{i} = add %{}, {}\n", i - 1, i + 1);
+    }
+    koopa += "  ret %39\n}\n";
+    let asm = generate_koopa(&koopa);
+    assert!(!uses_callee_saved(&asm), "{asm}");
+    assert!(!asm.contains("sp"), "{asm}");
+    assert_eq!(run(&asm), 820 & 0xff);
+}
+
+#[test]
+fn spills_when_registers_run_out() {
+    // 30 个值同时活跃, 超过可分配的 24 个寄存器
+    let mut koopa = String::from("fun @main(): i32 {\n%entry:\n");
+    for i in 0..30 {
+        koopa += &format!("  %v{i} = add {i}, 1\n");
+    }
+    koopa += "  %s0 = add %v0, %v1\n";
+    for i in 2..30 {
+        koopa += &format!("  %s{} = add %s{}, %v{i}\n", i - 1, i - 2);
+    }
+    koopa += "  ret %s28\n}\n";
+    let asm = generate_koopa(&koopa);
+    assert!(asm.contains("(sp)"), "{asm}");
+    assert_eq!(run(&asm), (1..=30).sum::<i32>() & 0xff);
+}
+
+#[test]
+fn stack_arguments() {
+    // 前 8 个参数通过 a0-a7 传递, 其余放在调用者的栈顶
+    let params: Vec<String> = (0..10).map(|i| format!("%p{i}: i32")).collect();
+    let mut koopa = format!(
+        "fun @f({}): i32 {{\n%entry:\n  %s0 = mul %p0, 1\n",
+        params.join(", ")
+    );
+    for i in 1..10 {
+        koopa += &format!("  %t{i} = mul %p{i}, {}\n", i + 1);
+        koopa += &format!("  %s{i} = add %s{}, %t{i}\n", i - 1);
+    }
+    koopa += "  ret %s9\n}\n\n";
+    let args: Vec<String> = (1..=10).map(|i| i.to_string()).collect();
+    koopa += &format!(
+        "fun @main(): i32 {{\n%entry:\n  %r = call @f({})\n  ret %r\n}}\n",
+        args.join(", ")
+    );
+    let asm = generate_koopa(&koopa);
+    assert!(asm.contains("    li t5, 9\n    sw t5, 0(sp)\n"), "{asm}");
+    assert!(asm.contains("    li t5, 10\n    sw t5, 4(sp)\n"), "{asm}");
+    assert_eq!(run(&asm), (1..=10).map(|i| i * i).sum::<i32>() & 0xff);
+}
+
+#[test]
+fn block_arguments_swap() {
+    // 每次循环交换 a 和 b, 需要打破并行移动中的环
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  jump %loop(1, 2, 0)
+
+%loop(%a: i32, %b: i32, %i: i32):
+  %n = add %i, 1
+  %c = lt %n, 4
+  br %c, %loop(%b, %a, %n), %end(%a, %b)
+
+%end(%x: i32, %y: i32):
+  %r = mul %x, 10
+  %s = add %r, %y
+  ret %s
+}
+";
+    let asm = generate_koopa(koopa);
+    // 真分支带参数时先跳到中转标号
+    assert!(asm.contains(".Lmain_edge.0:\n"), "{asm}");
+    assert_eq!(run(&asm), 21);
+}
+
+#[test]
+fn memory_and_arrays() {
+    let koopa = "\
+global @g = alloc [i32, 4], {1, 2, 3, 4}
+global @n = alloc i32, 5
+
+fun @sum(%p: *i32, %len: i32): i32 {
+%entry:
+  jump %loop(0, 0)
+
+%loop(%i: i32, %acc: i32):
+  %c = lt %i, %len
+  br %c, %body, %end
+
+%body:
+  %q = getptr %p, %i
+  %v = load %q
+  %acc2 = add %acc, %v
+  %i2 = add %i, 1
+  jump %loop(%i2, %acc2)
+
+%end:
+  ret %acc
+}
+
+fun @main(): i32 {
+%entry:
+  @a = alloc [i32, 3]
+  %a0 = getelemptr @a, 0
+  store 10, %a0
+  %a1 = getelemptr @a, 1
+  %n = load @n
+  store %n, %a1
+  %a2 = getelemptr @a, 2
+  store 7, %a2
+  %g0 = getelemptr @g, 0
+  %s1 = call @sum(%g0, 4)
+  %s2 = call @sum(%a0, 3)
+  %r = add %s1, %s2
+  ret %r
+}
+";
+    let asm = generate_koopa(koopa);
+    assert!(
+        asm.starts_with("    .data\n    .globl g\ng:\n    .word 1\n"),
+        "{asm}"
+    );
+    // 下标是变量时按元素大小移位后相加
+    assert!(asm.contains("    slli t4, "), "{asm}");
+    assert_eq!(run(&asm), 32);
+}
+
+#[test]
+fn riscv64_pointers() {
+    let koopa = "\
+fun @f(%p: *[i32, 3], %i: i32): i32 {
+%entry:
+  @x = alloc *[i32, 3]
+  store %p, @x
+  %p2 = load @x
+  %q = getelemptr %p2, %i
+  %v = load %q
+  %w = add %v, 1
+  ret %w
+}
+";
+    let isa = Riscv::new(Target::Riscv64, true).unwrap();
+    let asm = generate_for(&parse_koopa(koopa), isa);
+    // 指针占 8 字节, 地址按 64 位计算; int 仍然用 lw 和 *w 指令
+    assert!(
+        asm.contains("    sd t0, 0(sp)\n    ld t0, 0(sp)\n"),
+        "{asm}"
+    );
+    assert!(
+        asm.contains("    slli t4, t1, 2\n    add t2, t0, t4\n"),
+        "{asm}"
+    );
+    assert!(asm.contains("    lw "), "{asm}");
+    assert!(asm.contains("    addw "), "{asm}");
+    assert!(Riscv::new(Target::Riscv64, false).is_err());
+}
+
+// 没有 M 扩展时乘除法由例程或移位加法完成, 在模拟器中运行, 与 Rust 的回绕运算比较
+const MULDIV_EDGES: [i32; 9] = [0, 1, -1, 3, -2, 7, -7, i32::MAX, i32::MIN];
+// 0, 1, -1, 2 的幂, 需要多次移位加法的数和各种符号
+const MUL_CONSTS: [i32; 11] = [
+    0,
+    1,
+    -1,
+    8,
+    -16,
+    1 << 30,
+    1000003,
+    -7,
+    0x5555_5555,
+    i32::MAX,
+    i32::MIN,
+];
+
+// Koopa IR 中的操作数, INT_MIN 在 main 开头由 %min 算出
+fn operand(value: i32) -> String {
+    if value == i32::MIN {
+        "%min".to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+#[test]
+fn soft_muldiv_matches_wrapping_ops() {
+    let mut koopa = String::from(
+        "\
+decl @putint(i32)
+decl @putch(i32)
+
+fun @print(%x: i32) {
+%entry:
+  call @putint(%x)
+  call @putch(10)
+  ret
+}
+
+fun @mul(%a: i32, %b: i32) {
+%entry:
+  %m = mul %a, %b
+  call @print(%m)
+  ret
+}
+
+fun @divmod(%a: i32, %b: i32) {
+%entry:
+  %d = div %a, %b
+  call @print(%d)
+  %r = mod %a, %b
+  call @print(%r)
+  ret
+}
+
+fun @consts(%x: i32) {
+%entry:
+",
+    );
+    // 常数交替放在左边和右边
+    for (i, c) in MUL_CONSTS.into_iter().enumerate() {
+        if i % 2 == 0 {
+            koopa += &format!("  %m{i} = mul %x, {c}\n");
+        } else {
+            koopa += &format!("  %m{i} = mul {c}, %x\n");
+        }
+        koopa += &format!("  call @print(%m{i})\n");
+    }
+    koopa += "  ret\n}\n\nfun @main(): i32 {\n%entry:\n  %min = sub -2147483647, 1\n";
+    let mut wanted = Vec::new();
+    for a in MULDIV_EDGES {
+        for b in MULDIV_EDGES {
+            koopa += &format!("  call @mul({}, {})\n", operand(a), operand(b));
+            wanted.push(a.wrapping_mul(b));
+            if b != 0 {
+                koopa += &format!("  call @divmod({}, {})\n", operand(a), operand(b));
+                wanted.push(a.wrapping_div(b));
+                wanted.push(a.wrapping_rem(b));
+            }
+        }
+        koopa += &format!("  call @consts({})\n", operand(a));
+        wanted.extend(MUL_CONSTS.map(|c| a.wrapping_mul(c)));
+    }
+    koopa += "  ret 0\n}\n";
+
+    let isa = Riscv::new(Target::Riscv32, false).unwrap();
+    let asm = generate_for(&parse_koopa(&koopa), isa);
+    for line in asm.lines() {
+        let op = line.split_whitespace().next().unwrap_or("");
+        assert!(
+            !["mul", "div", "rem"].contains(&op),
+            "RV32I output uses `{line}`"
+        );
+    }
+    let mut emulator = compiler::emulator::Emulator::from_asm(&asm).unwrap();
+    assert_eq!(emulator.run(Some(10_000_000)), Ok(0), "{asm}");
+    let output: Vec<i32> = String::from_utf8(emulator.stdout().to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| line.parse().unwrap())
+        .collect();
+    assert_eq!(output, wanted);
+}
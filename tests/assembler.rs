@@ -0,0 +1,425 @@
+use compiler::assembler::{
+    assemble, disassemble, link, runtime, write_elf, write_executable, RelocKind, SectionKind,
+};
+use compiler::emulator::Emulator;
+use std::process::Command;
+
+// 内置汇编器, 链接器和 ELF 输出的测试.
+// 编码的正确性与 llvm-mc 对照 (没有安装时跳过), 每条指令的编解码往返在 assembler.rs 的单元测试中
+
+fn text(asm: &str) -> Vec<u8> {
+    let object = assemble(asm).unwrap_or_else(|err| panic!("{err}\n{asm}"));
+    object.section(SectionKind::Text).data.clone()
+}
+
+// 这些编码取自 llvm-mc -triple=riscv32 -mattr=+m -show-encoding
+#[test]
+fn known_encodings() {
+    let cases: &[(&str, &[u8])] = &[
+        ("addi a0, a0, 1", &[0x13, 0x05, 0x15, 0x00]),
+        ("mul a0, a1, a2", &[0x33, 0x85, 0xc5, 0x02]),
+        ("sw ra, 12(sp)", &[0x23, 0x26, 0x11, 0x00]),
+        ("lui a0, 0x12345", &[0x37, 0x55, 0x34, 0x12]),
+        ("beq a0, a1, -8", &[0xe3, 0x0c, 0xb5, 0xfe]),
+        ("jal ra, 2048", &[0xef, 0x00, 0x10, 0x00]),
+        ("srai t0, t1, 31", &[0x93, 0x52, 0xf3, 0x41]),
+        // 低 12 位是负数, 高 20 位进一
+        (
+            "li a0, 0x12345fff",
+            &[0x37, 0x65, 0x34, 0x12, 0x13, 0x05, 0xf5, 0xff],
+        ),
+        ("li a0, -2048", &[0x13, 0x05, 0x00, 0x80]),
+    ];
+    for (asm, expected) in cases {
+        assert_eq!(text(asm), *expected, "{asm}");
+    }
+}
+
+// 用 llvm-mc 汇编同样的代码, 返回 .text 的字节; 没有 llvm-mc 时返回 None
+fn llvm_mc(asm: &str) -> Option<Vec<u8>> {
+    let path = std::env::temp_dir().join(format!("compiler-asm-{}.S", std::process::id()));
+    std::fs::write(&path, asm).unwrap();
+    let output = Command::new("llvm-mc")
+        .args(["-triple=riscv32", "-mattr=+m", "-show-encoding"])
+        .arg(&path)
+        .output();
+    std::fs::remove_file(&path).unwrap();
+    let output = output.ok()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut bytes = Vec::new();
+    for line in stdout.lines() {
+        let Some((_, encoding)) = line.split_once("# encoding: [") else {
+            continue;
+        };
+        for byte in encoding.trim_end_matches(']').split(',') {
+            bytes.push(u8::from_str_radix(byte.trim_start_matches("0x"), 16).unwrap());
+        }
+    }
+    Some(bytes)
+}
+
+#[test]
+fn matches_llvm_mc() {
+    // 基本指令的每种格式和后端用到的伪指令, 跳转目标写成数字偏移
+    let asm = "\
+    add a0, a1, a2
+    sub t0, t1, t2
+    sll s0, s1, s2
+    slt a3, a4, a5
+    sltu a6, a7, s3
+    xor s4, s5, s6
+    srl s7, s8, s9
+    sra s10, s11, t3
+    or t4, t5, t6
+    and ra, sp, gp
+    mul a0, a1, a2
+    mulh a0, a1, a2
+    mulhu a0, a1, a2
+    div a0, a1, a2
+    divu a0, a1, a2
+    rem a0, a1, a2
+    remu a0, a1, a2
+    addi a0, a1, -2048
+    slti a0, a1, 2047
+    sltiu a0, a1, 1
+    xori a0, a1, -1
+    ori a0, a1, 255
+    andi a0, a1, 15
+    slli a0, a1, 1
+    srli a0, a1, 31
+    srai a0, a1, 7
+    lb a0, -1(sp)
+    lh a0, 2(sp)
+    lw a0, 2047(sp)
+    lbu a0, 0(s0)
+    lhu a0, -2048(s0)
+    sb a0, -1(sp)
+    sh a0, 2(sp)
+    sw a0, 2047(sp)
+    beq a0, a1, 16
+    bne a0, a1, -16
+    blt a0, a1, 4094
+    bge a0, a1, -4096
+    bltu a0, a1, 8
+    bgeu a0, a1, -8
+    lui a0, 0xfffff
+    auipc a0, 0x80000
+    jal ra, -1048576
+    jalr ra, 12(a0)
+    ecall
+    nop
+    li a0, 2047
+    li a0, -2049
+    li a0, 0x7fffffff
+    li a0, 0x80000000
+    li a0, 4096
+    mv a0, a1
+    not a0, a1
+    neg a0, a1
+    seqz a0, a1
+    snez a0, a1
+    sltz a0, a1
+    sgtz a0, a1
+    sgt a0, a1, a2
+    sgtu a0, a1, a2
+    beqz a0, 8
+    bnez a0, -8
+    blez a0, 8
+    bgez a0, 8
+    bltz a0, 8
+    bgtz a0, 8
+    bgt a0, a1, 8
+    ble a0, a1, 8
+    bgtu a0, a1, 8
+    bleu a0, a1, 8
+    j -4
+    jal 4
+    jr t0
+    jalr t0
+    ret
+";
+    let Some(expected) = llvm_mc(asm) else {
+        eprintln!("llvm-mc not found, skipped");
+        return;
+    };
+    let code = text(asm);
+    if code != expected {
+        // 逐条比较, 报告第一条不一样的指令
+        let lines: Vec<_> = disassemble(&code).lines().map(str::to_owned).collect();
+        let wanted: Vec<_> = disassemble(&expected).lines().map(str::to_owned).collect();
+        let i = lines.iter().zip(&wanted).position(|(a, b)| a != b);
+        panic!("first difference at instruction {i:?}:\n{lines:?}\n{wanted:?}");
+    }
+}
+
+#[test]
+fn labels_and_relocations() {
+    let asm = "\
+    .text
+    .globl main
+main:
+    j .Lend
+    call foo
+    la a0, g
+.Lend:
+    ret
+
+    .data
+    .globl g
+g:
+    .word 1, main
+    .bss
+    .zero 8
+";
+    let object = assemble(asm).unwrap();
+    // 局部标号在汇编时解析
+    let code = &object.section(SectionKind::Text).data;
+    assert_eq!(disassemble(&code[..4]), "    jal zero, 20\n");
+    let relocs: Vec<_> = object
+        .section(SectionKind::Text)
+        .relocs
+        .iter()
+        .map(|reloc| (reloc.offset, reloc.kind, reloc.symbol.as_str()))
+        .collect();
+    assert_eq!(
+        relocs,
+        [
+            (4, RelocKind::CallPlt, "foo"),
+            (12, RelocKind::Hi20, "g"),
+            (16, RelocKind::Lo12I, "g"),
+        ]
+    );
+    let data = object.section(SectionKind::Data);
+    assert_eq!(data.data.len(), 8);
+    assert_eq!(data.relocs[0].kind, RelocKind::Abs32);
+    assert_eq!(object.section(SectionKind::Bss).data.len(), 8);
+    assert_eq!(object.undefined_symbols(), ["foo"]);
+    assert!(object.symbol("g").unwrap().global);
+    assert!(!object.symbol(".Lend").unwrap().global);
+}
+
+#[test]
+fn errors_carry_line_numbers() {
+    let cases = [
+        (
+            "    nop\n    frob a0\n",
+            "line 2: unknown instruction `frob`",
+        ),
+        ("    addi a0, x32, 1\n", "line 1: unknown register `x32`"),
+        (
+            "    addi a0, a0, 2048\n",
+            "line 1: immediate 2048 is out of range",
+        ),
+        (
+            "    slli a0, a0, 32\n",
+            "line 1: immediate 32 is out of range",
+        ),
+        ("    add a0, a1\n", "line 1: expected 3 operands, found 2"),
+        ("    li a0, 1x\n", "line 1: invalid immediate `1x`"),
+        ("    lw a0, a1\n", "line 1: expected imm(reg), found `a1`"),
+        ("a:\n    nop\na:\n", "line 3: label `a` is defined twice"),
+        (
+            "    .bss\n    .word 1\n",
+            "line 2: only zeros can be placed in .bss",
+        ),
+        (
+            "    .text\n    .foo 1\n",
+            "line 2: unknown directive `.foo`",
+        ),
+        ("    .string \"a\n", "line 1: expected a string literal"),
+    ];
+    for (asm, expected) in cases {
+        assert_eq!(assemble(asm).unwrap_err(), expected, "{asm}");
+    }
+}
+
+#[test]
+fn link_errors() {
+    let main = assemble("    .globl main\nmain:\n    call f\n    ret\n").unwrap();
+    assert_eq!(
+        link(&[main.clone(), runtime()]).unwrap_err(),
+        "undefined reference to `f`"
+    );
+    let f = assemble("    .globl f\nf:\n    ret\n").unwrap();
+    assert!(link(&[main.clone(), f.clone(), runtime()]).is_ok());
+    assert_eq!(
+        link(&[main.clone(), f.clone(), f, runtime()]).unwrap_err(),
+        "multiple definition of `f`"
+    );
+    assert_eq!(
+        link(&[assemble("main:\n    ret\n").unwrap()]).unwrap_err(),
+        "undefined entry symbol `_start`"
+    );
+}
+
+const PROGRAM: &str = "\
+    .text
+    .globl main
+main:
+    la t0, x
+    lw a0, 0(t0)
+    la t1, y
+    lw a1, 0(t1)
+    add a0, a0, a1
+    ret
+
+    .data
+x:
+    .word 40
+    .bss
+y:
+    .zero 4
+";
+
+fn u16_at(file: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(file[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(file: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+}
+
+// readelf 的输出; 没有安装时返回 None
+fn readelf(file: &[u8], args: &[&str]) -> Option<String> {
+    let path =
+        std::env::temp_dir().join(format!("compiler-elf-{}-{}", std::process::id(), args[0]));
+    std::fs::write(&path, file).unwrap();
+    let output = Command::new("readelf").args(args).arg(&path).output();
+    std::fs::remove_file(&path).unwrap();
+    let output = output.ok()?;
+    assert!(output.status.success());
+    Some(String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn relocatable_elf() {
+    let object = assemble(PROGRAM).unwrap();
+    let file = write_elf(&object);
+    assert_eq!(&file[..6], b"\x7fELF\x01\x01");
+    // ET_REL, EM_RISCV
+    assert_eq!(u16_at(&file, 16), 1);
+    assert_eq!(u16_at(&file, 18), 243);
+    let Some(relocs) = readelf(&file, &["-r"]) else {
+        return;
+    };
+    assert!(relocs.contains("R_RISCV_HI20"), "{relocs}");
+    assert!(relocs.contains("R_RISCV_LO12_I"), "{relocs}");
+    let symbols = readelf(&file, &["-s"]).unwrap();
+    assert!(symbols.contains("GLOBAL DEFAULT    1 main"), "{symbols}");
+}
+
+#[test]
+fn executable_elf() {
+    let exe = link(&[assemble(PROGRAM).unwrap(), runtime()]).unwrap();
+    let file = write_executable(&exe);
+    // ET_EXEC, 入口为 _start
+    assert_eq!(u16_at(&file, 16), 2);
+    assert_eq!(u32_at(&file, 24), exe.entry);
+    // 从文件加载与直接运行链接结果一样
+    let mut emulator = Emulator::from_elf(&file).unwrap();
+    assert_eq!(emulator.run(Some(10_000)), Ok(40));
+    assert_eq!(Emulator::from_executable(&exe).run(Some(10_000)), Ok(40));
+    if let Some(header) = readelf(&file, &["-h"]) {
+        assert!(header.contains("EXEC (Executable file)"), "{header}");
+        assert!(header.contains("RISC-V"), "{header}");
+    }
+}
+
+#[test]
+fn bad_elf_is_rejected() {
+    let exe = link(&[assemble(PROGRAM).unwrap(), runtime()]).unwrap();
+    let file = write_executable(&exe);
+    assert!(Emulator::from_elf(&file[..20]).is_err());
+    assert!(Emulator::from_elf(&file[..file.len() / 2]).is_err());
+    let mut wrong_machine = file.clone();
+    wrong_machine[18] = 62;
+    assert!(Emulator::from_elf(&wrong_machine).is_err());
+    assert!(Emulator::from_elf(b"not an elf file").is_err());
+}
+
+#[test]
+fn link_resolves_across_objects() {
+    let main = assemble(
+        "\
+    .text
+    .globl main
+main:
+    addi sp, sp, -16
+    sw ra, 0(sp)
+    call helper
+    call inc
+    call inc
+    la t0, table
+    lw t1, 0(t0)
+    jalr t1
+    la t0, counter
+    lw a0, 0(t0)
+    lw ra, 0(sp)
+    addi sp, sp, 16
+    ret
+# 与另一个文件中的局部标号同名, 各自解析到自己的定义
+helper:
+    ret
+
+    .data
+table:
+    .word inc
+",
+    )
+    .unwrap();
+    let lib = assemble(
+        "\
+    .text
+    .globl inc
+inc:
+    la t0, counter
+    lw t1, 0(t0)
+    addi t1, t1, 1
+    sw t1, 0(t0)
+    ret
+helper:
+    ebreak
+
+    .bss
+    .globl counter
+counter:
+    .zero 4
+",
+    )
+    .unwrap();
+    let exe = link(&[main, lib, runtime()]).unwrap();
+    let addr = |name: &str| {
+        let symbols = exe.symbols.iter().filter(|symbol| symbol.name == name);
+        symbols.map(|symbol| symbol.offset).collect::<Vec<_>>()
+    };
+    // 局部标号各有一个地址, 全局符号只有一个
+    assert_eq!(addr("helper").len(), 2);
+    let inc = addr("inc")[0];
+    assert_eq!(addr("main")[0], exe.text_addr);
+    // .word inc 填入了绝对地址
+    assert_eq!(exe.data[..4], inc.to_le_bytes());
+    // 各段按页对齐, .bss 紧跟在 .data 之后
+    assert_eq!(exe.text_addr % 4096, 0);
+    assert_eq!(exe.data_addr % 4096, 0);
+    assert!(exe.data_addr >= exe.text_addr + exe.text.len() as u32);
+    assert_eq!(addr("counter")[0], exe.bss_addr);
+    assert_eq!(exe.bss_addr, exe.data_addr + exe.data.len() as u32);
+    assert_eq!(Emulator::from_executable(&exe).run(Some(10_000)), Ok(3));
+}
+
+#[test]
+fn link_rejects_out_of_range_branch() {
+    let main = assemble("    .globl main\nmain:\n    beqz a0, far\n    ret\n").unwrap();
+    let far = assemble("    .zero 8192\n    .globl far\nfar:\n    ret\n").unwrap();
+    assert_eq!(
+        link(&[main.clone(), far, runtime()]).unwrap_err(),
+        "relocation against `far` is out of range"
+    );
+    let near = assemble("    .globl far\nfar:\n    ret\n").unwrap();
+    assert!(link(&[main, near, runtime()]).is_ok());
+}
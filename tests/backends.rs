@@ -0,0 +1,554 @@
+use std::env::temp_dir;
+use std::fs::write;
+use std::path::PathBuf;
+use std::process::Command;
+
+use compiler::generate_asm::{GenerateAsm, ProgramInfo, X86_64};
+use compiler::generate_c::{CInfo, GenerateC};
+use compiler::generate_llvm::{GenerateLlvm, LlvmInfo};
+use compiler::generate_wasm::{GenerateWasm, WasmInfo};
+use compiler::opt::preset;
+use compiler::{compile, Options};
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BinaryOp, FunctionData, Program, Type};
+
+// 其他后端的测试: 用本机的 gcc 和 lli 运行生成的代码, 工具不存在时跳过
+
+fn parse_koopa(koopa: &str) -> Program {
+    koopa::front::Driver::from(koopa)
+        .generate_program()
+        .unwrap()
+}
+
+// 每个测试用自己的文件名, 测试可以并行运行
+fn temp_file(name: &str) -> PathBuf {
+    temp_dir().join(format!("compiler-backends-{}-{name}", std::process::id()))
+}
+
+// 运行命令, 返回退出码; 命令不存在时返回 None
+fn exit_code(command: &mut Command) -> Option<i32> {
+    let output = command.output().ok()?;
+    Some(output.status.code().unwrap())
+}
+
+fn run_x86(koopa: &str, name: &str) -> Option<i32> {
+    run_x86_program(&parse_koopa(koopa), name)
+}
+
+fn run_x86_program(program: &Program, name: &str) -> Option<i32> {
+    let mut buf = Vec::new();
+    let mut info = ProgramInfo::new(program, None);
+    info.set_isa(X86_64::default());
+    program.generate(&mut info, &mut buf);
+    let asm = temp_file(&format!("{name}.s"));
+    let exe = temp_file(name);
+    write(&asm, &buf).unwrap();
+    let status = exit_code(Command::new("gcc").arg(&asm).arg("-o").arg(&exe))?;
+    assert_eq!(status, 0, "{}", String::from_utf8(buf).unwrap());
+    exit_code(&mut Command::new(&exe))
+}
+
+fn run_llvm(koopa: &str, name: &str) -> Option<i32> {
+    run_llvm_program(&parse_koopa(koopa), name)
+}
+
+fn run_llvm_program(program: &Program, name: &str) -> Option<i32> {
+    let mut buf = Vec::new();
+    program.generate_llvm(&mut LlvmInfo::new(program), &mut buf);
+    let ll = temp_file(&format!("{name}.ll"));
+    write(&ll, &buf).unwrap();
+    // LLVM 15 之前要用 -opaque-pointers 打开 ptr 类型, 之后的版本不再认识这个选项
+    let output = Command::new("lli")
+        .arg("-opaque-pointers")
+        .arg(&ll)
+        .output()
+        .ok()?;
+    if String::from_utf8_lossy(&output.stderr).contains("Unknown command line argument") {
+        return exit_code(Command::new("lli").arg(&ll));
+    }
+    Some(output.status.code().unwrap())
+}
+
+// 在 node 中实例化 wasm 模块, 以 main 的返回值作为退出码
+const RUN_WASM: &str = "
+const module = new WebAssembly.Module(require('fs').readFileSync(process.argv[1]));
+process.exit(new WebAssembly.Instance(module, {}).exports.main() & 0xff);
+";
+
+fn run_wasm(koopa: &str, name: &str) -> Option<i32> {
+    run_wasm_program(&parse_koopa(koopa), name)
+}
+
+fn run_wasm_program(program: &Program, name: &str) -> Option<i32> {
+    let mut buf = Vec::new();
+    program.generate_wasm(&mut WasmInfo::new(program).unwrap(), &mut buf);
+    let text = String::from_utf8(buf).unwrap();
+    // 文本格式总是在进程内转换成二进制, 没有 node 时也能检查语法
+    let binary = wat::parse_str(&text).unwrap_or_else(|err| panic!("{err}\n{text}"));
+    let file = temp_file(&format!("{name}.wasm"));
+    write(&file, binary).unwrap();
+    let output = Command::new("node")
+        .arg("-e")
+        .arg(RUN_WASM)
+        .arg(&file)
+        .output()
+        .ok()?;
+    // 校验失败或陷入时 node 打印错误
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.is_empty(), "{stderr}\n{text}");
+    Some(output.status.code().unwrap())
+}
+
+fn run_c(koopa: &str, name: &str) -> Option<i32> {
+    let program = parse_koopa(koopa);
+    let mut buf = Vec::new();
+    program.generate_c(&mut CInfo::new(&program), &mut buf);
+    let c = temp_file(&format!("{name}.c"));
+    let exe = temp_file(&format!("{name}-c"));
+    write(&c, &buf).unwrap();
+    // 生成的 C 代码不应有警告, 如未使用的标号
+    let status = exit_code(
+        Command::new("gcc")
+            .args(["-Wall", "-Werror"])
+            .arg(&c)
+            .arg("-o")
+            .arg(&exe),
+    )?;
+    assert_eq!(status, 0, "{}", String::from_utf8(buf).unwrap());
+    exit_code(&mut Command::new(&exe))
+}
+
+const ARRAYS: &str = "\
+global @g = alloc [i32, 4], {1, 2, 3, 4}
+global @n = alloc i32, 5
+
+fun @sum(%p: *i32, %len: i32): i32 {
+%entry:
+  jump %loop(0, 0)
+
+%loop(%i: i32, %acc: i32):
+  %c = lt %i, %len
+  br %c, %body, %end
+
+%body:
+  %q = getptr %p, %i
+  %v = load %q
+  %acc2 = add %acc, %v
+  %i2 = add %i, 1
+  jump %loop(%i2, %acc2)
+
+%end:
+  ret %acc
+}
+
+fun @main(): i32 {
+%entry:
+  @a = alloc [i32, 3]
+  %a0 = getelemptr @a, 0
+  store 10, %a0
+  %a1 = getelemptr @a, 1
+  %n = load @n
+  store %n, %a1
+  %a2 = getelemptr @a, 2
+  store 7, %a2
+  %g0 = getelemptr @g, 0
+  %s1 = call @sum(%g0, 4)
+  %s2 = call @sum(%a0, 3)
+  %r = add %s1, %s2
+  ret %r
+}
+";
+
+// INT_MIN / -1 按回绕计算为 INT_MIN, INT_MIN % -1 为 0; 都成立时返回 1
+const INT_MIN_DIV: &str = "\
+fun @check(%x: i32, %y: i32): i32 {
+%entry:
+  %q = div %x, %y
+  %r = mod %x, %y
+  %q_ok = eq %q, %x
+  %r_ok = eq %r, 0
+  %ok = and %q_ok, %r_ok
+  ret %ok
+}
+
+fun @main(): i32 {
+%entry:
+  %max = sub 0, 2147483647
+  %min = sub %max, 1
+  %ok = call @check(%min, -1)
+  %q = div 7, -1
+  %seven = add %q, 8
+  %both = add %ok, %seven
+  ret %both
+}
+";
+
+// 除法向零取整, 余数的符号与被除数相同; 8 个比较都成立时返回 8
+const DIV_MOD_SIGNS: &str = "\
+fun @main(): i32 {
+%entry:
+  %max = sub 0, 2147483647
+  %min = sub %max, 1
+  %q1 = div -7, 2
+  %e1 = eq %q1, -3
+  %r1 = mod -7, 2
+  %e2 = eq %r1, -1
+  %q2 = div 7, -2
+  %e3 = eq %q2, -3
+  %r2 = mod 7, -2
+  %e4 = eq %r2, 1
+  %q3 = div -7, -2
+  %e5 = eq %q3, 3
+  %r3 = mod -7, -2
+  %e6 = eq %r3, -1
+  %q4 = div %min, 2
+  %e7 = eq %q4, -1073741824
+  %r4 = mod %min, 3
+  %e8 = eq %r4, -2
+  %s1 = add %e1, %e2
+  %s2 = add %s1, %e3
+  %s3 = add %s2, %e4
+  %s4 = add %s3, %e5
+  %s5 = add %s4, %e6
+  %s6 = add %s5, %e7
+  %s7 = add %s6, %e8
+  ret %s7
+}
+";
+
+// br 的两个目标是同一个块, 两条边传递不同的实参
+const SAME_TARGET: &str = "\
+fun @pick(%c: i32): i32 {
+%entry:
+  br %c, %end(3), %end(5)
+
+%end(%x: i32):
+  ret %x
+}
+
+fun @main(): i32 {
+%entry:
+  %a = call @pick(1)
+  %b = call @pick(0)
+  %b10 = mul %b, 10
+  %r = add %a, %b10
+  ret %r
+}
+";
+
+// 循环中的 alloc 只分配一次栈空间, 每次都重新分配时栈会溢出
+const ALLOC_IN_LOOP: &str = "\
+fun @main(): i32 {
+%entry:
+  jump %loop(0)
+
+%loop(%i: i32):
+  @a = alloc [i32, 16]
+  %p = getelemptr @a, 3
+  store 7, %p
+  %i1 = add %i, 1
+  %c = lt %i1, 3000000
+  br %c, %loop(%i1), %end
+
+%end:
+  %v = load %p
+  ret %v
+}
+";
+
+// 函数名与 C 标准库的函数相同
+const LIBC_NAMES: &str = "\
+fun @abs(%x: i32): i32 {
+%entry:
+  %neg = lt %x, 0
+  br %neg, %then, %end
+
+%then:
+  %y = sub 0, %x
+  ret %y
+
+%end:
+  ret %x
+}
+
+fun @exit(): i32 {
+%entry:
+  ret 4
+}
+
+fun @main(): i32 {
+%entry:
+  %a = call @abs(-3)
+  %b = call @exit()
+  %r = add %a, %b
+  ret %r
+}
+";
+
+#[test]
+fn c_mangles_function_names() {
+    if let Some(code) = run_c(LIBC_NAMES, "c-libc") {
+        assert_eq!(code, 7);
+    }
+}
+
+// 分支里的 if/else 和提前返回, 函数名也是 wasm 的导出名
+#[test]
+fn wasm_branches() {
+    if let Some(code) = run_wasm(LIBC_NAMES, "wasm-libc") {
+        assert_eq!(code, 7);
+    }
+}
+
+#[test]
+fn arrays_and_globals() {
+    for (backend, code) in [
+        ("x86", run_x86(ARRAYS, "x86-arrays")),
+        ("llvm", run_llvm(ARRAYS, "llvm-arrays")),
+        ("c", run_c(ARRAYS, "c-arrays")),
+        ("wasm", run_wasm(ARRAYS, "wasm-arrays")),
+    ] {
+        if let Some(code) = code {
+            assert_eq!(code, 32, "{backend}");
+        }
+    }
+}
+
+#[test]
+fn branch_to_same_block() {
+    for (backend, code) in [
+        ("x86", run_x86(SAME_TARGET, "x86-same")),
+        ("llvm", run_llvm(SAME_TARGET, "llvm-same")),
+        ("c", run_c(SAME_TARGET, "c-same")),
+        ("wasm", run_wasm(SAME_TARGET, "wasm-same")),
+    ] {
+        if let Some(code) = code {
+            assert_eq!(code, 53, "{backend}");
+        }
+    }
+}
+
+#[test]
+fn alloc_in_loop() {
+    for (backend, code) in [
+        ("x86", run_x86(ALLOC_IN_LOOP, "x86-alloc-loop")),
+        ("llvm", run_llvm(ALLOC_IN_LOOP, "llvm-alloc-loop")),
+        ("c", run_c(ALLOC_IN_LOOP, "c-alloc-loop")),
+        ("wasm", run_wasm(ALLOC_IN_LOOP, "wasm-alloc-loop")),
+    ] {
+        if let Some(code) = code {
+            assert_eq!(code, 7, "{backend}");
+        }
+    }
+}
+
+// 文本形式的 Koopa IR 总是给基本块命名, 也不允许跳回入口块, 这里直接构造.
+// 入口块是循环, LLVM 后端另加一个入口块, 循环中的 alloc 也要放到那里
+fn entry_loop_without_names() -> Program {
+    let mut program = Program::new();
+    let zero = program.new_value().zero_init(Type::get_i32());
+    let n = program.new_value().global_alloc(zero);
+    program.set_value_name(n, Some("@n".into()));
+    let func = program.new_func(FunctionData::new("@main".into(), vec![], Type::get_i32()));
+    let data = program.func_mut(func);
+    let entry = data.dfg_mut().new_bb().basic_block(None);
+    let end = data.dfg_mut().new_bb().basic_block(None);
+    let dfg = data.dfg_mut();
+    let a = dfg.new_value().alloc(Type::get_array(Type::get_i32(), 16));
+    let three = dfg.new_value().integer(3);
+    let p = dfg.new_value().get_elem_ptr(a, three);
+    let seven = dfg.new_value().integer(7);
+    let store = dfg.new_value().store(seven, p);
+    let old = dfg.new_value().load(n);
+    let one = dfg.new_value().integer(1);
+    let new = dfg.new_value().binary(BinaryOp::Add, old, one);
+    let update = dfg.new_value().store(new, n);
+    let limit = dfg.new_value().integer(3_000_000);
+    let cond = dfg.new_value().binary(BinaryOp::Lt, new, limit);
+    let br = dfg.new_value().branch(cond, entry, end);
+    let v = dfg.new_value().load(p);
+    let ret = dfg.new_value().ret(Some(v));
+    let body = [
+        (entry, vec![a, p, store, old, new, update, cond, br]),
+        (end, vec![v, ret]),
+    ];
+    for (bb, insts) in body {
+        data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
+        for inst in insts {
+            data.layout_mut()
+                .bb_mut(bb)
+                .insts_mut()
+                .push_key_back(inst)
+                .unwrap();
+        }
+    }
+    program
+}
+
+#[test]
+fn entry_loop_without_names_runs() {
+    let program = entry_loop_without_names();
+    for (backend, code) in [
+        ("llvm", run_llvm_program(&program, "llvm-entry-loop")),
+        ("wasm", run_wasm_program(&program, "wasm-entry-loop")),
+    ] {
+        if let Some(code) = code {
+            assert_eq!(code, 7, "{backend}");
+        }
+    }
+}
+
+#[test]
+fn int_min_div_minus_one_wraps() {
+    // 1 + (7 / -1 + 8)
+    for (backend, code) in [
+        ("x86", run_x86(INT_MIN_DIV, "x86-div")),
+        ("llvm", run_llvm(INT_MIN_DIV, "llvm-div")),
+        ("c", run_c(INT_MIN_DIV, "c-div")),
+        ("wasm", run_wasm(INT_MIN_DIV, "wasm-div")),
+    ] {
+        if let Some(code) = code {
+            assert_eq!(code, 2, "{backend}");
+        }
+    }
+}
+
+#[test]
+fn div_mod_signs() {
+    for (backend, code) in [
+        ("x86", run_x86(DIV_MOD_SIGNS, "x86-signs")),
+        ("llvm", run_llvm(DIV_MOD_SIGNS, "llvm-signs")),
+        ("c", run_c(DIV_MOD_SIGNS, "c-signs")),
+        ("wasm", run_wasm(DIV_MOD_SIGNS, "wasm-signs")),
+    ] {
+        if let Some(code) = code {
+            assert_eq!(code, 8, "{backend}");
+        }
+    }
+}
+
+// 两个入口互相跳转的循环不可归约, wasm 后端报错
+#[test]
+fn wasm_rejects_irreducible_cfg() {
+    let program = parse_koopa(
+        "\
+fun @main(%c: i32): i32 {
+%entry:
+  br %c, %a, %b
+
+%a:
+  jump %b
+
+%b:
+  jump %a
+}
+",
+    );
+    let Err(err) = WasmInfo::new(&program) else {
+        panic!("irreducible control flow should be rejected");
+    };
+    assert!(err.contains("@main"), "{err}");
+}
+
+// 8 个参数 (2 个通过栈传递), 递归和跨越调用的值
+const CALLS: &str = "\
+fun @sum8(%a: i32, %b: i32, %c: i32, %d: i32, %e: i32, %f: i32, %g: i32, %h: i32): i32 {
+%entry:
+  %0 = mul %h, 8
+  %1 = add %a, %0
+  %2 = sub %1, %g
+  %3 = mul %b, %c
+  %4 = add %2, %3
+  %5 = div %4, %d
+  %6 = add %5, %e
+  %7 = mod %6, %f
+  ret %7
+}
+
+fun @fib(%n: i32): i32 {
+%entry:
+  %c = lt %n, 2
+  br %c, %base, %rec
+
+%base:
+  ret %n
+
+%rec:
+  %n1 = sub %n, 1
+  %f1 = call @fib(%n1)
+  %n2 = sub %n, 2
+  %f2 = call @fib(%n2)
+  %r = add %f1, %f2
+  ret %r
+}
+
+fun @main(): i32 {
+%entry:
+  %k = add 40, 2
+  %x = call @sum8(1, 2, 3, 4, 5, 6, 7, 8)
+  %y = call @fib(10)
+  %z = add %x, %y
+  %w = add %z, %k
+  ret %w
+}
+";
+
+#[test]
+fn calls() {
+    // (1 + 64 - 7 + 6) / 4 + 5 = 21, 21 % 6 = 3; fib(10) = 55
+    for (backend, code) in [
+        ("x86", run_x86(CALLS, "x86-calls")),
+        ("wasm", run_wasm(CALLS, "wasm-calls")),
+    ] {
+        if let Some(code) = code {
+            assert_eq!(code, 3 + 55 + 42, "{backend}");
+        }
+    }
+}
+
+#[test]
+fn x86_spills() {
+    // 30 个值同时活跃, 超过可分配的寄存器
+    let mut koopa = String::from("fun @main(): i32 {\n%entry:\n");
+    for i in 0..30 {
+        koopa += &format!("  %v{i} = add {i}, 1\n");
+    }
+    koopa += "  %s0 = add %v0, %v1\n";
+    for i in 2..30 {
+        koopa += &format!("  %s{} = add %s{}, %v{i}\n", i - 1, i - 2);
+    }
+    koopa += "  ret %s28\n}\n";
+    if let Some(code) = run_x86(&koopa, "x86-spills") {
+        assert_eq!(code, (1..=30).sum::<i32>() & 0xff);
+    }
+}
+
+// -O0 时变量在栈上, -O2 时经过 mem2reg 变成寄存器中的值
+const SYSY: &str = "
+int main() {
+  int a = 7;
+  int b = a * 6 - 5;
+  int c = b / 3 + b % 4;
+  a = -c + (a > 3) * 100 + (b != 37 || c == 0);
+  return a;
+}
+";
+
+#[test]
+fn x86_compiles_sysy() {
+    for level in [0, 2] {
+        let options = Options {
+            passes: preset(level),
+            emit_asm: false,
+            ..Options::default()
+        };
+        let program = compile(SYSY, &options).unwrap().program;
+        // b = 37, c = 12 + 1 = 13, a = -13 + 100 + 0
+        if let Some(code) = run_x86_program(&program, &format!("x86-sysy-O{level}")) {
+            assert_eq!(code, 87, "x86 -O{level}");
+        }
+        if let Some(code) = run_wasm_program(&program, &format!("wasm-sysy-O{level}")) {
+            assert_eq!(code, 87, "wasm -O{level}");
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// 命令行的测试, 运行编译出的 compiler
+
+fn compiler(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_compiler"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn stdin_to_stdout() {
+    let output = compiler(&["-koopa", "-"], "int main() { return 3; }");
+    assert!(output.status.success());
+    let koopa = String::from_utf8(output.stdout).unwrap();
+    assert!(koopa.starts_with("fun @main(): i32 {"), "{koopa}");
+}
+
+// 默认不优化, 优化要用 -O 打开; -perf 默认 -O2
+#[test]
+fn optimization_is_opt_in() {
+    let source = "int main() { return 1 + 2; }";
+    let stdout = |args: &[&str]| String::from_utf8(compiler(args, source).stdout).unwrap();
+    assert!(stdout(&["-koopa", "-"]).contains("add 1, 2"));
+    assert!(!stdout(&["-koopa", "-", "-O1"]).contains("add 1, 2"));
+    assert_eq!(stdout(&["-perf", "-"]), stdout(&["-riscv", "-", "-O2"]));
+    assert_ne!(stdout(&["-riscv", "-"]), stdout(&["-riscv", "-", "-O2"]));
+}
+
+#[test]
+fn emit_tokens() {
+    let output = compiler(&["--emit=tokens", "-"], "int main()\n{ return 0; }");
+    assert!(output.status.success());
+    let tokens = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(tokens.lines().nth(4), Some("2:1\t{"));
+}
+
+#[test]
+fn bad_arguments() {
+    for args in [
+        &["-koopa"][..],
+        &["-koopa", "-riscv", "-"],
+        &["-riscv", "-", "-O3"],
+        &["-riscv", "-", "-bogus"],
+        // 与参数的先后无关
+        &["-riscv", "-", "-march=rv32i", "-target", "riscv64"],
+        &["-riscv", "-", "-target", "riscv64", "-march=rv32i"],
+    ] {
+        let output = compiler(args, "");
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("error: "));
+    }
+}
+
+#[test]
+fn compile_error_has_location() {
+    let output = compiler(&["-riscv", "-"], "int main() {\n  return 1 +;\n}\n");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("<stdin>:2:13: parse error:"), "{stderr}");
+}
+
+#[test]
+fn test_mode_fails_on_wrong_answer() {
+    let dir = std::env::temp_dir().join(format!("compiler-cli-test-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lv1")).unwrap();
+    std::fs::write(dir.join("lv1/ok.sy"), "int main() { return 3; }").unwrap();
+    std::fs::write(dir.join("lv1/ok.out"), "3\n").unwrap();
+    std::fs::write(dir.join("lv1/wrong.sy"), "int main() { return 4; }").unwrap();
+    std::fs::write(dir.join("lv1/wrong.out"), "5\n").unwrap();
+    let run = |filter: &str| compiler(&["-test", dir.to_str().unwrap(), "-s", filter], "");
+    let output = run("ok");
+    assert!(output.status.success());
+    let output = run("lv1");
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("koopa: 1/2 passed"), "{stdout}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "1 test cases failed\n");
+}
+
+#[test]
+fn difftest_without_divergence() {
+    let path = std::env::temp_dir().join(format!("compiler-cli-diff-{}.c", std::process::id()));
+    std::fs::write(&path, "int main() { int a = 7; return a * 6; }").unwrap();
+    let output = compiler(&["-difftest", path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "no divergence\n");
+}
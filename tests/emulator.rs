@@ -0,0 +1,216 @@
+use compiler::emulator::Emulator;
+
+// RV32IM 模拟器的测试: 每条指令的语义, 系统调用和出错的情况.
+// 结果用运行时库的 putint 输出, 比较程序的标准输出
+
+// main 依次执行 body 中的每一段, 每段结束时输出 a0 并换行. 数据段中有缓冲区 buf
+fn run_cases(bodies: &[String]) -> Vec<i32> {
+    let mut asm =
+        String::from("    .text\n    .globl main\nmain:\n    addi sp, sp, -16\n    sw ra, 0(sp)\n");
+    for body in bodies {
+        asm += body;
+        asm += "    call putint\n    li a0, 10\n    call putch\n";
+    }
+    asm += "    lw ra, 0(sp)\n    addi sp, sp, 16\n    li a0, 0\n    ret\n";
+    // 两页的缓冲区, 按页对齐
+    asm += "    .data\n    .align 12\nbuf:\n    .zero 8192\n";
+    let mut emulator = Emulator::from_asm(&asm).unwrap();
+    assert_eq!(emulator.run(Some(1_000_000)), Ok(0), "{asm}");
+    String::from_utf8(emulator.stdout().to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| line.parse().unwrap())
+        .collect()
+}
+
+const EDGES: [i32; 7] = [0, 1, -1, 7, -7, i32::MAX, i32::MIN];
+
+// 对所有边界值的组合执行 inst a0, a0, a1, 与 expected 的结果比较
+fn check_binary(inst: &str, expected: impl Fn(i32, i32) -> i32) {
+    let mut bodies = Vec::new();
+    let mut wanted = Vec::new();
+    for a in EDGES {
+        for b in EDGES {
+            bodies.push(format!(
+                "    li a0, {a}\n    li a1, {b}\n    {inst} a0, a0, a1\n"
+            ));
+            wanted.push(expected(a, b));
+        }
+    }
+    assert_eq!(run_cases(&bodies), wanted, "{inst}");
+}
+
+#[test]
+fn register_operations() {
+    check_binary("add", i32::wrapping_add);
+    check_binary("sub", i32::wrapping_sub);
+    check_binary("sll", |a, b| a.wrapping_shl(b as u32));
+    check_binary("srl", |a, b| (a as u32).wrapping_shr(b as u32) as i32);
+    check_binary("sra", |a, b| a.wrapping_shr(b as u32));
+    check_binary("slt", |a, b| (a < b) as i32);
+    check_binary("sltu", |a, b| ((a as u32) < (b as u32)) as i32);
+    check_binary("xor", |a, b| a ^ b);
+    check_binary("or", |a, b| a | b);
+    check_binary("and", |a, b| a & b);
+    check_binary("sgt", |a, b| (a > b) as i32);
+}
+
+#[test]
+fn multiply_and_divide() {
+    check_binary("mul", i32::wrapping_mul);
+    check_binary("mulh", |a, b| ((a as i64 * b as i64) >> 32) as i32);
+    check_binary("mulhu", |a, b| {
+        ((a as u32 as u64 * b as u32 as u64) >> 32) as i32
+    });
+    check_binary("mulhsu", |a, b| ((a as i64 * b as u32 as i64) >> 32) as i32);
+    // 除以 0 和 INT_MIN / -1 按规范给出结果
+    check_binary("div", |a, b| match b {
+        0 => -1,
+        _ => a.wrapping_div(b),
+    });
+    check_binary("rem", |a, b| match b {
+        0 => a,
+        _ => a.wrapping_rem(b),
+    });
+    check_binary("divu", |a, b| {
+        (a as u32).checked_div(b as u32).unwrap_or(u32::MAX) as i32
+    });
+    check_binary("remu", |a, b| {
+        (a as u32).checked_rem(b as u32).unwrap_or(a as u32) as i32
+    });
+}
+
+#[test]
+fn immediate_operations() {
+    // 立即数形式的运算与寄存器形式的不同, 例如 srai 是算术右移, sltiu 是无符号比较
+    let cases = [
+        ("addi a0, a0, -2048", -5, -2053),
+        ("slti a0, a0, -1", -5, 1),
+        ("sltiu a0, a0, -1", -5, 1),
+        ("sltiu a0, a0, 1", -5, 0),
+        ("xori a0, a0, -1", 5, -6),
+        ("ori a0, a0, 0x70", 5, 0x75),
+        ("andi a0, a0, -4", 7, 4),
+        ("slli a0, a0, 31", 3, i32::MIN),
+        ("srli a0, a0, 28", -1, 15),
+        ("srai a0, a0, 28", i32::MIN, -8),
+        ("lui a0, 0xfffff", 0, -4096),
+    ];
+    let bodies: Vec<String> = cases
+        .iter()
+        .map(|(inst, a, _)| format!("    li a0, {a}\n    {inst}\n"))
+        .collect();
+    let wanted: Vec<i32> = cases.iter().map(|case| case.2).collect();
+    assert_eq!(run_cases(&bodies), wanted);
+}
+
+#[test]
+fn loads_and_stores() {
+    let setup = "    la t0, buf\n    li t1, 0x8081f2f3\n    sw t1, 0(t0)\n";
+    let cases = [
+        ("lw a0, 0(t0)", 0x8081f2f3_u32 as i32),
+        ("lb a0, 0(t0)", 0xf3_u8 as i8 as i32),
+        ("lbu a0, 0(t0)", 0xf3),
+        ("lh a0, 2(t0)", 0x8081_u16 as i16 as i32),
+        ("lhu a0, 2(t0)", 0x8081),
+        ("sb zero, 1(t0)\n    lw a0, 0(t0)", 0x808100f3_u32 as i32),
+        ("sh zero, 2(t0)\n    lw a0, 0(t0)", 0xf2f3),
+        // 跨页的访问, 地址是 buf + 4094
+        (
+            "addi t2, t0, 2047\n    addi t2, t2, 2047\n    sw t1, 0(t2)\n    lh a0, 2(t2)",
+            0x8081_u16 as i16 as i32,
+        ),
+    ];
+    let bodies: Vec<String> = cases
+        .iter()
+        .map(|(inst, _)| format!("{setup}    {inst}\n"))
+        .collect();
+    let wanted: Vec<i32> = cases.iter().map(|case| case.1).collect();
+    assert_eq!(run_cases(&bodies), wanted);
+}
+
+type Cond = fn(i32, i32) -> bool;
+
+#[test]
+fn branches_and_jumps() {
+    let mut bodies = Vec::new();
+    let mut wanted = Vec::new();
+    let conds: [(&str, Cond); 6] = [
+        ("beq", |a, b| a == b),
+        ("bne", |a, b| a != b),
+        ("blt", |a, b| a < b),
+        ("bge", |a, b| a >= b),
+        ("bltu", |a, b| (a as u32) < (b as u32)),
+        ("bgeu", |a, b| (a as u32) >= (b as u32)),
+    ];
+    for (i, (inst, cond)) in conds.iter().enumerate() {
+        for (j, (a, b)) in [(1, 1), (-1, 1), (1, -1)].into_iter().enumerate() {
+            let label = format!(".Ltaken_{i}_{j}");
+            bodies.push(format!(
+                "    li t0, {a}\n    li t1, {b}\n    li a0, 1\n    {inst} t0, t1, {label}\n    li a0, 0\n{label}:\n"
+            ));
+            wanted.push(cond(a, b) as i32);
+        }
+    }
+    // jal 和 jalr 把返回地址写入 rd, auipc 加上自身的地址
+    bodies.push("    auipc t0, 0\n    jal t1, 4\n    sub a0, t1, t0\n".to_owned());
+    wanted.push(8);
+    bodies
+        .push("    auipc t0, 0\n    jalr t1, 12(t0)\n    ebreak\n    sub a0, t1, t0\n".to_owned());
+    wanted.push(8);
+    assert_eq!(run_cases(&bodies), wanted);
+}
+
+#[test]
+fn input_and_exit_code() {
+    let asm = "\
+    .globl main
+main:
+    addi sp, sp, -16
+    sw ra, 0(sp)
+    call getint
+    sw a0, 4(sp)
+    call getch
+    call getch
+    lw t0, 4(sp)
+    add a0, a0, t0
+    lw ra, 0(sp)
+    addi sp, sp, 16
+    ret
+";
+    let mut emulator = Emulator::from_asm(asm).unwrap();
+    emulator.set_input(b"-3 A".to_vec());
+    // 'A' - 3, 退出码只保留低 8 位
+    assert_eq!(emulator.run(Some(10_000)), Ok(62));
+    let stats = emulator.stats();
+    assert!(stats.cycles > stats.instructions, "{stats}");
+}
+
+#[test]
+fn runtime_errors() {
+    let cases = [
+        (
+            "    li t0, 16\n    lw a0, 0(t0)\n",
+            "segmentation fault at 0x00000010",
+        ),
+        ("    ebreak\n", "ebreak at"),
+        ("    .word 0xffffffff\n", "illegal instruction at"),
+        (
+            "    li t0, 16\n    jr t0\n",
+            "jump to non-code address 0x00000010",
+        ),
+        ("    j 0\n", "instruction limit 1000 exceeded"),
+        (
+            "    li a7, 1000\n    ecall\n",
+            "unsupported system call 1000",
+        ),
+    ];
+    for (body, expected) in cases {
+        let asm = format!("    .globl main\nmain:\n{body}    ret\n");
+        let err = Emulator::from_asm(&asm)
+            .unwrap()
+            .run(Some(1000))
+            .unwrap_err();
+        assert!(err.starts_with(expected), "{body}: {err}");
+    }
+}
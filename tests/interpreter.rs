@@ -0,0 +1,274 @@
+use compiler::interpreter::{AstInterpreter, KoopaInterpreter};
+use compiler::parse;
+use koopa::ir::Program;
+
+// AST 解释器和 Koopa IR 解释器的测试.
+// Koopa IR 手写, 覆盖前端还生成不了的函数调用, 数组和基本块参数
+
+fn parse_koopa(koopa: &str) -> Program {
+    koopa::front::Driver::from(koopa)
+        .generate_program()
+        .unwrap()
+}
+
+// 运行 Koopa IR, 返回退出码和输出
+fn run_koopa(koopa: &str, input: &[u8]) -> (Result<i32, String>, String) {
+    let program = parse_koopa(koopa);
+    let mut interpreter = KoopaInterpreter::new(&program);
+    interpreter.runtime().set_input(input.to_vec());
+    let result = interpreter.run(Some(1_000_000));
+    let stdout = String::from_utf8(interpreter.runtime().stdout().to_vec()).unwrap();
+    (result, stdout)
+}
+
+fn run_ast(source: &str) -> Result<i32, String> {
+    AstInterpreter::new().run(&parse(source).unwrap())
+}
+
+#[test]
+fn ast_expressions() {
+    let source = "\
+int main() {
+    const int a = 10, b = a * 2;
+    int x = a + b, y;
+    y = x / 7 + x % 7 - -3;
+    return !0 + (y > 5) + (1 || x / 0) + (0 && x / 0) + (y == 10) * 100;
+}
+";
+    // y = 4 + 2 + 3 = 9; 短路求值时不会执行除以 0
+    assert_eq!(run_ast(source), Ok(3));
+}
+
+#[test]
+fn ast_wraps_like_riscv() {
+    // 溢出回绕, 除以 0 的结果与 RISC-V 一致, 返回值只保留低 8 位
+    let source = "\
+int main() {
+    int max = 2147483647;
+    int min = max + 1;
+    return (min / -1 == min) + (min % -1 == 0) * 2 + (7 / 0 == -1) * 4 + (7 % 0 == 7) * 8 + 256;
+}
+";
+    assert_eq!(run_ast(source), Ok(15));
+}
+
+#[test]
+fn ast_errors() {
+    assert_eq!(
+        run_ast("int main() { return x; }"),
+        Err("undefined variable `x`".to_owned())
+    );
+    assert_eq!(
+        run_ast("int main() { const int c = 1; c = 2; return c; }"),
+        Err("assignment to constant `c`".to_owned())
+    );
+}
+
+#[test]
+fn koopa_calls_and_runtime() {
+    let koopa = "\
+decl @getint(): i32
+decl @putint(i32)
+decl @putch(i32)
+
+fun @fib(%n: i32): i32 {
+%entry:
+  %c = lt %n, 2
+  br %c, %base, %rec
+
+%base:
+  ret %n
+
+%rec:
+  %n1 = sub %n, 1
+  %a = call @fib(%n1)
+  %n2 = sub %n, 2
+  %b = call @fib(%n2)
+  %r = add %a, %b
+  ret %r
+}
+
+fun @main(): i32 {
+%entry:
+  %n = call @getint()
+  %f = call @fib(%n)
+  call @putint(%f)
+  call @putch(10)
+  ret %f
+}
+";
+    let (result, stdout) = run_koopa(koopa, b"20");
+    // fib(20) = 6765, 退出码只保留低 8 位
+    assert_eq!(result, Ok(6765 & 0xff));
+    assert_eq!(stdout, "6765\n");
+}
+
+#[test]
+fn koopa_arrays_and_block_params() {
+    let koopa = "\
+global @g = alloc [[i32, 2], 2], {{1, 2}, {3, 4}}
+
+decl @putarray(i32, *i32)
+
+fun @main(): i32 {
+%entry:
+  @a = alloc [i32, 4]
+  jump %loop(0)
+
+%loop(%i: i32):
+  %c = lt %i, 4
+  br %c, %body, %end
+
+%body:
+  %row = div %i, 2
+  %col = mod %i, 2
+  %p = getelemptr @g, %row
+  %q = getelemptr %p, %col
+  %v = load %q
+  %w = mul %v, 10
+  %d = getelemptr @a, %i
+  store %w, %d
+  %i2 = add %i, 1
+  jump %loop(%i2)
+
+%end:
+  %a0 = getelemptr @a, 0
+  call @putarray(4, %a0)
+  %a1 = getptr %a0, 3
+  %r = load %a1
+  ret %r
+}
+";
+    let (result, stdout) = run_koopa(koopa, b"");
+    assert_eq!(result, Ok(40));
+    assert_eq!(stdout, "4: 10 20 30 40\n");
+}
+
+#[test]
+fn koopa_alloc_in_loop_keeps_its_slot() {
+    // 循环中的 alloc 每次执行得到同一个地址, 上一次迭代存入的值仍然在
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  jump %loop(0)
+
+%loop(%i: i32):
+  @x = alloc i32
+  %v = load @x
+  %v2 = add %v, 1
+  store %v2, @x
+  %i2 = add %i, 1
+  %c = lt %i2, 1000
+  br %c, %loop(%i2), %end(%v2)
+
+%end(%r: i32):
+  ret %r
+}
+";
+    let (result, _) = run_koopa(koopa, b"");
+    assert_eq!(result, Ok(1000 & 0xff));
+}
+
+#[test]
+fn koopa_recursion_releases_frames() {
+    // 每层递归有一个数组, 返回时释放; 深度很大时也不依赖宿主的栈
+    let koopa = "\
+fun @depth(%n: i32): i32 {
+%entry:
+  @buf = alloc [i32, 16]
+  %p = getelemptr @buf, 15
+  store %n, %p
+  %c = eq %n, 0
+  br %c, %done, %rec
+
+%done:
+  ret 0
+
+%rec:
+  %n1 = sub %n, 1
+  %r = call @depth(%n1)
+  %v = load %p
+  %s = add %r, %v
+  ret %s
+}
+
+fun @main(): i32 {
+%entry:
+  %a = call @depth(2000)
+  %b = call @depth(2000)
+  %c = eq %a, %b
+  ret %c
+}
+";
+    let (result, _) = run_koopa(koopa, b"");
+    assert_eq!(result, Ok(1));
+}
+
+#[test]
+fn koopa_errors() {
+    let out_of_bounds = "\
+fun @main(): i32 {
+%entry:
+  @a = alloc [i32, 2]
+  %p = getelemptr @a, 100
+  %v = load %p
+  ret %v
+}
+";
+    let (result, _) = run_koopa(out_of_bounds, b"");
+    assert_eq!(result, Err("load from invalid address 100".to_owned()));
+    let infinite = "\
+fun @main(): i32 {
+%entry:
+  jump %loop
+
+%loop:
+  jump %loop
+}
+";
+    let program = parse_koopa(infinite);
+    assert_eq!(
+        KoopaInterpreter::new(&program).run(Some(1000)),
+        Err("instruction limit 1000 exceeded".to_owned())
+    );
+}
+
+#[test]
+fn koopa_trace() {
+    let koopa = "\
+decl @putch(i32)
+
+fun @main(): i32 {
+%entry:
+  call @putch(65)
+  br 1, %then, %end
+
+%then:
+  call @putch(66)
+  jump %end
+
+%end:
+  ret 0
+}
+";
+    let program = parse_koopa(koopa);
+    let mut interpreter = KoopaInterpreter::new(&program);
+    interpreter.set_trace();
+    assert_eq!(interpreter.run(None), Ok(0));
+    let trace: Vec<_> = interpreter
+        .trace()
+        .iter()
+        .map(|&(func, bb, len)| {
+            let name = program.func(func).dfg().bb(bb).name().clone().unwrap();
+            (name, len)
+        })
+        .collect();
+    // 进入基本块时程序已经输出的字节数
+    let expected = [("%entry", 0), ("%then", 1), ("%end", 2)];
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|&(name, len)| (name.to_owned(), len))
+        .collect();
+    assert_eq!(trace, expected);
+    assert_eq!(interpreter.steps(), 5);
+}
@@ -0,0 +1,254 @@
+use compiler::interpreter::KoopaInterpreter;
+use compiler::opt::{dump, preset, PassManager};
+use compiler::{compile, Options};
+
+// Koopa IR 优化遍的测试, 输入是手写的 Koopa IR
+
+fn optimize(koopa: &str, passes: &[&str]) -> String {
+    let mut program = koopa::front::Driver::from(koopa)
+        .generate_program()
+        .unwrap();
+    let passes: Vec<String> = passes.iter().map(|pass| pass.to_string()).collect();
+    PassManager::new(&passes)
+        .unwrap()
+        .run(&mut program)
+        .unwrap();
+    dump(&program)
+}
+
+#[test]
+fn const_fold_and_dce() {
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  @x = alloc i32
+  store 1, @x
+  %0 = add 2, 3
+  %1 = mul %0, 4
+  %2 = div %1, 0
+  %3 = sub %1, 1
+  ret %3
+}
+";
+    let expected = "\
+fun @main(): i32 {
+%entry:
+    ret 19
+}
+";
+    assert_eq!(optimize(koopa, &["const-fold", "dce"]), expected);
+}
+
+#[test]
+fn simplify_cfg() {
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  %0 = eq 1, 1
+  br %0, %then(7), %else
+
+%then(%a: i32):
+  jump %end(%a)
+
+%else:
+  jump %end(0)
+
+%end(%b: i32):
+  %1 = add %b, 1
+  ret %1
+}
+";
+    let expected = "\
+fun @main(): i32 {
+%entry:
+    ret 8
+}
+";
+    let passes = ["const-fold", "simplify-cfg", "const-fold", "dce"];
+    assert_eq!(optimize(koopa, &passes), expected);
+}
+
+#[test]
+fn dead_func() {
+    let koopa = "\
+decl @putint(i32)
+
+decl @getint(): i32
+
+fun @unused(): i32 {
+%entry:
+  ret 0
+}
+
+fun @main(): i32 {
+%entry:
+  call @putint(1)
+  ret 0
+}
+";
+    let optimized = optimize(koopa, &["dead-func"]);
+    assert!(optimized.contains("@putint"), "{optimized}");
+    assert!(!optimized.contains("@getint"), "{optimized}");
+    assert!(!optimized.contains("@unused"), "{optimized}");
+}
+
+#[test]
+fn unknown_pass() {
+    assert!(PassManager::new(&["inline".to_owned()]).is_err());
+}
+
+#[test]
+fn presets_keep_results() {
+    let source = "int main() { const int c = 3; int x = c * 4; x = x - 2 / 1; return x % 7 + !x; }";
+    let results: Vec<i32> = (0..=2)
+        .map(|level| {
+            let options = Options {
+                passes: preset(level),
+                emit_asm: false,
+                ..Options::default()
+            };
+            let artifacts = compile(source, &options).unwrap();
+            KoopaInterpreter::new(&artifacts.program).run(None).unwrap()
+        })
+        .collect();
+    assert_eq!(results, [3, 3, 3]);
+}
+
+#[test]
+fn simplify_cfg_keeps_loops() {
+    // 求 1 + 2 + ... + 10, %latch 只有一个前驱 %body, 合并到 %body 中; %dead 不可达
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  jump %cond(1, 0)
+
+%cond(%i: i32, %s: i32):
+  %0 = le %i, 10
+  br %0, %body, %end
+
+%body:
+  %1 = add %s, %i
+  %2 = add %i, 1
+  jump %latch
+
+%latch:
+  jump %cond(%2, %1)
+
+%dead:
+  jump %latch
+
+%end:
+  ret %s
+}
+";
+    let optimized = optimize(koopa, &["simplify-cfg"]);
+    assert!(
+        !optimized.contains("%dead") && !optimized.contains("%latch"),
+        "{optimized}"
+    );
+    let program = koopa::front::Driver::from(optimized)
+        .generate_program()
+        .unwrap();
+    assert_eq!(KoopaInterpreter::new(&program).run(None), Ok(55));
+}
+
+#[test]
+fn mem2reg() {
+    // int s = 0, i = 0; while (i < 10) { if (i % 3 == 0) s = s + i; i = i + 1; } return s;
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  @s = alloc i32
+  @i = alloc i32
+  @a = alloc [i32, 2]
+  store 0, @s
+  store 0, @i
+  jump %cond
+
+%cond:
+  %0 = load @i
+  %1 = lt %0, 10
+  br %1, %body, %end
+
+%body:
+  %2 = load @i
+  %3 = mod %2, 3
+  %4 = eq %3, 0
+  br %4, %then, %next
+
+%then:
+  %5 = load @s
+  %6 = load @i
+  %7 = add %5, %6
+  store %7, @s
+  jump %next
+
+%next:
+  %8 = load @i
+  %9 = add %8, 1
+  store %9, @i
+  jump %cond
+
+%end:
+  %10 = getelemptr @a, 0
+  store 1, %10
+  %11 = load @s
+  ret %11
+}
+";
+    let optimized = optimize(koopa, &["mem2reg"]);
+    // 数组不提升
+    assert!(optimized.contains("alloc [i32, 2]"), "{optimized}");
+    assert!(
+        !optimized.contains("alloc i32") && !optimized.contains("load"),
+        "{optimized}"
+    );
+    let program = koopa::front::Driver::from(optimized)
+        .generate_program()
+        .unwrap();
+    assert_eq!(KoopaInterpreter::new(&program).run(None), Ok(18));
+}
+
+#[test]
+fn dce_removes_dead_params() {
+    // int x = 0, i = 0; while (i < 10) { x = x + i; i = i + 1; } return i;
+    // x 只在循环中传给自己, mem2reg 给它添加的参数由 dce 删除
+    let koopa = "\
+fun @main(): i32 {
+%entry:
+  @x = alloc i32
+  @i = alloc i32
+  store 0, @x
+  store 0, @i
+  jump %cond
+
+%cond:
+  %0 = load @i
+  %1 = lt %0, 10
+  br %1, %body, %end
+
+%body:
+  %2 = load @x
+  %3 = load @i
+  %4 = add %2, %3
+  store %4, @x
+  %5 = add %3, 1
+  store %5, @i
+  jump %cond
+
+%end:
+  %6 = load @i
+  ret %6
+}
+";
+    let promoted = optimize(koopa, &["mem2reg"]);
+    assert!(promoted.contains("%cond(%0: i32, %1: i32)"), "{promoted}");
+    let optimized = optimize(koopa, &["mem2reg", "dce"]);
+    assert!(optimized.contains("%cond(%0: i32):"), "{optimized}");
+    // 计算 x 的 add 也随之删除
+    assert_eq!(optimized.matches(" add ").count(), 1, "{optimized}");
+    let program = koopa::front::Driver::from(optimized)
+        .generate_program()
+        .unwrap();
+    assert_eq!(KoopaInterpreter::new(&program).run(None), Ok(10));
+}
@@ -0,0 +1,89 @@
+use std::env::var_os;
+use std::fs::{read_dir, read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+
+use compiler::{compile, Options};
+
+// 快照测试.
+// tests/snapshots 下的每个 xxx.c 用默认选项编译, 生成的 Koopa IR 和 RISC-V 汇编与同名的 xxx.koopa 和 xxx.s 比较.
+// 后端不支持的程序没有 xxx.s, 此时要求后端报错.
+// 设置环境变量 UPDATE_SNAPSHOTS=1 运行时用当前的输出覆盖期望文件, 修改编译器后用 git diff 检查变化.
+
+fn inputs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let mut inputs: Vec<PathBuf> = read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "c"))
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+// 第一处不同的行, 用来在失败信息中定位
+fn first_diff(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => break,
+            (e, a) if e == a => continue,
+            (e, a) => {
+                return format!(
+                    "line {line}: expected {:?}, got {:?}",
+                    e.unwrap_or("<eof>"),
+                    a.unwrap_or("<eof>")
+                )
+            }
+        }
+    }
+    "trailing whitespace differs".to_owned()
+}
+
+#[test]
+fn snapshots() {
+    let update = var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut failures = Vec::new();
+    for input in inputs() {
+        let source = read_to_string(&input).unwrap();
+        // 前端出错时整个用例失败, 后端出错时没有汇编
+        let (koopa, asm) = match compile(&source, &Options::default()) {
+            Ok(artifacts) => (artifacts.koopa, artifacts.asm),
+            Err(_) => {
+                let options = Options {
+                    emit_asm: false,
+                    ..Options::default()
+                };
+                let artifacts = compile(&source, &options)
+                    .unwrap_or_else(|err| panic!("{}: {err}", input.display()));
+                (artifacts.koopa, None)
+            }
+        };
+        for (ext, actual) in [("koopa", Some(koopa)), ("s", asm)] {
+            let snapshot = input.with_extension(ext);
+            let expected = read_to_string(&snapshot).ok();
+            if update {
+                match &actual {
+                    Some(actual) => write(&snapshot, actual).unwrap(),
+                    None if expected.is_some() => remove_file(&snapshot).unwrap(),
+                    None => {}
+                }
+                continue;
+            }
+            let name = snapshot.file_name().unwrap().to_str().unwrap().to_owned();
+            match (expected, actual) {
+                (Some(expected), Some(actual)) if expected != actual => {
+                    failures.push(format!("{name}: {}", first_diff(&expected, &actual)))
+                }
+                (Some(_), None) => failures.push(format!("{name}: backend failed")),
+                (None, Some(_)) => failures.push(format!("{name}: missing snapshot")),
+                _ => {}
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "snapshots differ (run with UPDATE_SNAPSHOTS=1 to update):\n{}",
+        failures.join("\n")
+    );
+}
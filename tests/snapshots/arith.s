@@ -0,0 +1,18 @@
+    .text
+    .globl main
+main:
+    li t5, 2
+    li t6, 3
+    mul t0, t5, t6
+    li t5, 1
+    add t1, t5, t0
+    li t5, 4
+    li t6, 5
+    sub t0, t5, t6
+    li t6, 2
+    div t2, t0, t6
+    li t6, 3
+    rem t0, t2, t6
+    sub t2, t1, t0
+    mv a0, t2
+    ret
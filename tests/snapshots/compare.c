@@ -0,0 +1,3 @@
+int main() {
+  return (1 < 2) + (3 >= 3) * 2 + (4 == 5) - (6 != 7) + (8 > 9);
+}
@@ -0,0 +1,29 @@
+    .text
+    .globl main
+main:
+    li t5, 1
+    li t6, 2
+    slt t0, t5, t6
+    li t5, 3
+    li t6, 3
+    slt t1, t5, t6
+    seqz t1, t1
+    li t6, 2
+    mul t2, t1, t6
+    add t1, t0, t2
+    li t5, 4
+    li t6, 5
+    xor t0, t5, t6
+    seqz t0, t0
+    add t2, t1, t0
+    li t5, 6
+    li t6, 7
+    xor t0, t5, t6
+    snez t0, t0
+    sub t1, t2, t0
+    li t5, 8
+    li t6, 9
+    sgt t0, t5, t6
+    add t2, t1, t0
+    mv a0, t2
+    ret
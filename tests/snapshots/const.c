@@ -0,0 +1,5 @@
+int main() {
+  const int a = 10, b = a * 2;
+  const int c = b - a / 3;
+  return c + a;
+}
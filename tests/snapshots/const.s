@@ -0,0 +1,8 @@
+    .text
+    .globl main
+main:
+    li t5, 17
+    li t6, 10
+    add t0, t5, t6
+    mv a0, t0
+    ret
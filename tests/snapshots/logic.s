@@ -0,0 +1,25 @@
+    .text
+    .globl main
+main:
+    li t5, 2
+    xor t0, t5, x0
+    snez t0, t0
+    li t5, 3
+    xor t1, t5, x0
+    snez t1, t1
+    and t2, t0, t1
+    xor t0, t2, x0
+    snez t0, t0
+    xor t1, x0, x0
+    snez t1, t1
+    li t5, 1
+    xor t2, t5, x0
+    seqz t2, t2
+    xor t3, t2, x0
+    snez t3, t3
+    and t2, t1, t3
+    xor t1, t2, x0
+    snez t1, t1
+    or t2, t0, t1
+    mv a0, t2
+    ret
@@ -0,0 +1,4 @@
+int main() {
+  // 注释
+  return /* 块注释 */ 0x1f;
+}
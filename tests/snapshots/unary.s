@@ -0,0 +1,10 @@
+    .text
+    .globl main
+main:
+    li t6, 15
+    sub t0, x0, t6
+    xor t1, t0, x0
+    seqz t1, t1
+    sub t0, x0, t1
+    mv a0, t0
+    ret
@@ -0,0 +1,7 @@
+int main() {
+  const int n = 4;
+  int x = n * 2, y;
+  y = x + 1;
+  x = x * y - n;
+  return x;
+}
@@ -0,0 +1,20 @@
+    .text
+    .globl main
+main:
+    addi sp, sp, -16
+    li t5, 4
+    li t6, 2
+    mul t0, t5, t6
+    sw t0, 0(sp)
+    li t6, 1
+    add t1, t0, t6
+    sw t1, 4(sp)
+    lw t0, 0(sp)
+    lw t1, 4(sp)
+    mul t2, t0, t1
+    li t6, 4
+    sub t0, t2, t6
+    sw t0, 0(sp)
+    mv a0, t0
+    addi sp, sp, 16
+    ret